@@ -0,0 +1,959 @@
+use crate::compression::decompressed;
+use crate::errors::{ConnError, OperationError, WriteReadLineError};
+use crate::expiration::Expiration;
+use crate::flags::Flags;
+use crate::item::Item;
+use crate::protocol::{self, Arg};
+use crate::selector::{ServerList, ServerSelector};
+use crate::stats::ServerStats;
+use crate::trace;
+use crate::{
+    legal_key, Client, DEFAULT_MAX_VALUE_SIZE, RESULT_DELETED, RESULT_TOUCHED, VERB_ADD, VERB_DECR,
+    VERB_DELETE, VERB_GET, VERB_INCR, VERB_REPLACE, VERB_SET, VERB_STATS, VERB_TOUCH,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+
+// Client for async code on tokio, speaking the ASCII protocol with `Client`'s parsing and
+// formatting; Cheap to clone, clones share the connection pools, and every method takes `&self`
+// so a client can be shared between tasks; Connections are dialed on first use; Every operation
+// (dial included) is bounded by the client timeout through `tokio::time::timeout`, after which
+// its connection is dropped; Every method is cancel safe: dropping its future early, e.g. from an
+// outer `tokio::time::timeout`, drops the connection it was using mid-response rather than
+// returning it to the pool, so the client can be used right away; Pooled connections belong to
+// the runtime that dialed them, so a client shouldn't be shared between runtimes; Key prefixes,
+// compression, retries and TLS are only supported by `Client` so far;
+#[derive(Debug, Clone)]
+pub struct AsyncClient {
+    shared: Arc<Shared>,
+    timeout: Duration,
+    max_conns: Option<usize>,
+    checkout_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Shared {
+    servers: Box<dyn ServerSelector>,
+    max_idle_conns: usize,
+    // Pool of every server, created on first use; Only locked to look a pool up, never across an
+    // await
+    pools: Mutex<HashMap<SocketAddr, Arc<ServerPool>>>,
+}
+
+impl AsyncClient {
+    // A `timeout` of `None` uses the default of 500ms, like `Client::new`;
+    pub fn new(
+        server_addr: String,
+        timeout: Option<Duration>,
+        max_idle_conns: u8,
+    ) -> Result<Self, ConnError> {
+        let servers = ServerList::new(&[&server_addr])?;
+        AsyncClient::new_from_selector(servers, timeout, max_idle_conns)
+    }
+
+    pub fn new_from_selector(
+        selector: impl ServerSelector + 'static,
+        timeout: Option<Duration>,
+        max_idle_conns: u8,
+    ) -> Result<Self, ConnError> {
+        let timeout = Client::net_timout(timeout)?;
+        let shared = Shared {
+            servers: Box::new(selector),
+            max_idle_conns: usize::from(Client::max_idle_conns(max_idle_conns)),
+            pools: Mutex::new(HashMap::new()),
+        };
+        Ok(AsyncClient {
+            shared: Arc::new(shared),
+            timeout,
+            max_conns: None,
+            checkout_timeout: timeout,
+        })
+    }
+
+    // Bounds the dial and the round trip of every operation; Clones made afterwards share it,
+    // while existing clones keep their own timeout over the same pools, so
+    // `client.clone().with_timeout(..)` gives a handle with a tighter deadline;
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), ConnError> {
+        self.timeout = Client::net_timout(Some(timeout))?;
+        Ok(())
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self, ConnError> {
+        self.set_timeout(timeout)?;
+        Ok(self)
+    }
+
+    // Caps the connections checked out to each server at once; Operations over the cap wait for
+    // a connection to be returned, in the order they asked for one, for up to the checkout
+    // timeout before failing with `PoolExhausted`; A server's cap is fixed when its pool is
+    // created on first use, so set it before the client is used or cloned; `None` (the default)
+    // leaves it unbounded;
+    pub fn set_max_connections_per_server(&mut self, max_conns: Option<usize>) {
+        self.max_conns = max_conns.map(|max_conns| max_conns.max(1));
+    }
+
+    pub fn with_max_connections_per_server(mut self, max_conns: Option<usize>) -> Self {
+        self.set_max_connections_per_server(max_conns);
+        self
+    }
+
+    // How long an operation waits for a connection when its server is at the max connections,
+    // the client timeout by default; The wait isn't counted in the operation's own timeout;
+    pub fn set_checkout_timeout(&mut self, checkout_timeout: Duration) {
+        self.checkout_timeout = checkout_timeout;
+    }
+
+    pub fn with_checkout_timeout(mut self, checkout_timeout: Duration) -> Self {
+        self.set_checkout_timeout(checkout_timeout);
+        self
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Item>, OperationError> {
+        trace::in_async_retrieval_span(trace::op_span!("memcache.get", key), async {
+            legal_key(key)?;
+            let command = command_line(VERB_GET, [Arg::Str(key)]);
+            let mut items = self.retrieve(self.pick_server(key)?, command).await?;
+            if let Some(item) = items.last() {
+                trace::record_value_size(item.value.len());
+            }
+            items.pop().map(returned_item).transpose()
+        })
+        .await
+    }
+
+    // `get` failing with `Timeout` unless it completes within `timeout`, waiting for a pooled
+    // connection included;
+    pub async fn get_with_timeout(
+        &self,
+        key: &str,
+        timeout: Duration,
+    ) -> Result<Option<Item>, OperationError> {
+        trace::in_async_retrieval_span(trace::op_span!("memcache.get_with_timeout", key), async {
+            timed(timeout, self.get(key)).await
+        })
+        .await
+    }
+
+    // Keys missing from the server are absent from the returned map; Fails with
+    // `ServerFailures` if any server failed, see `get_multi_partial` to keep the other servers'
+    // items;
+    pub async fn get_multi(&self, keys: &[&str]) -> Result<HashMap<String, Item>, OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.get_multi"), async {
+            let results = self.get_multi_partial(keys).await?;
+            if !results.failures.is_empty() {
+                return Err(OperationError::ServerFailures(results.failures));
+            }
+            Ok(results.items)
+        })
+        .await
+    }
+
+    // Asks every server for its keys at once, on tasks of the current runtime, so a multi get
+    // takes about as long as the slowest server rather than the sum of them; A failing server
+    // is reported in `GetMultiResults::failures` next to the items of the others; Only invalid
+    // keys fail as a whole;
+    pub async fn get_multi_partial(
+        &self,
+        keys: &[&str],
+    ) -> Result<GetMultiResults, OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.get_multi_partial"), async {
+            keys.iter().try_for_each(|key| legal_key(key))?;
+            let mut groups: HashMap<SocketAddr, Vec<&str>> = HashMap::new();
+            for key in keys {
+                groups.entry(self.pick_server(key)?).or_default().push(key);
+            }
+
+            let mut tasks = JoinSet::new();
+            for (addr, keys) in groups {
+                let command = command_line(VERB_GET, keys.iter().map(|key| Arg::Str(key)));
+                let client = self.clone();
+                tasks.spawn(async move { (addr, client.retrieve(addr, command).await) });
+            }
+            let mut results = GetMultiResults::default();
+            while let Some(joined) = tasks.join_next().await {
+                let (addr, retrieved) = match joined {
+                    Ok(done) => done,
+                    // Tasks are only aborted when the set is dropped, so this was a panic
+                    Err(error) => std::panic::resume_unwind(error.into_panic()),
+                };
+                let found = retrieved.and_then(|found| {
+                    found
+                        .into_iter()
+                        .map(returned_item)
+                        .collect::<Result<Vec<_>, _>>()
+                });
+                match found {
+                    Ok(found) => {
+                        results
+                            .items
+                            .extend(found.into_iter().map(|item| (item.key.clone(), item)));
+                    }
+                    Err(error) => results.failures.push((addr, error)),
+                }
+            }
+            Ok(results)
+        })
+        .await
+    }
+
+    pub async fn set(&self, item: &Item) -> Result<(), OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.set", &item.key), async {
+            self.store(VERB_SET, item).await
+        })
+        .await
+    }
+
+    // `set` bounded by `timeout` like `get_with_timeout`;
+    pub async fn set_with_timeout(
+        &self,
+        item: &Item,
+        timeout: Duration,
+    ) -> Result<(), OperationError> {
+        trace::in_async_span(
+            trace::op_span!("memcache.set_with_timeout", &item.key),
+            async { timed(timeout, self.set(item)).await },
+        )
+        .await
+    }
+
+    pub async fn add(&self, item: &Item) -> Result<(), OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.add", &item.key), async {
+            self.store(VERB_ADD, item).await
+        })
+        .await
+    }
+
+    pub async fn replace(&self, item: &Item) -> Result<(), OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.replace", &item.key), async {
+            self.store(VERB_REPLACE, item).await
+        })
+        .await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.delete", key), async {
+            legal_key(key)?;
+            let command = command_line(VERB_DELETE, [Arg::Str(key)]);
+            self.round_trip(self.pick_server(key)?, command, |line| {
+                protocol::expected_result(line, VERB_DELETE, RESULT_DELETED)
+            })
+            .await
+        })
+        .await
+    }
+
+    pub async fn increment(&self, key: &str, delta: u64) -> Result<u64, OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.increment", key), async {
+            self.incr_decr(VERB_INCR, key, delta).await
+        })
+        .await
+    }
+
+    pub async fn decrement(&self, key: &str, delta: u64) -> Result<u64, OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.decrement", key), async {
+            self.incr_decr(VERB_DECR, key, delta).await
+        })
+        .await
+    }
+
+    pub async fn touch(&self, key: &str, expiration: Expiration) -> Result<(), OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.touch", key), async {
+            legal_key(key)?;
+            let exptime = expiration.wire_value()?;
+            let command = command_line(VERB_TOUCH, [Arg::Str(key), Arg::Int(exptime.into())]);
+            self.round_trip(self.pick_server(key)?, command, |line| {
+                protocol::expected_result(line, VERB_TOUCH, RESULT_TOUCHED)
+            })
+            .await
+        })
+        .await
+    }
+
+    // Stats of every server, like `Client::stats`;
+    pub async fn stats(
+        &self,
+    ) -> Result<HashMap<SocketAddr, HashMap<String, String>>, OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.stats"), async {
+            let mut stats = HashMap::new();
+            let mut failures = Vec::new();
+            for addr in self.shared.servers.addrs() {
+                let result = self
+                    .with_conn(addr, |conn| conn.stats(command_line(VERB_STATS, [])))
+                    .await;
+                match result {
+                    Ok(server_stats) => {
+                        stats.insert(addr, server_stats);
+                    }
+                    Err(error) => failures.push((addr, error)),
+                }
+            }
+            if !failures.is_empty() {
+                return Err(OperationError::ServerFailures(failures));
+            }
+            Ok(stats)
+        })
+        .await
+    }
+
+    // `stats` of every server parsed into a `ServerStats`, like `Client::server_stats`;
+    pub async fn server_stats(&self) -> Result<HashMap<SocketAddr, ServerStats>, OperationError> {
+        trace::in_async_span(trace::op_span!("memcache.server_stats"), async {
+            Ok(self
+                .stats()
+                .await?
+                .into_iter()
+                .map(|(addr, stats)| (addr, ServerStats::from_stats(stats)))
+                .collect())
+        })
+        .await
+    }
+
+    async fn store(&self, verb: &str, item: &Item) -> Result<(), OperationError> {
+        legal_key(&item.key)?;
+        Flags::from_user(item.flags)?;
+        let stored = item.stored();
+        if stored.value.len() > DEFAULT_MAX_VALUE_SIZE {
+            return Err(OperationError::ValueTooLarge {
+                size: stored.value.len(),
+                limit: DEFAULT_MAX_VALUE_SIZE,
+            });
+        }
+        trace::record_value_size(stored.value.len());
+        let mut command = Vec::with_capacity(stored.value.len() + 64);
+        protocol::write_storage_command(&mut command, verb, &item.key, &stored, None, false)?;
+        self.round_trip(self.pick_server(&item.key)?, command, |line| {
+            protocol::storage_result(line, verb)
+        })
+        .await
+    }
+
+    async fn incr_decr(&self, verb: &str, key: &str, delta: u64) -> Result<u64, OperationError> {
+        legal_key(key)?;
+        let command = command_line(verb, [Arg::Str(key), Arg::Uint(delta)]);
+        self.round_trip(self.pick_server(key)?, command, |line| {
+            protocol::incr_decr_result(line, verb)
+        })
+        .await
+    }
+
+    // Sends `command` and parses its one line response;
+    async fn round_trip<T>(
+        &self,
+        addr: SocketAddr,
+        command: Vec<u8>,
+        parse: impl FnOnce(&[u8]) -> Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        self.with_conn(addr, |conn| conn.write_read_line(command, parse))
+            .await
+    }
+
+    async fn retrieve(
+        &self,
+        addr: SocketAddr,
+        command: Vec<u8>,
+    ) -> Result<Vec<Item>, OperationError> {
+        self.with_conn(addr, |conn| conn.retrieve(command)).await
+    }
+
+    // Runs `f` on a connection to `addr` within the client timeout, returning the connection to
+    // the pool unless it was left in an unknown state, like `Client::release_conn`;
+    async fn with_conn<T, F, Fut>(&self, addr: SocketAddr, f: F) -> Result<T, OperationError>
+    where
+        F: FnOnce(AsyncConn) -> Fut,
+        Fut: Future<Output = (AsyncConn, Result<T, OperationError>)>,
+    {
+        trace::record_addr(addr);
+        let mut pooled = self.checkout(addr).await?;
+        let conn = match pooled.conn.take() {
+            Some(conn) => {
+                trace::conn_reused(addr);
+                conn
+            }
+            None => {
+                let conn = timed(self.timeout, AsyncConn::dial(addr)).await?;
+                trace::conn_dialed(addr);
+                conn
+            }
+        };
+        let (conn, result) = match tokio::time::timeout(self.timeout, f(conn)).await {
+            Ok(done) => done,
+            // The connection goes with the unfinished operation
+            Err(elapsed) => {
+                trace::conn_discarded(addr);
+                return Err(OperationError::Timeout(elapsed.into()));
+            }
+        };
+        match &result {
+            Err(error) if error.fatal_to_connection() => trace::conn_discarded(addr),
+            _ => pooled.conn = Some(conn),
+        }
+        result
+    }
+
+    // Waits for a permit to use a connection to `addr`, handing over an idle connection if there
+    // is one, the caller dials otherwise;
+    async fn checkout(&self, addr: SocketAddr) -> Result<PooledConn, OperationError> {
+        let pool = self.pool(addr);
+        let permit = match &pool.permits {
+            Some(permits) => {
+                let acquire = Arc::clone(permits).acquire_owned();
+                match tokio::time::timeout(self.checkout_timeout, acquire).await {
+                    Ok(Ok(permit)) => Some(permit),
+                    // `Ok(Err(_))` can't happen as the semaphore is never closed
+                    Ok(Err(_)) | Err(_) => return Err(OperationError::PoolExhausted(addr)),
+                }
+            }
+            None => None,
+        };
+        let conn = lock(&pool.free_conns).pop();
+        Ok(PooledConn {
+            pool,
+            conn,
+            _permit: permit,
+        })
+    }
+
+    fn pool(&self, addr: SocketAddr) -> Arc<ServerPool> {
+        let mut pools = lock(&self.shared.pools);
+        let pool = pools.entry(addr).or_insert_with(|| {
+            Arc::new(ServerPool {
+                permits: self
+                    .max_conns
+                    .map(|max_conns| Arc::new(Semaphore::new(max_conns))),
+                max_idle_conns: self.shared.max_idle_conns,
+                free_conns: Mutex::new(Vec::new()),
+            })
+        });
+        Arc::clone(pool)
+    }
+
+    fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError> {
+        self.shared.servers.pick_server(key)
+    }
+}
+
+// Outcome of `AsyncClient::get_multi_partial`;
+#[derive(Debug, Default)]
+pub struct GetMultiResults {
+    // Items found on the servers that answered
+    pub items: HashMap<String, Item>,
+    // Servers that failed, whose keys are missing from `items`
+    pub failures: Vec<(SocketAddr, OperationError)>,
+}
+
+#[derive(Debug)]
+struct ServerPool {
+    // One per connection that may be checked out at once, `None` when unbounded; tokio's
+    // semaphore queues waiters first come first served, so a flood of new operations can't
+    // starve earlier ones
+    permits: Option<Arc<Semaphore>>,
+    max_idle_conns: usize,
+    free_conns: Mutex<Vec<AsyncConn>>,
+}
+
+// A checked out connection slot; Dropping it returns `conn` to its pool, if it's still set and the
+// pool has room, then releases the permit so the next waiter finds the connection idle;
+// Operations take `conn` out while they run and only put it back once it's known to be in a
+// clean state, so a failed or cancelled operation's connection is discarded;
+struct PooledConn {
+    pool: Arc<ServerPool>,
+    conn: Option<AsyncConn>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for PooledConn {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            let mut free_conns = lock(&self.pool.free_conns);
+            if free_conns.len() < self.pool.max_idle_conns {
+                free_conns.push(conn);
+            }
+        }
+    }
+}
+
+// A panic while the pool was locked can't leave it inconsistent, it's only pushed and popped
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+async fn timed<T>(
+    timeout: Duration,
+    operation: impl Future<Output = Result<T, OperationError>>,
+) -> Result<T, OperationError> {
+    tokio::time::timeout(timeout, operation)
+        .await
+        .unwrap_or_else(|elapsed| Err(OperationError::Timeout(elapsed.into())))
+}
+
+// `<verb> <args...>\r\n`, ready to be moved into the future sending it;
+fn command_line<'a>(verb: &str, args: impl IntoIterator<Item = Arg<'a>>) -> Vec<u8> {
+    let mut command = Vec::new();
+    // NOTE: Writing to a `Vec` can't fail
+    let _ = protocol::write_command(&mut command, verb, args);
+    command
+}
+
+// An item as read, decompressed and without the reserved flags, like `Client::returned_item`;
+fn returned_item(item: Item) -> Result<Item, OperationError> {
+    let mut item = decompressed(item)?;
+    item.flags = Flags::from_wire(item.flags).user();
+    Ok(item)
+}
+
+#[derive(Debug)]
+struct AsyncConn {
+    addr: SocketAddr,
+    reader: BufReader<OwnedReadHalf>,
+    writer: BufWriter<OwnedWriteHalf>,
+}
+
+impl AsyncConn {
+    async fn dial(addr: SocketAddr) -> Result<Self, OperationError> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|error| OperationError::Connect(addr, error))?;
+        stream
+            .set_nodelay(true)
+            .map_err(|error| OperationError::Connect(addr, error))?;
+        let (reader, writer) = stream.into_split();
+        Ok(AsyncConn {
+            addr,
+            reader: BufReader::new(reader),
+            writer: BufWriter::new(writer),
+        })
+    }
+
+    async fn write_read_line<T>(
+        mut self,
+        command: Vec<u8>,
+        parse: impl FnOnce(&[u8]) -> Result<T, OperationError>,
+    ) -> (Self, Result<T, OperationError>) {
+        let result = match self.write(&command).await {
+            Ok(()) => self.read_line().await,
+            Err(error) => Err(error),
+        };
+        (self, result.and_then(|line| parse(&line)))
+    }
+
+    async fn retrieve(mut self, command: Vec<u8>) -> (Self, Result<Vec<Item>, OperationError>) {
+        let result = self.read_items(command).await;
+        (self, result)
+    }
+
+    async fn read_items(&mut self, command: Vec<u8>) -> Result<Vec<Item>, OperationError> {
+        self.write(&command).await?;
+        let mut items = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            let Some(header) = protocol::parse_value_header(&line, VERB_GET, false)? else {
+                return Ok(items);
+            };
+            let mut block = vec![0; header.size + 2];
+            self.reader.read_exact(&mut block).await.map_err(|error| {
+                OperationError::CorruptResponse(format!("could not read value: {}", error))
+            })?;
+            items.push(protocol::value_item(header, block)?);
+        }
+    }
+
+    async fn stats(
+        mut self,
+        command: Vec<u8>,
+    ) -> (Self, Result<HashMap<String, String>, OperationError>) {
+        let result = self.read_stats(command).await;
+        (self, result)
+    }
+
+    async fn read_stats(
+        &mut self,
+        command: Vec<u8>,
+    ) -> Result<HashMap<String, String>, OperationError> {
+        self.write(&command).await?;
+        let mut stats = HashMap::new();
+        while let Some((name, value)) = protocol::parse_stat_line(&self.read_line().await?)? {
+            stats.insert(name, value);
+        }
+        if stats.is_empty() {
+            return Err(OperationError::NoStats);
+        }
+        Ok(stats)
+    }
+
+    async fn write(&mut self, buf: &[u8]) -> Result<(), OperationError> {
+        self.writer
+            .write_all(buf)
+            .await
+            .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))?;
+        self.writer
+            .flush()
+            .await
+            .map_err(|error| OperationError::Io(WriteReadLineError::Flush(error)))
+    }
+
+    // Reads a line, reporting a server hanging up as an error instead of an empty line;
+    async fn read_line(&mut self) -> Result<Vec<u8>, OperationError> {
+        let mut line = Vec::new();
+        match self.reader.read_until(b'\n', &mut line).await {
+            Ok(0) => Err(OperationError::Io(WriteReadLineError::Read(
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed by the server",
+                ),
+            ))),
+            Ok(_) => Ok(line),
+            Err(error) => Err(OperationError::Io(WriteReadLineError::Read(error))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncClient;
+    use crate::selector::ServerList;
+    use crate::{
+        errors::OperationError,
+        expiration::Expiration,
+        item::Item,
+        tests::{lock_local_server, slow_server, LOCALHOST_TCP_ADDR},
+    };
+    use std::future::Future;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("could not start a runtime")
+            .block_on(future)
+    }
+
+    fn async_client() -> AsyncClient {
+        match AsyncClient::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not create an async client: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_async_client() {
+        let _guard = lock_local_server();
+        let client = async_client();
+        block_on(async {
+            let _ = client.delete("async_key").await;
+            let item = Item::new("async_key", "value").unwrap().with_flags(3);
+            if let Err(error) = client.set(&item).await {
+                panic!("did not expect set to fail: {}", error)
+            }
+            match client.get("async_key").await {
+                Ok(Some(got)) if got.value == b"value" && got.flags == 3 => (),
+                other => panic!("expected the stored item. Got: {:?}", other),
+            }
+            match client.add(&item).await {
+                Err(OperationError::NotStored) => (),
+                other => panic!("expected add of an existing key to fail. Got: {:?}", other),
+            }
+            if let Err(error) = client.replace(&Item::new("async_key", "7").unwrap()).await {
+                panic!("did not expect replace to fail: {}", error)
+            }
+            match client.increment("async_key", 5).await {
+                Ok(12) => (),
+                other => panic!("expected the counter to increment to 12. Got: {:?}", other),
+            }
+            match client.decrement("async_key", 20).await {
+                Ok(0) => (),
+                other => panic!("expected the counter to floor at zero. Got: {:?}", other),
+            }
+            let ttl = Expiration::In(Duration::from_secs(60));
+            if let Err(error) = client.touch("async_key", ttl).await {
+                panic!("did not expect touch to fail: {}", error)
+            }
+
+            let _ = client.delete("async_other").await;
+            match client.get_multi(&["async_key", "async_other"]).await {
+                Ok(items) if items.len() == 1 && items["async_key"].value == b"0" => (),
+                other => panic!("expected only the stored item. Got: {:?}", other),
+            }
+            if let Err(error) = client.delete("async_key").await {
+                panic!("did not expect delete to fail: {}", error)
+            }
+            match (
+                client.get("async_key").await,
+                client.delete("async_key").await,
+            ) {
+                (Ok(None), Err(OperationError::CacheMiss)) => (),
+                other => panic!("expected the item to be deleted. Got: {:?}", other),
+            }
+            match client.touch("async_key", ttl).await {
+                Err(OperationError::CacheMiss) => (),
+                other => panic!("expected touch of a missing key to fail. Got: {:?}", other),
+            }
+
+            match client.stats().await {
+                Ok(stats) if stats.values().all(|stats| stats.contains_key("pid")) => (),
+                other => panic!("expected the server stats. Got: {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn async_clients_are_shared_between_tasks() {
+        let _guard = lock_local_server();
+        let client = async_client();
+        block_on(async {
+            if let Err(error) = client.set(&Item::new("async_counter", "0").unwrap()).await {
+                panic!("did not expect set to fail: {}", error)
+            }
+            let tasks: Vec<_> = (0..8)
+                .map(|_| {
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        for _ in 0..10 {
+                            client.increment("async_counter", 1).await.unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+            match client.get("async_counter").await {
+                Ok(Some(item)) if item.value == b"80" => (),
+                other => panic!("expected every increment to count. Got: {:?}", other),
+            }
+        });
+        let idle: usize = super::lock(&client.shared.pools)
+            .values()
+            .map(|pool| super::lock(&pool.free_conns).len())
+            .sum();
+        if idle > client.shared.max_idle_conns {
+            panic!(
+                "expected at most the max idle connections to be kept. Got: {}",
+                idle
+            )
+        }
+    }
+
+    #[test]
+    fn async_operations_time_out() {
+        // Accepts connections (through the backlog) but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let client = AsyncClient::new(addr, Some(Duration::from_millis(100)), 0).unwrap();
+        let started = Instant::now();
+        match block_on(client.get("key")) {
+            Err(OperationError::Timeout(_)) => (),
+            other => panic!("expected the get to time out. Got: {:?}", other),
+        }
+        if started.elapsed() > Duration::from_secs(2) {
+            panic!(
+                "expected the timeout to apply. Took: {:?}",
+                started.elapsed()
+            )
+        }
+        drop(listener);
+    }
+
+    #[test]
+    fn get_multi_asks_servers_concurrently() {
+        let latency = Duration::from_millis(400);
+        let addrs: Vec<String> = (0..3).map(|_| slow_server(latency)).collect();
+        let addrs: Vec<&str> = addrs.iter().map(String::as_str).collect();
+        let servers = ServerList::new(&addrs).unwrap();
+        let client =
+            AsyncClient::new_from_selector(servers, Some(Duration::from_secs(2)), 0).unwrap();
+        let keys: Vec<String> = (0..300).map(|i| format!("fan_out_{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+        let started = Instant::now();
+        match block_on(client.get_multi(&keys)) {
+            Ok(items) if items.len() == keys.len() => (),
+            other => panic!("expected every key to be found. Got: {:?}", other),
+        }
+        // Asking the servers one after the other would take three times the latency
+        if started.elapsed() >= latency * 2 {
+            panic!(
+                "expected the servers to be asked at once. Took: {:?}",
+                started.elapsed()
+            )
+        }
+    }
+
+    #[test]
+    fn get_multi_partial_keeps_the_items_of_servers_that_answered() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead = listener.local_addr().unwrap();
+        drop(listener);
+        let (first, second) = (slow_server(Duration::ZERO), slow_server(Duration::ZERO));
+        let servers = ServerList::new(&[&first, &dead.to_string(), &second]).unwrap();
+        let client = AsyncClient::new_from_selector(servers, None, 0).unwrap();
+        let keys: Vec<String> = (0..30).map(|i| format!("partial_{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let expected = keys
+            .iter()
+            .filter(|key| client.pick_server(key).unwrap() != dead)
+            .count();
+
+        // One runtime for both, as the pooled connections belong to it
+        block_on(async {
+            match client.get_multi_partial(&keys).await {
+                Ok(results) => match results.failures.as_slice() {
+                    [(addr, OperationError::Connect(..))]
+                        if *addr == dead && results.items.len() == expected && expected > 0 => {}
+                    other => panic!(
+                        "expected only {} to fail next to {} items. Got: {:?} and {} items",
+                        dead,
+                        expected,
+                        other,
+                        results.items.len()
+                    ),
+                },
+                Err(error) => panic!("did not expect the multi get to fail: {}", error),
+            }
+            match client.get_multi(&keys).await {
+                Err(OperationError::ServerFailures(failures)) if failures.len() == 1 => (),
+                other => panic!("expected the server failure. Got: {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn cancelled_operations_discard_their_connection() {
+        let addr = slow_server(Duration::from_millis(300));
+        let client = AsyncClient::new(addr, Some(Duration::from_secs(2)), 0)
+            .unwrap()
+            .with_max_connections_per_server(Some(1));
+        block_on(async {
+            let cancelled = tokio::time::timeout(Duration::from_millis(50), client.get("first"));
+            if cancelled.await.is_ok() {
+                panic!("expected the get to be cancelled")
+            }
+            // A reused connection would read the cancelled get's response
+            match client.get("second").await {
+                Ok(Some(item)) if item.key == "second" => (),
+                other => panic!("expected the second item. Got: {:?}", other),
+            }
+
+            let (started, deadline) = (Instant::now(), Duration::from_millis(50));
+            match client.get_with_timeout("third", deadline).await {
+                Err(OperationError::Timeout(_)) if started.elapsed() < deadline * 5 => (),
+                other => panic!("expected the get to time out. Got: {:?}", other),
+            }
+            let item = Item::new("fourth", "v").unwrap();
+            match client
+                .set_with_timeout(&item, Duration::from_millis(50))
+                .await
+            {
+                Err(OperationError::Timeout(_)) => (),
+                other => panic!("expected the set to time out. Got: {:?}", other),
+            }
+            let fast = client
+                .clone()
+                .with_timeout(Duration::from_millis(50))
+                .unwrap();
+            match fast.get("fifth").await {
+                Err(OperationError::Timeout(_)) => (),
+                other => panic!("expected the clone's timeout to apply. Got: {:?}", other),
+            }
+            match client.get("sixth").await {
+                Ok(Some(item)) if item.key == "sixth" => (),
+                other => panic!("expected the sixth item. Got: {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn pooled_checkouts_are_bounded_and_first_come_first_served() {
+        let _guard = lock_local_server();
+        let client = async_client().with_max_connections_per_server(Some(2));
+        block_on(async {
+            if let Err(error) = client.set(&Item::new("async_pooled", "v").unwrap()).await {
+                panic!("did not expect set to fail: {}", error)
+            }
+            // One more task than there are connections, each getting many times over
+            let tasks: Vec<_> = (0..3)
+                .map(|_| {
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        for _ in 0..20 {
+                            client.get("async_pooled").await?;
+                        }
+                        Ok::<_, OperationError>(())
+                    })
+                })
+                .collect();
+            for task in tasks {
+                match tokio::time::timeout(Duration::from_secs(10), task).await {
+                    Ok(Ok(Ok(()))) => (),
+                    other => panic!("expected every get to complete. Got: {:?}", other),
+                }
+            }
+
+            // A fresh client, as its pools' caps are fixed on first use
+            let client = async_client().with_max_connections_per_server(Some(1));
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let tasks: Vec<_> = (0..5)
+                .map(|task| {
+                    let (client, order) = (client.clone(), Arc::clone(&order));
+                    tokio::spawn(async move {
+                        client.get("async_pooled").await.unwrap();
+                        order.lock().unwrap().push(task);
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+            match &order.lock().unwrap()[..] {
+                [0, 1, 2, 3, 4] => (),
+                other => panic!("expected waiters to be served in order. Got: {:?}", other),
+            };
+        });
+        for pool in super::lock(&client.shared.pools).values() {
+            let idle = super::lock(&pool.free_conns).len();
+            if idle > 2 {
+                panic!("expected at most 2 connections to be open. Got: {}", idle)
+            }
+        }
+    }
+
+    #[test]
+    fn pool_exhaustion_fails_instead_of_waiting() {
+        // Accepts connections (through the backlog) but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let client = AsyncClient::new(addr, Some(Duration::from_secs(1)), 0)
+            .unwrap()
+            .with_max_connections_per_server(Some(1))
+            .with_checkout_timeout(Duration::from_millis(50));
+        block_on(async {
+            let holder = {
+                let client = client.clone();
+                tokio::spawn(async move { client.get("key").await })
+            };
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            match client.get("key").await {
+                Err(OperationError::PoolExhausted(_)) => (),
+                other => panic!("expected the pool to be exhausted. Got: {:?}", other),
+            }
+            match holder.await.unwrap() {
+                Err(OperationError::Timeout(_)) => (),
+                other => panic!("expected the first get to time out. Got: {:?}", other),
+            }
+        });
+        drop(listener);
+    }
+}
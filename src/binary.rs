@@ -0,0 +1,640 @@
+use crate::{
+    errors::{OperationError, WriteReadLineError},
+    item::{Item, StoredValue},
+    legal_key, trace, Client, Conn, VERB_ADD, VERB_CAS, VERB_DECR, VERB_DELETE, VERB_GET,
+    VERB_GETS, VERB_INCR, VERB_REPLACE, VERB_SET, VERB_TOUCH,
+};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+
+const MAGIC_REQUEST: u8 = 0x80;
+const MAGIC_RESPONSE: u8 = 0x81;
+const HEADER_LEN: usize = 24;
+
+pub(crate) const OPCODE_GET: u8 = 0x00;
+pub(crate) const OPCODE_SET: u8 = 0x01;
+pub(crate) const OPCODE_ADD: u8 = 0x02;
+pub(crate) const OPCODE_REPLACE: u8 = 0x03;
+const OPCODE_DELETE: u8 = 0x04;
+pub(crate) const OPCODE_INCREMENT: u8 = 0x05;
+pub(crate) const OPCODE_DECREMENT: u8 = 0x06;
+pub(crate) const OPCODE_QUIT: u8 = 0x07;
+const OPCODE_NOOP: u8 = 0x0a;
+const OPCODE_VERSION: u8 = 0x0b;
+const OPCODE_GETKQ: u8 = 0x0d;
+const OPCODE_TOUCH: u8 = 0x1c;
+
+const STATUS_NO_ERROR: u16 = 0x0000;
+const STATUS_KEY_NOT_FOUND: u16 = 0x0001;
+const STATUS_KEY_EXISTS: u16 = 0x0002;
+const STATUS_VALUE_TOO_LARGE: u16 = 0x0003;
+const STATUS_INVALID_ARGUMENTS: u16 = 0x0004;
+const STATUS_ITEM_NOT_STORED: u16 = 0x0005;
+const STATUS_NON_NUMERIC_VALUE: u16 = 0x0006;
+const STATUS_UNKNOWN_COMMAND: u16 = 0x0081;
+
+// Counter expiration telling the server to fail instead of creating a missing counter
+const NO_AUTO_CREATE: u32 = 0xffff_ffff;
+
+// Wire protocol spoken by every connection of a client;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolMode {
+    #[default]
+    Ascii,
+    Binary,
+}
+
+// Response packet, with the body already split into its sections;
+#[derive(Debug)]
+pub(crate) struct BinaryResponse {
+    pub(crate) opcode: u8,
+    pub(crate) status: u16,
+    pub(crate) opaque: u32,
+    pub(crate) cas: u64,
+    pub(crate) extras: Vec<u8>,
+    pub(crate) key: Vec<u8>,
+    pub(crate) value: Vec<u8>,
+}
+
+impl BinaryResponse {
+    // Maps the status onto the matching `OperationError`; The body of error responses carries
+    // a human readable message;
+    fn check_status(&self) -> Result<(), OperationError> {
+        let message = || String::from_utf8_lossy(&self.value).into_owned();
+        match self.status {
+            STATUS_NO_ERROR => Ok(()),
+            STATUS_KEY_NOT_FOUND => Err(OperationError::CacheMiss),
+            STATUS_KEY_EXISTS => Err(OperationError::CASConflict),
+            STATUS_ITEM_NOT_STORED => Err(OperationError::NotStored),
+            STATUS_INVALID_ARGUMENTS | STATUS_NON_NUMERIC_VALUE | STATUS_UNKNOWN_COMMAND => {
+                Err(OperationError::Client(message()))
+            }
+            // Too large, out of memory, busy and the like
+            _ => Err(OperationError::Server(message())),
+        }
+    }
+}
+
+impl Conn {
+    // Writes (without flushing) a request packet;
+    pub(crate) fn write_binary_request(
+        &mut self,
+        opcode: u8,
+        extras: &[u8],
+        key: &[u8],
+        value: &[u8],
+        opaque: u32,
+        cas: u64,
+    ) -> Result<(), WriteReadLineError> {
+        let body_len = extras.len() + key.len() + value.len();
+        let mut header = [0; HEADER_LEN];
+        header[0] = MAGIC_REQUEST;
+        header[1] = opcode;
+        header[2..4].copy_from_slice(&(key.len() as u16).to_be_bytes());
+        header[4] = extras.len() as u8;
+        // Data type and vbucket id are left as 0
+        header[8..12].copy_from_slice(&(body_len as u32).to_be_bytes());
+        header[12..16].copy_from_slice(&opaque.to_be_bytes());
+        header[16..24].copy_from_slice(&cas.to_be_bytes());
+        for buf in [&header[..], extras, key, value] {
+            self.writer
+                .write_all(buf)
+                .map_err(WriteReadLineError::Write)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_binary_response(&mut self) -> Result<BinaryResponse, OperationError> {
+        let mut header = [0; HEADER_LEN];
+        self.reader
+            .read_exact(&mut header)
+            .map_err(|error| OperationError::Io(WriteReadLineError::Read(error)))?;
+        if header[0] != MAGIC_RESPONSE {
+            return Err(OperationError::CorruptResponse(format!(
+                "unexpected binary response magic: {:#04x}",
+                header[0]
+            )));
+        }
+        let key_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let extras_len = header[4] as usize;
+        let body_len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        if extras_len + key_len > body_len {
+            return Err(OperationError::CorruptResponse(
+                "binary response body shorter than its extras and key".to_string(),
+            ));
+        }
+        let mut body = vec![0; body_len];
+        self.reader
+            .read_exact(&mut body)
+            .map_err(|error| OperationError::Io(WriteReadLineError::Read(error)))?;
+        let value = body.split_off(extras_len + key_len);
+        let key = body.split_off(extras_len);
+        Ok(BinaryResponse {
+            opcode: header[1],
+            status: u16::from_be_bytes([header[6], header[7]]),
+            opaque: u32::from_be_bytes([header[12], header[13], header[14], header[15]]),
+            cas: u64::from_be_bytes(header[16..24].try_into().unwrap_or_default()),
+            extras: body,
+            key,
+            value,
+        })
+    }
+
+    // Writes a single request, flushes it and reads back its response;
+    fn binary_round_trip(
+        &mut self,
+        opcode: u8,
+        extras: &[u8],
+        key: &[u8],
+        value: &[u8],
+        cas: u64,
+    ) -> Result<BinaryResponse, OperationError> {
+        self.write_binary_request(opcode, extras, key, value, 0, cas)?;
+        self.writer
+            .flush()
+            .map_err(|error| OperationError::Io(WriteReadLineError::Flush(error)))?;
+        let response = self.read_binary_response()?;
+        if response.opcode != opcode {
+            return Err(OperationError::CorruptResponse(format!(
+                "expected a response to opcode {:#04x}, got {:#04x}",
+                opcode, response.opcode
+            )));
+        }
+        Ok(response)
+    }
+}
+
+impl Client {
+    // `with_cas` mirrors `gets`; Binary responses always carry the CAS but `get` leaves it unset
+    // like its ASCII counterpart;
+    pub(crate) fn binary_get(
+        &mut self,
+        key: &str,
+        with_cas: bool,
+    ) -> Result<Option<Item>, OperationError> {
+        legal_key(key)?;
+        let verb = if with_cas { VERB_GETS } else { VERB_GET };
+        let response = self.with_retried_key_conn(verb, key, |conn| {
+            conn.binary_round_trip(OPCODE_GET, &[], key.as_bytes(), &[], 0)
+        })?;
+        match response.check_status() {
+            Ok(()) => (),
+            Err(OperationError::CacheMiss) => return Ok(None),
+            Err(error) => return Err(error),
+        }
+        let flags = binary_flags(&response.extras)?;
+        trace::record_value_size(response.value.len());
+        let mut item = Item::from_parts(key.to_string(), response.value, flags, 0);
+        if with_cas {
+            item.cas_id = response.cas;
+        }
+        Ok(Some(item))
+    }
+
+    // Pipelines a quiet `getkq` per key, fenced by a `noop`; Misses produce no response at all,
+    // so the noop's response ends the read and every key that wasn't answered is a miss;
+    // Opaques are the keys' indexes (and the fence's is one past the last) so a response can't
+    // be assigned to the wrong key;
+    pub(crate) fn binary_get_multi_from_conn(
+        conn: &mut Conn,
+        keys: &[&str],
+        with_cas: bool,
+        items: &mut HashMap<String, Item>,
+    ) -> Result<(), OperationError> {
+        for (opaque, key) in keys.iter().enumerate() {
+            conn.write_binary_request(OPCODE_GETKQ, &[], key.as_bytes(), &[], opaque as u32, 0)?;
+        }
+        let fence = keys.len() as u32;
+        conn.write_binary_request(OPCODE_NOOP, &[], &[], &[], fence, 0)?;
+        conn.writer
+            .flush()
+            .map_err(|error| OperationError::Io(WriteReadLineError::Flush(error)))?;
+
+        // The first failed key, reported once the responses are read up to the fence so the
+        // connection is left in step
+        let mut failure = None;
+        loop {
+            let response = conn.read_binary_response()?;
+            if response.opcode == OPCODE_NOOP && response.opaque == fence {
+                return match failure {
+                    Some(error) => Err(error),
+                    None => response.check_status(),
+                };
+            }
+            let unexpected = || {
+                OperationError::CorruptResponse(format!(
+                    "unexpected response to opcode {:#04x} with opaque {}",
+                    response.opcode, response.opaque
+                ))
+            };
+            let key = match keys.get(response.opaque as usize) {
+                Some(key) if response.opcode == OPCODE_GETKQ => key,
+                _ => return Err(unexpected()),
+            };
+            // Error responses may leave the key out
+            if let Err(error) = response.check_status() {
+                failure.get_or_insert(error);
+                continue;
+            }
+            if response.key != key.as_bytes() {
+                return Err(unexpected());
+            }
+            let flags = binary_flags(&response.extras)?;
+            let mut item = Item::from_parts(key.to_string(), response.value, flags, 0);
+            if with_cas {
+                item.cas_id = response.cas;
+            }
+            items.insert(item.key.clone(), item);
+        }
+    }
+
+    // Both a failed `add` (existing key) and a failed `replace` (missing key) surface as
+    // `NotStored`, the same as over ASCII;
+    pub(crate) fn binary_store(
+        &mut self,
+        opcode: u8,
+        key: &str,
+        stored: &StoredValue,
+        cas: u64,
+    ) -> Result<(), OperationError> {
+        legal_key(key)?;
+        let mut extras = [0; 8];
+        extras[..4].copy_from_slice(&stored.flags.to_be_bytes());
+        extras[4..]
+            .copy_from_slice(&binary_expiration(stored.expiration.wire_value()?).to_be_bytes());
+        trace::record_value_size(stored.value.len());
+        let verb = match opcode {
+            OPCODE_ADD => VERB_ADD,
+            OPCODE_REPLACE => VERB_REPLACE,
+            _ if cas != 0 => VERB_CAS,
+            _ => VERB_SET,
+        };
+        let response = self.with_key_conn(verb, key, |conn| {
+            conn.binary_round_trip(opcode, &extras, key.as_bytes(), &stored.value, cas)
+        })?;
+        match (opcode, response.check_status()) {
+            (OPCODE_ADD, Err(OperationError::CASConflict))
+            | (OPCODE_REPLACE, Err(OperationError::CacheMiss)) => Err(OperationError::NotStored),
+            (_, result) => result,
+        }
+    }
+
+    pub(crate) fn binary_delete(&mut self, key: &str) -> Result<(), OperationError> {
+        legal_key(key)?;
+        self.with_retried_key_conn(VERB_DELETE, key, |conn| {
+            conn.binary_round_trip(OPCODE_DELETE, &[], key.as_bytes(), &[], 0)
+        })?
+        .check_status()
+    }
+
+    pub(crate) fn binary_incr_decr(
+        &mut self,
+        opcode: u8,
+        key: &str,
+        delta: u64,
+    ) -> Result<u64, OperationError> {
+        legal_key(key)?;
+        let mut extras = [0; 20];
+        extras[..8].copy_from_slice(&delta.to_be_bytes());
+        // The initial value (bytes 8..16) is unused as missing counters aren't created
+        extras[16..].copy_from_slice(&NO_AUTO_CREATE.to_be_bytes());
+        let verb = match opcode {
+            OPCODE_INCREMENT => VERB_INCR,
+            _ => VERB_DECR,
+        };
+        let response = self.with_key_conn(verb, key, |conn| {
+            conn.binary_round_trip(opcode, &extras, key.as_bytes(), &[], 0)
+        })?;
+        response.check_status()?;
+        response
+            .value
+            .as_slice()
+            .try_into()
+            .map(u64::from_be_bytes)
+            .map_err(|_| {
+                OperationError::CorruptResponse("counter value is not 8 bytes long".to_string())
+            })
+    }
+
+    pub(crate) fn binary_touch(&mut self, key: &str, exptime: i32) -> Result<(), OperationError> {
+        legal_key(key)?;
+        let extras = binary_expiration(exptime).to_be_bytes();
+        self.with_retried_key_conn(VERB_TOUCH, key, |conn| {
+            conn.binary_round_trip(OPCODE_TOUCH, &extras, key.as_bytes(), &[], 0)
+        })?
+        .check_status()
+    }
+
+    pub(crate) fn binary_version(conn: &mut Conn) -> Result<String, OperationError> {
+        let response = conn.binary_round_trip(OPCODE_VERSION, &[], &[], &[], 0)?;
+        response.check_status()?;
+        String::from_utf8(response.value)
+            .map_err(|_| OperationError::CorruptResponse("invalid UTF-8 sequence".to_string()))
+    }
+
+    pub(crate) fn binary_noop(&mut self, addr: SocketAddr) -> Result<(), OperationError> {
+        self.with_conn(addr, |conn| {
+            conn.binary_round_trip(OPCODE_NOOP, &[], &[], &[], 0)
+        })?
+        .check_status()
+    }
+
+    // Commands without a binary implementation fail up front rather than writing ASCII onto a
+    // binary connection;
+    pub(crate) fn ascii_only(&self, command: &str) -> Result<(), OperationError> {
+        match self.dial_options.protocol {
+            ProtocolMode::Ascii => Ok(()),
+            ProtocolMode::Binary => Err(OperationError::Client(format!(
+                "{} is not supported with the binary protocol",
+                command
+            ))),
+        }
+    }
+}
+
+// Get responses carry the item's flags as their only extras;
+fn binary_flags(extras: &[u8]) -> Result<u32, OperationError> {
+    extras
+        .get(..4)
+        .and_then(|flags| flags.try_into().ok())
+        .map(u32::from_be_bytes)
+        .ok_or_else(|| OperationError::CorruptResponse("missing flags in get response".to_string()))
+}
+
+// The binary protocol has no negative expirations; A unix timestamp in the past expires the item
+// immediately, the same as a negative expiration over ASCII;
+fn binary_expiration(expiration: i32) -> u32 {
+    if expiration < 0 {
+        1
+    } else {
+        expiration as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ProtocolMode, HEADER_LEN, MAGIC_RESPONSE, OPCODE_GET, OPCODE_GETKQ, OPCODE_NOOP,
+        STATUS_NO_ERROR,
+    };
+    use crate::{
+        errors::OperationError,
+        expiration::Expiration,
+        item::Item,
+        tests::{lock_local_server, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    // Packs a response packet;
+    fn response_packet(
+        opcode: u8,
+        status: u16,
+        opaque: u32,
+        extras: &[u8],
+        key: &[u8],
+        value: &[u8],
+    ) -> Vec<u8> {
+        let mut packet = vec![0; HEADER_LEN];
+        packet[0] = MAGIC_RESPONSE;
+        packet[1] = opcode;
+        packet[2..4].copy_from_slice(&(key.len() as u16).to_be_bytes());
+        packet[4] = extras.len() as u8;
+        packet[6..8].copy_from_slice(&status.to_be_bytes());
+        let body_len = extras.len() + key.len() + value.len();
+        packet[8..12].copy_from_slice(&(body_len as u32).to_be_bytes());
+        packet[12..16].copy_from_slice(&opaque.to_be_bytes());
+        for section in [extras, key, value] {
+            packet.extend_from_slice(section);
+        }
+        packet
+    }
+
+    // Binary counterpart of `scripted_server`, counting request packets instead of lines;
+    fn scripted_binary_server(script: Vec<(usize, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let read_request = |stream: &mut TcpStream| {
+                    let mut header = [0; HEADER_LEN];
+                    stream.read_exact(&mut header)?;
+                    let body_len =
+                        u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+                    let mut body = vec![0; body_len as usize];
+                    stream.read_exact(&mut body)
+                };
+                for (requests, response) in &script {
+                    for _ in 0..*requests {
+                        let _ = read_request(&mut stream);
+                    }
+                    let _ = stream.write_all(response);
+                }
+                // Keep the connection open until the client hangs up
+                let _ = stream.read(&mut [0]);
+            }
+        });
+        addr
+    }
+
+    fn binary_client() -> Client {
+        match Client::with_protocol(
+            String::from(LOCALHOST_TCP_ADDR),
+            None,
+            0,
+            ProtocolMode::Binary,
+        ) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_binary_protocol() {
+        let _guard = lock_local_server();
+        let mut client = binary_client();
+
+        if let Err(error) = client.ping() {
+            panic!("did not expect ping to fail: {}", error)
+        }
+        match client.version() {
+            Ok(versions) if versions.values().all(|version| !version.is_empty()) => (),
+            other => panic!("expected a version. Got: {:?}", other),
+        }
+
+        let _ = client.delete("binary_key");
+        let item = || Item::from_parts("binary_key".to_string(), Vec::from("value"), 42, 0);
+        match client.replace(&item()) {
+            Err(OperationError::NotStored) => (),
+            other => panic!(
+                "expected replace of a missing key to fail. Got: {:?}",
+                other
+            ),
+        }
+        if let Err(error) = client.add(&item()) {
+            panic!("did not expect add to fail: {}", error)
+        }
+        match client.add(&item()) {
+            Err(OperationError::NotStored) => (),
+            other => panic!("expected add of an existing key to fail. Got: {:?}", other),
+        }
+
+        match client.get("binary_key") {
+            Ok(Some(item)) if item.value == b"value" && item.flags == 42 && item.cas_id == 0 => (),
+            other => panic!("expected the stored item. Got: {:?}", other),
+        }
+        let read = match client.gets("binary_key") {
+            Ok(Some(item)) if item.cas_id != 0 => item,
+            other => panic!("expected an item with its cas. Got: {:?}", other),
+        };
+        if let Err(error) = client.compare_and_swap(&read) {
+            panic!("did not expect compare and swap to fail: {}", error)
+        }
+        match client.compare_and_swap(&read) {
+            Err(OperationError::CASConflict) => (),
+            other => panic!("expected a stale cas to conflict. Got: {:?}", other),
+        }
+        if let Err(error) = client.touch("binary_key", Expiration::In(Duration::from_secs(60))) {
+            panic!("did not expect touch to fail: {}", error)
+        }
+
+        match client.increment("binary_key", 1) {
+            Err(OperationError::Client(_)) => (),
+            other => panic!(
+                "expected incr of a non-numeric value to fail. Got: {:?}",
+                other
+            ),
+        }
+        let counter = Item::from_parts("binary_key".to_string(), Vec::from("10"), 0, 0);
+        if let Err(error) = client.set(&counter) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.increment("binary_key", 5) {
+            Ok(15) => (),
+            other => panic!("expected 15. Got: {:?}", other),
+        }
+        match client.decrement("binary_key", 20) {
+            Ok(0) => (),
+            other => panic!("expected decrement to stop at 0. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete("binary_key") {
+            panic!("did not expect delete to fail: {}", error)
+        }
+        match client.get("binary_key") {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+        match client.delete("binary_key") {
+            Err(OperationError::CacheMiss) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+        match client.increment("binary_key", 1) {
+            Err(OperationError::CacheMiss) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+        client.close();
+    }
+
+    #[test]
+    fn ascii_only_command_fails_in_binary_mode() {
+        let _guard = lock_local_server();
+        let mut client = binary_client();
+        match client.stats() {
+            Err(OperationError::Client(_)) => (),
+            other => panic!("expected a client error. Got: {:?}", other),
+        }
+        // The connection is still usable afterwards
+        if let Err(error) = client.ping() {
+            panic!("did not expect ping to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn test_binary_get_multi() {
+        let _guard = lock_local_server();
+        let mut client = binary_client();
+
+        let keys: Vec<String> = (0..300)
+            .map(|i| format!("binary_get_multi_{}", i))
+            .collect();
+        for (i, key) in keys.iter().enumerate() {
+            let _ = client.delete(key);
+            if i % 2 == 0 {
+                let item =
+                    Item::from_parts(key.clone(), Vec::from(format!("value_{}", i)), i as u32, 0);
+                if let Err(error) = client.set(&item) {
+                    panic!("did not expect set to fail: {}", error)
+                }
+            }
+        }
+
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let items = match client.gets_multi(&keys) {
+            Ok(items) => items,
+            Err(error) => panic!("did not expect gets multi to fail: {}", error),
+        };
+        if items.len() != 150 {
+            panic!("expected 150 items. Got: {}", items.len())
+        }
+        for (i, key) in keys.iter().enumerate() {
+            match items.get(*key) {
+                Some(item)
+                    if i % 2 == 0
+                        && item.value == format!("value_{}", i).as_bytes()
+                        && item.flags == i as u32
+                        && item.cas_id != 0 => {}
+                None if i % 2 == 1 => (),
+                other => panic!("unexpected item for {}: {:?}", key, other),
+            }
+        }
+
+        // The connection is still aligned after the fence
+        match client.get_multi(&keys[..2]) {
+            Ok(items) if items.len() == 1 && items[keys[0]].cas_id == 0 => (),
+            other => panic!("expected a single item. Got: {:?}", other),
+        }
+
+        for key in keys.iter().step_by(2) {
+            if let Err(error) = client.delete(key) {
+                panic!("did not expect delete to fail: {}", error)
+            }
+        }
+    }
+
+    #[test]
+    fn get_multi_reads_past_an_error_status_up_to_the_noop() {
+        let flags = 0u32.to_be_bytes();
+        let multi_response = [
+            response_packet(OPCODE_GETKQ, 0x0082, 0, &[], &[], b"out of memory"),
+            response_packet(OPCODE_GETKQ, STATUS_NO_ERROR, 1, &flags, b"b", b"value"),
+            response_packet(OPCODE_NOOP, STATUS_NO_ERROR, 2, &[], &[], &[]),
+        ]
+        .concat();
+        let addr = scripted_binary_server(vec![
+            (3, multi_response),
+            (
+                1,
+                response_packet(OPCODE_GET, STATUS_NO_ERROR, 0, &flags, &[], b"x"),
+            ),
+        ]);
+        let mut client = Client::with_protocol(addr, None, 0, ProtocolMode::Binary)
+            .expect("could not connect to mock server");
+
+        match client.get_multi(&["a", "b"]) {
+            Err(OperationError::ServerFailures(failures)) => match failures.as_slice() {
+                [(_, OperationError::Server(message))] if message == "out of memory" => (),
+                _ => panic!("expected the server error. Got: {:?}", failures),
+            },
+            other => panic!("expected a server failure. Got: {:?}", other),
+        }
+        // The connection must be left at the start of the next response
+        match client.get("next") {
+            Ok(Some(item)) if item.value == b"x" => (),
+            other => panic!("expected the next get to hit. Got: {:?}", other),
+        }
+    }
+}
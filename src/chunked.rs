@@ -0,0 +1,370 @@
+use crate::compression::decompressed;
+use crate::errors::OperationError;
+use crate::expiration::Expiration;
+use crate::flags::Flags;
+use crate::item::StoredValue;
+use crate::trace;
+use crate::{legal_key, Client};
+use std::borrow::Cow;
+
+// Codec id flagging manifests, of the range kept for the codecs of this crate
+const MANIFEST_CODEC_ID: u8 = 6;
+
+// Most chunks a value is split in, so a corrupt manifest can't have `get_chunked` build keys for
+// billions of them; 64GB of chunks at the default chunk size
+const MAX_CHUNKS: usize = 1 << 16;
+
+fn chunk_key(key: &str, index: usize) -> String {
+    format!("{}:chunk:{}", key, index)
+}
+
+// What a manifest records of its value: `<chunks> <bytes> <crc32>`
+#[derive(Debug, PartialEq)]
+struct Manifest {
+    chunks: usize,
+    len: usize,
+    checksum: u32,
+}
+
+impl Manifest {
+    fn of(value: &[u8], chunk_size: usize) -> Self {
+        Manifest {
+            chunks: value.len().div_ceil(chunk_size),
+            len: value.len(),
+            checksum: crc32fast::hash(value),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        format!("{} {} {:08x}", self.chunks, self.len, self.checksum).into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, OperationError> {
+        let corrupt = || OperationError::CorruptResponse("invalid chunk manifest".to_string());
+        let text = std::str::from_utf8(bytes).map_err(|_| corrupt())?;
+        let mut fields = text.split(' ');
+        let mut next = || fields.next().ok_or_else(corrupt);
+        let manifest = Manifest {
+            chunks: next()?.parse().map_err(|_| corrupt())?,
+            len: next()?.parse().map_err(|_| corrupt())?,
+            checksum: u32::from_str_radix(next()?, 16).map_err(|_| corrupt())?,
+        };
+        if manifest.chunks > MAX_CHUNKS {
+            return Err(corrupt());
+        }
+        Ok(manifest)
+    }
+}
+
+impl Client {
+    // Stores a value that may be larger than the server's item size limit: values over
+    // `chunk_size` are split in parts stored under `<key>:chunk:<n>`, followed by a manifest at
+    // `key` recording how many there are and their checksum, so a reader never finds a manifest
+    // before its chunks; Smaller values are stored at `key` as they are; Chunks left over by a
+    // previous value with more of them are left to expire; Values needing more than `MAX_CHUNKS`
+    // chunks fail with `ValueTooLarge` before anything is stored; Read with `get_chunked`;
+    pub fn set_chunked(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        expiration: Expiration,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.set_chunked", key), || {
+            legal_key(&self.prefixed(key))?;
+            if value.len() <= self.chunk_size {
+                let stored = StoredValue {
+                    value: Cow::Borrowed(value),
+                    flags: 0,
+                    expiration,
+                };
+                return self.set_encoded(key, stored);
+            }
+            let manifest = Manifest::of(value, self.chunk_size);
+            if manifest.chunks > MAX_CHUNKS {
+                return Err(OperationError::ValueTooLarge {
+                    size: value.len(),
+                    limit: MAX_CHUNKS * self.chunk_size,
+                });
+            }
+            // Checks the longest chunk key before any chunk gets stored
+            legal_key(&self.prefixed(&chunk_key(key, manifest.chunks - 1)))?;
+            for (index, chunk) in value.chunks(self.chunk_size).enumerate() {
+                let stored = StoredValue {
+                    value: Cow::Borrowed(chunk),
+                    flags: 0,
+                    expiration,
+                };
+                self.set_encoded(&chunk_key(key, index), stored)?;
+            }
+            let stored = StoredValue {
+                value: Cow::Owned(manifest.encode()),
+                flags: Flags::default().with_codec(MANIFEST_CODEC_ID).wire(),
+                expiration,
+            };
+            self.set_encoded(key, stored)
+        })
+    }
+
+    // Reads a value stored by `set_chunked`, fetching its chunks with one `get_multi`; Fails with
+    // `MissingChunk` when a chunk is gone, and `StaleChunks` when the chunks don't match the
+    // manifest's checksum, or the manifest's chunk count doesn't match its length split at this
+    // client's `chunk_size`, e.g. stored by a client with another one;
+    pub fn get_chunked(&mut self, key: &str) -> Result<Option<Vec<u8>>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get_chunked", key), || {
+            let item = match self.get_encoded(key)? {
+                Some(item) => decompressed(item)?,
+                None => return Ok(None),
+            };
+            if Flags::from_wire(item.flags).codec() != MANIFEST_CODEC_ID {
+                return self.returned_item(item).map(|item| Some(item.value));
+            }
+            let manifest = Manifest::decode(&item.value)?;
+            if manifest.chunks != manifest.len.div_ceil(self.chunk_size) {
+                return Err(OperationError::StaleChunks);
+            }
+            let keys: Vec<String> = (0..manifest.chunks)
+                .map(|index| chunk_key(key, index))
+                .collect();
+            let mut chunks =
+                self.get_multi(&keys.iter().map(String::as_str).collect::<Vec<_>>())?;
+            // Sized from the chunks read rather than the length the manifest claims
+            let len = chunks.values().map(|chunk| chunk.value.len()).sum();
+            let mut value = Vec::with_capacity(len);
+            for (index, chunk_key) in keys.iter().enumerate() {
+                match chunks.remove(chunk_key) {
+                    Some(chunk) => value.extend_from_slice(&chunk.value),
+                    None => {
+                        return Err(OperationError::MissingChunk {
+                            index,
+                            chunks: manifest.chunks,
+                        })
+                    }
+                }
+            }
+            if value.len() != manifest.len || crc32fast::hash(&value) != manifest.checksum {
+                return Err(OperationError::StaleChunks);
+            }
+            Ok(Some(value))
+        })
+    }
+
+    // Deletes a value stored by `set_chunked`, its manifest first so readers never find one whose
+    // chunks are gone; Fails with `CacheMiss` when there's no value at `key`;
+    pub fn delete_chunked(&mut self, key: &str) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.delete_chunked", key), || {
+            let manifest = match self.get_encoded(key)? {
+                Some(item) if Flags::from_wire(item.flags).codec() == MANIFEST_CODEC_ID => {
+                    Some(Manifest::decode(&item.value)?)
+                }
+                _ => None,
+            };
+            self.delete(key)?;
+            for index in 0..manifest.map_or(0, |manifest| manifest.chunks) {
+                match self.delete(&chunk_key(key, index)) {
+                    Ok(()) | Err(OperationError::CacheMiss) => (),
+                    Err(error) => return Err(error),
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chunk_key, Manifest, MANIFEST_CODEC_ID, MAX_CHUNKS};
+    use crate::{
+        errors::OperationError,
+        expiration::Expiration,
+        flags::Flags,
+        item::{Item, StoredValue},
+        tests::{lock_local_server, noise, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+
+    #[test]
+    fn test_chunk_manifest() {
+        let manifest = Manifest::of(&[7; 2500], 1000);
+        if manifest.chunks != 3 || manifest.len != 2500 {
+            panic!("expected 3 chunks of 2500 bytes. Got: {:?}", manifest)
+        }
+        match Manifest::decode(&manifest.encode()) {
+            Ok(decoded) if decoded == manifest => (),
+            other => panic!("expected the manifest to read back. Got: {:?}", other),
+        }
+        match Manifest::decode(b"3 2500") {
+            Err(OperationError::CorruptResponse(_)) => (),
+            other => panic!("expected an incomplete manifest to fail. Got: {:?}", other),
+        }
+        match Manifest::decode(format!("{} 2500 00000000", MAX_CHUNKS + 1).as_bytes()) {
+            Err(OperationError::CorruptResponse(_)) => (),
+            other => panic!("expected too many chunks to fail. Got: {:?}", other),
+        }
+        if chunk_key("blob", 2) != "blob:chunk:2" {
+            panic!("expected chunks under derived keys")
+        }
+    }
+
+    #[test]
+    fn test_chunked_values() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        // Well over the server's item size limit
+        let blob = noise(3 * 1024 * 1024 + 17);
+        if let Err(error) = client.set_chunked("chunked_blob", &blob, Expiration::Never) {
+            panic!("did not expect set chunked to fail: {}", error)
+        }
+        match client.get_chunked("chunked_blob") {
+            Ok(Some(value)) if value == blob => (),
+            other => panic!(
+                "expected the blob back. Got: {:?}",
+                other.map(|value| value.map(|value| value.len()))
+            ),
+        }
+
+        client.set_chunk_size(1000);
+        let value = noise(4500);
+        if let Err(error) = client.set_chunked("chunked", &value, Expiration::Never) {
+            panic!("did not expect set chunked to fail: {}", error)
+        }
+        match client.get("chunked:chunk:4") {
+            Ok(Some(item)) if item.value == value[4000..] => (),
+            other => panic!("expected the last chunk. Got: {:?}", other),
+        }
+        let other = Item::new("chunked:chunk:1", noise(1000).as_slice()).unwrap();
+        if let Err(error) = client.set(&other) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.get_chunked("chunked") {
+            Err(OperationError::StaleChunks) => (),
+            other => panic!("expected stale chunks. Got: {:?}", other),
+        }
+        client.delete("chunked:chunk:1").unwrap();
+        match client.get_chunked("chunked") {
+            Err(OperationError::MissingChunk {
+                index: 1,
+                chunks: 5,
+            }) => (),
+            other => panic!("expected a missing chunk. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete_chunked("chunked") {
+            panic!("did not expect delete chunked to fail: {}", error)
+        }
+        for key in ["chunked", "chunked:chunk:0", "chunked:chunk:4"] {
+            match client.get(key) {
+                Ok(None) => (),
+                other => panic!("expected {} to be deleted. Got: {:?}", key, other),
+            }
+        }
+        match client.get_chunked("chunked") {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+
+        // Values that fit a chunk are stored as they are
+        if let Err(error) = client.set_chunked("chunked", b"small", Expiration::Never) {
+            panic!("did not expect set chunked to fail: {}", error)
+        }
+        match (client.get("chunked"), client.get_chunked("chunked")) {
+            (Ok(Some(item)), Ok(Some(value))) if item.value == b"small" && value == b"small" => (),
+            other => panic!("expected the small value as is. Got: {:?}", other),
+        }
+        if let Err(error) = client.delete_chunked("chunked") {
+            panic!("did not expect delete chunked to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn set_chunked_checks_every_chunk_key_before_storing() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        client.set_chunk_size(10);
+
+        // `:chunk:9` fits under the key limit but `:chunk:10` doesn't
+        let key = "k".repeat(242);
+        let _ = client.delete(&chunk_key(&key, 0));
+        match client.set_chunked(&key, &[7; 101], Expiration::Never) {
+            Err(OperationError::MalformedKey(_)) => (),
+            other => panic!(
+                "expected the last chunk key to be refused. Got: {:?}",
+                other
+            ),
+        }
+        match client.get(&chunk_key(&key, 0)) {
+            Ok(None) => (),
+            other => panic!("expected no chunk to be stored. Got: {:?}", other),
+        }
+
+        client.set_chunk_size(1);
+        let _ = client.delete(&chunk_key("too_many_chunks", 0));
+        match client.set_chunked("too_many_chunks", &[7; MAX_CHUNKS + 1], Expiration::Never) {
+            Err(OperationError::ValueTooLarge { size, limit })
+                if (size, limit) == (MAX_CHUNKS + 1, MAX_CHUNKS) => {}
+            other => panic!("expected too many chunks to be refused. Got: {:?}", other),
+        }
+        match client.get(&chunk_key("too_many_chunks", 0)) {
+            Ok(None) => (),
+            other => panic!("expected no chunk to be stored. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_chunked_does_not_trust_the_manifest_length() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let manifest = StoredValue {
+            value: format!("1 {} 00000000", usize::MAX).into_bytes().into(),
+            flags: Flags::default().with_codec(MANIFEST_CODEC_ID).wire(),
+            expiration: Expiration::Never,
+        };
+        if let Err(error) = client.set_encoded("bogus_manifest", manifest) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        let chunk = Item::new(chunk_key("bogus_manifest", 0), "chunk").unwrap();
+        if let Err(error) = client.set(&chunk) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.get_chunked("bogus_manifest") {
+            Err(OperationError::StaleChunks) => (),
+            other => panic!("expected stale chunks. Got: {:?}", other),
+        }
+
+        // Nor its chunk count, whether it's more than a value is ever split in or more than its
+        // length takes
+        for (manifest, rejected) in [
+            (format!("{} 5 00000000", MAX_CHUNKS), "stale"),
+            (format!("{} 5 00000000", usize::MAX), "corrupt"),
+        ] {
+            let manifest = StoredValue {
+                value: manifest.into_bytes().into(),
+                flags: Flags::default().with_codec(MANIFEST_CODEC_ID).wire(),
+                expiration: Expiration::Never,
+            };
+            if let Err(error) = client.set_encoded("bogus_manifest", manifest) {
+                panic!("did not expect set to fail: {}", error)
+            }
+            match (client.get_chunked("bogus_manifest"), rejected) {
+                (Err(OperationError::CorruptResponse(_)), "corrupt") => (),
+                (Err(OperationError::StaleChunks), "stale") => (),
+                (other, _) => panic!("expected a {} manifest. Got: {:?}", rejected, other),
+            }
+        }
+        // `delete_chunked` can't read the last manifest either
+        for key in ["bogus_manifest".to_string(), chunk_key("bogus_manifest", 0)] {
+            if let Err(error) = client.delete(&key) {
+                panic!("did not expect delete to fail: {}", error)
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::compression::decompressed;
+use crate::errors::OperationError;
+use crate::expiration::Expiration;
+use crate::flags::{Flags, MAX_CODEC_ID};
+use crate::item::StoredValue;
+use crate::trace;
+use crate::Client;
+use std::borrow::Cow;
+
+// Turns values into the bytes and user flags that are stored, and back; `ID` is recorded in the
+// reserved flag bits, so reading a value with another codec than the one it was stored with
+// fails with `NotEncodedAs` instead of decoding garbage;
+pub trait ValueCodec<T> {
+    // 0 to 127, unique among the codecs sharing a cache; `0` is `RawCodec` and 1 to 15 are kept
+    // for the codecs of this crate, such as the `ValueType`s of `set_as`
+    const ID: u8;
+    // Named by `NotEncodedAs` errors
+    const NAME: &'static str;
+
+    // Values that already are bytes can be borrowed instead of copied;
+    fn encode(value: &T) -> Result<(Cow<'_, [u8]>, u32), OperationError>;
+
+    // `flags` are the user flags `encode` returned;
+    fn decode(bytes: Vec<u8>, flags: u32) -> Result<T, OperationError>;
+}
+
+// Stores bytes as they are, without copying them either way; Values are the same as the ones
+// of `set` and `get`;
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RawCodec;
+
+impl ValueCodec<Vec<u8>> for RawCodec {
+    const ID: u8 = 0;
+    const NAME: &'static str = "raw bytes";
+
+    fn encode(value: &Vec<u8>) -> Result<(Cow<'_, [u8]>, u32), OperationError> {
+        Ok((Cow::Borrowed(value), 0))
+    }
+
+    fn decode(bytes: Vec<u8>, _flags: u32) -> Result<Vec<u8>, OperationError> {
+        Ok(bytes)
+    }
+}
+
+impl Client {
+    // Stores `value` as encoded by `C`, e.g. `set_with::<JsonCodec, _>(key, &value, expiration)`;
+    pub fn set_with<C: ValueCodec<T>, T>(
+        &mut self,
+        key: &str,
+        value: &T,
+        expiration: Expiration,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.set_with", key), || {
+            if C::ID > MAX_CODEC_ID {
+                return Err(OperationError::Client(format!(
+                    "id of codec {} is over {}",
+                    C::NAME,
+                    MAX_CODEC_ID
+                )));
+            }
+            let (bytes, flags) = C::encode(value)?;
+            let flags = Flags::from_user(flags)?.with_codec(C::ID);
+            let stored = StoredValue {
+                value: bytes,
+                flags: flags.wire(),
+                expiration,
+            };
+            self.set_encoded(key, stored)
+        })
+    }
+
+    // Reads a value stored by `set_with::<C, _>`; Values stored with another codec fail with
+    // `NotEncodedAs`;
+    pub fn get_with<C: ValueCodec<T>, T>(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<T>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get_with", key), || {
+            let item = match self.get_encoded(key)? {
+                Some(item) => decompressed(item)?,
+                None => return Ok(None),
+            };
+            let flags = Flags::from_wire(item.flags);
+            if flags.codec() != C::ID {
+                return Err(OperationError::NotEncodedAs(C::NAME));
+            }
+            C::decode(item.value, flags.user()).map(Some)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RawCodec, ValueCodec};
+    use crate::{
+        errors::OperationError,
+        expiration::Expiration,
+        tests::{lock_local_server, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+    use std::borrow::Cow;
+
+    // Stores numbers as little-endian bytes with the user flags they were stored with
+    struct LeU64Codec;
+
+    impl ValueCodec<u64> for LeU64Codec {
+        const ID: u8 = 100;
+        const NAME: &'static str = "little-endian u64";
+
+        fn encode(value: &u64) -> Result<(Cow<'_, [u8]>, u32), OperationError> {
+            Ok((Cow::Owned(value.to_le_bytes().to_vec()), 8))
+        }
+
+        fn decode(bytes: Vec<u8>, flags: u32) -> Result<u64, OperationError> {
+            match <[u8; 8]>::try_from(bytes) {
+                Ok(bytes) if flags == 8 => Ok(u64::from_le_bytes(bytes)),
+                _ => Err(OperationError::Codec("not a little-endian u64".into())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_value_codecs() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        if let Err(error) = client.set_with::<LeU64Codec, _>("codec_number", &42, Expiration::Never)
+        {
+            panic!("did not expect set with to fail: {}", error)
+        }
+        match client.get_with::<LeU64Codec, _>("codec_number") {
+            Ok(Some(42)) => (),
+            other => panic!("expected 42 back. Got: {:?}", other),
+        }
+        match client.get("codec_number") {
+            Ok(Some(item)) if item.flags == 8 && item.value == 42u64.to_le_bytes() => (),
+            other => panic!(
+                "expected the encoded bytes and user flags. Got: {:?}",
+                other
+            ),
+        }
+        match client.get_with::<RawCodec, _>("codec_number") {
+            Err(OperationError::NotEncodedAs("raw bytes")) => (),
+            other => panic!(
+                "expected a not encoded as raw bytes error. Got: {:?}",
+                other
+            ),
+        }
+
+        let value = Vec::from("raw value");
+        if let Err(error) = client.set_with::<RawCodec, _>("codec_raw", &value, Expiration::Never) {
+            panic!("did not expect set with to fail: {}", error)
+        }
+        match client.get("codec_raw") {
+            Ok(Some(item)) if item.flags == 0 && item.value == value => (),
+            other => panic!("expected a plain item. Got: {:?}", other),
+        }
+        match client.get_with::<LeU64Codec, _>("codec_raw") {
+            Err(OperationError::NotEncodedAs("little-endian u64")) => (),
+            other => panic!("expected a not encoded error. Got: {:?}", other),
+        }
+        match client.get_with::<RawCodec, _>("codec_raw") {
+            Ok(Some(got)) if got == value => (),
+            other => panic!("expected the raw value back. Got: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::errors::OperationError;
+use crate::flags::Flags;
+use crate::item::Item;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+// Zlib compressed `value`, `None` when that's no smaller than the value itself (e.g. for
+// already compressed images);
+pub(crate) fn compress(value: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::with_capacity(value.len() / 2), Compression::default());
+    // Writing to a `Vec` doesn't fail
+    encoder.write_all(value).ok()?;
+    let compressed = encoder.finish().ok()?;
+    (compressed.len() < value.len()).then_some(compressed)
+}
+
+// `item` with its value decompressed and the compressed flag cleared when the client compressed
+// it, unchanged otherwise;
+pub(crate) fn decompressed(item: Item) -> Result<Item, OperationError> {
+    let flags = Flags::from_wire(item.flags);
+    if !flags.compressed() {
+        return Ok(item);
+    }
+    let mut value = Vec::with_capacity(item.value.len() * 2);
+    decompress_into(&item.value, &mut value)?;
+    Ok(Item {
+        value,
+        flags: flags.with_compressed(false).wire(),
+        ..item
+    })
+}
+
+// Appends the decompressed `value` to `buf`, which may be left with part of it on errors;
+pub(crate) fn decompress_into(value: &[u8], buf: &mut Vec<u8>) -> Result<(), OperationError> {
+    ZlibDecoder::new(value)
+        .read_to_end(buf)
+        .map(|_| ())
+        .map_err(OperationError::Decompress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompressed};
+    use crate::errors::OperationError;
+    use crate::flags::Flags;
+    use crate::item::Item;
+    use crate::tests::noise;
+
+    #[test]
+    fn test_compression() {
+        let value = b"<li class=\"fragment\">item</li>".repeat(100);
+        let compressed = match compress(&value) {
+            Some(compressed) if compressed.len() < value.len() / 10 => compressed,
+            other => panic!("expected the value to compress 10:1. Got: {:?}", other),
+        };
+        let flags = Flags::from_user(7).unwrap().with_compressed(true).wire();
+        match decompressed(Item::from_parts("key".to_string(), compressed, flags, 0)) {
+            Ok(item) if item.value == value && item.flags == 7 => (),
+            other => panic!("expected the value back. Got: {:?}", other),
+        }
+
+        // Bytes that don't repeat don't compress, zlib only adds its header to them
+        if let Some(compressed) = compress(&noise(4096)) {
+            panic!("expected no compression. Got {} bytes", compressed.len())
+        }
+
+        let corrupt = Item::from_parts("key".to_string(), Vec::from("not zlib"), 1 << 31, 0);
+        match decompressed(corrupt) {
+            Err(OperationError::Decompress(_)) => (),
+            other => panic!("expected a decompress error. Got: {:?}", other),
+        }
+        let plain = Item::from_parts("key".to_string(), Vec::from("not zlib"), 0, 0);
+        match decompressed(plain.clone()) {
+            Ok(item) if item == plain => (),
+            other => panic!("expected the plain item unchanged. Got: {:?}", other),
+        }
+    }
+}
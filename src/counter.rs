@@ -0,0 +1,191 @@
+use crate::errors::OperationError;
+use crate::expiration::Expiration;
+use crate::Client;
+
+// A counter at `key`, created on first use with `initial` (plus or minus that first delta) and
+// `ttl`; Holds no connection, only its configuration, so it's cheap to create and share between
+// clients; Decrementing floors at zero, as the server does;
+#[derive(Debug, Clone)]
+pub struct Counter {
+    key: String,
+    initial: u64,
+    ttl: Expiration,
+    refresh_ttl: bool,
+}
+
+impl Client {
+    pub fn counter(&self, key: &str, initial: u64, ttl: Expiration) -> Counter {
+        Counter {
+            key: key.to_string(),
+            initial,
+            ttl,
+            refresh_ttl: false,
+        }
+    }
+}
+
+impl Counter {
+    // Whether changing an existing counter also `touch`es it with the TTL, so it only expires
+    // once it stops changing;
+    pub fn set_refresh_ttl(&mut self, refresh_ttl: bool) {
+        self.refresh_ttl = refresh_ttl;
+    }
+
+    pub fn with_refresh_ttl(mut self, refresh_ttl: bool) -> Self {
+        self.set_refresh_ttl(refresh_ttl);
+        self
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    // Returns the new value;
+    pub fn incr(&self, client: &mut Client, delta: u64) -> Result<u64, OperationError> {
+        self.arithmetic(client, false, delta)
+    }
+
+    // Returns the new value, floored at zero;
+    pub fn decr(&self, client: &mut Client, delta: u64) -> Result<u64, OperationError> {
+        self.arithmetic(client, true, delta)
+    }
+
+    fn arithmetic(
+        &self,
+        client: &mut Client,
+        decrement: bool,
+        delta: u64,
+    ) -> Result<u64, OperationError> {
+        let created = match decrement {
+            true => self.initial.saturating_sub(delta),
+            false => self.initial.wrapping_add(delta),
+        };
+        let value = client.arithmetic_or_add(decrement, &self.key, delta, created, self.ttl)?;
+        if self.refresh_ttl {
+            match client.touch(&self.key, self.ttl) {
+                // Expired since, the next change creates it again
+                Ok(()) | Err(OperationError::CacheMiss) => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(value)
+    }
+
+    // The current value, `initial` while the counter doesn't exist (without creating it);
+    pub fn get(&self, client: &mut Client) -> Result<u64, OperationError> {
+        Ok(client.get_u64(&self.key)?.unwrap_or(self.initial))
+    }
+
+    // Sets the counter back to `initial`, with a fresh TTL;
+    pub fn reset(&self, client: &mut Client) -> Result<(), OperationError> {
+        client.set_u64(&self.key, self.initial, self.ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        expiration::Expiration,
+        meta::MetaGetFlags,
+        tests::{lock_local_server, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_counter() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let ttl = Expiration::In(Duration::from_secs(60));
+        let _ = client.delete("page_views");
+        let counter = client.counter("page_views", 10, ttl);
+
+        match counter.get(&mut client) {
+            Ok(10) => (),
+            other => panic!(
+                "expected a missing counter to read as initial. Got: {:?}",
+                other
+            ),
+        }
+        match counter.incr(&mut client, 5) {
+            Ok(15) => (),
+            other => panic!("expected the counter to be created at 15. Got: {:?}", other),
+        }
+        match counter.incr(&mut client, 1) {
+            Ok(16) => (),
+            other => panic!("expected the counter to increment to 16. Got: {:?}", other),
+        }
+        match counter.decr(&mut client, 100) {
+            Ok(0) => (),
+            other => panic!("expected the counter to floor at zero. Got: {:?}", other),
+        }
+        if let Err(error) = counter.reset(&mut client) {
+            panic!("did not expect reset to fail: {}", error)
+        }
+        match counter.get(&mut client) {
+            Ok(10) => (),
+            other => panic!("expected the counter to be reset. Got: {:?}", other),
+        }
+
+        let _ = client.delete("page_views");
+        match counter.decr(&mut client, 100) {
+            Ok(0) => (),
+            other => panic!(
+                "expected the counter to be created at zero. Got: {:?}",
+                other
+            ),
+        }
+
+        // Only a refreshing counter moves the ttl of one that already exists
+        if let Err(error) = client.touch("page_views", Expiration::Never) {
+            panic!("did not expect touch to fail: {}", error)
+        }
+        let refreshed = counter.clone().with_refresh_ttl(true);
+        match refreshed.incr(&mut client, 2) {
+            Ok(2) => (),
+            other => panic!("expected the counter to increment to 2. Got: {:?}", other),
+        }
+        let flags = MetaGetFlags {
+            ttl: true,
+            ..Default::default()
+        };
+        match client.meta_get("page_views", &flags) {
+            Ok(Some(item)) if matches!(item.ttl, Some(ttl) if ttl > 0 && ttl <= 60) => {}
+            other => panic!("expected the ttl to be refreshed. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn counters_are_created_once_by_concurrent_clients() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let _ = client.delete("concurrent_counter");
+        let counter = client.counter("concurrent_counter", 100, Expiration::Never);
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let counter = counter.clone();
+                std::thread::spawn(move || {
+                    let mut client =
+                        Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0).unwrap();
+                    for _ in 0..25 {
+                        counter.incr(&mut client, 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        match counter.get(&mut client) {
+            Ok(200) => (),
+            other => panic!("expected every increment to count once. Got: {:?}", other),
+        }
+    }
+}
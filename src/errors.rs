@@ -1,52 +1,335 @@
 #[allow(dead_code)]
+use crate::flags::Flags;
+use crate::value::ValueType;
 use std::io::{self};
-use std::net::AddrParseError;
+use std::net::{AddrParseError, SocketAddr};
 
 #[derive(Debug)]
 pub enum ConnError {
-    AddrParseError(AddrParseError),
-    TcpConnectError(io::Error),
+    // The server address, quoted as given, isn't a socket address nor a `host:port`
+    AddrParseError(String, AddrParseError),
+    // The server address, quoted as given, along with what's wrong with it
+    InvalidServerAddr(String, String),
+    // The server hostname could not be resolved
+    Resolve(String, io::Error),
+    TcpConnectError(SocketAddr, io::Error),
+    // A zero timeout would disable socket timeouts instead of failing immediately
+    InvalidTimeout,
+    // A ketama ring needs at least one point per server
+    InvalidPointsPerServer,
+    // The key prefix has a character keys can't have or leaves no room for the key
+    InvalidKeyPrefix(KeyError),
+    // The TLS configuration was rejected, e.g. no usable root certificate or a bad server name
+    #[cfg(feature = "tls")]
+    TlsConfig(String),
+    #[cfg(feature = "tls")]
+    TlsHandshake(SocketAddr, io::Error),
 }
 
-impl From<AddrParseError> for ConnError {
-    fn from(error: AddrParseError) -> Self {
-        Self::AddrParseError(error)
+impl std::fmt::Display for ConnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnError::AddrParseError(server, error) => {
+                write!(
+                    f,
+                    "could not parse the server address `{}`: {}",
+                    server, error
+                )
+            }
+            ConnError::InvalidServerAddr(server, reason) => {
+                write!(f, "invalid server address `{}`: {}", server, reason)
+            }
+            ConnError::Resolve(host, error) => {
+                write!(f, "could not resolve {}: {}", host, error)
+            }
+            ConnError::TcpConnectError(addr, error) => {
+                write!(f, "could not connect to {}: {}", addr, error)
+            }
+            ConnError::InvalidTimeout => {
+                write!(f, "the timeout must be greater than zero")
+            }
+            ConnError::InvalidPointsPerServer => {
+                write!(f, "the points per server must be greater than zero")
+            }
+            ConnError::InvalidKeyPrefix(reason) => {
+                write!(f, "invalid key prefix: {}", reason)
+            }
+            #[cfg(feature = "tls")]
+            ConnError::TlsConfig(error_msg) => {
+                write!(f, "invalid TLS configuration: {}", error_msg)
+            }
+            #[cfg(feature = "tls")]
+            ConnError::TlsHandshake(addr, error) => {
+                write!(f, "TLS handshake with {} failed: {}", addr, error)
+            }
+        }
     }
 }
 
-impl From<io::Error> for ConnError {
-    fn from(error: io::Error) -> Self {
-        Self::TcpConnectError(error)
+impl std::error::Error for ConnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnError::AddrParseError(_, error) => Some(error),
+            ConnError::Resolve(_, error) | ConnError::TcpConnectError(_, error) => Some(error),
+            ConnError::InvalidKeyPrefix(reason) => Some(reason),
+            #[cfg(feature = "tls")]
+            ConnError::TlsHandshake(_, error) => Some(error),
+            _ => None,
+        }
     }
 }
 
-impl std::fmt::Display for ConnError {
+// Why a key can't be sent, the ASCII protocol splits commands on whitespace and ends them on
+// control characters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    Empty,
+    // The length of the key, over 250 bytes
+    TooLong(usize),
+    // The first space, control character or DEL in the key, along with its position
+    IllegalByte(u8, usize),
+}
+
+impl std::fmt::Display for KeyError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ConnError::AddrParseError(error) => {
-                write!(f, "could not parse the server address: {}", error)
+            KeyError::Empty => write!(f, "the key is empty"),
+            KeyError::TooLong(len) => {
+                write!(f, "the key is {} bytes long, at most 250 are allowed", len)
             }
-            ConnError::TcpConnectError(error) => {
-                write!(f, "could reach the server: {}", error)
+            KeyError::IllegalByte(byte, at) => {
+                write!(
+                    f,
+                    "the key contains the illegal byte {:#04x} at {}, such keys can be sent \
+                     base64 encoded through `meta_get_binary`, `meta_set_binary` and \
+                     `meta_delete_binary`",
+                    byte, at
+                )
             }
         }
     }
 }
 
-impl std::error::Error for ConnError {}
+impl std::error::Error for KeyError {}
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum OperationError {
     CacheMiss,
     CASConflict,
+    // `cas_loop` kept conflicting with other writers for all its attempts
+    CASAttemptsExhausted(u32),
     NotStored,
-    Server,
+    // The server's message, e.g. `out of memory storing object` or `object too large for cache`
+    Server(String),
     Client(String),
     NoStats,
-    MalformedKey,
+    MalformedKey(KeyError),
+    // The value is over the client's `max_value_size`, or needs more chunks than `set_chunked`
+    // splits a value in, nothing was sent
+    ValueTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    // The item flags set bits of `Flags::RESERVED_MASK`
+    ReservedFlags(u32),
+    // The value wasn't stored in the encoding it's read as, e.g. read by `get_json` but not
+    // stored by `set_json`
+    NotEncodedAs(&'static str),
+    // `get_as` read a value stored as another type, e.g. a string read as `u64`
+    ValueTypeMismatch {
+        stored: ValueType,
+        requested: ValueType,
+    },
+    // `get_string` read a value that isn't UTF-8
+    InvalidUtf8(std::string::FromUtf8Error),
+    // `get_u64` read a value that isn't a decimal `u64`
+    InvalidCounter(std::num::ParseIntError),
+    // `get_chunked` found a manifest but not its chunk `index`, e.g. evicted or expired first
+    MissingChunk {
+        index: usize,
+        chunks: usize,
+    },
+    // `get_chunked` found chunks that don't add up to the manifest's value, e.g. left over from
+    // another value or overwritten by a concurrent `set_chunked`
+    StaleChunks,
+    // The value is flagged as compressed by the client but didn't decompress
+    Decompress(io::Error),
+    // The reader `set_from_reader` copies the value from failed or ended before the declared
+    // length; The command was cut short by closing the connection, nothing was stored
+    ValueSource(io::Error),
+    // A `ValueCodec` failed to encode or decode a value
+    Codec(Box<dyn std::error::Error + Send + Sync>),
+    // Serializing or deserializing a JSON value failed
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+    // An `Expiration` with no exptime, such as a time in the past
+    InvalidExpiration(String),
     NoServers,
+    // The server already has `max_open_conns_per_server` connections open
+    PoolExhausted(SocketAddr),
+    // Dialing the server didn't complete within the client timeout; The error is `TimedOut`
+    ConnectTimeout(SocketAddr, io::Error),
+    Connect(SocketAddr, io::Error),
+    // The TCP connection was established but the TLS handshake with the server failed
+    #[cfg(feature = "tls")]
+    TlsHandshake(SocketAddr, io::Error),
+    // A read or write didn't complete within the client timeout; The error is `TimedOut`
+    Timeout(io::Error),
     CorruptResponse(String),
+    // The server answered `ERROR` to the command, not knowing it, e.g. a meta command sent to a
+    // server older than 1.6; Unlike a corrupt response the connection is still in sync
+    UnsupportedCommand(String),
     Io(WriteReadLineError),
+    // The thread asking a server for its part of a parallel multi get panicked
+    #[cfg(feature = "parallel")]
+    WorkerPanicked(String),
+    ServerFailures(Vec<(SocketAddr, OperationError)>),
+    // An error on the connection to `addr` while running `verb` on `key` (as sent, prefix
+    // included); See `without_context` to match the error itself
+    WithContext {
+        verb: String,
+        key: String,
+        addr: SocketAddr,
+        source: Box<OperationError>,
+    },
+}
+
+// Broad classes of `OperationError`, see `OperationError::kind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    // The command was carried out and this is its outcome, e.g. a cache miss, a CAS conflict or
+    // an item that wasn't stored; The servers and connections are fine
+    Data,
+    // The client refused the arguments before sending anything, e.g. a malformed key or a value
+    // over the size limit
+    InvalidInput,
+    // The value was read but isn't what it's read as, e.g. not UTF-8 or not decompressible
+    Value,
+    // The server understood the request but answered with an error, e.g. `SERVER_ERROR`
+    Server,
+    // The command or its response didn't make it, e.g. a refused connection, a timeout or a
+    // corrupt response
+    Transport,
+}
+
+impl OperationError {
+    // The class of the error; for `ServerFailures` it's `Transport` if any of the servers failed
+    // at that level, the class of the first failure otherwise;
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            OperationError::CacheMiss
+            | OperationError::CASConflict
+            | OperationError::CASAttemptsExhausted(_)
+            | OperationError::NotStored
+            | OperationError::NoStats
+            | OperationError::MissingChunk { .. }
+            | OperationError::StaleChunks => ErrorKind::Data,
+            OperationError::MalformedKey(_)
+            | OperationError::ValueTooLarge { .. }
+            | OperationError::ReservedFlags(_)
+            | OperationError::ValueSource(_)
+            | OperationError::InvalidExpiration(_) => ErrorKind::InvalidInput,
+            OperationError::NotEncodedAs(_)
+            | OperationError::ValueTypeMismatch { .. }
+            | OperationError::InvalidUtf8(_)
+            | OperationError::InvalidCounter(_)
+            | OperationError::Decompress(_)
+            | OperationError::Codec(_) => ErrorKind::Value,
+            #[cfg(feature = "serde")]
+            OperationError::Json(_) => ErrorKind::Value,
+            OperationError::Server(_)
+            | OperationError::Client(_)
+            | OperationError::UnsupportedCommand(_) => ErrorKind::Server,
+            OperationError::NoServers
+            | OperationError::PoolExhausted(_)
+            | OperationError::ConnectTimeout(..)
+            | OperationError::Connect(..)
+            | OperationError::Timeout(_)
+            | OperationError::CorruptResponse(_)
+            | OperationError::Io(_) => ErrorKind::Transport,
+            #[cfg(feature = "tls")]
+            OperationError::TlsHandshake(..) => ErrorKind::Transport,
+            #[cfg(feature = "parallel")]
+            OperationError::WorkerPanicked(_) => ErrorKind::Transport,
+            OperationError::WithContext { source, .. } => source.kind(),
+            OperationError::ServerFailures(failures) => {
+                let mut kinds = failures.iter().map(|(_, error)| error.kind());
+                let first = kinds.next().unwrap_or(ErrorKind::Transport);
+                if first == ErrorKind::Transport || kinds.any(|kind| kind == ErrorKind::Transport) {
+                    return ErrorKind::Transport;
+                }
+                first
+            }
+        }
+    }
+
+    // Whether the connection the error happened on is left in an unknown state and mustn't be
+    // reused: the rest of the response may still be in flight (IO errors, timeouts, short
+    // reads) or the client lost track of where it is (corrupt responses); Any other error is a
+    // whole response, after which the next one is read where it's expected;
+    pub(crate) fn fatal_to_connection(&self) -> bool {
+        matches!(
+            self.without_context(),
+            OperationError::Io(_) | OperationError::Timeout(_) | OperationError::CorruptResponse(_)
+        )
+    }
+
+    // The error `WithContext` wraps, or the error itself;
+    pub fn without_context(&self) -> &OperationError {
+        match self {
+            OperationError::WithContext { source, .. } => source.without_context(),
+            error => error,
+        }
+    }
+
+    // Wraps errors that happened on the connection to `addr` in `WithContext`; Outcomes such as
+    // a cache miss and errors raised before anything was sent are returned as they are;
+    pub(crate) fn with_context(self, verb: &str, key: &str, addr: SocketAddr) -> OperationError {
+        match self {
+            OperationError::Io(_)
+            | OperationError::Timeout(_)
+            | OperationError::CorruptResponse(_)
+            | OperationError::Server(_)
+            | OperationError::Client(_)
+            | OperationError::UnsupportedCommand(_) => OperationError::WithContext {
+                verb: verb.to_string(),
+                key: key.to_string(),
+                addr,
+                source: Box::new(self),
+            },
+            error => error,
+        }
+    }
+
+    pub fn is_cache_miss(&self) -> bool {
+        matches!(self.without_context(), OperationError::CacheMiss)
+    }
+
+    // Whether a CAS found the item changed since it was read, retrying may succeed;
+    // `CASAttemptsExhausted` doesn't count, it's what's left after retrying;
+    pub fn is_cas_conflict(&self) -> bool {
+        matches!(self.without_context(), OperationError::CASConflict)
+    }
+
+    pub fn is_not_stored(&self) -> bool {
+        matches!(self.without_context(), OperationError::NotStored)
+    }
+
+    // Whether reading from or writing to an established connection failed;
+    pub fn is_io(&self) -> bool {
+        matches!(self.without_context(), OperationError::Io(_))
+    }
+
+    // Whether the server didn't answer or accept the connection in time;
+    pub fn is_timeout(&self) -> bool {
+        match self.without_context() {
+            OperationError::Timeout(_) | OperationError::ConnectTimeout(..) => true,
+            OperationError::Io(error) => error.is_timeout(),
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Display for OperationError {
@@ -58,11 +341,18 @@ impl std::fmt::Display for OperationError {
             OperationError::CASConflict => {
                 write!(f, "memcache: CAS conflict error")
             }
+            OperationError::CASAttemptsExhausted(attempts) => {
+                write!(
+                    f,
+                    "memcache: compare-and-swap conflicted on all {} attempts",
+                    attempts
+                )
+            }
             OperationError::NotStored => {
                 write!(f, "memcache: not stored error")
             }
-            OperationError::Server => {
-                write!(f, "memcache: server error")
+            OperationError::Server(error_msg) => {
+                write!(f, "memcache: server error: {}", error_msg)
             }
             OperationError::Client(error_msg) => {
                 write!(f, "memcache: client error: {}", error_msg)
@@ -70,23 +360,225 @@ impl std::fmt::Display for OperationError {
             OperationError::NoStats => {
                 write!(f, "memcache: no stats error")
             }
-            OperationError::MalformedKey => {
-                write!(f, "memcache: malformed key error")
+            OperationError::MalformedKey(reason) => {
+                write!(f, "memcache: malformed key error: {}", reason)
+            }
+            OperationError::ValueTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "memcache: value of {} bytes is over the {} byte limit",
+                    size, limit
+                )
+            }
+            OperationError::ReservedFlags(flags) => {
+                write!(
+                    f,
+                    "memcache: flags {:#x} set bits reserved by the client ({:#x})",
+                    flags,
+                    Flags::RESERVED_MASK
+                )
+            }
+            OperationError::NotEncodedAs(encoding) => {
+                write!(f, "memcache: value was not stored as {}", encoding)
+            }
+            OperationError::ValueTypeMismatch { stored, requested } => {
+                write!(
+                    f,
+                    "memcache: value stored as {:?} can't be read as {:?}",
+                    stored, requested
+                )
+            }
+            OperationError::InvalidUtf8(error) => {
+                write!(f, "memcache: value is not UTF-8: {}", error)
+            }
+            OperationError::InvalidCounter(error) => {
+                write!(f, "memcache: value is not a decimal u64: {}", error)
+            }
+            OperationError::MissingChunk { index, chunks } => {
+                write!(f, "memcache: chunk {} of {} is missing", index, chunks)
+            }
+            #[cfg(feature = "parallel")]
+            OperationError::WorkerPanicked(message) => {
+                write!(f, "memcache: multi get worker panicked: {}", message)
+            }
+            OperationError::StaleChunks => {
+                write!(f, "memcache: chunks don't match their manifest")
+            }
+            OperationError::Decompress(error) => {
+                write!(f, "memcache: could not decompress value: {}", error)
+            }
+            OperationError::ValueSource(error) => {
+                write!(f, "memcache: could not read the value to store: {}", error)
+            }
+            OperationError::Codec(error) => {
+                write!(f, "memcache: codec error: {}", error)
+            }
+            #[cfg(feature = "serde")]
+            OperationError::Json(error) => {
+                write!(f, "memcache: JSON error: {}", error)
+            }
+            OperationError::InvalidExpiration(reason) => {
+                write!(f, "memcache: invalid expiration: {}", reason)
             }
             OperationError::NoServers => {
                 write!(f, "memcache: no servers error")
             }
+            OperationError::PoolExhausted(addr) => {
+                write!(f, "memcache: too many open connections to {}", addr)
+            }
+            OperationError::ConnectTimeout(addr, error) => {
+                write!(f, "memcache: connect timeout to {}: {}", addr, error)
+            }
+            OperationError::Connect(addr, error) => {
+                write!(f, "memcache: could not connect to {}: {}", addr, error)
+            }
+            #[cfg(feature = "tls")]
+            OperationError::TlsHandshake(addr, error) => {
+                write!(f, "memcache: TLS handshake with {} failed: {}", addr, error)
+            }
+            OperationError::Timeout(error) => {
+                write!(
+                    f,
+                    "memcache: timeout reading from or writing to the server: {}",
+                    error
+                )
+            }
             OperationError::CorruptResponse(error_msg) => {
                 write!(f, "memcache: corrupt response error: {}", error_msg)
             }
+            OperationError::UnsupportedCommand(verb) => {
+                write!(f, "memcache: server does not support the {} command", verb)
+            }
             OperationError::Io(error) => {
                 write!(f, "memcache: IO error: {}", error)
             }
+            OperationError::WithContext {
+                verb,
+                key,
+                addr,
+                source,
+            } => {
+                write!(
+                    f,
+                    "{} ({} `{}` on {})",
+                    source,
+                    verb,
+                    key_excerpt(key),
+                    addr
+                )
+            }
+            OperationError::ServerFailures(failures) => {
+                write!(f, "memcache: operation failed on")?;
+                for (i, (addr, error)) in failures.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ";" };
+                    write!(f, "{} server {} ({})", separator, addr, error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for OperationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OperationError::MalformedKey(reason) => Some(reason),
+            OperationError::InvalidUtf8(error) => Some(error),
+            OperationError::InvalidCounter(error) => Some(error),
+            OperationError::Decompress(error) | OperationError::ValueSource(error) => Some(error),
+            OperationError::Codec(error) => Some(error.as_ref()),
+            #[cfg(feature = "serde")]
+            OperationError::Json(error) => Some(error),
+            OperationError::Connect(_, error)
+            | OperationError::ConnectTimeout(_, error)
+            | OperationError::Timeout(error) => Some(error),
+            #[cfg(feature = "tls")]
+            OperationError::TlsHandshake(_, error) => Some(error),
+            OperationError::Io(error) => Some(error),
+            OperationError::WithContext { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<WriteReadLineError> for OperationError {
+    fn from(error: WriteReadLineError) -> Self {
+        OperationError::Io(error)
+    }
+}
+
+// Error of `get_or_set_with`, keeping the closure's errors apart from the client's
+#[derive(Debug)]
+pub enum GetOrSetError<E> {
+    // The closure computing the value failed; Nothing was stored
+    Compute(E),
+    Operation(OperationError),
+}
+
+impl<E> From<OperationError> for GetOrSetError<E> {
+    fn from(error: OperationError) -> Self {
+        GetOrSetError::Operation(error)
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for GetOrSetError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GetOrSetError::Compute(error) => {
+                write!(f, "memcache: computing the value failed: {}", error)
+            }
+            GetOrSetError::Operation(error) => error.fmt(f),
         }
     }
 }
 
-impl std::error::Error for OperationError {}
+impl<E: std::error::Error + 'static> std::error::Error for GetOrSetError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GetOrSetError::Compute(error) => Some(error),
+            GetOrSetError::Operation(error) => Some(error),
+        }
+    }
+}
+
+// Longest part of a response line quoted in an error
+const MAX_RESPONSE_EXCERPT: usize = 64;
+// Longest part of a key quoted in an error, keys run up to 250 bytes
+const MAX_KEY_EXCERPT: usize = 64;
+
+// A response line as quoted in errors; Cut short, as a connection that lost track of the
+// responses reads value bytes as lines and values mustn't end up in errors or logs;
+pub(crate) fn response_excerpt(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\r\n").unwrap_or(line);
+    if line.len() <= MAX_RESPONSE_EXCERPT {
+        return String::from_utf8_lossy(line).into_owned();
+    }
+    format!(
+        "{}... ({} bytes)",
+        String::from_utf8_lossy(&line[..MAX_RESPONSE_EXCERPT]),
+        line.len()
+    )
+}
+
+// A key as quoted in errors, cut short on a character boundary;
+fn key_excerpt(key: &str) -> String {
+    if key.len() <= MAX_KEY_EXCERPT {
+        return key.to_string();
+    }
+    let mut end = MAX_KEY_EXCERPT;
+    while !key.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes)", &key[..end], key.len())
+}
+
+// The error of a timed out socket as `TimedOut`, wrapping the `WouldBlock` Unix reports;
+pub(crate) fn timed_out(error: io::Error) -> io::Error {
+    match error.kind() {
+        io::ErrorKind::TimedOut => error,
+        _ => io::Error::new(io::ErrorKind::TimedOut, error),
+    }
+}
 
 #[derive(Debug)]
 pub enum WriteReadLineError {
@@ -95,6 +587,26 @@ pub enum WriteReadLineError {
     Read(io::Error),
 }
 
+impl WriteReadLineError {
+    pub(crate) fn into_inner(self) -> io::Error {
+        let (WriteReadLineError::Write(error)
+        | WriteReadLineError::Flush(error)
+        | WriteReadLineError::Read(error)) = self;
+        error
+    }
+
+    // Socket timeouts surface as `WouldBlock` on Unix and `TimedOut` on Windows;
+    pub(crate) fn is_timeout(&self) -> bool {
+        let (WriteReadLineError::Write(error)
+        | WriteReadLineError::Flush(error)
+        | WriteReadLineError::Read(error)) = self;
+        matches!(
+            error.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    }
+}
+
 impl std::fmt::Display for WriteReadLineError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -110,3 +622,12 @@ impl std::fmt::Display for WriteReadLineError {
         }
     }
 }
+
+impl std::error::Error for WriteReadLineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        let (WriteReadLineError::Write(error)
+        | WriteReadLineError::Flush(error)
+        | WriteReadLineError::Read(error)) = self;
+        Some(error)
+    }
+}
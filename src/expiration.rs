@@ -0,0 +1,132 @@
+use crate::errors::OperationError;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Longest expiration the server reads as relative; Anything past it is read as a unix timestamp
+const MAX_RELATIVE_EXPIRATION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// When an item expires; Converted to the protocol's exptime when the command is written, so a
+// duration over 30 days is sent as the unix timestamp it ends at instead of being misread as one;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Expiration {
+    #[default]
+    Never,
+    // Rounded up to whole seconds
+    In(Duration),
+    At(SystemTime),
+    // Sent as is, e.g. a negative value to expire the item immediately
+    Raw(i32),
+}
+
+impl Expiration {
+    // The exptime sent to the server; Fails for times in the past and ones after 2038 that don't
+    // fit the protocol;
+    pub fn wire_value(&self) -> Result<i32, OperationError> {
+        self.wire_value_at(SystemTime::now())
+    }
+
+    pub(crate) fn wire_value_at(&self, now: SystemTime) -> Result<i32, OperationError> {
+        match *self {
+            Expiration::Never => Ok(0),
+            Expiration::Raw(exptime) => Ok(exptime),
+            Expiration::In(duration) if duration.is_zero() => Err(
+                OperationError::InvalidExpiration("expiration duration is zero".to_string()),
+            ),
+            Expiration::In(duration) if duration <= MAX_RELATIVE_EXPIRATION => {
+                Ok(whole_secs(duration) as i32)
+            }
+            Expiration::In(duration) => unix_timestamp(now.checked_add(duration)),
+            Expiration::At(time) if time <= now => Err(OperationError::InvalidExpiration(format!(
+                "expiration time is {:?} in the past",
+                elapsed(time, now)
+            ))),
+            Expiration::At(time) => unix_timestamp(Some(time)),
+        }
+    }
+
+    // Reads an exptime back, `0` being `Never`;
+    pub(crate) fn from_wire(exptime: i32) -> Self {
+        match exptime {
+            0 => Expiration::Never,
+            exptime => Expiration::Raw(exptime),
+        }
+    }
+}
+
+fn whole_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+fn elapsed(time: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(time).unwrap_or_default()
+}
+
+fn unix_timestamp(time: Option<SystemTime>) -> Result<i32, OperationError> {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .and_then(|since_epoch| i32::try_from(whole_secs(since_epoch)).ok())
+        .ok_or_else(|| {
+            OperationError::InvalidExpiration(
+                "expiration time doesn't fit a 32-bit unix timestamp".to_string(),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expiration;
+    use crate::errors::OperationError;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_expiration_wire_values() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let thirty_days = Duration::from_secs(2_592_000);
+        let cases = [
+            (Expiration::Never, 0),
+            (Expiration::Raw(-1), -1),
+            (Expiration::Raw(1_800_000_000), 1_800_000_000),
+            (Expiration::In(Duration::from_secs(60)), 60),
+            (Expiration::In(Duration::from_millis(1500)), 2),
+            (Expiration::In(Duration::from_millis(1)), 1),
+            (Expiration::In(thirty_days), 2_592_000),
+            (
+                Expiration::In(thirty_days + Duration::from_secs(1)),
+                1_702_592_001,
+            ),
+            (Expiration::At(now + Duration::from_secs(90)), 1_700_000_090),
+            (
+                Expiration::At(now + Duration::from_millis(1500)),
+                1_700_000_002,
+            ),
+            (Expiration::At(now + thirty_days * 2), 1_705_184_000),
+        ];
+        for (expiration, expected) in cases {
+            match expiration.wire_value_at(now) {
+                Ok(wire) if wire == expected => (),
+                other => panic!(
+                    "expected {:?} to be sent as {}. Got: {:?}",
+                    expiration, expected, other
+                ),
+            }
+        }
+
+        let invalid = [
+            Expiration::In(Duration::ZERO),
+            Expiration::At(now),
+            Expiration::At(now - Duration::from_secs(1)),
+            Expiration::In(Duration::from_secs(100 * 365 * 24 * 60 * 60)),
+            Expiration::At(UNIX_EPOCH + Duration::from_secs(1 << 31)),
+        ];
+        for expiration in invalid {
+            match expiration.wire_value_at(now) {
+                Err(OperationError::InvalidExpiration(_)) => (),
+                other => panic!("expected {:?} to be rejected. Got: {:?}", expiration, other),
+            }
+        }
+
+        if Expiration::from_wire(0) != Expiration::Never
+            || Expiration::from_wire(5) != Expiration::Raw(5)
+        {
+            panic!("expected exptimes to read back")
+        }
+    }
+}
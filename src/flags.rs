@@ -0,0 +1,130 @@
+use crate::errors::OperationError;
+
+// Reserved bits holding the `ValueCodec::ID` a value was stored with, `0` for plain bytes
+const CODEC_MASK: u32 = 0x7f00_0000;
+const CODEC_SHIFT: u32 = 24;
+pub(crate) const MAX_CODEC_ID: u8 = (CODEC_MASK >> CODEC_SHIFT) as u8;
+// Reserved bit of values the client compressed
+const FLAG_COMPRESSED: u32 = 1 << 31;
+
+// The 32-bit flags stored with every item, split between the caller and the client: the low 24
+// bits (`USER_MASK`) are the caller's `Item::flags` and pass through untouched, while the high
+// 8 bits (`RESERVED_MASK`) record how the client encoded the value, e.g. compressed or
+// serialized, so it can be decoded on read; Values stored as plain bytes leave the reserved bits
+// clear, so other clients see the same flags the caller set;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Flags(u32);
+
+impl Flags {
+    pub const USER_MASK: u32 = 0x00ff_ffff;
+    pub const RESERVED_MASK: u32 = !Flags::USER_MASK;
+
+    // Fails when `user` sets any of the reserved bits;
+    pub fn from_user(user: u32) -> Result<Self, OperationError> {
+        if user & Flags::RESERVED_MASK != 0 {
+            return Err(OperationError::ReservedFlags(user));
+        }
+        Ok(Flags(user))
+    }
+
+    // Flags as read from the server;
+    pub fn from_wire(wire: u32) -> Self {
+        Flags(wire)
+    }
+
+    pub fn user(&self) -> u32 {
+        self.0 & Flags::USER_MASK
+    }
+
+    // The reserved bits, in place;
+    pub fn reserved(&self) -> u32 {
+        self.0 & Flags::RESERVED_MASK
+    }
+
+    // Id of the codec the value was stored with;
+    pub fn codec(&self) -> u8 {
+        ((self.0 & CODEC_MASK) >> CODEC_SHIFT) as u8
+    }
+
+    pub(crate) fn with_codec(self, id: u8) -> Self {
+        Flags((self.0 & !CODEC_MASK) | ((u32::from(id) << CODEC_SHIFT) & CODEC_MASK))
+    }
+
+    // Whether the client compressed the value;
+    pub fn compressed(&self) -> bool {
+        self.0 & FLAG_COMPRESSED != 0
+    }
+
+    pub(crate) fn with_compressed(self, compressed: bool) -> Self {
+        match compressed {
+            true => Flags(self.0 | FLAG_COMPRESSED),
+            false => Flags(self.0 & !FLAG_COMPRESSED),
+        }
+    }
+
+    // Sets reserved bits, leaving the user flags as they are;
+    pub(crate) fn with_reserved(self, bits: u32) -> Self {
+        Flags(self.0 | (bits & Flags::RESERVED_MASK))
+    }
+
+    // Flags as sent to the server;
+    pub fn wire(&self) -> u32 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Flags;
+    use crate::errors::OperationError;
+
+    #[test]
+    fn test_flags_partitioning() {
+        for user in [0, 42, Flags::USER_MASK] {
+            match Flags::from_user(user) {
+                Ok(flags) if flags.wire() == user && flags.user() == user => (),
+                other => panic!("expected {:#x} to pass through. Got: {:?}", user, other),
+            }
+        }
+        for user in [1 << 24, 1 << 31, u32::MAX] {
+            match Flags::from_user(user) {
+                Err(OperationError::ReservedFlags(flags)) if flags == user => (),
+                other => panic!("expected {:#x} to be rejected. Got: {:?}", user, other),
+            }
+        }
+
+        let flags = Flags::from_user(5)
+            .unwrap()
+            .with_reserved(0x8100_0000 | 0xff);
+        if flags.wire() != 0x8100_0005 || flags.user() != 5 || flags.reserved() != 0x8100_0000 {
+            panic!(
+                "expected reserved bits next to the user flags. Got: {:#x}",
+                flags.wire()
+            )
+        }
+        if Flags::from_wire(flags.wire()) != flags {
+            panic!("expected flags to read back")
+        }
+
+        let flags = Flags::from_user(5).unwrap().with_codec(3);
+        if flags.wire() != 0x0300_0005 || flags.codec() != 3 || flags.with_codec(1).codec() != 1 {
+            panic!(
+                "expected the codec id in the reserved bits. Got: {:#x}",
+                flags.wire()
+            )
+        }
+        let compressed = flags.with_compressed(true);
+        if compressed.wire() != 0x8300_0005 || !compressed.compressed() {
+            panic!(
+                "expected the compressed bit to be set. Got: {:#x}",
+                compressed.wire()
+            )
+        }
+        if compressed.with_compressed(false) != flags || compressed.codec() != 3 {
+            panic!(
+                "expected the compressed bit to be cleared. Got: {:#x}",
+                compressed.wire()
+            )
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// How keys (and ring points) are hashed onto servers; Clients sharing a cache with another
+// client library must use the same hash, otherwise every key is looked up on the wrong server;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeyHasher {
+    // CRC32 (IEEE), like gomemcache
+    #[default]
+    Crc32,
+    Fnv1a32,
+    Fnv1a64,
+    // XXH64 with a seed of 0
+    XxHash64,
+    // The first 8 bytes of the MD5 digest, little-endian
+    Md5,
+}
+
+impl KeyHasher {
+    pub fn hash(&self, input: &[u8]) -> u64 {
+        match self {
+            KeyHasher::Crc32 => u64::from(crc32fast::hash(input)),
+            KeyHasher::Fnv1a32 => u64::from(input.iter().fold(0x811c9dc5_u32, |hash, byte| {
+                (hash ^ u32::from(*byte)).wrapping_mul(0x01000193)
+            })),
+            KeyHasher::Fnv1a64 => input.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+                (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+            }),
+            KeyHasher::XxHash64 => xxhash_rust::xxh64::xxh64(input, 0),
+            KeyHasher::Md5 => {
+                let digest = md5::compute(input).0;
+                let mut hash = [0; 8];
+                hash.copy_from_slice(&digest[..8]);
+                u64::from_le_bytes(hash)
+            }
+        }
+    }
+
+    // How many of the low bits of `hash` are set by the algorithm;
+    pub(crate) fn bits(&self) -> u32 {
+        match self {
+            KeyHasher::Crc32 | KeyHasher::Fnv1a32 => 32,
+            KeyHasher::Fnv1a64 | KeyHasher::XxHash64 | KeyHasher::Md5 => 64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyHasher;
+
+    #[test]
+    fn test_key_hashers() {
+        #[rustfmt::skip]
+        let golden: [(KeyHasher, [u64; 3]); 5] = [
+            (KeyHasher::Crc32, [0x0, 0xe8b7be43, 0x352441c2]),
+            (KeyHasher::Fnv1a32, [0x811c9dc5, 0xe40c292c, 0x1a47e90b]),
+            (KeyHasher::Fnv1a64, [0xcbf29ce484222325, 0xaf63dc4c8601ec8c, 0xe71fa2190541574b]),
+            (KeyHasher::XxHash64, [0xef46db3751d8e999, 0xd24ec4f1a98c6e5b, 0x44bc2cf5ad770999]),
+            (KeyHasher::Md5, [0x04b2008fd98c1dd4, 0xa8b6f1c0b975c10c, 0xb04fd23c98500190]),
+        ];
+        for (hasher, hashes) in golden {
+            let got = ["", "a", "abc"].map(|key| hasher.hash(key.as_bytes()));
+            if got != hashes {
+                panic!(
+                    "expected {:?} to hash to {:x?}. Got: {:x?}",
+                    hasher, hashes, got
+                )
+            }
+        }
+    }
+}
@@ -1,21 +1,177 @@
-#[derive(Debug)]
+use crate::errors::OperationError;
+use crate::expiration::Expiration;
+use crate::legal_key;
+use std::borrow::Cow;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+// Value bytes shown by `Debug`
+const VALUE_PREVIEW_LEN: usize = 16;
+
+#[derive(Clone, PartialEq)]
 pub struct Item {
     // NOTE: Maybe not a `String`?
     pub key: String,
     pub value: Vec<u8>,
+    // Up to `Flags::USER_MASK`, the bits above are reserved for the client
     pub flags: u32,
-    pub expiration: i32,
+    pub expiration: Expiration,
     pub cas_id: u64,
 }
 
 impl Item {
-    pub(crate) fn new(key: String, value: Vec<u8>, flags: u32, expiration: i32) -> Self {
+    // Checks the key up front, so a malformed one fails here rather than on the first command;
+    // Flags and CAS id start at zero and the item never expires;
+    pub fn new(key: impl Into<String>, value: impl Into<Vec<u8>>) -> Result<Self, OperationError> {
+        let key = key.into();
+        legal_key(&key)?;
+        Ok(Self::from_parts(key, value.into(), 0, 0))
+    }
+
+    pub fn with_flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn with_expiration(mut self, expiration: Expiration) -> Self {
+        self.expiration = expiration;
+        self
+    }
+
+    pub fn expire_in(self, duration: Duration) -> Self {
+        self.with_expiration(Expiration::In(duration))
+    }
+
+    pub fn expire_at(self, time: SystemTime) -> Self {
+        self.with_expiration(Expiration::At(time))
+    }
+
+    // The cas unique the item was read with, e.g. by `gets`;
+    pub fn with_cas_id(mut self, cas_id: u64) -> Self {
+        self.cas_id = cas_id;
+        self
+    }
+
+    // Skips the key check, for keys read back from the server; `expiration` is a raw exptime;
+    pub(crate) fn from_parts(key: String, value: Vec<u8>, flags: u32, expiration: i32) -> Self {
         Self {
             key,
             value,
             flags,
-            expiration,
+            expiration: Expiration::from_wire(expiration),
             cas_id: 0, //  NOTE: Add
         }
     }
 }
+
+// What `Client::get_into` returns alongside the value it appended to the caller's buffer;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItemMeta {
+    // Without the reserved bits, like `Item::flags`
+    pub flags: u32,
+    pub cas_id: u64,
+    // Bytes appended to the buffer, after decompression
+    pub len: usize,
+}
+
+// What a storage command writes, borrowed from an `Item` or owned when the value was encoded by
+// a `ValueCodec` or compressed; `flags` may carry reserved bits;
+pub(crate) struct StoredValue<'a> {
+    pub(crate) value: Cow<'a, [u8]>,
+    pub(crate) flags: u32,
+    pub(crate) expiration: Expiration,
+}
+
+impl Item {
+    pub(crate) fn stored(&self) -> StoredValue<'_> {
+        StoredValue {
+            value: Cow::Borrowed(&self.value),
+            flags: self.flags,
+            expiration: self.expiration,
+        }
+    }
+}
+
+// Values can be large and hold personal data, so only their length and first bytes are shown;
+impl fmt::Debug for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Item")
+            .field("key", &self.key)
+            .field("value", &ValuePreview(&self.value))
+            .field("flags", &self.flags)
+            .field("expiration", &self.expiration)
+            .field("cas_id", &self.cas_id)
+            .finish()
+    }
+}
+
+// Debug output of a value, e.g. `5 bytes: 76616c7565`;
+pub(crate) struct ValuePreview<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for ValuePreview<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes: ", self.0.len())?;
+        for byte in self.0.iter().take(VALUE_PREVIEW_LEN) {
+            write!(f, "{:02x}", byte)?;
+        }
+        if self.0.len() > VALUE_PREVIEW_LEN {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Item;
+    use crate::errors::{KeyError, OperationError};
+    use crate::expiration::Expiration;
+    use std::time::Duration;
+
+    #[test]
+    fn test_item_builder() {
+        let item = match Item::new("key", "value") {
+            Ok(item) => item
+                .with_flags(42)
+                .expire_in(Duration::from_secs(60))
+                .with_cas_id(7),
+            Err(error) => panic!("did not expect building the item to fail: {}", error),
+        };
+        let expected = Item {
+            key: String::from("key"),
+            value: Vec::from("value"),
+            flags: 42,
+            expiration: Expiration::In(Duration::from_secs(60)),
+            cas_id: 7,
+        };
+        if item != expected {
+            panic!("expected {:?}. Got: {:?}", expected, item)
+        }
+        if item.clone().with_cas_id(8) == item {
+            panic!("expected items with other CAS ids to differ")
+        }
+
+        match Item::new("a key", vec![0u8; 3]) {
+            Err(OperationError::MalformedKey(KeyError::IllegalByte(b' ', 1))) => (),
+            other => panic!("expected a malformed key error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_item_debug_redacts_the_value() {
+        let item = Item::new("key", vec![b'v'; 1024 * 1024]).unwrap();
+        let debug = format!("{:?}", item);
+        if debug.len() > 200
+            || !debug.contains("1048576 bytes: 76767676767676767676767676767676...")
+        {
+            panic!("expected a short preview of the value. Got: {}", debug)
+        }
+        let debug = format!("{:?}", Item::new("key", "value").unwrap());
+        if !debug.contains("value: 5 bytes: 76616c7565,") {
+            panic!(
+                "expected the whole value to be shown when short. Got: {}",
+                debug
+            )
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::codec::ValueCodec;
+use crate::errors::OperationError;
+use crate::expiration::Expiration;
+use crate::Client;
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Cow;
+
+// Stores values serialized as JSON;
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+impl<T: Serialize + DeserializeOwned> ValueCodec<T> for JsonCodec {
+    const ID: u8 = 1;
+    const NAME: &'static str = "JSON";
+
+    fn encode(value: &T) -> Result<(Cow<'_, [u8]>, u32), OperationError> {
+        let bytes = serde_json::to_vec(value).map_err(OperationError::Json)?;
+        Ok((Cow::Owned(bytes), 0))
+    }
+
+    fn decode(bytes: Vec<u8>, _flags: u32) -> Result<T, OperationError> {
+        serde_json::from_slice(&bytes).map_err(OperationError::Json)
+    }
+}
+
+impl Client {
+    // `set_with::<JsonCodec, _>`;
+    pub fn set_json<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: &str,
+        value: &T,
+        expiration: Expiration,
+    ) -> Result<(), OperationError> {
+        self.set_with::<JsonCodec, T>(key, value, expiration)
+    }
+
+    // `get_with::<JsonCodec, _>`; Values stored any other way fail with `NotEncodedAs` instead of
+    // being deserialized, even when they happen to be valid JSON;
+    pub fn get_json<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<T>, OperationError> {
+        self.get_with::<JsonCodec, T>(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        errors::OperationError,
+        expiration::Expiration,
+        item::Item,
+        tests::{lock_local_server, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Profile {
+        name: String,
+        visits: u64,
+    }
+
+    #[test]
+    fn test_json_values() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let profile = Profile {
+            name: String::from("ferris"),
+            visits: 3,
+        };
+        if let Err(error) = client.set_json("json_profile", &profile, Expiration::Never) {
+            panic!("did not expect set json to fail: {}", error)
+        }
+        match client.get_json::<Profile>("json_profile") {
+            Ok(Some(got)) if got == profile => (),
+            other => panic!("expected the profile back. Got: {:?}", other),
+        }
+        // Read as plain bytes the value is the JSON document, without the reserved flag
+        let document = br#"{"name":"ferris","visits":3}"#;
+        match client.get("json_profile") {
+            Ok(Some(item)) if item.flags == 0 && item.value == document => (),
+            other => panic!("expected the JSON document. Got: {:?}", other),
+        }
+        match client.get_json::<Vec<u64>>("json_profile") {
+            Err(OperationError::Json(_)) => (),
+            other => panic!("expected a JSON error. Got: {:?}", other),
+        }
+
+        let item = Item::new("json_plain", "[1, 2, 3]").unwrap();
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.get_json::<Vec<u64>>("json_plain") {
+            Err(OperationError::NotEncodedAs("JSON")) => (),
+            other => panic!("expected a not encoded as JSON error. Got: {:?}", other),
+        }
+        match client.get_json::<Profile>("json_missing") {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+    }
+}
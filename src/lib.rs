@@ -1,16 +1,91 @@
 #![allow(dead_code)]
+#[cfg(feature = "async")]
+mod async_client;
+mod binary;
+mod chunked;
+mod codec;
+mod compression;
+mod counter;
 mod errors;
+mod expiration;
+mod flags;
+mod hasher;
 mod item;
+#[cfg(feature = "serde")]
+mod json;
+mod lock;
+mod meta;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod patterns;
+mod pipeline;
+mod protocol;
+mod retry;
+mod selector;
+mod stats;
+#[cfg(feature = "tls")]
+mod tls;
+mod trace;
+mod value;
+mod value_reader;
 use crate::{
-    errors::{ConnError, OperationError, WriteReadLineError},
-    item::Item,
+    binary::{
+        OPCODE_ADD, OPCODE_DECREMENT, OPCODE_INCREMENT, OPCODE_QUIT, OPCODE_REPLACE, OPCODE_SET,
+    },
+    compression::{compress, decompress_into, decompressed},
+    errors::{timed_out, WriteReadLineError},
+    item::StoredValue,
+    protocol::Arg,
 };
+use socket2::{SockRef, TcpKeepalive};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, BufRead, Read, Write};
-use std::net::{SocketAddr, TcpStream};
-use std::str::FromStr;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "tls")]
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-const DEFAULT_NET_TIMEOUT: u32 = 500;
+#[cfg(feature = "async")]
+pub use crate::async_client::{AsyncClient, GetMultiResults};
+pub use crate::binary::ProtocolMode;
+pub use crate::codec::{RawCodec, ValueCodec};
+pub use crate::counter::Counter;
+pub use crate::errors::{ConnError, ErrorKind, GetOrSetError, KeyError, OperationError};
+pub use crate::expiration::Expiration;
+pub use crate::flags::Flags;
+pub use crate::hasher::KeyHasher;
+pub use crate::item::{Item, ItemMeta};
+#[cfg(feature = "serde")]
+pub use crate::json::JsonCodec;
+pub use crate::lock::{LockGuard, MemcacheLock};
+pub use crate::meta::{
+    ItemDebugInfo, MetaArithFlags, MetaArithMode, MetaDeleteFlags, MetaGetFlags, MetaItem,
+    MetaResult, MetaSetFlags, MetaSetMode,
+};
+pub use crate::pipeline::{Pipeline, PipelineResponse};
+pub use crate::retry::RetryPolicy;
+pub use crate::selector::{
+    AutoEject, KetamaSelector, RendezvousSelector, RoundRobinSelector, ServerList, ServerSelector,
+    ServerState,
+};
+pub use crate::stats::{ClusterStats, ServerStats};
+#[cfg(feature = "tls")]
+pub use crate::tls::ClientTlsConfig;
+pub use crate::value::{FromMemcacheValue, ToMemcacheValue, ValueType};
+pub use crate::value_reader::ValueReader;
+
+const DEFAULT_NET_TIMEOUT: Duration = Duration::from_millis(500);
 const DEFAULT_MAX_IDLE_CONNS: u8 = 2;
+const DEFAULT_MAX_IDLE_AGE: Duration = Duration::from_secs(90);
+const MAX_INCREMENT_WITH_INITIAL_ATTEMPTS: u8 = 3;
+const MAX_KEY_LEN: usize = 250;
+// The server's default item size limit (`-I 1m`)
+const DEFAULT_MAX_VALUE_SIZE: usize = 1024 * 1024;
+// Leaves room for the key and item header under the server's default 1MB item size limit
+const DEFAULT_CHUNK_SIZE: usize = 1000 * 1000;
 
 const CR_LF: &[u8] = b"\r\n";
 const RESULT_OK: &[u8] = b"OK\r\n";
@@ -21,7 +96,13 @@ const RESULT_NOT_FOUND: &[u8] = b"NOT_FOUND\r\n";
 const RESULT_DELETED: &[u8] = b"DELETED\r\n";
 const RESULT_END: &[u8] = b"END\r\n";
 const RESULT_TOUCHED: &[u8] = b"TOUCHED\r\n";
+// Answers commands the server doesn't know, e.g. the meta commands before 1.6
+const RESULT_ERROR: &[u8] = b"ERROR\r\n";
 const RESULT_CLIENT_ERROR_PREFIX: &[u8] = b"CLIENT_ERROR ";
+const RESULT_SERVER_ERROR_PREFIX: &[u8] = b"SERVER_ERROR ";
+const RESULT_VALUE_PREFIX: &[u8] = b"VALUE ";
+const RESULT_STAT_PREFIX: &[u8] = b"STAT ";
+const RESULT_VERSION_PREFIX: &[u8] = b"VERSION ";
 
 const VERB_SET: &str = "set";
 const VERB_ADD: &str = "add";
@@ -40,428 +121,4855 @@ const VERB_GATS: &str = "gats";
 const VERB_STATS: &str = "stats";
 const VERB_FLUSH_ALL: &str = "flush_all";
 const VERB_VERSION: &str = "version";
+const VERB_VERBOSITY: &str = "verbosity";
 const VERB_QUIT: &str = "quit";
 
+const NOREPLY: &str = "noreply";
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Client {
-    // Server address
-    server_addr: SocketAddr,
-    // Server connections
-    conns: Vec<Conn>,
-    // Socket read/write timeout.
-    timeout: u32,
-    // Max idle connections
+    // Maps keys onto the servers they're spread over
+    servers: Box<dyn ServerSelector>,
+    // How often server hostnames are resolved again, `None` only does so when connecting fails
+    dns_refresh_interval: Option<Duration>,
+    // Connection pool of every server, keyed by its resolved address
+    pools: HashMap<SocketAddr, ServerPool>,
+    // Settings of every new connection
+    dial_options: DialOptions,
+    // Max idle connections per server
     max_idle_cons: u8,
+    // Idle connections older than this are closed instead of being reused; Firewalls and load
+    // balancers tend to silently drop long idle connections
+    max_idle_age: Duration,
+    // Cap on the open connections of every pool, unbounded when `None`
+    max_open_conns: Option<usize>,
+    // How many other servers are tried when connecting to the one a key maps to fails, `0`
+    // (the default) disables failover
+    failover_attempts: usize,
+    // Ejects servers that keep failing to connect, off when `None`
+    auto_eject: Option<AutoEject>,
+    // How connect failures and IO errors of safe commands are retried
+    retry_policy: RetryPolicy,
+    // Prepended to every key sent, empty by default
+    key_prefix: String,
+    // Values over this many bytes are rejected before being sent
+    max_value_size: usize,
+    // Values over this many bytes are compressed, never when `None`
+    compression_threshold: Option<usize>,
+    // Largest part of a `set_chunked` value stored under one key
+    chunk_size: usize,
+    // Whether multi gets spanning servers ask them from one thread each
+    #[cfg(feature = "parallel")]
+    parallel_multi_get: bool,
+}
+
+// Connections to a single server;
+#[derive(Debug)]
+struct ServerPool {
+    // Server as configured, keys `pool_stats`
+    name: String,
+    // Idle connections, at most `max_idle_cons` of them
+    free_conns: Vec<Conn>,
+    // Live connections to the server, idle and checked out; Every `Conn` holds a handle and
+    // decrements it when dropped
+    open_conns: Arc<AtomicUsize>,
+    // Cumulative pool counters, see `PoolStats`
+    counters: PoolCounters,
+}
+
+impl ServerPool {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            free_conns: Vec::new(),
+            open_conns: Arc::new(AtomicUsize::new(0)),
+            counters: PoolCounters::default(),
+        }
+    }
+
+    fn close_idle_connections(&mut self) -> usize {
+        let closed = self.free_conns.len();
+        for mut conn in self.free_conns.drain(..) {
+            conn.close();
+        }
+        closed
+    }
+}
+
+// Connection pool health of a server;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    // Connections parked in the pool
+    pub idle: usize,
+    // Connections currently in use by an operation
+    pub checked_out: usize,
+    // Connection attempts since the client was created, failed ones included
+    pub dials: u64,
+    pub dial_failures: u64,
+    // Connections dropped after an IO error, timeout or corrupt response, or with a value left
+    // unread by a `ValueReader`
+    pub discarded: u64,
+}
+
+#[derive(Debug, Default)]
+struct PoolCounters {
+    dials: u64,
+    dial_failures: u64,
+    discarded: u64,
+}
+
+#[derive(Debug, Clone)]
+struct DialOptions {
+    // Socket connect/read/write timeout
+    timeout: Duration,
+    // Wire protocol of every connection
+    protocol: ProtocolMode,
+    // Disables Nagle's algorithm, which otherwise delays the small writes of every command
+    tcp_nodelay: bool,
+    // Idle time before TCP keepalive probes are sent, `None` leaves keepalive off
+    keepalive: Option<Duration>,
+    // Wraps every connection in TLS when set
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTlsConfig>,
 }
 
 impl Client {
-    pub fn new(server_addr: String, timeout: u32, max_idle_conns: u8) -> Result<Self, ConnError> {
-        let socket_addr = SocketAddr::from_str(&server_addr)?;
-        let tcp_stream = TcpStream::connect(socket_addr)?;
-
-        let mut server_conns: Vec<Conn> = Vec::new();
-        // NOTE: Lazily create connections or start with one?
-        let conn = Conn::new(tcp_stream).map_err(|error| {
-            ConnError::TcpConnectError(io::Error::new(
-                io::ErrorKind::Other,
-                format!("failed to create connection: {}", error),
-            ))
-        })?;
-        server_conns.push(conn);
+    // A `timeout` of `None` uses the default of 500ms;
+    pub fn new(
+        server_addr: String,
+        timeout: Option<Duration>,
+        max_idle_conns: u8,
+    ) -> Result<Self, ConnError> {
+        Client::with_protocol(server_addr, timeout, max_idle_conns, ProtocolMode::Ascii)
+    }
+
+    pub fn with_protocol(
+        server_addr: String,
+        timeout: Option<Duration>,
+        max_idle_conns: u8,
+        protocol: ProtocolMode,
+    ) -> Result<Self, ConnError> {
+        Client::with_servers(&[&server_addr], timeout, max_idle_conns, protocol)
+    }
+
+    // Spreads keys over `servers`, see `ServerList`; Every server is connected to up front;
+    pub fn with_servers(
+        servers: &[&str],
+        timeout: Option<Duration>,
+        max_idle_conns: u8,
+        protocol: ProtocolMode,
+    ) -> Result<Self, ConnError> {
+        let servers = ServerList::new(servers)?;
+        Client::new_from_selector(servers, timeout, max_idle_conns, protocol)
+    }
+
+    // Routes every key through `selector` instead of a `ServerList`, see `ServerSelector`; Every
+    // server it returns from `addrs` is connected to up front;
+    pub fn new_from_selector(
+        selector: impl ServerSelector + 'static,
+        timeout: Option<Duration>,
+        max_idle_conns: u8,
+        protocol: ProtocolMode,
+    ) -> Result<Self, ConnError> {
+        let dial_options = DialOptions {
+            timeout: Client::net_timout(timeout)?,
+            protocol,
+            tcp_nodelay: true,
+            keepalive: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        };
+
+        // Pools are named after the servers as configured when there's a list of them
+        let servers: Vec<(String, SocketAddr)> = match selector.server_list() {
+            Some(server_list) => server_list.entries(),
+            None => selector
+                .addrs()
+                .into_iter()
+                .map(|addr| (addr.to_string(), addr))
+                .collect(),
+        };
+        let mut pools = HashMap::new();
+        for (name, addr) in servers {
+            if pools.contains_key(&addr) {
+                continue;
+            }
+            let mut pool = ServerPool::new(name);
+            // NOTE: Lazily create connections or start with one?
+            let conn = Conn::dial(addr, &dial_options, &pool.open_conns)?;
+            trace::conn_dialed(addr);
+            pool.free_conns.push(conn);
+            pool.counters.dials = 1;
+            pools.insert(addr, pool);
+        }
 
         Ok(Self {
-            server_addr: socket_addr,
-            conns: server_conns,
-            timeout: Client::net_timout(timeout),
+            servers: Box::new(selector),
+            dns_refresh_interval: None,
+            pools,
+            dial_options,
             max_idle_cons: Client::max_idle_conns(max_idle_conns),
+            max_idle_age: DEFAULT_MAX_IDLE_AGE,
+            max_open_conns: None,
+            failover_attempts: 0,
+            auto_eject: None,
+            retry_policy: RetryPolicy::none(),
+            key_prefix: String::new(),
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            compression_threshold: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            #[cfg(feature = "parallel")]
+            parallel_multi_get: false,
         })
     }
 
-    pub fn ping(&mut self) -> Result<(), OperationError> {
-        // TODO: Select server
-        match self.conns[0].write_read_line(format!("{}\r\n", VERB_VERSION).as_bytes()) {
-            Ok(_) => Ok(()),
-            Err(error) => Err(OperationError::Io(error)),
+    // Routes keys through `selector` from now on, e.g. a `KetamaSelector`; Pools of servers it
+    // doesn't return from `addrs` are closed, the others are kept;
+    pub fn set_selector(&mut self, selector: impl ServerSelector + 'static) {
+        let addrs: HashSet<SocketAddr> = selector.addrs().into_iter().collect();
+        self.pools.retain(|addr, pool| {
+            if addrs.contains(addr) {
+                return true;
+            }
+            pool.close_idle_connections();
+            false
+        });
+        self.servers = Box::new(selector);
+    }
+
+    pub fn with_selector(mut self, selector: impl ServerSelector + 'static) -> Self {
+        self.set_selector(selector);
+        self
+    }
+
+    // Adds `server` to the selector, see `ServerSelector::add_server`; Keys start mapping onto it
+    // right away, its connections are dialed as needed;
+    pub fn add_server(&mut self, server: &str) -> Result<(), ConnError> {
+        let addr = self.servers.add_server(server)?;
+        self.pools
+            .entry(addr)
+            .or_insert_with(|| ServerPool::new(server.to_string()));
+        Ok(())
+    }
+
+    // Removes the server configured as `server` from the selector and closes its idle
+    // connections, returning whether it was there; Removing the last server makes keyed
+    // operations fail with `NoServers`;
+    pub fn remove_server(&mut self, server: &str) -> bool {
+        let Some(addr) = self.servers.remove_server(server) else {
+            return false;
+        };
+        // The same address may still be listed under another name
+        if !self.servers.addrs().contains(&addr) {
+            if let Some(mut pool) = self.pools.remove(&addr) {
+                pool.close_idle_connections();
+            }
         }
+        true
     }
 
-    // Abstraction `with_key_addr` missing as we only support a single server for now;
-    // TODO: Unwraps
-    pub fn get(&mut self, key: String) -> Result<Option<Item>, OperationError> {
-        if !legal_key(&key) {
-            return Err(OperationError::MalformedKey);
+    // Changes the timeout of new and pooled connections; A zero timeout is rejected as sockets
+    // would treat it as no timeout at all;
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), ConnError> {
+        let timeout = Client::net_timout(Some(timeout))?;
+        for conn in self.idle_conns_mut() {
+            conn.set_timeout(timeout)
+                .map_err(|error| ConnError::TcpConnectError(conn.addr, error))?;
         }
-        let conn = &mut self.conns[0];
-        conn.writer
-            .write_fmt(format_args!("{} {}\r\n", VERB_GET, key))
-            .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))?;
-        conn.writer
-            .flush()
-            .map_err(|error| OperationError::Io(WriteReadLineError::Flush(error)))?;
+        self.dial_options.timeout = timeout;
+        Ok(())
+    }
 
-        // Parse get response
-        let mut read_buf: Vec<u8> = Vec::new();
-        conn.reader
-            .read_until(b'\n', &mut read_buf)
-            .map_err(|error| OperationError::Io(WriteReadLineError::Read(error)))?;
-        if read_buf.as_slice() == RESULT_END {
-            return Ok(None);
+    // Changes how many idle connections are kept around per server; Lowering it closes the
+    // excess ones;
+    pub fn set_max_idle_conns(&mut self, max_idle_conns: u8) {
+        self.max_idle_cons = Client::max_idle_conns(max_idle_conns);
+        for pool in self.pools.values_mut() {
+            while pool.free_conns.len() > self.max_idle_cons as usize {
+                if let Some(mut conn) = pool.free_conns.pop() {
+                    conn.close();
+                }
+            }
         }
-        // Scan get response line
-        if read_buf.ends_with(CR_LF) {
-            read_buf.pop();
-            read_buf.pop();
+    }
+
+    // Caps the connections open to each server, idle and checked out; Once reached, operations
+    // needing a new connection fail with `PoolExhausted` rather than waiting as no connection can
+    // be returned while the client is borrowed; `None` (the default) leaves it unbounded;
+    pub fn set_max_open_conns_per_server(&mut self, max_open_conns: Option<usize>) {
+        self.max_open_conns = max_open_conns;
+    }
+
+    // When connecting to the server a key maps to fails, tries up to `attempts` of the servers
+    // following it in the list before returning the original error; Only connect failures fail
+    // over, protocol and IO errors on an established connection don't; `0` (the default) keeps
+    // every key strictly on its own server;
+    // Failover applies to single key operations, multi-key ones report the server as failed;
+    pub fn set_failover_attempts(&mut self, attempts: usize) {
+        self.failover_attempts = attempts;
+    }
+
+    // Retries transient failures as `retry_policy` allows, see `RetryPolicy`; Defaults to
+    // `RetryPolicy::none()`, which fails fast;
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    // Stops mapping keys onto a server that keeps failing to connect, see `AutoEject`; Its keys
+    // are spread over the remaining servers meanwhile, `None` (the default) reinstates every
+    // ejected server;
+    pub fn set_auto_eject(&mut self, auto_eject: Option<AutoEject>) {
+        self.auto_eject = auto_eject;
+        if let (None, Some(server_list)) = (auto_eject, self.servers.server_list()) {
+            server_list.reinstate_all();
         }
-        let mut split = read_buf.split(|&x| x == b' ');
-        let _ = split.next(); // NOTE: Ignore first token
-        let key = String::from_utf8(split.next().unwrap().to_vec()).map_err(|error| {
-            OperationError::CorruptResponse(format!("could not parse the item key: {}", error))
-        })?;
-        let flags = String::from_utf8(split.next().unwrap().to_vec()).map_err(|error| {
-            OperationError::CorruptResponse(format!("could not parse flags: {}", error))
-        })?;
-        let flags = match flags.parse::<u32>() {
-            Ok(flags) => flags,
-            Err(error) => {
-                return Err(OperationError::CorruptResponse(format!(
-                    "could not convert flags into an integer: {}",
-                    error
-                )))
-            }
-        };
+    }
 
-        let size = String::from_utf8(split.next().unwrap().to_vec()).map_err(|error| {
-            OperationError::CorruptResponse(format!("could not parse size: {}", error))
-        })?;
+    // Returns whether every server is live or ejected, keyed by server address; Servers of a
+    // selector without a `ServerList` are never ejected;
+    pub fn server_states(&self) -> HashMap<SocketAddr, ServerState> {
+        match self.servers.server_list() {
+            Some(server_list) => server_list.states().into_iter().collect(),
+            None => self
+                .servers
+                .addrs()
+                .into_iter()
+                .map(|addr| (addr, ServerState::Live))
+                .collect(),
+        }
+    }
 
-        let size = match size.parse::<u32>() {
-            Ok(size) => size,
-            Err(error) => {
-                return Err(OperationError::CorruptResponse(format!(
-                    "could parse the item value size: {}",
-                    error
-                )))
+    // Returns the pool stats of every server, keyed by the server as configured;
+    pub fn pool_stats(&self) -> HashMap<String, PoolStats> {
+        self.pools
+            .values()
+            .map(|pool| {
+                let idle = pool.free_conns.len();
+                let stats = PoolStats {
+                    idle,
+                    checked_out: pool.open_conns.load(Ordering::Relaxed).saturating_sub(idle),
+                    dials: pool.counters.dials,
+                    dial_failures: pool.counters.dial_failures,
+                    discarded: pool.counters.discarded,
+                };
+                (pool.name.clone(), stats)
+            })
+            .collect()
+    }
+
+    // Resolves server hostnames again once `interval` has passed since they were last resolved;
+    // Either way a server is resolved again when connecting to its cached address fails, and
+    // idle connections to an address the name no longer points to are closed;
+    pub fn set_dns_refresh_interval(&mut self, interval: Option<Duration>) {
+        self.dns_refresh_interval = interval;
+    }
+
+    // Changes how long a connection can sit idle in the pool before it's closed; Defaults to 90s;
+    pub fn set_max_idle_age(&mut self, max_idle_age: Duration) {
+        self.max_idle_age = max_idle_age;
+    }
+
+    // Closes the idle connections older than the max idle age, returning how many were closed;
+    // Meant to be called periodically by long-lived services, as stale connections are otherwise
+    // only noticed (and skipped) when checked out;
+    pub fn prune_idle_connections(&mut self) -> usize {
+        let max_idle_age = self.max_idle_age;
+        let mut pruned = 0;
+        for pool in self.pools.values_mut() {
+            let (stale, fresh) = pool
+                .free_conns
+                .drain(..)
+                .partition::<Vec<Conn>, _>(|conn| conn.last_used.elapsed() > max_idle_age);
+            pool.free_conns = fresh;
+            pruned += stale.len();
+            for mut conn in stale {
+                conn.close();
             }
-        };
+        }
+        pruned
+    }
 
-        let mut value_buf = vec![0; size as usize + 2];
-        conn.reader.read_exact(&mut value_buf).map_err(|error| {
-            OperationError::CorruptResponse(format!("could not read value: {}", error))
-        })?;
-        if !value_buf.ends_with(CR_LF) {
-            return Err(OperationError::CorruptResponse(
-                "corrupt get result read".to_string(),
-            ));
-        } else {
-            value_buf.pop();
-            value_buf.pop();
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self, ConnError> {
+        self.set_timeout(timeout)?;
+        Ok(self)
+    }
+
+    // Sets `TCP_NODELAY` on new and pooled connections; Enabled by default;
+    pub fn set_tcp_nodelay(&mut self, nodelay: bool) -> Result<(), ConnError> {
+        for conn in self.idle_conns_mut() {
+            conn.tcp_stream()
+                .set_nodelay(nodelay)
+                .map_err(|error| ConnError::TcpConnectError(conn.addr, error))?;
         }
+        self.dial_options.tcp_nodelay = nodelay;
+        Ok(())
+    }
 
-        // NOTE: Still missing read `END\r\n`
-        let _ = conn.reader.read_until(b'\n', &mut Vec::new());
+    pub fn with_tcp_nodelay(mut self, nodelay: bool) -> Result<Self, ConnError> {
+        self.set_tcp_nodelay(nodelay)?;
+        Ok(self)
+    }
 
-        Ok(Some(Item::new(key, value_buf, flags, 0)))
+    // Enables TCP keepalive on new and pooled connections, probing after `keepalive` of idleness;
+    // `None` (the default) turns it off;
+    // Keepalive keeps NAT and firewall state alive while connections sit in the pool and lets
+    // the kernel notice dead peers, whereas the max idle age closes connections on the client
+    // side however healthy they are; With a keepalive shorter than the idle timeout of the
+    // network in between, the max idle age can safely be raised;
+    pub fn set_keepalive(&mut self, keepalive: Option<Duration>) -> Result<(), ConnError> {
+        for conn in self.idle_conns_mut() {
+            set_stream_keepalive(conn.tcp_stream(), keepalive)
+                .map_err(|error| ConnError::TcpConnectError(conn.addr, error))?;
+        }
+        self.dial_options.keepalive = keepalive;
+        Ok(())
     }
 
-    // NOTE: Item reference?
-    pub fn add(&mut self, item: Item) -> Result<(), OperationError> {
-        Client::populate_one(&mut self.conns[0], VERB_ADD, item)
+    pub fn with_keepalive(mut self, keepalive: Option<Duration>) -> Result<Self, ConnError> {
+        self.set_keepalive(keepalive)?;
+        Ok(self)
     }
 
-    pub fn set(&mut self, item: Item) -> Result<(), OperationError> {
-        Client::populate_one(&mut self.conns[0], VERB_SET, item)
+    // Prepends `prefix` to every key sent, so services sharing the servers can't clash; Keys in
+    // returned items and maps come back without it, while servers are picked and key lengths
+    // checked by the full key, the same as clients prefixing keys by hand; Empty (the default)
+    // sends keys as given;
+    pub fn set_key_prefix(&mut self, prefix: &str) -> Result<(), ConnError> {
+        if prefix.len() >= MAX_KEY_LEN {
+            return Err(ConnError::InvalidKeyPrefix(KeyError::TooLong(prefix.len())));
+        }
+        if let Some(error) = illegal_key_byte(prefix) {
+            return Err(ConnError::InvalidKeyPrefix(error));
+        }
+        self.key_prefix = prefix.to_string();
+        Ok(())
     }
 
-    pub fn replace(&mut self, item: Item) -> Result<(), OperationError> {
-        Client::populate_one(&mut self.conns[0], VERB_REPLACE, item)
+    pub fn with_key_prefix(mut self, prefix: &str) -> Result<Self, ConnError> {
+        self.set_key_prefix(prefix)?;
+        Ok(self)
     }
 
-    pub fn append(&mut self, item: Item) -> Result<(), OperationError> {
-        Client::populate_one(&mut self.conns[0], VERB_APPEND, item)
+    // Storage commands with values over `max_value_size` bytes (after compression) fail with
+    // `ValueTooLarge` before anything is written, instead of after the server read the whole
+    // value; Defaults to 1MB, the server's default item size limit, raise it to match servers
+    // started with e.g. `-I 4m`; The server's limit also covers the key and item header, so
+    // values just under it can still be refused by the server;
+    pub fn set_max_value_size(&mut self, max_value_size: usize) {
+        self.max_value_size = max_value_size;
     }
 
-    pub fn prepend(&mut self, item: Item) -> Result<(), OperationError> {
-        Client::populate_one(&mut self.conns[0], VERB_PREPEND, item)
+    pub fn with_max_value_size(mut self, max_value_size: usize) -> Self {
+        self.set_max_value_size(max_value_size);
+        self
     }
 
-    pub fn increment(&mut self, key: String, delta: u64) -> Result<u64, OperationError> {
-        Client::incr_decr(&mut self.conns[0], VERB_INCR, key, delta)
+    // Zlib compresses values over `threshold` bytes stored by `set`, `add`, `replace`,
+    // `set_noreply`, `set_multi` and `set_with`, unless compressing doesn't make them smaller;
+    // Compressed values are flagged with a reserved flag bit and decompressed by every get
+    // whatever the threshold of the client reading them; `append` and `prepend` never compress,
+    // nor should they be used on compressed values; `None` (the default) compresses nothing;
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.compression_threshold = threshold;
     }
 
-    pub fn decrement(&mut self, key: String, delta: u64) -> Result<u64, OperationError> {
-        Client::incr_decr(&mut self.conns[0], VERB_DECR, key, delta)
+    pub fn with_compression_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.set_compression_threshold(threshold);
+        self
     }
 
-    pub fn delete(&mut self, key: String) -> Result<(), OperationError> {
-        Client::write_expectf(
-            &mut self.conns[0],
-            RESULT_DELETED,
-            format!("{} {}\r\n", VERB_DELETE, key).as_bytes(),
-        )
+    // Largest part `set_chunked` stores under a single key; Defaults to 1MB less room for the key
+    // and item header, to fit the server's default item size limit;
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
     }
 
-    // NOTE: Doesn't support optional `expiration` in seconds parameter;
-    pub fn flush_all(&mut self) -> Result<(), OperationError> {
-        Client::write_expectf(
-            &mut self.conns[0],
-            RESULT_OK,
-            format!("{}\r\n", VERB_FLUSH_ALL).as_bytes(),
-        )
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.set_chunk_size(chunk_size);
+        self
     }
 
-    pub fn delete_all(&mut self) -> Result<(), OperationError> {
-        Client::write_expectf(
-            &mut self.conns[0],
-            RESULT_OK,
-            format!("{}\r\n", VERB_FLUSH_ALL).as_bytes(),
-        )
+    // Speaks TLS to the servers from now on, `None` goes back to plain TCP; Pooled connections
+    // were dialed with the previous setting so they are closed;
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: Option<ClientTlsConfig>) {
+        self.close_idle_connections();
+        self.dial_options.tls = tls;
     }
 
-    // TODO
-    pub fn touch(&mut self, key: String, seconds: u32) -> Result<(), OperationError> {
-        todo!()
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: Option<ClientTlsConfig>) -> Self {
+        self.set_tls(tls);
+        self
     }
 
-    // TODO: returns?
-    // NOTE: Populate one what?
-    // NOTE: Why does this not use `write_read_line`?
-    fn populate_one(conn: &mut Conn, verb: &str, item: Item) -> Result<(), OperationError> {
-        if !legal_key(&item.key) {
-            return Err(OperationError::MalformedKey);
-        }
-        // NOTE: Include all in one write?
-        conn.writer
-            .write_fmt(format_args!(
-                "{} {} {} {} {}\r\n",
-                verb,
-                item.key,
-                item.flags,
-                item.expiration,
-                item.value.len(),
-            ))
-            .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))?;
-        conn.writer
-            .write_all(&item.value)
-            .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))?;
-        conn.writer
-            .write_all(b"\r\n")
-            .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))?;
-        conn.writer
-            .flush()
-            .map_err(|error| OperationError::Io(WriteReadLineError::Flush(error)))?;
-        let mut read_buf: Vec<u8> = Vec::new();
-        conn.reader
-            .read_until(b'\n', &mut read_buf)
-            .map_err(|error| OperationError::Io(WriteReadLineError::Read(error)))?;
+    // Checks every server, returning the first failure;
+    pub fn ping(&mut self) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.ping"), || {
+            for addr in self.server_addrs()? {
+                if self.dial_options.protocol == ProtocolMode::Binary {
+                    self.binary_noop(addr)?;
+                    continue;
+                }
+                self.with_conn(addr, |conn| Client::version_from_conn(conn).map(drop))?;
+            }
+            Ok(())
+        })
+    }
 
-        match read_buf.as_slice() {
-            RESULT_STORED => Ok(()),
-            RESULT_NOT_STORED => Err(OperationError::NotStored),
-            RESULT_EXISTS => Err(OperationError::CASConflict),
-            RESULT_NOT_FOUND => Err(OperationError::CacheMiss),
-            _ => Err(OperationError::CorruptResponse(format!(
-                "unexpected response from server: {}",
-                String::from_utf8(read_buf).unwrap_or_default(), // TODO: Unwrap
-            ))),
-        }
+    // Sends `quit` on every pooled connection and shuts it down; Later operations dial fresh
+    // connections;
+    pub fn close(&mut self) {
+        self.close_idle_connections();
     }
 
-    fn incr_decr(
-        conn: &mut Conn,
-        verb: &str,
-        key: String,
-        delta: u64,
-    ) -> Result<u64, OperationError> {
-        let line = conn
-            .write_read_line(format!("{} {} {}\r\n", verb, key, delta).as_bytes())
-            .map_err(OperationError::Io)?;
-        if line.as_slice() == RESULT_NOT_FOUND {
-            return Err(OperationError::CacheMiss);
-        }
-        if line.starts_with(RESULT_CLIENT_ERROR_PREFIX) {
-            let error_msg =
-                String::from_utf8(line[RESULT_CLIENT_ERROR_PREFIX.len()..&line.len() - 2].to_vec())
-                    .unwrap_or_default(); // TODO: FIX
-            return Err(OperationError::Client(error_msg));
-        }
-        String::from_utf8(line[..line.len() - 2].to_vec())
-            .map_err(|_| OperationError::CorruptResponse("invalid UTF-8 sequence".to_string()))?
-            .parse::<u64>()
-            .map_err(|_| OperationError::CorruptResponse("failed to parse integer".to_string()))
+    // Like `close`, but returns how many idle connections were closed; Configuration is left
+    // untouched so the next operation just dials a fresh connection;
+    pub fn close_idle_connections(&mut self) -> usize {
+        self.pools
+            .values_mut()
+            .map(ServerPool::close_idle_connections)
+            .sum()
     }
 
-    // NOTE: `expect` String?
-    // NOTE: Different arguments from Go's implementation;
-    fn write_expectf(
-        conn: &mut Conn,
-        expect: &[u8],
-        write_buf: &[u8],
-    ) -> Result<(), OperationError> {
-        let line = conn
-            .write_read_line(write_buf) // TODO: ?
-            .map_err(OperationError::Io)?;
+    // Returns the version reported by every server, keyed by server address;
+    pub fn version(&mut self) -> Result<HashMap<SocketAddr, String>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.version"), || {
+            let version_from_conn = match self.dial_options.protocol {
+                ProtocolMode::Ascii => Client::version_from_conn,
+                ProtocolMode::Binary => Client::binary_version,
+            };
+            let mut versions = HashMap::new();
+            let mut failures = Vec::new();
+            for addr in self.server_addrs()? {
+                match self.with_retried_conn(addr, version_from_conn) {
+                    Ok(version) => {
+                        versions.insert(addr, version);
+                    }
+                    Err(error) => failures.push((addr, error)),
+                }
+            }
+            if !failures.is_empty() {
+                return Err(OperationError::ServerFailures(failures));
+            }
+            Ok(versions)
+        })
+    }
 
-        match line.as_slice() {
-            _ if line.as_slice() == expect => Ok(()),
-            RESULT_OK => Ok(()),
-            RESULT_NOT_STORED => Err(OperationError::NotStored),
-            RESULT_EXISTS => Err(OperationError::CASConflict),
-            RESULT_NOT_FOUND => Err(OperationError::CacheMiss),
-            _ => Err(OperationError::CorruptResponse(format!(
-                "unexpected response line: {}", // TODO: Include command here `from {}`
-                String::from_utf8(line).unwrap_or_default()  // TODO: Unwrap
-            ))),
-        }
+    pub fn get(&mut self, key: &str) -> Result<Option<Item>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get", key), || {
+            let item = self.get_encoded(key)?;
+            item.map(|item| self.returned_item(item)).transpose()
+        })
     }
 
-    fn net_timout(input_value: u32) -> u32 {
-        match input_value {
-            0 => DEFAULT_NET_TIMEOUT,
-            _ => input_value,
-        }
+    // `get` appending the value to `buf` rather than allocating one, e.g. to copy it straight
+    // into a response; The value is read with `gets`, so `ItemMeta::cas_id` is set; `buf` is
+    // appended to, and left exactly as it was passed in on misses and errors;
+    pub fn get_into(
+        &mut self,
+        key: &str,
+        buf: &mut Vec<u8>,
+    ) -> Result<Option<ItemMeta>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get_into", key), || {
+            let start = buf.len();
+            let result = self.get_encoded_into(key, buf).and_then(|meta| {
+                let Some(meta) = meta else {
+                    return Ok(None);
+                };
+                let flags = Flags::from_wire(meta.flags);
+                if flags.compressed() {
+                    let compressed = buf.split_off(start);
+                    decompress_into(&compressed, buf)?;
+                }
+                Ok(Some(ItemMeta {
+                    flags: flags.user(),
+                    len: buf.len() - start,
+                    ..meta
+                }))
+            });
+            if result.is_err() {
+                buf.truncate(start);
+            }
+            result
+        })
     }
 
-    fn max_idle_conns(input_value: u8) -> u8 {
-        match input_value {
-            0 => DEFAULT_MAX_IDLE_CONNS,
-            _ => input_value,
+    // `get_into` with the flags as stored and the value as read;
+    fn get_encoded_into(
+        &mut self,
+        key: &str,
+        buf: &mut Vec<u8>,
+    ) -> Result<Option<ItemMeta>, OperationError> {
+        let key = &self.prefixed(key);
+        if self.dial_options.protocol == ProtocolMode::Binary {
+            // Binary responses are read into an `Item`, whose value is copied over
+            return Ok(self.binary_get(key, true)?.map(|item| {
+                buf.extend_from_slice(&item.value);
+                ItemMeta {
+                    flags: item.flags,
+                    cas_id: item.cas_id,
+                    len: item.value.len(),
+                }
+            }));
         }
+        legal_key(key)?;
+        let start = buf.len();
+        self.with_retried_key_conn(VERB_GETS, key, |conn| {
+            // A stale connection may have failed halfway through the value
+            buf.truncate(start);
+            Client::retrieve_into(conn, key, buf)
+        })
     }
-}
 
-#[derive(Debug)]
-struct Conn {
-    // stream: TcpStream, // NOTE: Is this needed?
-    reader: io::BufReader<TcpStream>,
-    writer: io::BufWriter<TcpStream>,
-}
+    // Reads the value of a `gets <key>` response straight into `buf`;
+    fn retrieve_into(
+        conn: &mut Conn,
+        key: &str,
+        buf: &mut Vec<u8>,
+    ) -> Result<Option<ItemMeta>, OperationError> {
+        conn.write_command(VERB_GETS, [Arg::Str(key)])?;
+        let line = conn.read_line()?;
+        let Some(header) = protocol::parse_value_header(line, VERB_GETS, true)? else {
+            return Ok(None);
+        };
+        trace::record_value_size(header.size);
+        let start = buf.len();
+        buf.resize(start + header.size, 0);
+        conn.reader.read_exact(&mut buf[start..]).map_err(|error| {
+            OperationError::CorruptResponse(format!("could not read value: {}", error))
+        })?;
+        Client::read_value_end(conn)?;
+        Ok(Some(ItemMeta {
+            flags: header.flags,
+            cas_id: header.cas_id,
+            len: header.size,
+        }))
+    }
 
-impl Conn {
-    fn new(stream: TcpStream) -> Result<Self, std::io::Error> {
-        Ok(Self {
-            reader: io::BufReader::new(stream.try_clone()?),
-            writer: io::BufWriter::new(stream),
+    // `get` returning the item with the key and flags as stored;
+    pub(crate) fn get_encoded(&mut self, key: &str) -> Result<Option<Item>, OperationError> {
+        let key = &self.prefixed(key);
+        if self.dial_options.protocol == ProtocolMode::Binary {
+            return self.binary_get(key, false);
+        }
+        legal_key(key)?;
+        self.with_retried_key_conn(VERB_GET, key, |conn| {
+            Client::retrieve_one(conn, VERB_GET, [Arg::Str(key)], false)
         })
     }
 
-    fn write_read_line(&mut self, write_buf: &[u8]) -> Result<Vec<u8>, WriteReadLineError> {
-        self.writer
-            .write_all(write_buf)
-            .map_err(WriteReadLineError::Write)?;
-        self.writer.flush().map_err(WriteReadLineError::Flush)?;
-        let mut read_buf: Vec<u8> = Vec::new();
-        self.reader
-            .read_until(b'\n', &mut read_buf)
-            .map_err(WriteReadLineError::Read)?;
-        Ok(read_buf)
+    pub fn get_and_touch(
+        &mut self,
+        key: &str,
+        expiration: Expiration,
+    ) -> Result<Option<Item>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get_and_touch", key), || {
+            self.ascii_only(VERB_GAT)?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            let exptime = expiration.wire_value()?;
+            let item = self.with_key_conn(VERB_GAT, key, |conn| {
+                let args = [Arg::Int(exptime.into()), Arg::Str(key)];
+                Client::retrieve_one(conn, VERB_GAT, args, false)
+            })?;
+            item.map(|item| self.returned_item(item)).transpose()
+        })
     }
-}
 
-fn legal_key(key: &String) -> bool {
-    if key.len() > 250 {
-        return false;
+    pub fn gats(
+        &mut self,
+        key: &str,
+        expiration: Expiration,
+    ) -> Result<Option<Item>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.gats", key), || {
+            self.ascii_only(VERB_GATS)?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            let exptime = expiration.wire_value()?;
+            let item = self.with_key_conn(VERB_GATS, key, |conn| {
+                let args = [Arg::Int(exptime.into()), Arg::Str(key)];
+                Client::retrieve_one(conn, VERB_GATS, args, true)
+            })?;
+            item.map(|item| self.returned_item(item)).transpose()
+        })
     }
-    true
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{errors::ConnError, item::Item};
+    pub fn gets(&mut self, key: &str) -> Result<Option<Item>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.gets", key), || {
+            let key = &self.prefixed(key);
+            let item = if self.dial_options.protocol == ProtocolMode::Binary {
+                self.binary_get(key, true)?
+            } else {
+                legal_key(key)?;
+                self.with_retried_key_conn(VERB_GETS, key, |conn| {
+                    Client::retrieve_one(conn, VERB_GETS, [Arg::Str(key)], true)
+                })?
+            };
+            item.map(|item| self.returned_item(item)).transpose()
+        })
+    }
 
-    use super::Client;
-    const LOCALHOST_TCP_ADDR: &str = "127.0.0.1:11211";
+    // Keys missing from the server are absent from the returned map;
+    pub fn get_multi(&mut self, keys: &[&str]) -> Result<HashMap<String, Item>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.get_multi"), || {
+            self.retrieve_multi(keys, VERB_GET, false)
+        })
+    }
 
-    #[test]
-    fn invalid_server_addr_returns_err() {
-        let result = Client::new(String::from("alksdjasld"), 0, 0);
-        match result {
-            Ok(_) => panic!("expected creation of new client to fail"),
-            Err(error) => match error {
-                ConnError::AddrParseError(_) => (), // Expected error,
-                _ => panic!("unexpected error. Got: {:?}", error),
-            },
-        };
+    // Like `get_multi` but every item carries its cas unique in `Item::cas_id`;
+    pub fn gets_multi(&mut self, keys: &[&str]) -> Result<HashMap<String, Item>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.gets_multi"), || {
+            self.retrieve_multi(keys, VERB_GETS, true)
+        })
     }
 
-    #[test]
-    fn test_local_host() {
-        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), 0, 0) {
-            Ok(client) => client,
-            Err(error) => panic!("could not connect to local server: {:?}", error),
-        };
+    fn retrieve_multi(
+        &mut self,
+        keys: &[&str],
+        verb: &str,
+        with_cas: bool,
+    ) -> Result<HashMap<String, Item>, OperationError> {
+        let keys = self.prefixed_keys(keys);
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        keys.iter().try_for_each(|key| legal_key(key))?;
+        let mut items = HashMap::new();
+        if keys.is_empty() {
+            return Ok(items);
+        }
 
-        if let Err(_) = client.ping() {
-            panic!("expected ping to succeed")
+        let mut failures = Vec::new();
+        let groups = self.group_by_server(keys.to_vec(), |key| *key)?;
+        #[cfg(feature = "parallel")]
+        let groups = match self.parallel_multi_get && groups.len() > 1 {
+            true => self.parallel_retrieve_multi(groups, verb, with_cas, &mut items, &mut failures),
+            false => groups,
+        };
+        for (addr, keys) in groups {
+            let result = self.with_retried_conn(addr, |conn| {
+                Client::retrieve_multi_from_conn(conn, verb, with_cas, &keys, &mut items)
+            });
+            if let Err(error) = result {
+                failures.push((addr, error));
+            }
+        }
+        if !failures.is_empty() {
+            return Err(OperationError::ServerFailures(failures));
         }
+        self.returned_items(items)
+    }
 
-        // NOTE: Setting `expiration` to 5 seconds so tests don't fail on subsequent runs;
-        let item_key = "color".to_string();
-        let item_value = Vec::from("red");
-        let item_flags = 32;
-        let item = Item::new(item_key.clone(), item_value.clone(), item_flags, 5);
-        if let Err(_) = client.add(item) {
-            panic!("expected item to be successfully persisted")
+    // The multi get of either protocol;
+    fn retrieve_multi_from_conn(
+        conn: &mut Conn,
+        verb: &str,
+        with_cas: bool,
+        keys: &[&str],
+        items: &mut HashMap<String, Item>,
+    ) -> Result<(), OperationError> {
+        match conn.protocol {
+            ProtocolMode::Ascii => Client::get_multi_from_conn(conn, verb, with_cas, keys, items),
+            ProtocolMode::Binary => Client::binary_get_multi_from_conn(conn, keys, with_cas, items),
         }
+    }
 
-        // NOTE: Clone?
-        let item = match client.get(item_key.clone()) {
-            Ok(item) => item,
-            Err(error) => panic!("expected item to be successfully retrieved: {}", error),
-        };
+    // NOTE: Item reference?
+    pub fn add(&mut self, item: &Item) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.add", &item.key), || {
+            Flags::from_user(item.flags)?;
+            self.add_encoded(&item.key, item.stored())
+        })
+    }
 
-        if let Some(item) = item {
-            if item.value != item_value {
-                panic!("expected value to be red")
-            }
-            if item.flags != item_flags {
-                panic!("expected flags to be 0")
-            }
-        } else {
-            panic!("expected an item")
+    // `add` of a value whose flags may carry reserved bits;
+    pub(crate) fn add_encoded(
+        &mut self,
+        key: &str,
+        stored: StoredValue,
+    ) -> Result<(), OperationError> {
+        let stored = self.compressed(stored);
+        self.check_value_size(&stored.value)?;
+        let key = &self.prefixed(key);
+        if self.dial_options.protocol == ProtocolMode::Binary {
+            return self.binary_store(OPCODE_ADD, key, &stored, 0);
         }
+        self.with_key_conn(VERB_ADD, key, |conn| {
+            Client::populate_one(conn, VERB_ADD, key, &stored, None, false)
+        })
+    }
 
-        // Test `increment` and `decrement`
-        let item_key = "number".to_string();
-        let num = 26;
-        let delta = 10;
-        let num_item = Item::new(item_key.clone(), Vec::from(num.to_string()), 0, 15);
-        if let Err(error) = client.set(num_item) {
-            panic!("did not expect set to fail: {}", error)
+    pub fn set(&mut self, item: &Item) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.set", &item.key), || {
+            Flags::from_user(item.flags)?;
+            self.set_encoded(&item.key, item.stored())
+        })
+    }
+
+    // `set` of a value whose flags may carry reserved bits;
+    pub(crate) fn set_encoded(
+        &mut self,
+        key: &str,
+        stored: StoredValue,
+    ) -> Result<(), OperationError> {
+        let stored = self.compressed(stored);
+        self.check_value_size(&stored.value)?;
+        let key = &self.prefixed(key);
+        if self.dial_options.protocol == ProtocolMode::Binary {
+            return self.binary_store(OPCODE_SET, key, &stored, 0);
         }
+        self.with_key_conn(VERB_SET, key, |conn| {
+            Client::populate_one(conn, VERB_SET, key, &stored, None, false)
+        })
+    }
 
-        match client.increment(item_key.clone(), delta) {
-            Ok(incr_num) => {
-                if incr_num != num + delta {
-                    panic!("expected incremented number ({}) to match with the initial number plus delta ({})", incr_num, num + delta)
-                }
-            }
-            Err(error) => {
-                panic!("did not expected increment to fail: {}", error)
+    pub fn replace(&mut self, item: &Item) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.replace", &item.key), || {
+            Flags::from_user(item.flags)?;
+            let stored = self.compressed(item.stored());
+            self.check_value_size(&stored.value)?;
+            let key = &self.prefixed(&item.key);
+            if self.dial_options.protocol == ProtocolMode::Binary {
+                return self.binary_store(OPCODE_REPLACE, key, &stored, 0);
             }
-        }
+            self.with_key_conn(VERB_REPLACE, key, |conn| {
+                Client::populate_one(conn, VERB_REPLACE, key, &stored, None, false)
+            })
+        })
+    }
 
-        match client.decrement(item_key.clone(), delta) {
-            Ok(incr_num) => {
-                if incr_num != num {
-                    panic!(
-                        "expected decremented number ({}) to match with the initial number ({})",
-                        incr_num, num
-                    )
+    // Stores `item` only if it wasn't changed since `item.cas_id` was read by `gets`, failing with
+    // `CASConflict` if it was and `CacheMiss` if it's gone;
+    pub fn compare_and_swap(&mut self, item: &Item) -> Result<(), OperationError> {
+        trace::in_span(
+            trace::op_span!("memcache.compare_and_swap", &item.key),
+            || {
+                Flags::from_user(item.flags)?;
+                let stored = self.compressed(item.stored());
+                self.check_value_size(&stored.value)?;
+                let key = &self.prefixed(&item.key);
+                if self.dial_options.protocol == ProtocolMode::Binary {
+                    return self.binary_store(OPCODE_SET, key, &stored, item.cas_id);
                 }
-            }
-            Err(error) => {
-                panic!("did not expected increment to fail: {}", error)
-            }
-        }
+                self.with_key_conn(VERB_CAS, key, |conn| {
+                    Client::populate_one(conn, VERB_CAS, key, &stored, Some(item.cas_id), false)
+                })
+            },
+        )
+    }
 
-        // Test `delete`
-        if let Err(error) = client.delete(item_key) {
-            panic!("Did not expect delete to fail: {}", error)
+    pub fn append(&mut self, item: &Item) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.append", &item.key), || {
+            self.ascii_only(VERB_APPEND)?;
+            Flags::from_user(item.flags)?;
+            self.check_value_size(&item.value)?;
+            let key = &self.prefixed(&item.key);
+            self.with_key_conn(VERB_APPEND, key, |conn| {
+                Client::populate_one(conn, VERB_APPEND, key, &item.stored(), None, false)
+            })
+        })
+    }
+
+    pub fn prepend(&mut self, item: &Item) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.prepend", &item.key), || {
+            self.ascii_only(VERB_PREPEND)?;
+            Flags::from_user(item.flags)?;
+            self.check_value_size(&item.value)?;
+            let key = &self.prefixed(&item.key);
+            self.with_key_conn(VERB_PREPEND, key, |conn| {
+                Client::populate_one(conn, VERB_PREPEND, key, &item.stored(), None, false)
+            })
+        })
+    }
+
+    pub fn increment(&mut self, key: &str, delta: u64) -> Result<u64, OperationError> {
+        trace::in_span(trace::op_span!("memcache.increment", key), || {
+            let key = &self.prefixed(key);
+            if self.dial_options.protocol == ProtocolMode::Binary {
+                return self.binary_incr_decr(OPCODE_INCREMENT, key, delta);
+            }
+            legal_key(key)?;
+            self.with_key_conn(VERB_INCR, key, |conn| {
+                Client::incr_decr(conn, VERB_INCR, key, delta)
+            })
+        })
+    }
+
+    // Increments the counter, creating it with `initial` when missing; When two clients race to
+    // create it only one `add` wins, the other one retries the increment on the winner's value;
+    pub fn increment_with_initial(
+        &mut self,
+        key: &str,
+        delta: u64,
+        initial: u64,
+        expiration: Expiration,
+    ) -> Result<u64, OperationError> {
+        trace::in_span(
+            trace::op_span!("memcache.increment_with_initial", key),
+            || self.arithmetic_or_add(false, key, delta, initial, expiration),
+        )
+    }
+
+    // `increment` (or `decrement`) of a counter that's created with `created` when missing;
+    pub(crate) fn arithmetic_or_add(
+        &mut self,
+        decrement: bool,
+        key: &str,
+        delta: u64,
+        created: u64,
+        expiration: Expiration,
+    ) -> Result<u64, OperationError> {
+        // `increment`, `decrement` and `add` prefix the key themselves
+        legal_key(&self.prefixed(key))?;
+        let mut attempts = 0;
+        loop {
+            let result = match decrement {
+                true => self.decrement(key, delta),
+                false => self.increment(key, delta),
+            };
+            match result {
+                Err(OperationError::CacheMiss) => (),
+                result => return result,
+            }
+            let item = Item::from_parts(key.to_string(), Vec::from(created.to_string()), 0, 0)
+                .with_expiration(expiration);
+            match self.add(&item) {
+                Ok(()) => return Ok(created),
+                // Another client created the counter first
+                Err(OperationError::NotStored) => (),
+                Err(error) => return Err(error),
+            }
+            // NOTE: The counter can expire or be deleted between the `add` and the retried `incr`
+            attempts += 1;
+            if attempts == MAX_INCREMENT_WITH_INITIAL_ATTEMPTS {
+                return Err(OperationError::CacheMiss);
+            }
+        }
+    }
+
+    pub fn decrement(&mut self, key: &str, delta: u64) -> Result<u64, OperationError> {
+        trace::in_span(trace::op_span!("memcache.decrement", key), || {
+            let key = &self.prefixed(key);
+            if self.dial_options.protocol == ProtocolMode::Binary {
+                return self.binary_incr_decr(OPCODE_DECREMENT, key, delta);
+            }
+            legal_key(key)?;
+            self.with_key_conn(VERB_DECR, key, |conn| {
+                Client::incr_decr(conn, VERB_DECR, key, delta)
+            })
+        })
+    }
+
+    pub fn delete(&mut self, key: &str) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.delete", key), || {
+            let key = &self.prefixed(key);
+            if self.dial_options.protocol == ProtocolMode::Binary {
+                return self.binary_delete(key);
+            }
+            legal_key(key)?;
+            self.with_retried_key_conn(VERB_DELETE, key, |conn| {
+                Client::write_expectf(conn, RESULT_DELETED, VERB_DELETE, [Arg::Str(key)])
+            })
+        })
+    }
+
+    // Pipelines a `set` per item with a single flush; Returns the keys that were not stored along
+    // with the reason, while connection level failures fail the whole batch;
+    pub fn set_multi(
+        &mut self,
+        items: &[Item],
+    ) -> Result<Vec<(String, OperationError)>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.set_multi"), || {
+            self.ascii_only("set_multi")?;
+            let mut pipeline = self.pipeline();
+            for item in items {
+                pipeline.set(item);
+            }
+            let results = pipeline.execute(self)?;
+            Ok(items
+                .iter()
+                .zip(results)
+                .filter_map(|(item, result)| result.err().map(|error| (item.key.clone(), error)))
+                .collect())
+        })
+    }
+
+    // Pipelines a `delete` per key with a single flush; Returns whether each key was present
+    // (deleted) or already missing;
+    pub fn delete_multi(&mut self, keys: &[&str]) -> Result<HashMap<String, bool>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.delete_multi"), || {
+            self.ascii_only("delete_multi")?;
+            self.prefixed_keys(keys)
+                .iter()
+                .try_for_each(|key| legal_key(key))?;
+            let mut pipeline = self.pipeline();
+            for key in keys {
+                pipeline.delete(key);
+            }
+            let mut deleted = HashMap::new();
+            for (key, result) in keys.iter().zip(pipeline.execute(self)?) {
+                let was_present = match result {
+                    Ok(_) => true,
+                    Err(OperationError::CacheMiss) => false,
+                    Err(error) => return Err(error),
+                };
+                deleted.insert(key.to_string(), was_present);
+            }
+            Ok(deleted)
+        })
+    }
+
+    // Pipelines a `touch` per key with a single flush; Returns the keys the server didn't have so
+    // the caller can re-populate them;
+    pub fn touch_multi(
+        &mut self,
+        keys: &[&str],
+        expiration: Expiration,
+    ) -> Result<HashSet<String>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.touch_multi"), || {
+            self.ascii_only("touch_multi")?;
+            expiration.wire_value()?;
+            self.prefixed_keys(keys)
+                .iter()
+                .try_for_each(|key| legal_key(key))?;
+            let mut pipeline = self.pipeline();
+            for key in keys {
+                pipeline.touch(key, expiration);
+            }
+            let mut missing = HashSet::new();
+            for (key, result) in keys.iter().zip(pipeline.execute(self)?) {
+                match result {
+                    Ok(_) => (),
+                    Err(OperationError::CacheMiss) => {
+                        missing.insert(key.to_string());
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            Ok(missing)
+        })
+    }
+
+    // The `*_noreply` variants ask the server not to answer and return as soon as the command is
+    // written; Failures (e.g. a set that wasn't stored or a delete of a missing key) are silent;
+    pub fn set_noreply(&mut self, item: &Item) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.set_noreply", &item.key), || {
+            self.ascii_only("set_noreply")?;
+            Flags::from_user(item.flags)?;
+            let stored = self.compressed(item.stored());
+            self.check_value_size(&stored.value)?;
+            let key = &self.prefixed(&item.key);
+            self.with_key_conn(VERB_SET, key, |conn| {
+                Client::populate_one(conn, VERB_SET, key, &stored, None, true)
+            })
+        })
+    }
+
+    pub fn delete_noreply(&mut self, key: &str) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.delete_noreply", key), || {
+            self.ascii_only("delete_noreply")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            self.with_key_conn(VERB_DELETE, key, |conn| {
+                conn.write_command(VERB_DELETE, [Arg::Str(key), Arg::Str(NOREPLY)])
+                    .map_err(OperationError::Io)
+            })
+        })
+    }
+
+    pub fn touch_noreply(
+        &mut self,
+        key: &str,
+        expiration: Expiration,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.touch_noreply", key), || {
+            self.ascii_only("touch_noreply")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            let exptime = expiration.wire_value()?;
+            self.with_key_conn(VERB_TOUCH, key, |conn| {
+                let args = [Arg::Str(key), Arg::Int(exptime.into()), Arg::Str(NOREPLY)];
+                conn.write_command(VERB_TOUCH, args)
+                    .map_err(OperationError::Io)
+            })
+        })
+    }
+
+    // Returns the `STAT <name> <value>` pairs reported by every server, keyed by server address;
+    pub fn stats(
+        &mut self,
+    ) -> Result<HashMap<SocketAddr, HashMap<String, String>>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.stats"), || {
+            self.ascii_only(VERB_STATS)?;
+            self.stats_command(&[])
+        })
+    }
+
+    // Sends `stats <arg>` (e.g. `items`, `slabs` or `sizes`); Reads are bound by the client
+    // timeout, so slow sub-commands such as `stats sizes` fail with a timeout instead of hanging;
+    pub fn stats_with_arg(
+        &mut self,
+        arg: &str,
+    ) -> Result<HashMap<SocketAddr, HashMap<String, String>>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.stats_with_arg"), || {
+            self.ascii_only(VERB_STATS)?;
+            if arg.is_empty() || arg.bytes().any(|byte| byte == b'\r' || byte == b'\n') {
+                return Err(OperationError::Client(format!(
+                    "invalid stats argument: {:?}",
+                    arg
+                )));
+            }
+            self.stats_command(&[Arg::Str(arg)])
+        })
+    }
+
+    // `stats` of every server parsed into a `ServerStats`;
+    pub fn server_stats(&mut self) -> Result<HashMap<SocketAddr, ServerStats>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.server_stats"), || {
+            Ok(self
+                .stats()?
+                .into_iter()
+                .map(|(addr, stats)| (addr, ServerStats::from_stats(stats)))
+                .collect())
+        })
+    }
+
+    // Totals of the `stats` of every server; Servers that fail are reported in `failures` and
+    // left out, it only fails with `ServerFailures` when none of them answered;
+    pub fn aggregate_stats(&mut self) -> Result<ClusterStats, OperationError> {
+        trace::in_span(trace::op_span!("memcache.aggregate_stats"), || {
+            self.ascii_only(VERB_STATS)?;
+            let mut failures = Vec::new();
+            let stats = self.stats_of_each_server(&[], &mut failures)?;
+            if stats.is_empty() {
+                return Err(OperationError::ServerFailures(failures));
+            }
+            let stats: Vec<ServerStats> =
+                stats.into_values().map(ServerStats::from_stats).collect();
+            Ok(ClusterStats {
+                failures,
+                ..ClusterStats::from_servers(&stats)
+            })
+        })
+    }
+
+    // Invalidates all items on every server, after `delay` seconds if provided; Every server is
+    // attempted and the ones that failed are reported together;
+    pub fn flush_all(&mut self, delay: Option<u32>) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.flush_all"), || {
+            self.ascii_only(VERB_FLUSH_ALL)?;
+            let delay = delay.map(|delay| Arg::Uint(delay.into()));
+            self.broadcast_expect(RESULT_OK, VERB_FLUSH_ALL, delay.as_slice())
+        })
+    }
+
+    // Sets the logging verbosity of every server; Every server is attempted and the ones that
+    // failed are reported together;
+    pub fn verbosity(&mut self, level: u32) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.verbosity"), || {
+            self.ascii_only(VERB_VERBOSITY)?;
+            self.broadcast_expect(RESULT_OK, VERB_VERBOSITY, &[Arg::Uint(level.into())])
+        })
+    }
+
+    pub fn delete_all(&mut self) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.delete_all"), || {
+            self.flush_all(None)
+        })
+    }
+
+    pub fn touch(&mut self, key: &str, expiration: Expiration) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.touch", key), || {
+            let key = &self.prefixed(key);
+            let exptime = expiration.wire_value()?;
+            if self.dial_options.protocol == ProtocolMode::Binary {
+                return self.binary_touch(key, exptime);
+            }
+            legal_key(key)?;
+            self.with_retried_key_conn(VERB_TOUCH, key, |conn| {
+                let args = [Arg::Str(key), Arg::Int(exptime.into())];
+                Client::write_expectf(conn, RESULT_TOUCHED, VERB_TOUCH, args)
+            })
+        })
+    }
+
+    fn retrieve_one<'a>(
+        conn: &mut Conn,
+        verb: &str,
+        args: impl IntoIterator<Item = Arg<'a>>,
+        with_cas: bool,
+    ) -> Result<Option<Item>, OperationError> {
+        conn.write_command(verb, args)?;
+
+        let mut item = None;
+        Client::parse_get_response(conn, verb, with_cas, |found| item = Some(found))?;
+        Ok(item)
+    }
+
+    fn get_multi_from_conn(
+        conn: &mut Conn,
+        verb: &str,
+        with_cas: bool,
+        keys: &[&str],
+        items: &mut HashMap<String, Item>,
+    ) -> Result<(), OperationError> {
+        conn.write_command(verb, keys.iter().map(|key| Arg::Str(key)))?;
+
+        Client::parse_get_response(conn, verb, with_cas, |item| {
+            items.insert(item.key.clone(), item);
+        })
+    }
+
+    // Parses `VALUE` blocks until `END\r\n`, handing every item to `cb`;
+    fn parse_get_response(
+        conn: &mut Conn,
+        verb: &str,
+        with_cas: bool,
+        mut cb: impl FnMut(Item),
+    ) -> Result<(), OperationError> {
+        loop {
+            let line = conn.read_line()?;
+            let Some(header) = protocol::parse_value_header(line, verb, with_cas)? else {
+                return Ok(());
+            };
+            trace::record_value_size(header.size);
+            let mut value_buf = vec![0; header.size + 2];
+            conn.reader.read_exact(&mut value_buf).map_err(|error| {
+                OperationError::CorruptResponse(format!("could not read value: {}", error))
+            })?;
+            cb(protocol::value_item(header, value_buf)?);
+        }
+    }
+
+    // Everything but the value size that can fail before a storage command is written, so one
+    // bad item of a batch doesn't abort the others;
+    fn check_storable(&self, key: &str, item: &Item) -> Result<(), OperationError> {
+        legal_key(key)?;
+        Flags::from_user(item.flags)?;
+        item.expiration.wire_value()?;
+        Ok(())
+    }
+
+    // `stored` compressed if it's over the compression threshold and compressing shrinks it;
+    fn compressed<'a>(&self, stored: StoredValue<'a>) -> StoredValue<'a> {
+        match self.compression_threshold {
+            Some(threshold) if stored.value.len() > threshold => match compress(&stored.value) {
+                Some(value) => StoredValue {
+                    value: Cow::Owned(value),
+                    flags: Flags::from_wire(stored.flags).with_compressed(true).wire(),
+                    ..stored
+                },
+                None => stored,
+            },
+            _ => stored,
+        }
+    }
+
+    fn check_value_size(&self, value: &[u8]) -> Result<(), OperationError> {
+        self.check_value_len(value.len())
+    }
+
+    fn check_value_len(&self, len: usize) -> Result<(), OperationError> {
+        if len > self.max_value_size {
+            return Err(OperationError::ValueTooLarge {
+                size: len,
+                limit: self.max_value_size,
+            });
+        }
+        Ok(())
+    }
+
+    // TODO: returns?
+    // NOTE: Populate one what?
+    // NOTE: Why does this not use `write_read_line`?
+    // With `noreply` the server sends no response, so nothing is read back;
+    // `key` is the key of the value as sent, i.e. prefixed;
+    fn populate_one(
+        conn: &mut Conn,
+        verb: &str,
+        key: &str,
+        stored: &StoredValue,
+        cas: Option<u64>,
+        noreply: bool,
+    ) -> Result<(), OperationError> {
+        legal_key(key)?;
+        trace::record_value_size(stored.value.len());
+        protocol::write_storage_command(&mut conn.writer, verb, key, stored, cas, noreply)?;
+        conn.writer
+            .flush()
+            .map_err(|error| OperationError::Io(WriteReadLineError::Flush(error)))?;
+        if noreply {
+            return Ok(());
+        }
+        Client::read_storage_response(conn, verb)
+    }
+
+    fn read_storage_response(conn: &mut Conn, verb: &str) -> Result<(), OperationError> {
+        protocol::storage_result(conn.read_line()?, verb)
+    }
+
+    // Sends the command to every server expecting `expect` back, collecting the failures;
+    fn broadcast_expect(
+        &mut self,
+        expect: &[u8],
+        verb: &str,
+        args: &[Arg<'_>],
+    ) -> Result<(), OperationError> {
+        let mut failures = Vec::new();
+        for addr in self.server_addrs()? {
+            let result = self.with_conn(addr, |conn| {
+                Client::write_expectf(conn, expect, verb, args.iter().copied())
+            });
+            if let Err(error) = result {
+                failures.push((addr, error));
+            }
+        }
+        if !failures.is_empty() {
+            return Err(OperationError::ServerFailures(failures));
+        }
+        Ok(())
+    }
+
+    fn stats_command(
+        &mut self,
+        args: &[Arg<'_>],
+    ) -> Result<HashMap<SocketAddr, HashMap<String, String>>, OperationError> {
+        let mut failures = Vec::new();
+        let stats = self.stats_of_each_server(args, &mut failures)?;
+        if !failures.is_empty() {
+            return Err(OperationError::ServerFailures(failures));
+        }
+        Ok(stats)
+    }
+
+    // The stats of the servers that answered, the errors of those that didn't are pushed to
+    // `failures`;
+    fn stats_of_each_server(
+        &mut self,
+        args: &[Arg<'_>],
+        failures: &mut Vec<(SocketAddr, OperationError)>,
+    ) -> Result<HashMap<SocketAddr, HashMap<String, String>>, OperationError> {
+        let mut stats = HashMap::new();
+        for addr in self.server_addrs()? {
+            match self.with_retried_conn(addr, |conn| Client::stats_from_conn(conn, args)) {
+                Ok(server_stats) => {
+                    stats.insert(addr, server_stats);
+                }
+                Err(error) => failures.push((addr, error)),
+            }
+        }
+        Ok(stats)
+    }
+
+    fn version_from_conn(conn: &mut Conn) -> Result<String, OperationError> {
+        conn.write_command(VERB_VERSION, [])?;
+        protocol::version_result(conn.read_line()?)
+    }
+
+    // Reads `STAT <name> <value>\r\n` lines until `END\r\n`;
+    fn stats_from_conn(
+        conn: &mut Conn,
+        args: &[Arg<'_>],
+    ) -> Result<HashMap<String, String>, OperationError> {
+        conn.write_command(VERB_STATS, args.iter().copied())?;
+        let mut stats = HashMap::new();
+        while let Some((name, value)) = protocol::parse_stat_line(conn.read_line()?)? {
+            stats.insert(name, value);
+        }
+        if stats.is_empty() {
+            return Err(OperationError::NoStats);
+        }
+        Ok(stats)
+    }
+
+    fn incr_decr(
+        conn: &mut Conn,
+        verb: &str,
+        key: &str,
+        delta: u64,
+    ) -> Result<u64, OperationError> {
+        conn.write_command(verb, [Arg::Str(key), Arg::Uint(delta)])?;
+        protocol::incr_decr_result(conn.read_line()?, verb)
+    }
+
+    // NOTE: Different arguments from Go's implementation;
+    fn write_expectf<'a>(
+        conn: &mut Conn,
+        expect: &[u8],
+        verb: &str,
+        args: impl IntoIterator<Item = Arg<'a>>,
+    ) -> Result<(), OperationError> {
+        conn.write_command(verb, args)?;
+        protocol::expected_result(conn.read_line()?, verb, expect)
+    }
+
+    // Runs `f` on a connection to `addr` checked out of the pool, returning it afterwards;
+    fn with_conn<T>(
+        &mut self,
+        addr: SocketAddr,
+        f: impl FnOnce(&mut Conn) -> Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        let mut conn = self.get_conn(addr)?;
+        trace::record_addr(conn.addr);
+        let result = f(&mut conn);
+        self.release_conn(conn, result)
+    }
+
+    // Like `with_conn` but on the server `key` maps to, see `set_failover_attempts`;
+    // Errors on the connection name `verb`, `key` and the server, see `WithContext`;
+    fn with_key_conn<T>(
+        &mut self,
+        verb: &str,
+        key: &str,
+        f: impl FnOnce(&mut Conn) -> Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        let addr = self.pick_server(key)?;
+        let mut conn = match self.get_free_conn(addr) {
+            Some(conn) => conn,
+            None => self.dial_key_conn(addr, Instant::now())?,
+        };
+        let addr = conn.addr;
+        trace::record_addr(addr);
+        let result = f(&mut conn);
+        self.release_conn(conn, result)
+            .map_err(|error| error.with_context(verb, key, addr))
+    }
+
+    // Like `with_conn` but for commands that are safe to resend; The server can close a pooled
+    // connection between checkout and write, which only shows up as an IO error once the command
+    // is sent, so it's retried once on a freshly dialed connection; IO errors and timeouts on
+    // fresh connections are only retried as the retry policy allows;
+    fn with_retried_conn<T>(
+        &mut self,
+        addr: SocketAddr,
+        f: impl FnMut(&mut Conn) -> Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        self.retry_on_stale_conn(addr, Client::dial_conn, f)
+    }
+
+    fn with_retried_key_conn<T>(
+        &mut self,
+        verb: &str,
+        key: &str,
+        mut f: impl FnMut(&mut Conn) -> Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        let addr = self.pick_server(key)?;
+        // Failover may have moved the key to another server
+        let mut used = addr;
+        self.retry_on_stale_conn(addr, Client::dial_key_conn, |conn| {
+            used = conn.addr;
+            trace::record_addr(used);
+            f(conn)
+        })
+        .map_err(|error| error.with_context(verb, key, used))
+    }
+
+    fn retry_on_stale_conn<T>(
+        &mut self,
+        addr: SocketAddr,
+        dial: fn(&mut Self, SocketAddr, Instant) -> Result<Conn, OperationError>,
+        mut f: impl FnMut(&mut Conn) -> Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        let started = Instant::now();
+        if let Some(mut conn) = self.get_free_conn(addr) {
+            match f(&mut conn) {
+                Err(OperationError::Io(error)) if !error.is_timeout() => self.discard_conn(conn),
+                result => return self.release_conn(conn, result),
+            }
+        }
+        let mut attempts = 1;
+        loop {
+            let mut conn = dial(self, addr, started)?;
+            let result = f(&mut conn);
+            let error = match self.release_conn(conn, result) {
+                Err(error @ (OperationError::Io(_) | OperationError::Timeout(_))) => error,
+                result => return result,
+            };
+            let Some(delay) = self.retry_policy.next_delay(attempts, started) else {
+                return Err(error);
+            };
+            std::thread::sleep(delay);
+            attempts += 1;
+        }
+    }
+
+    // Returns `conn` to the pool unless the error is fatal to it, see
+    // `OperationError::fatal_to_connection`, in which case it's dropped;
+    fn release_conn<T>(
+        &mut self,
+        conn: Conn,
+        result: Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        match &result {
+            Err(error) if error.fatal_to_connection() => self.discard_conn(conn),
+            _ => self.put_free_conn(conn),
+        }
+        match result {
+            Err(OperationError::Io(error)) if error.is_timeout() => {
+                Err(OperationError::Timeout(timed_out(error.into_inner())))
+            }
+            result => result,
+        }
+    }
+
+    // Aborts `conn`, counting it as discarded by its pool; Whatever is left in its write buffer,
+    // e.g. half a command cut short by an error, is dropped rather than flushed after the caller
+    // was told the command failed, and no `quit` is sent where a value may be expected;
+    fn discard_conn(&mut self, mut conn: Conn) {
+        conn.abort();
+        trace::conn_discarded(conn.addr);
+        if let Some(pool) = self.pools.get_mut(&conn.addr) {
+            pool.counters.discarded += 1;
+        }
+    }
+
+    // `key` with the key prefix prepended, see `set_key_prefix`;
+    fn prefixed<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if self.key_prefix.is_empty() {
+            return Cow::Borrowed(key);
+        }
+        Cow::Owned(format!("{}{}", self.key_prefix, key))
+    }
+
+    fn prefixed_keys<'a>(&self, keys: &[&'a str]) -> Vec<Cow<'a, str>> {
+        keys.iter().map(|key| self.prefixed(key)).collect()
+    }
+
+    // Strips the key prefix off a key read back from the server;
+    fn unprefixed(&self, key: String) -> String {
+        match key.strip_prefix(self.key_prefix.as_str()) {
+            Some(unprefixed) if !self.key_prefix.is_empty() => unprefixed.to_string(),
+            _ => key,
+        }
+    }
+
+    // Items are read with the key, value and flags as stored and handed back without the key
+    // prefix, decompressed and without the flags the client reserved;
+    fn returned_item(&self, item: Item) -> Result<Item, OperationError> {
+        let item = decompressed(item)?;
+        Ok(Item {
+            key: self.unprefixed(item.key),
+            flags: Flags::from_wire(item.flags).user(),
+            ..item
+        })
+    }
+
+    fn returned_items(
+        &self,
+        items: HashMap<String, Item>,
+    ) -> Result<HashMap<String, Item>, OperationError> {
+        items
+            .into_values()
+            .map(|item| {
+                let item = self.returned_item(item)?;
+                Ok((item.key.clone(), item))
+            })
+            .collect()
+    }
+
+    // Maps `key` onto a server, resolving the servers due for a DNS refresh first;
+    fn pick_server(&mut self, key: &str) -> Result<SocketAddr, OperationError> {
+        self.update_servers();
+        self.servers.pick_server(key)
+    }
+
+    // Addresses of every server `ServerSelector::each` goes through, once each, for commands
+    // sent to all of them;
+    fn server_addrs(&mut self) -> Result<Vec<SocketAddr>, OperationError> {
+        self.update_servers();
+        let mut addrs = Vec::new();
+        let mut seen = HashSet::new();
+        self.servers.each(&mut |addr| {
+            if seen.insert(addr) {
+                addrs.push(addr);
+            }
+            Ok(())
+        })?;
+        Ok(addrs)
+    }
+
+    // Groups `entries` by the server their key maps to;
+    fn group_by_server<T>(
+        &mut self,
+        entries: Vec<T>,
+        key: impl Fn(&T) -> &str,
+    ) -> Result<HashMap<SocketAddr, Vec<T>>, OperationError> {
+        self.update_servers();
+        let mut groups: HashMap<SocketAddr, Vec<T>> = HashMap::new();
+        for entry in entries {
+            let addr = self.servers.pick_server(key(&entry))?;
+            groups.entry(addr).or_default().push(entry);
+        }
+        Ok(groups)
+    }
+
+    // Brings the server list up to date before keys are mapped onto it;
+    fn update_servers(&mut self) {
+        self.refresh_due_server_addrs();
+        self.probe_ejected_servers();
+    }
+
+    fn refresh_due_server_addrs(&mut self) {
+        let Some(interval) = self.dns_refresh_interval else {
+            return;
+        };
+        let due = self
+            .servers
+            .server_list()
+            .map_or_else(Vec::new, |server_list| {
+                server_list.resolved_before(interval)
+            });
+        for addr in due {
+            self.refresh_server_addr(addr);
+        }
+    }
+
+    // Reinstates the servers whose ejection window is over once they answer a `version`, the ones
+    // that don't are ejected for another window;
+    fn probe_ejected_servers(&mut self) {
+        let Some(auto_eject) = self.auto_eject else {
+            return;
+        };
+        let version_from_conn = match self.dial_options.protocol {
+            ProtocolMode::Ascii => Client::version_from_conn,
+            ProtocolMode::Binary => Client::binary_version,
+        };
+        let due = self
+            .servers
+            .server_list()
+            .map_or_else(Vec::new, ServerList::probes_due);
+        for addr in due {
+            let probe = self.with_conn(addr, version_from_conn);
+            let Some(server_list) = self.servers.server_list() else {
+                return;
+            };
+            match probe {
+                Ok(_) => server_list.reinstate(addr),
+                Err(_) => server_list.eject(addr, auto_eject.window),
+            }
+        }
+    }
+
+    // Takes an idle connection to `addr` out of the pool, dialing a new one if there are none;
+    fn get_conn(&mut self, addr: SocketAddr) -> Result<Conn, OperationError> {
+        match self.get_free_conn(addr) {
+            Some(conn) => Ok(conn),
+            None => self.dial_conn(addr, Instant::now()),
+        }
+    }
+
+    // Pops the most recently used idle connection that is still usable, closing stale ones;
+    fn get_free_conn(&mut self, addr: SocketAddr) -> Option<Conn> {
+        while let Some(mut conn) = self.pools.get_mut(&addr)?.free_conns.pop() {
+            if conn.last_used.elapsed() <= self.max_idle_age && conn.is_alive() {
+                trace::conn_reused(addr);
+                return Some(conn);
+            }
+            // Nothing is in flight on an idle connection, so it can be closed cleanly
+            conn.close();
+            self.discard_conn(conn);
+        }
+        None
+    }
+
+    // Like `dial_conn`, but with failover enabled a connect failure moves on to the servers
+    // following `addr`, returning the original error if none of them can be reached either;
+    fn dial_key_conn(
+        &mut self,
+        addr: SocketAddr,
+        started: Instant,
+    ) -> Result<Conn, OperationError> {
+        let error = match self.dial_conn(addr, started) {
+            Err(error) if self.failover_attempts > 0 && is_connect_error(&error) => error,
+            result => return result,
+        };
+        let alternates = self
+            .servers
+            .server_list()
+            .map_or_else(Vec::new, |server_list| server_list.failover_addrs(addr));
+        for alternate in alternates.into_iter().take(self.failover_attempts) {
+            let conn = match self.get_free_conn(alternate) {
+                Some(conn) => Ok(conn),
+                None => self.dial_conn(alternate, started),
+            };
+            if let Ok(conn) = conn {
+                return Ok(conn);
+            }
+        }
+        Err(error)
+    }
+
+    // Dials `addr`, reconnecting as the retry policy allows for an operation that began at
+    // `started`;
+    fn dial_conn(
+        &mut self,
+        mut addr: SocketAddr,
+        started: Instant,
+    ) -> Result<Conn, OperationError> {
+        if let Some(max_open_conns) = self.max_open_conns {
+            let open_conns = self
+                .pools
+                .get(&addr)
+                .map_or(0, |pool| pool.open_conns.load(Ordering::Relaxed));
+            if open_conns >= max_open_conns {
+                return Err(OperationError::PoolExhausted(addr));
+            }
+        }
+        let mut attempts = 1;
+        loop {
+            let mut error = match self.dial_server_addr(addr) {
+                Err(error @ (OperationError::Connect(..) | OperationError::ConnectTimeout(..))) => {
+                    error
+                }
+                result => return result,
+            };
+            // The hostname may point somewhere else by now
+            if let Some(new_addr) = self.refresh_server_addr(addr) {
+                addr = new_addr;
+                error = match self.dial_server_addr(addr) {
+                    Err(
+                        error @ (OperationError::Connect(..) | OperationError::ConnectTimeout(..)),
+                    ) => error,
+                    result => return result,
+                };
+            }
+            let Some(delay) = self.retry_policy.next_delay(attempts, started) else {
+                return Err(error);
+            };
+            std::thread::sleep(delay);
+            attempts += 1;
+        }
+    }
+
+    // Resolves the server at `addr` again, returning its new address if it moved; Literal
+    // addresses never do, and a failed lookup keeps the cached address; The pool follows the
+    // server, minus its idle connections to the old address;
+    fn refresh_server_addr(&mut self, addr: SocketAddr) -> Option<SocketAddr> {
+        let new_addr = self.servers.server_list()?.resolve_again(addr)?;
+        self.move_pool(addr, new_addr);
+        Some(new_addr)
+    }
+
+    fn move_pool(&mut self, from: SocketAddr, to: SocketAddr) {
+        if let Some(mut pool) = self.pools.remove(&from) {
+            pool.close_idle_connections();
+            self.pools.entry(to).or_insert(pool);
+        }
+    }
+
+    fn dial_server_addr(&mut self, addr: SocketAddr) -> Result<Conn, OperationError> {
+        let pool = self
+            .pools
+            .entry(addr)
+            .or_insert_with(|| ServerPool::new(addr.to_string()));
+        pool.counters.dials += 1;
+        let conn =
+            Conn::dial(addr, &self.dial_options, &pool.open_conns).map_err(|error| match error {
+                ConnError::TcpConnectError(_, error)
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                    ) =>
+                {
+                    OperationError::ConnectTimeout(addr, timed_out(error))
+                }
+                ConnError::TcpConnectError(_, error) => OperationError::Connect(addr, error),
+                #[cfg(feature = "tls")]
+                ConnError::TlsHandshake(_, error) => OperationError::TlsHandshake(addr, error),
+                error => OperationError::Connect(addr, io::Error::other(error.to_string())),
+            });
+        match (&conn, self.auto_eject, self.servers.server_list()) {
+            (Ok(_), _, Some(server_list)) => server_list.record_connect(addr),
+            (Err(error), Some(auto_eject), Some(server_list)) if is_connect_error(error) => {
+                server_list.record_connect_failure(addr, auto_eject)
+            }
+            _ => (),
+        }
+        match &conn {
+            Ok(_) => trace::conn_dialed(addr),
+            Err(_) => pool.counters.dial_failures += 1,
+        }
+        conn
+    }
+
+    // Parks `conn` in the pool of its server, or closes it if there are already `max_idle_cons`
+    // idle ones or the server has since moved to another address;
+    fn put_free_conn(&mut self, mut conn: Conn) {
+        match self.pools.get_mut(&conn.addr) {
+            Some(pool) if pool.free_conns.len() < self.max_idle_cons as usize => {
+                conn.last_used = Instant::now();
+                pool.free_conns.push(conn);
+            }
+            _ => conn.close(),
+        }
+    }
+
+    fn idle_conns_mut(&mut self) -> impl Iterator<Item = &mut Conn> {
+        self.pools
+            .values_mut()
+            .flat_map(|pool| pool.free_conns.iter_mut())
+    }
+
+    fn net_timout(input_value: Option<Duration>) -> Result<Duration, ConnError> {
+        match input_value {
+            None => Ok(DEFAULT_NET_TIMEOUT),
+            Some(Duration::ZERO) => Err(ConnError::InvalidTimeout),
+            Some(timeout) => Ok(timeout),
+        }
+    }
+
+    fn max_idle_conns(input_value: u8) -> u8 {
+        match input_value {
+            0 => DEFAULT_MAX_IDLE_CONNS,
+            _ => input_value,
+        }
+    }
+}
+
+// Transport under a connection's reader and writer; Each side holds its own handle to the same
+// socket, a TLS session is shared behind a mutex as reads and writes both advance its state
+#[derive(Debug)]
+enum ConnStream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls {
+        session: Arc<Mutex<tls::TlsStream>>,
+        // Socket under the session, kept around for socket options and shutdowns
+        tcp: TcpStream,
+    },
+}
+
+impl ConnStream {
+    fn try_clone(&self) -> Result<Self, std::io::Error> {
+        Ok(match self {
+            ConnStream::Plain(stream) => ConnStream::Plain(stream.try_clone()?),
+            #[cfg(feature = "tls")]
+            ConnStream::Tls { session, tcp } => ConnStream::Tls {
+                session: session.clone(),
+                tcp: tcp.try_clone()?,
+            },
+        })
+    }
+
+    fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            ConnStream::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            ConnStream::Tls { tcp, .. } => tcp,
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+fn lock_session(session: &Mutex<tls::TlsStream>) -> std::sync::MutexGuard<'_, tls::TlsStream> {
+    // A panic mid read or write leaves nothing that the next call can't cope with
+    session
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Read for ConnStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ConnStream::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            ConnStream::Tls { session, .. } => lock_session(session).read(buf),
+        }
+    }
+}
+
+impl Write for ConnStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ConnStream::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            ConnStream::Tls { session, .. } => lock_session(session).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ConnStream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            ConnStream::Tls { session, .. } => lock_session(session).flush(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Conn {
+    reader: io::BufReader<ConnStream>,
+    writer: io::BufWriter<ConnStream>,
+    protocol: ProtocolMode,
+    // Server the connection was dialed to, whose pool it goes back to
+    addr: SocketAddr,
+    // When the connection was last returned to the pool
+    last_used: Instant,
+    // Open connections count of the server pool, decremented on drop
+    open_conns: Arc<AtomicUsize>,
+    // Set once `quit` was sent and the stream shut down
+    closed: bool,
+    // Holds the last response line read, see `read_line`; Keeps its capacity between calls so
+    // reading a line doesn't allocate once the connection has warmed up
+    line_buf: Vec<u8>,
+}
+
+impl Conn {
+    fn new(
+        stream: ConnStream,
+        protocol: ProtocolMode,
+        addr: SocketAddr,
+        open_conns: &Arc<AtomicUsize>,
+    ) -> Result<Self, std::io::Error> {
+        let reader = io::BufReader::new(stream.try_clone()?);
+        open_conns.fetch_add(1, Ordering::Relaxed);
+        Ok(Self {
+            reader,
+            writer: io::BufWriter::new(stream),
+            protocol,
+            addr,
+            last_used: Instant::now(),
+            open_conns: open_conns.clone(),
+            closed: false,
+            line_buf: Vec::new(),
+        })
+    }
+
+    fn dial(
+        addr: SocketAddr,
+        options: &DialOptions,
+        open_conns: &Arc<AtomicUsize>,
+    ) -> Result<Self, ConnError> {
+        let connect_error = |error| ConnError::TcpConnectError(addr, error);
+        // A blackholed server would otherwise hang the caller for the OS connect timeout
+        let stream = TcpStream::connect_timeout(&addr, options.timeout).map_err(connect_error)?;
+        stream
+            .set_nodelay(options.tcp_nodelay)
+            .map_err(connect_error)?;
+        set_stream_keepalive(&stream, options.keepalive).map_err(connect_error)?;
+        // The handshake is bounded by the same timeout as any other read or write
+        stream
+            .set_read_timeout(Some(options.timeout))
+            .map_err(connect_error)?;
+        stream
+            .set_write_timeout(Some(options.timeout))
+            .map_err(connect_error)?;
+        #[cfg(feature = "tls")]
+        let stream = match &options.tls {
+            Some(tls) => {
+                let tcp = stream.try_clone().map_err(connect_error)?;
+                let session = tls
+                    .connect(stream)
+                    .map_err(|error| ConnError::TlsHandshake(addr, error))?;
+                ConnStream::Tls {
+                    session: Arc::new(Mutex::new(session)),
+                    tcp,
+                }
+            }
+            None => ConnStream::Plain(stream),
+        };
+        #[cfg(not(feature = "tls"))]
+        let stream = ConnStream::Plain(stream);
+        Conn::new(stream, options.protocol, addr, open_conns).map_err(connect_error)
+    }
+
+    // The reader and writer share the same socket, so setting them once covers both;
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), std::io::Error> {
+        let stream = self.tcp_stream();
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))
+    }
+
+    // Best effort `quit` followed by a shutdown of the stream; Errors are ignored as the
+    // connection is being discarded anyway;
+    fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        let _ = match self.protocol {
+            ProtocolMode::Ascii => protocol::write_command(&mut self.writer, VERB_QUIT, []),
+            ProtocolMode::Binary => self.write_binary_request(OPCODE_QUIT, &[], &[], &[], 0, 0),
+        };
+        let _ = self.writer.flush();
+        let _ = self.tcp_stream().shutdown(Shutdown::Both);
+    }
+
+    // Shuts the stream down without sending `quit` or what's left in the write buffer, for
+    // commands cut short that the server must not see the rest of;
+    fn abort(&mut self) {
+        self.closed = true;
+        let _ = self.tcp_stream().shutdown(Shutdown::Both);
+    }
+
+    fn tcp_stream(&self) -> &TcpStream {
+        self.writer.get_ref().tcp_stream()
+    }
+
+    // Checks, without blocking, that the server hasn't hung up on an idle connection; Otherwise
+    // the next write would still succeed and only the read of the response would fail; Bytes
+    // waiting on an idle connection answer no request, so they would be taken for the response to
+    // the next one and the connection isn't usable either;
+    fn is_alive(&self) -> bool {
+        if !self.reader.buffer().is_empty() {
+            return false;
+        }
+        let stream = self.tcp_stream();
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let alive = match stream.peek(&mut [0; 1]) {
+            Ok(_) => false,
+            Err(error) => error.kind() == io::ErrorKind::WouldBlock,
+        };
+        stream.set_nonblocking(false).is_ok() && alive
+    }
+
+    // Sends `<verb> <args...>\r\n`, see `protocol::write_command`;
+    fn write_command<'a>(
+        &mut self,
+        verb: &str,
+        args: impl IntoIterator<Item = Arg<'a>>,
+    ) -> Result<(), WriteReadLineError> {
+        protocol::write_command(&mut self.writer, verb, args)?;
+        self.writer.flush().map_err(WriteReadLineError::Flush)
+    }
+
+    fn write_line(&mut self, write_buf: &[u8]) -> Result<(), WriteReadLineError> {
+        self.writer
+            .write_all(write_buf)
+            .map_err(WriteReadLineError::Write)?;
+        self.writer.flush().map_err(WriteReadLineError::Flush)
+    }
+
+    // Reads a response line into `line_buf`, which is cleared first so a line left over by a
+    // failed read never shows up in the next one; The line is borrowed until the next read,
+    // callers keeping it copy it out;
+    fn read_line(&mut self) -> Result<&[u8], WriteReadLineError> {
+        self.line_buf.clear();
+        read_until_newline(&mut self.reader, &mut self.line_buf)
+            .map_err(WriteReadLineError::Read)?;
+        Ok(&self.line_buf)
+    }
+
+    fn write_read_line(&mut self, write_buf: &[u8]) -> Result<&[u8], WriteReadLineError> {
+        self.write_line(write_buf)?;
+        self.read_line()
+    }
+}
+
+// Connections that go out of scope without being closed, e.g. the ones over the pool limit, still
+// let the server know instead of leaving it a half closed socket;
+impl Drop for Conn {
+    fn drop(&mut self) {
+        self.close();
+        self.open_conns.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Failures to establish a connection, after which nothing was sent to the server;
+fn is_connect_error(error: &OperationError) -> bool {
+    match error {
+        OperationError::Connect(..) | OperationError::ConnectTimeout(..) => true,
+        #[cfg(feature = "tls")]
+        OperationError::TlsHandshake(..) => true,
+        _ => false,
+    }
+}
+
+// `read_until` that reports a server hanging up as an error instead of an empty line;
+fn read_until_newline(reader: &mut impl BufRead, buf: &mut Vec<u8>) -> io::Result<usize> {
+    match reader.read_until(b'\n', buf)? {
+        0 => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed by the server",
+        )),
+        read => Ok(read),
+    }
+}
+
+fn set_stream_keepalive(stream: &TcpStream, keepalive: Option<Duration>) -> io::Result<()> {
+    let socket = SockRef::from(stream);
+    match keepalive {
+        Some(time) => socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time)),
+        None => socket.set_keepalive(false),
+    }
+}
+
+// Returns the next space separated field of a response line or a `CorruptResponse` naming the
+// missing field;
+fn next_field<'a>(
+    split: &mut impl Iterator<Item = &'a [u8]>,
+    name: &str,
+) -> Result<&'a [u8], OperationError> {
+    split.next().ok_or_else(|| {
+        OperationError::CorruptResponse(format!("missing {} in response line", name))
+    })
+}
+
+// Keys must be 1 to 250 bytes long without spaces, control characters or DEL, otherwise the
+// server splits the command in the wrong place and the rest of it is read as another command;
+fn legal_key(key: &str) -> Result<(), OperationError> {
+    if key.is_empty() {
+        return Err(OperationError::MalformedKey(KeyError::Empty));
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err(OperationError::MalformedKey(KeyError::TooLong(key.len())));
+    }
+    match illegal_key_byte(key) {
+        Some(error) => Err(OperationError::MalformedKey(error)),
+        None => Ok(()),
+    }
+}
+
+// The first space, control character or DEL of `key`;
+fn illegal_key_byte(key: &str) -> Option<KeyError> {
+    key.bytes()
+        .position(|byte| byte <= b' ' || byte == 0x7f)
+        .map(|at| KeyError::IllegalByte(key.as_bytes()[at], at))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        errors::{
+            ConnError, ErrorKind, GetOrSetError, KeyError, OperationError, WriteReadLineError,
+        },
+        expiration::Expiration,
+        flags::Flags,
+        item::{Item, ItemMeta, StoredValue},
+    };
+
+    use super::{
+        legal_key, AutoEject, Client, Conn, KetamaSelector, PoolStats, ProtocolMode, RetryPolicy,
+        ServerList, ServerPool, ServerState, DEFAULT_NET_TIMEOUT,
+    };
+    use crate::meta::{MetaGetFlags, MetaSetFlags};
+    use socket2::SockRef;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc;
+    use std::sync::{Mutex, MutexGuard};
+    use std::time::{Duration, Instant};
+    pub(crate) const LOCALHOST_TCP_ADDR: &str = "127.0.0.1:11211";
+
+    // Tests against the local server share its keyspace (and `flush_all` wipes it), so they
+    // must not run concurrently.
+    static LOCAL_SERVER: Mutex<()> = Mutex::new(());
+
+    pub(crate) fn lock_local_server() -> MutexGuard<'static, ()> {
+        LOCAL_SERVER
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    // Pseudo-random bytes (xorshift), which don't compress
+    pub(crate) fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545f4914f6cdd1d_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    // Records the largest allocation of the thread while `LARGEST_ALLOCATION` is set, to check
+    // values are written without being copied, and counts them while `ALLOCATIONS` is set
+    struct TrackingAllocator;
+
+    thread_local! {
+        static LARGEST_ALLOCATION: Cell<Option<usize>> = const { Cell::new(None) };
+        static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    unsafe impl GlobalAlloc for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = LARGEST_ALLOCATION.try_with(|largest| {
+                if let Some(size) = largest.get() {
+                    largest.set(Some(size.max(layout.size())));
+                }
+            });
+            let _ = ALLOCATIONS.try_with(|count| {
+                if let Some(allocations) = count.get() {
+                    count.set(Some(allocations + 1));
+                }
+            });
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+    // The largest allocation made by `f`, along with what it returned
+    fn largest_allocation<T>(f: impl FnOnce() -> T) -> (usize, T) {
+        LARGEST_ALLOCATION.with(|largest| largest.set(Some(0)));
+        let result = f();
+        let largest = LARGEST_ALLOCATION.with(|largest| largest.replace(None));
+        (largest.unwrap_or_default(), result)
+    }
+
+    // How many allocations `f` made, along with what it returned
+    fn allocation_count<T>(f: impl FnOnce() -> T) -> (usize, T) {
+        ALLOCATIONS.with(|count| count.set(Some(0)));
+        let result = f();
+        let count = ALLOCATIONS.with(|count| count.replace(None));
+        (count.unwrap_or_default(), result)
+    }
+
+    // Most tests connect to a single server, and so have a single pool
+    impl Client {
+        pub(crate) fn server_addr(&self) -> SocketAddr {
+            match self.servers.addrs().as_slice() {
+                [addr] => *addr,
+                addrs => panic!("expected a single server. Got: {:?}", addrs),
+            }
+        }
+
+        pub(crate) fn pool(&self) -> &ServerPool {
+            &self.pools[&self.server_addr()]
+        }
+
+        pub(crate) fn pool_mut(&mut self) -> &mut ServerPool {
+            let addr = self.server_addr();
+            self.pools
+                .get_mut(&addr)
+                .expect("expected a pool for the server")
+        }
+
+        // Pretends the server at `from` was resolved to `to`
+        fn move_server(&mut self, from: SocketAddr, to: SocketAddr) {
+            self.servers
+                .server_list()
+                .expect("expected a server list")
+                .set_addr(from, to);
+            self.move_pool(from, to);
+        }
+    }
+
+    // Answers every get (or gets), after `latency`, with each requested key holding `v`
+    pub(crate) fn slow_server(latency: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut line = String::new();
+                    while matches!(reader.read_line(&mut line), Ok(read) if read > 0) {
+                        std::thread::sleep(latency);
+                        let mut words = line.split_whitespace();
+                        let cas = if words.next() == Some("gets") {
+                            " 1"
+                        } else {
+                            ""
+                        };
+                        let mut response = String::new();
+                        for key in words {
+                            response.push_str(&format!("VALUE {} 0 1{}\r\nv\r\n", key, cas));
+                        }
+                        response.push_str("END\r\n");
+                        if stream.write_all(response.as_bytes()).is_err() {
+                            return;
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    // Starts a server that answers the first request line it receives with `response`;
+    pub(crate) fn mock_server(response: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let _ = reader.read_line(&mut String::new());
+                let _ = stream.write_all(response);
+                // Keep the connection open until the client hangs up
+                let _ = reader.read_line(&mut String::new());
+            }
+        });
+        addr
+    }
+
+    // Starts a server that, for every `(lines, response)` step of `script`, reads `lines` request
+    // lines before writing `response`, so pipelined requests get a single answer and the requests
+    // after them their own;
+    pub(crate) fn scripted_server(script: &'static [(usize, &'static [u8])]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                for (lines, response) in script {
+                    for _ in 0..*lines {
+                        let _ = reader.read_line(&mut String::new());
+                    }
+                    let _ = stream.write_all(response);
+                }
+                // Keep the connection open until the client hangs up
+                let _ = reader.read_line(&mut String::new());
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn invalid_server_addr_returns_err() {
+        let result = Client::new(String::from("alksdjasld"), None, 0);
+        match result {
+            Ok(_) => panic!("expected creation of new client to fail"),
+            Err(error) => match error {
+                ConnError::AddrParseError(server, _) if server == "alksdjasld" => (), // Expected error,
+                _ => panic!("unexpected error. Got: {:?}", error),
+            },
+        };
+    }
+
+    #[test]
+    fn server_hostnames_are_resolved() {
+        let mut client = match Client::new(String::from("localhost:11211"), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server by name: {:?}", error),
+        };
+        if !client.server_addr().ip().is_loopback() || client.server_addr().port() != 11211 {
+            panic!(
+                "expected localhost to resolve. Got: {}",
+                client.server_addr()
+            )
+        }
+        if let Err(error) = client.ping() {
+            panic!("did not expect ping to fail: {}", error)
+        }
+
+        match Client::new(String::from("does-not-exist.invalid:11211"), None, 0) {
+            Err(ConnError::Resolve(host, _)) if host == "does-not-exist.invalid" => (),
+            other => panic!("expected the name resolution to fail. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn server_hostnames_are_resolved_again_when_connecting_fails() {
+        let mut client = match Client::new(String::from("localhost:11211"), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server by name: {:?}", error),
+        };
+        let resolved_addr = client.server_addr();
+        // Pretend the name used to point to an address nothing listens on any more
+        let stale_addr = "127.0.0.1:1".parse().unwrap();
+        client.move_server(resolved_addr, stale_addr);
+        if let Err(error) = client.ping() {
+            panic!("expected the name to be resolved again: {}", error)
+        }
+        if client.server_addr() != resolved_addr {
+            panic!(
+                "expected the cached address to be replaced. Got: {}",
+                client.server_addr()
+            )
+        }
+
+        client.set_dns_refresh_interval(Some(Duration::ZERO));
+        client.move_server(resolved_addr, stale_addr);
+        if let Err(error) = client.ping() {
+            panic!("did not expect ping to fail: {}", error)
+        }
+        if client.server_addr() != resolved_addr || client.pool().free_conns.len() != 1 {
+            panic!("expected the name to be resolved again on checkout")
+        }
+    }
+
+    #[test]
+    fn ipv6_server_addrs() {
+        let Ok(listener) = TcpListener::bind("[::1]:0") else {
+            // No IPv6 loopback to test against
+            return;
+        };
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let _ = reader.read_line(&mut String::new());
+                let _ = stream.write_all(b"VERSION 1.6.0\r\n");
+                let _ = reader.read_line(&mut String::new());
+            }
+        });
+
+        let mut client = match Client::new(format!("[::1]:{}", addr.port()), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to an IPv6 server: {:?}", error),
+        };
+        match client.version() {
+            Ok(versions) if versions.contains_key(&addr) => (),
+            other => panic!(
+                "expected the version keyed by the IPv6 address. Got: {:?}",
+                other
+            ),
+        }
+        if addr.to_string() != format!("[::1]:{}", addr.port()) {
+            panic!(
+                "expected the address to be displayed bracketed. Got: {}",
+                addr
+            )
+        }
+
+        match Client::new(format!("::1:{}", addr.port()), None, 0) {
+            Err(error @ ConnError::InvalidServerAddr(_, _))
+                if error
+                    .to_string()
+                    .contains(&format!("`::1:{}`", addr.port())) => {}
+            other => panic!(
+                "expected the unbracketed address to be rejected. Got: {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn connect_failure_names_the_server() {
+        let up = mock_server(b"");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let down = listener.local_addr().unwrap();
+        drop(listener);
+        match Client::with_servers(&[&up, &down.to_string()], None, 0, ProtocolMode::Ascii) {
+            Err(error @ ConnError::TcpConnectError(addr, _))
+                if addr == down && error.to_string().contains(&down.to_string()) => {}
+            other => panic!("expected a connect error naming {}. Got: {:?}", down, other),
+        }
+    }
+
+    #[test]
+    fn connect_to_unroutable_addr_respects_timeout() {
+        let started = Instant::now();
+        let result = Client::new(
+            String::from("10.255.255.1:11211"),
+            Some(Duration::from_millis(200)),
+            0,
+        );
+        // Some networks accept every connection, what matters is that the dial doesn't hang
+        if started.elapsed() > Duration::from_secs(1) {
+            panic!(
+                "expected the dial to give up within the timeout. Got: {:?}",
+                result
+            )
+        }
+        match result {
+            Ok(_) | Err(ConnError::TcpConnectError(_, _)) => (),
+            Err(error) => panic!("unexpected error. Got: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn redial_failure_returns_connect_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap();
+        let mut client =
+            Client::new(addr.to_string(), None, 0).expect("could not connect to mock server");
+        drop(listener);
+        client.close();
+
+        match client.get("key") {
+            Err(OperationError::Connect(failed_addr, _)) if failed_addr == addr => (),
+            other => panic!("expected a connect error. Got: {:?}", other),
+        }
+    }
+
+    // Kind of the first `io::Error` in the source chain of `error`;
+    fn io_error_kind(error: &(dyn std::error::Error + 'static)) -> Option<std::io::ErrorKind> {
+        let mut source = Some(error);
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<std::io::Error>() {
+                return Some(error.kind());
+            }
+            source = error.source();
+        }
+        None
+    }
+
+    #[test]
+    fn connect_errors_chain_to_the_io_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap();
+        let mut client =
+            Client::new(addr.to_string(), None, 0).expect("could not connect to mock server");
+        drop(listener);
+        client.close();
+
+        match client.get("key") {
+            Err(error) if io_error_kind(&error) == Some(std::io::ErrorKind::ConnectionRefused) => {}
+            other => panic!("expected a refused connection. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_kinds_separate_data_from_transport() {
+        let addr = mock_server(b"NOT_FOUND\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.delete("key") {
+            Err(error) if error.is_cache_miss() && error.kind() == ErrorKind::Data => (),
+            other => panic!("expected a data level cache miss. Got: {:?}", other),
+        }
+
+        let timeout = OperationError::Io(WriteReadLineError::Read(std::io::Error::from(
+            std::io::ErrorKind::WouldBlock,
+        )));
+        if !timeout.is_io() || !timeout.is_timeout() || timeout.kind() != ErrorKind::Transport {
+            panic!("expected a transport level timeout. Got: {:?}", timeout)
+        }
+
+        // A partly failed multi-server operation is as bad as its worst failure
+        let addr: SocketAddr = "127.0.0.1:11211".parse().unwrap();
+        let failures = OperationError::ServerFailures(vec![
+            (addr, OperationError::Server("out of memory".to_string())),
+            (
+                addr,
+                OperationError::Timeout(std::io::ErrorKind::TimedOut.into()),
+            ),
+        ]);
+        match failures.kind() {
+            ErrorKind::Transport => (),
+            other => panic!("expected a transport failure. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn io_errors_chain_through_write_read_line_error() {
+        let error = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        let error = OperationError::from(WriteReadLineError::Read(error));
+        match io_error_kind(&error) {
+            Some(std::io::ErrorKind::TimedOut) => (),
+            other => panic!("expected a timed out io error. Got: {:?}", other),
+        }
+
+        // Through `get_or_set_with`'s error, whether the client or the closure failed
+        let error = GetOrSetError::<std::io::Error>::Operation(error);
+        match io_error_kind(&error) {
+            Some(std::io::ErrorKind::TimedOut) => (),
+            other => panic!("expected a timed out io error. Got: {:?}", other),
+        }
+        let error = GetOrSetError::Compute(std::io::Error::from(std::io::ErrorKind::NotFound));
+        let source = std::error::Error::source(&error)
+            .and_then(|source| source.downcast_ref::<std::io::Error>());
+        match source.map(std::io::Error::kind) {
+            Some(std::io::ErrorKind::NotFound) => (),
+            other => panic!(
+                "expected the closure's error as the source. Got: {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn timeouts_chain_to_the_timed_out_io_error() {
+        let addr = mock_server(b"");
+        let mut client = Client::new(addr, Some(Duration::from_millis(50)), 0)
+            .expect("could not connect to mock server");
+        let error = match client.get("key") {
+            Err(error) if matches!(error.without_context(), OperationError::Timeout(_)) => error,
+            other => panic!("expected a timeout. Got: {:?}", other),
+        };
+        match io_error_kind(&error) {
+            Some(std::io::ErrorKind::TimedOut) => (),
+            other => panic!("expected a timed out io error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn zero_timeout_is_rejected() {
+        match Client::new(String::from(LOCALHOST_TCP_ADDR), Some(Duration::ZERO), 0) {
+            Err(ConnError::InvalidTimeout) => (),
+            other => panic!("expected an invalid timeout error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn set_timeout_applies_to_pooled_connections() {
+        let addr = mock_server(b"");
+        let client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let mut client = match client.with_timeout(Duration::from_millis(50)) {
+            Ok(client) => client,
+            Err(error) => panic!("did not expect with_timeout to fail: {}", error),
+        };
+        let start = Instant::now();
+        match client
+            .get("key")
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::Timeout(_)) => (),
+            other => panic!("expected a timeout. Got: {:?}", other),
+        }
+        if start.elapsed() >= DEFAULT_NET_TIMEOUT {
+            panic!("expected the pooled connection to use the new timeout")
+        }
+        match client.set_timeout(Duration::ZERO) {
+            Err(ConnError::InvalidTimeout) => (),
+            other => panic!("expected an invalid timeout error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn put_free_conn_closes_connections_over_the_limit() {
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 2) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let mut conns = Vec::new();
+        for _ in 0..4 {
+            match client.get_conn(client.server_addr()) {
+                Ok(conn) => conns.push(conn),
+                Err(error) => panic!("did not expect get_conn to fail: {}", error),
+            }
+        }
+        for conn in conns {
+            client.put_free_conn(conn);
+        }
+        if client.pool().free_conns.len() != 2 {
+            panic!(
+                "expected 2 idle connections. Got: {}",
+                client.pool().free_conns.len()
+            )
+        }
+
+        client.set_max_idle_conns(1);
+        if client.pool().free_conns.len() != 1 {
+            panic!(
+                "expected 1 idle connection. Got: {}",
+                client.pool().free_conns.len()
+            )
+        }
+    }
+
+    #[test]
+    fn operations_reuse_pooled_connections() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let mut local_ports = Vec::new();
+        for _ in 0..2 {
+            if let Err(error) = client.version() {
+                panic!("did not expect version to fail: {}", error)
+            }
+            match client.pool().free_conns.as_slice() {
+                [conn] => local_ports.push(conn.tcp_stream().local_addr().unwrap().port()),
+                conns => panic!("expected a single idle connection. Got: {:?}", conns),
+            }
+        }
+        if local_ports[0] != local_ports[1] {
+            panic!(
+                "expected the connection to be reused. Got: {:?}",
+                local_ports
+            )
+        }
+    }
+
+    #[test]
+    fn stale_idle_connections_are_not_reused() {
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        client.set_max_idle_age(Duration::from_millis(20));
+        let local_port = |client: &Client| {
+            client.pool().free_conns[0]
+                .tcp_stream()
+                .local_addr()
+                .unwrap()
+                .port()
+        };
+        let stale_port = local_port(&client);
+        std::thread::sleep(Duration::from_millis(50));
+        if let Err(error) = client.ping() {
+            panic!("did not expect ping to fail: {}", error)
+        }
+        if client.pool().free_conns.len() != 1 || local_port(&client) == stale_port {
+            panic!("expected the stale connection to be replaced")
+        }
+
+        std::thread::sleep(Duration::from_millis(50));
+        if client.prune_idle_connections() != 1 || !client.pool().free_conns.is_empty() {
+            panic!("expected the stale connection to be pruned")
+        }
+    }
+
+    #[test]
+    fn idle_connections_closed_by_the_server_are_not_reused() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            // The first connection is closed straight away, the next ones answer a version
+            let mut incoming = listener.incoming().flatten();
+            drop(incoming.next());
+            for mut stream in incoming {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let _ = reader.read_line(&mut String::new());
+                let _ = stream.write_all(b"VERSION 1.6.0\r\n");
+                let _ = reader.read_line(&mut String::new());
+            }
+        });
+
+        let mut client = match Client::new(addr.clone(), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to mock server: {:?}", error),
+        };
+        // Give the server time to hang up on the pooled connection
+        std::thread::sleep(Duration::from_millis(50));
+        if client.pool().free_conns[0].is_alive() {
+            panic!("expected the closed connection to be detected")
+        }
+        match client.version() {
+            Ok(versions) if versions.len() == 1 => (),
+            other => panic!("expected the connection to be replaced. Got: {:?}", other),
+        }
+        if !client.pool().free_conns[0].is_alive() {
+            panic!("expected the new connection to be alive")
+        }
+        match client.pool_stats().get(&addr) {
+            Some(PoolStats { discarded: 1, .. }) => (),
+            other => panic!(
+                "expected the closed connection to be discarded. Got: {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn idle_connections_with_unread_bytes_are_not_reused() {
+        // `END` answers no request
+        let addr = mock_server(b"VERSION 1.6.0\r\nEND\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.version() {
+            Ok(versions) if versions.values().all(|version| version == "1.6.0") => (),
+            other => panic!("expected a version. Got: {:?}", other),
+        }
+        if client.pool().free_conns[0].is_alive() {
+            panic!("expected the connection with a stray response to be discarded")
+        }
+    }
+
+    // Starts a server that hangs up on the first `hang_ups` requests it receives, as if it closed
+    // the connection right after checkout, and answers the first request of later connections
+    // with `response`;
+    fn hang_up_server(hang_ups: usize, response: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut hung_up = 0;
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let _ = reader.read_line(&mut String::new());
+                if hung_up < hang_ups {
+                    hung_up += 1;
+                    continue;
+                }
+                let _ = stream.write_all(response);
+                let _ = reader.read_line(&mut String::new());
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn safe_commands_are_retried_once_on_a_stale_pooled_connection() {
+        let addr = hang_up_server(1, b"END\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.get("key") {
+            Ok(None) => (),
+            other => panic!(
+                "expected the get to be retried transparently. Got: {:?}",
+                other
+            ),
+        }
+        if client.pool().free_conns.len() != 1 {
+            panic!("expected the new connection to be pooled")
+        }
+    }
+
+    #[test]
+    fn storage_commands_are_not_retried_on_a_stale_pooled_connection() {
+        let addr = hang_up_server(1, b"STORED\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client
+            .set(&Item::from_parts(
+                String::from("key"),
+                b"value".to_vec(),
+                0,
+                0,
+            ))
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::Io(_)) => (),
+            other => panic!("expected the set to fail. Got: {:?}", other),
+        }
+        if !client.pool().free_conns.is_empty() {
+            panic!("expected the failed connection to be dropped")
+        }
+    }
+
+    #[test]
+    fn transient_failures_are_retried_per_the_retry_policy() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(10),
+            multiplier: 2.0,
+            jitter: false,
+            deadline: None,
+        };
+        // The pooled connection and the first redial are hung up on
+        let addr = hang_up_server(2, b"END\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client
+            .get("key")
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::Io(_)) => (),
+            other => panic!("expected no retries by default. Got: {:?}", other),
+        }
+        let addr = hang_up_server(2, b"END\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        client.set_retry_policy(RetryPolicy {
+            deadline: Some(Duration::from_millis(5)),
+            ..policy
+        });
+        match client
+            .get("key")
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::Io(_)) => (),
+            other => panic!("expected the deadline to stop retries. Got: {:?}", other),
+        }
+        let addr = hang_up_server(2, b"END\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        client.set_retry_policy(policy);
+        let started = Instant::now();
+        match client.get("key") {
+            Ok(None) => (),
+            other => panic!("expected the get to be retried. Got: {:?}", other),
+        }
+        if started.elapsed() < policy.base_delay {
+            panic!("expected the retry to back off")
+        }
+
+        // Reconnecting to a server that's gone is retried too
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut client = Client::new(addr.clone(), None, 0).expect("could not connect");
+        drop(listener);
+        client.close();
+        client.set_retry_policy(policy);
+        match client.get("key") {
+            Err(OperationError::Connect(..)) => (),
+            other => panic!("expected a connect error. Got: {:?}", other),
+        }
+        match client.pool_stats().get(&addr) {
+            Some(PoolStats {
+                dials: 4,
+                dial_failures: 3,
+                ..
+            }) => (),
+            other => panic!("expected 3 connect attempts. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn close_idle_connections_reports_the_closed_connections() {
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        if client.close_idle_connections() != 1 || !client.pool().free_conns.is_empty() {
+            panic!("expected the pooled connection to be closed")
+        }
+        if client.close_idle_connections() != 0 {
+            panic!("expected no connections left to close")
+        }
+        if let Err(error) = client.ping() {
+            panic!(
+                "did not expect ping to fail after closing idle connections: {}",
+                error
+            )
+        }
+        if client.pool().free_conns.len() != 1 {
+            panic!("expected a fresh connection to be pooled")
+        }
+    }
+
+    #[test]
+    fn max_open_conns_per_server_fails_fast_once_reached() {
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        client.set_max_open_conns_per_server(Some(1));
+        let conn = match client.get_conn(client.server_addr()) {
+            Ok(conn) => conn,
+            Err(error) => panic!(
+                "expected the pooled connection to be checked out: {}",
+                error
+            ),
+        };
+        match client.get_conn(client.server_addr()) {
+            Err(OperationError::PoolExhausted(_)) => (),
+            other => panic!("expected the pool to be exhausted. Got: {:?}", other),
+        }
+        drop(conn);
+        if let Err(error) = client.ping() {
+            panic!(
+                "did not expect ping to fail once the connection was dropped: {}",
+                error
+            )
+        }
+        if client.pool().open_conns.load(Ordering::Relaxed) != client.pool().free_conns.len() {
+            panic!("expected only the pooled connection to be counted as open")
+        }
+    }
+
+    #[test]
+    fn dropped_connections_send_quit_and_shut_down() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let (lines_tx, lines_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            if let Ok((stream, _)) = listener.accept() {
+                // Everything the client sends until it shuts the connection down
+                let lines: Vec<String> = BufReader::new(stream)
+                    .lines()
+                    .map_while(Result::ok)
+                    .collect();
+                let _ = lines_tx.send(lines);
+            }
+        });
+
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let conn = client
+            .get_conn(client.server_addr())
+            .expect("expected the pooled connection to be checked out");
+        drop(conn);
+        match lines_rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(lines) if lines == ["quit"] => (),
+            other => panic!(
+                "expected a single quit before the shutdown. Got: {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn connections_discarded_after_a_write_timeout_send_nothing_more() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        // Small socket buffers so writes time out while the server isn't reading
+        SockRef::from(&listener)
+            .set_recv_buffer_size(4096)
+            .expect("could not shrink the receive buffer");
+        let (drain_tx, drain_rx) = mpsc::channel();
+        let (received_tx, received_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                // Everything the client sends until it shuts the connection down
+                let _ = drain_rx.recv();
+                let mut received = Vec::new();
+                let _ = std::io::Read::read_to_end(&mut stream, &mut received);
+                let _ = received_tx.send(received);
+            }
+        });
+
+        let mut client = Client::new(addr, Some(Duration::from_millis(100)), 0)
+            .expect("could not connect to mock server");
+        let mut conn = client
+            .get_conn(client.server_addr())
+            .expect("expected the pooled connection to be checked out");
+        SockRef::from(conn.tcp_stream())
+            .set_send_buffer_size(4096)
+            .expect("could not shrink the send buffer");
+        let mut written = 0;
+        let error = loop {
+            match conn.writer.write_all(&[b'x'; 100]) {
+                Ok(()) => written += 100,
+                Err(error) => break error,
+            }
+        };
+        if !WriteReadLineError::Write(error).is_timeout() {
+            panic!("expected the write to time out")
+        }
+        // The server must get what the socket took, without the buffered rest or a `quit`
+        let buffered = conn.writer.buffer().len();
+        drain_tx.send(()).unwrap();
+        client.discard_conn(conn);
+        match received_rx.recv_timeout(Duration::from_secs(2)) {
+            Ok(received) if received.len() == written - buffered => (),
+            other => panic!(
+                "expected only the {} bytes written before the timeout. Got: {:?}",
+                written - buffered,
+                other.map(|received| {
+                    let extra = received.get(written - buffered..).unwrap_or_default();
+                    String::from_utf8_lossy(extra).into_owned()
+                })
+            ),
+        }
+    }
+
+    #[test]
+    fn pool_stats_track_dials_and_discarded_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            // The pooled connection is hung up on, the one dialed to retry gets a corrupt response
+            if let Ok((stream, _)) = listener.accept() {
+                let _ = BufReader::new(stream).read_line(&mut String::new());
+            }
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let _ = reader.read_line(&mut String::new());
+                let _ = stream.write_all(b"garbage\r\n");
+            }
+        });
+        let mut client =
+            Client::new(addr.clone(), None, 0).expect("could not connect to mock server");
+        let expected = PoolStats {
+            idle: 1,
+            dials: 1,
+            ..PoolStats::default()
+        };
+        if client.pool_stats().get(&addr) != Some(&expected) {
+            panic!(
+                "unexpected initial pool stats. Got: {:?}",
+                client.pool_stats()
+            )
+        }
+
+        let conn = client
+            .get_conn(client.server_addr())
+            .expect("expected the pooled connection to be checked out");
+        match client.pool_stats().get(&addr) {
+            Some(PoolStats {
+                idle: 0,
+                checked_out: 1,
+                ..
+            }) => (),
+            other => panic!(
+                "expected the connection to be checked out. Got: {:?}",
+                other
+            ),
+        }
+        client.put_free_conn(conn);
+
+        if client.get("key").is_ok() {
+            panic!("expected the corrupt response to fail the get")
+        }
+        // Both the cached address and the configured one, which the mock server stopped
+        // listening on, fail to connect
+        server.join().unwrap();
+        client.move_server(client.server_addr(), "127.0.0.1:1".parse().unwrap());
+        if client.get("key").is_ok() {
+            panic!("expected the get to fail without a server")
+        }
+        let expected = PoolStats {
+            idle: 0,
+            checked_out: 0,
+            dials: 4,
+            dial_failures: 2,
+            discarded: 2,
+        };
+        if client.pool_stats().get(&addr) != Some(&expected) {
+            panic!("unexpected pool stats. Got: {:?}", client.pool_stats())
+        }
+    }
+
+    // Starts a server that answers every request line with `response`, sending the lines it
+    // received down the returned channel;
+    pub(crate) fn recording_server(response: &'static [u8]) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let (lines_tx, lines_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut writer = stream.try_clone().unwrap();
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    let _ = writer.write_all(response);
+                    if lines_tx.send(line).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        (addr, lines_rx)
+    }
+
+    #[test]
+    fn multi_key_operations_are_split_by_server() {
+        let (first_addr, first_lines) = recording_server(b"END\r\n");
+        let (second_addr, second_lines) = recording_server(b"END\r\n");
+        let mut client =
+            Client::with_servers(&[&first_addr, &second_addr], None, 0, ProtocolMode::Ascii)
+                .expect("could not connect to mock servers");
+        let keys = ["a", "b", "c", "d", "e", "f"];
+        match client.get_multi(&keys) {
+            Ok(items) if items.is_empty() => (),
+            other => panic!("expected every key to be missing. Got: {:?}", other),
+        }
+        for (addr, lines) in [(first_addr, first_lines), (second_addr, second_lines)] {
+            let server_keys: Vec<&str> = keys
+                .into_iter()
+                .filter(|key| client.servers.pick_server(key).unwrap().to_string() == addr)
+                .collect();
+            if server_keys.is_empty() {
+                panic!("expected some of the keys to map to {}", addr)
+            }
+            match lines.recv_timeout(Duration::from_secs(1)) {
+                Ok(line) if line == format!("get {}", server_keys.join(" ")) => (),
+                other => panic!(
+                    "expected {} to get {:?}. Got: {:?}",
+                    addr, server_keys, other
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn keys_are_routed_through_the_selector_of_the_client() {
+        let (first_addr, first_lines) = recording_server(b"END\r\n");
+        let (second_addr, second_lines) = recording_server(b"END\r\n");
+        let servers = [first_addr.as_str(), second_addr.as_str()];
+        let ketama = KetamaSelector::new(&servers).expect("could not build the ring");
+        // A key the ring and CRC32 modulo place on different servers
+        let key = (0..)
+            .map(|i| format!("key{}", i))
+            .find(|key| {
+                let modulo = ServerList::new(&servers).unwrap().pick_server(key).unwrap();
+                ketama.pick_server(key).unwrap() != modulo
+            })
+            .unwrap();
+        let ketama_addr = ketama.pick_server(&key).unwrap().to_string();
+        let mut client = Client::with_servers(&servers, None, 0, ProtocolMode::Ascii)
+            .expect("could not connect to mock servers")
+            .with_selector(ketama);
+
+        match client.get(&key) {
+            Ok(None) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+        let lines = match ketama_addr == first_addr {
+            true => first_lines,
+            false => second_lines,
+        };
+        match lines.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) if line == format!("get {}", key) => (),
+            other => panic!("expected the get on {}. Got: {:?}", ketama_addr, other),
+        }
+    }
+
+    #[test]
+    fn keys_are_prefixed_and_routed_by_the_full_key() {
+        let (first_addr, first_lines) = recording_server(b"END\r\n");
+        let (second_addr, second_lines) = recording_server(b"END\r\n");
+        let servers = [first_addr.as_str(), second_addr.as_str()];
+        let server_list = ServerList::new(&servers).unwrap();
+        // A key placed on one server and its prefixed form on the other
+        let key = (0..)
+            .map(|i| format!("key{}", i))
+            .find(|key| {
+                server_list.pick_server(key).unwrap()
+                    != server_list.pick_server(&format!("svc:{}", key)).unwrap()
+            })
+            .unwrap();
+        let prefixed_addr = server_list.pick_server(&format!("svc:{}", key)).unwrap();
+        let mut client = Client::with_servers(&servers, None, 0, ProtocolMode::Ascii)
+            .expect("could not connect to mock servers")
+            .with_key_prefix("svc:")
+            .expect("could not set the key prefix");
+
+        match client.get(&key) {
+            Ok(None) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+        let lines = match prefixed_addr.to_string() == first_addr {
+            true => first_lines,
+            false => second_lines,
+        };
+        match lines.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) if line == format!("get svc:{}", key) => (),
+            other => panic!("expected the get on {}. Got: {:?}", prefixed_addr, other),
+        }
+
+        match client.get(&"k".repeat(247)) {
+            Err(OperationError::MalformedKey(KeyError::TooLong(251))) => (),
+            other => panic!("expected the prefix to count. Got: {:?}", other),
+        }
+        for (prefix, reason) in [
+            ("a b", KeyError::IllegalByte(b' ', 1)),
+            (&"p".repeat(250), KeyError::TooLong(250)),
+        ] {
+            match client.set_key_prefix(prefix) {
+                Err(ConnError::InvalidKeyPrefix(got)) if got == reason => (),
+                other => panic!("expected {:?} for {:?}. Got: {:?}", reason, prefix, other),
+            }
+        }
+    }
+
+    #[test]
+    fn prefixed_and_raw_clients_share_data() {
+        let _guard = lock_local_server();
+        let mut raw = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0)
+            .expect("could not connect to local server");
+        let mut client = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0)
+            .expect("could not connect to local server")
+            .with_key_prefix("svc:")
+            .expect("could not set the key prefix");
+
+        if let Err(error) = client.set(&Item::new("prefix_a", "a").unwrap()) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match raw.get("svc:prefix_a") {
+            Ok(Some(item)) if item.key == "svc:prefix_a" && item.value == b"a" => (),
+            other => panic!("expected the prefixed item. Got: {:?}", other),
+        }
+        match raw.get("prefix_a") {
+            Ok(None) => (),
+            other => panic!("expected no unprefixed item. Got: {:?}", other),
+        }
+
+        if let Err(error) = raw.set(&Item::new("svc:prefix_b", "1").unwrap()) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.gets("prefix_b") {
+            Ok(Some(item)) if item.key == "prefix_b" && item.value == b"1" => (),
+            other => panic!("expected the item without its prefix. Got: {:?}", other),
+        }
+        match client.increment("prefix_b", 2) {
+            Ok(3) => (),
+            other => panic!(
+                "expected the raw counter to be incremented. Got: {:?}",
+                other
+            ),
+        }
+        match client.get_multi(&["prefix_a", "prefix_b", "prefix_missing"]) {
+            Ok(items)
+                if items.len() == 2
+                    && items.iter().all(|(key, item)| *key == item.key)
+                    && items["prefix_a"].value == b"a"
+                    && items["prefix_b"].value == b"3" => {}
+            other => panic!("expected both items without their prefix. Got: {:?}", other),
+        }
+        match client.touch_multi(
+            &["prefix_a", "prefix_missing"],
+            Expiration::In(Duration::from_secs(60)),
+        ) {
+            Ok(missing) if missing.len() == 1 && missing.contains("prefix_missing") => (),
+            other => panic!(
+                "expected the missing key without its prefix. Got: {:?}",
+                other
+            ),
+        }
+        match client.delete_multi(&["prefix_a", "prefix_b"]) {
+            Ok(deleted) if deleted.get("prefix_a") == Some(&true) && deleted.len() == 2 => (),
+            other => panic!("expected both keys deleted. Got: {:?}", other),
+        }
+        match raw.get_multi(&["svc:prefix_a", "svc:prefix_b"]) {
+            Ok(items) if items.is_empty() => (),
+            other => panic!("expected the prefixed items to be gone. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn servers_can_be_added_and_removed() {
+        let (first_addr, first_lines) = recording_server(b"END\r\n");
+        let (second_addr, second_lines) = recording_server(b"END\r\n");
+        let mut client =
+            Client::new(first_addr.clone(), None, 0).expect("could not connect to mock server");
+        if let Err(error) = client.add_server(&second_addr) {
+            panic!("did not expect adding the server to fail: {}", error)
+        }
+        // A key CRC32 modulo places on the added server
+        let key = (0..)
+            .map(|i| format!("key{}", i))
+            .find(|key| client.servers.pick_server(key).unwrap().to_string() == second_addr)
+            .unwrap();
+        match client.get(&key) {
+            Ok(None) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+        match second_lines.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) if line == format!("get {}", key) => (),
+            other => panic!("expected the get on the added server. Got: {:?}", other),
+        }
+
+        if !client.remove_server(&second_addr) || client.remove_server(&second_addr) {
+            panic!("expected the server to be removed once")
+        }
+        if client.pool_stats().contains_key(&second_addr) {
+            panic!("expected the pool of the removed server to be closed")
+        }
+        match client.get(&key) {
+            Ok(None) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+        match first_lines.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) if line == format!("get {}", key) => (),
+            other => panic!("expected the get on the remaining server. Got: {:?}", other),
+        }
+
+        client.remove_server(&first_addr);
+        match client.get(&key) {
+            Err(OperationError::NoServers) => (),
+            other => panic!("expected no servers. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failover_to_the_next_server_is_opt_in() {
+        // The first server goes away once the client connected to it
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let down_addr = listener.local_addr().unwrap().to_string();
+        let down_server = std::thread::spawn(move || drop(listener.accept()));
+        let (up_addr, up_lines) = recording_server(b"END\r\n");
+        let mut client =
+            Client::with_servers(&[&down_addr, &up_addr], None, 0, ProtocolMode::Ascii)
+                .expect("could not connect to mock servers");
+        down_server.join().unwrap();
+
+        let key = (0..)
+            .map(|i| format!("key_{}", i))
+            .find(|key| client.servers.pick_server(key).unwrap().to_string() == down_addr)
+            .unwrap();
+        match client.get(&key) {
+            Err(OperationError::Connect(addr, _)) if addr.to_string() == down_addr => (),
+            other => panic!("expected failover to be off by default. Got: {:?}", other),
+        }
+        client.set_failover_attempts(1);
+        match client.get(&key) {
+            Ok(None) => (),
+            other => panic!("expected the get to fail over. Got: {:?}", other),
+        }
+        match up_lines.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) if line == format!("get {}", key) => (),
+            other => panic!("expected the next server to be asked. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn servers_failing_to_connect_are_ejected_until_they_answer_again() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let down_addr = listener.local_addr().unwrap();
+        let down_server = std::thread::spawn(move || drop(listener.accept()));
+        let (up_addr, up_lines) = recording_server(b"END\r\n");
+        let servers = [down_addr.to_string(), up_addr];
+        let mut client =
+            Client::with_servers(&[&servers[0], &servers[1]], None, 0, ProtocolMode::Ascii)
+                .expect("could not connect to mock servers");
+        down_server.join().unwrap();
+        client.set_auto_eject(Some(AutoEject {
+            failure_threshold: 2,
+            window: Duration::from_millis(100),
+        }));
+
+        let key = (0..)
+            .map(|i| format!("key_{}", i))
+            .find(|key| client.servers.pick_server(key).unwrap() == down_addr)
+            .unwrap();
+        for _ in 0..2 {
+            match client.get(&key) {
+                Err(OperationError::Connect(addr, _)) if addr == down_addr => (),
+                other => panic!("expected a connect error. Got: {:?}", other),
+            }
+        }
+        if client.server_states()[&down_addr] != ServerState::Ejected {
+            panic!(
+                "expected the server to be ejected. Got: {:?}",
+                client.server_states()
+            )
+        }
+        match client.get(&key) {
+            Ok(None) => (),
+            other => panic!(
+                "expected the key to move to the live server. Got: {:?}",
+                other
+            ),
+        }
+        match up_lines.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) if line == format!("get {}", key) => (),
+            other => panic!("expected the live server to be asked. Got: {:?}", other),
+        }
+
+        // The server comes back and answers the probe once the window is over
+        let listener = TcpListener::bind(down_addr).expect("could not bind mock server again");
+        let (lines_tx, lines_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut writer = stream.try_clone().unwrap();
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    let response: &[u8] = match line.as_str() {
+                        "version" => b"VERSION 1.6.0\r\n",
+                        _ => b"END\r\n",
+                    };
+                    let _ = writer.write_all(response);
+                    let _ = lines_tx.send(line);
+                }
+            }
+        });
+        std::thread::sleep(Duration::from_millis(150));
+        match client.get(&key) {
+            Ok(None) => (),
+            other => panic!("did not expect get to fail. Got: {:?}", other),
+        }
+        let lines: Vec<String> = (0..2)
+            .map_while(|_| lines_rx.recv_timeout(Duration::from_secs(1)).ok())
+            .collect();
+        if lines != ["version".to_string(), format!("get {}", key)] {
+            panic!("expected a probe and then the get. Got: {:?}", lines)
+        }
+        if client.server_states()[&down_addr] != ServerState::Live {
+            panic!("expected the server to be reinstated")
+        }
+    }
+
+    #[test]
+    fn tcp_nodelay_applies_to_pooled_and_new_connections() {
+        let client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let nodelay = |conn: &Conn| conn.tcp_stream().nodelay().unwrap();
+        if !client.pool().free_conns.iter().all(nodelay) {
+            panic!("expected TCP_NODELAY to be enabled by default")
+        }
+
+        let mut client = match client.with_tcp_nodelay(false) {
+            Ok(client) => client,
+            Err(error) => panic!("did not expect with_tcp_nodelay to fail: {}", error),
+        };
+        if client.pool().free_conns.iter().any(nodelay) {
+            panic!("expected TCP_NODELAY to be disabled on pooled connections")
+        }
+        client.pool_mut().free_conns.clear();
+        match client.get_conn(client.server_addr()) {
+            Ok(conn) if !nodelay(&conn) => (),
+            Ok(_) => panic!("expected TCP_NODELAY to be disabled on new connections"),
+            Err(error) => panic!("did not expect get_conn to fail: {}", error),
+        }
+    }
+
+    #[test]
+    fn keepalive_applies_to_pooled_and_new_connections() {
+        let client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let keepalive = |conn: &Conn| SockRef::from(conn.tcp_stream()).keepalive().unwrap();
+        if client.pool().free_conns.iter().any(keepalive) {
+            panic!("expected keepalive to be disabled by default")
+        }
+
+        let mut client = match client.with_keepalive(Some(Duration::from_secs(30))) {
+            Ok(client) => client,
+            Err(error) => panic!("did not expect with_keepalive to fail: {}", error),
+        };
+        if !client.pool().free_conns.iter().all(keepalive) {
+            panic!("expected keepalive to be enabled on pooled connections")
+        }
+        client.pool_mut().free_conns.clear();
+        match client.get_conn(client.server_addr()) {
+            Ok(conn) if keepalive(&conn) => (),
+            Ok(_) => panic!("expected keepalive to be enabled on new connections"),
+            Err(error) => panic!("did not expect get_conn to fail: {}", error),
+        }
+    }
+
+    #[test]
+    fn test_local_host() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        if client.ping().is_err() {
+            panic!("expected ping to succeed")
+        }
+
+        // NOTE: Setting `expiration` to 5 seconds so tests don't fail on subsequent runs;
+        let item_key = "color".to_string();
+        let item_value = Vec::from("red");
+        let item_flags = 32;
+        let item = Item::from_parts(item_key.clone(), item_value.clone(), item_flags, 5);
+        if client.add(&item).is_err() {
+            panic!("expected item to be successfully persisted")
+        }
+
+        // NOTE: Clone?
+        let item = match client.get(&item_key) {
+            Ok(item) => item,
+            Err(error) => panic!("expected item to be successfully retrieved: {}", error),
+        };
+
+        if let Some(item) = item {
+            if item.value != item_value {
+                panic!("expected value to be red")
+            }
+            if item.flags != item_flags {
+                panic!("expected flags to be 0")
+            }
+        } else {
+            panic!("expected an item")
+        }
+
+        // Test `increment` and `decrement`
+        let item_key = "number".to_string();
+        let num = 26;
+        let delta = 10;
+        let num_item = Item::from_parts(item_key.clone(), Vec::from(num.to_string()), 0, 15);
+        if let Err(error) = client.set(&num_item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+
+        match client.increment(&item_key, delta) {
+            Ok(incr_num) => {
+                if incr_num != num + delta {
+                    panic!("expected incremented number ({}) to match with the initial number plus delta ({})", incr_num, num + delta)
+                }
+            }
+            Err(error) => {
+                panic!("did not expected increment to fail: {}", error)
+            }
+        }
+
+        match client.decrement(&item_key, delta) {
+            Ok(incr_num) => {
+                if incr_num != num {
+                    panic!(
+                        "expected decremented number ({}) to match with the initial number ({})",
+                        incr_num, num
+                    )
+                }
+            }
+            Err(error) => {
+                panic!("did not expected increment to fail: {}", error)
+            }
+        }
+
+        // Test `delete`
+        if let Err(error) = client.delete(&item_key) {
+            panic!("Did not expect delete to fail: {}", error)
+        }
+        // Test `flush_all`
+        if let Err(error) = client.flush_all(None) {
+            panic!("Did not expect flush all to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn test_touch() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let item_key = "touch_key".to_string();
+        let item = Item::from_parts(item_key.clone(), Vec::from("value"), 0, 1);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+
+        if let Err(error) = client.touch(&item_key, Expiration::In(Duration::from_secs(60))) {
+            panic!("did not expect touch to fail: {}", error)
+        }
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        match client.get(&item_key) {
+            Ok(Some(_)) => (),
+            Ok(None) => panic!("expected touched item to still be retrievable"),
+            Err(error) => panic!("did not expect get to fail: {}", error),
+        }
+
+        match client.touch("touch_missing_key", Expiration::In(Duration::from_secs(60))) {
+            Err(OperationError::CacheMiss) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete(&item_key) {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn test_get_and_touch() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let item_key = "gat_key".to_string();
+        let item_value = Vec::from("session");
+        let item = Item::from_parts(item_key.clone(), item_value.clone(), 7, 1);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+
+        match client.get_and_touch(&item_key, Expiration::In(Duration::from_secs(60))) {
+            Ok(Some(item)) => {
+                if item.value != item_value {
+                    panic!("expected value to be session")
+                }
+                if item.flags != 7 {
+                    panic!("expected flags to be 7")
+                }
+            }
+            Ok(None) => panic!("expected an item"),
+            Err(error) => panic!("did not expect get and touch to fail: {}", error),
+        }
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        match client.get(&item_key) {
+            Ok(Some(_)) => (),
+            Ok(None) => panic!("expected touched item to still be retrievable"),
+            Err(error) => panic!("did not expect get to fail: {}", error),
+        }
+
+        match client.get_and_touch("gat_missing_key", Expiration::In(Duration::from_secs(60))) {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete(&item_key) {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn test_gats() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let item_key = "gats_key".to_string();
+        let item = Item::from_parts(item_key.clone(), Vec::from("value"), 0, 60);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+
+        match client.gats(&item_key, Expiration::In(Duration::from_secs(60))) {
+            Ok(Some(item)) => {
+                if item.cas_id == 0 {
+                    panic!("expected the cas unique to be populated")
+                }
+            }
+            Ok(None) => panic!("expected an item"),
+            Err(error) => panic!("did not expect gats to fail: {}", error),
+        }
+
+        match client.gats("gats_missing_key", Expiration::In(Duration::from_secs(60))) {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete(&item_key) {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn gats_missing_cas_returns_corrupt_response() {
+        let addr = mock_server(b"VALUE key 0 5\r\nvalue\r\nEND\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client
+            .gats("key", Expiration::In(Duration::from_secs(60)))
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::CorruptResponse(_)) => (),
+            other => panic!("expected a corrupt response error. Got: {:?}", other),
+        }
+
+        let addr = mock_server(b"VALUE key 0 5 abc\r\nvalue\r\nEND\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client
+            .gats("key", Expiration::In(Duration::from_secs(60)))
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::CorruptResponse(_)) => (),
+            other => panic!("expected a corrupt response error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_get_into() {
+        let _guard = lock_local_server();
+        let mut client = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0)
+            .unwrap()
+            .with_compression_threshold(Some(64));
+        let value = b"<li>item</li>".repeat(20);
+        for (key, value) in [("get_into_plain", &b"value"[..]), ("get_into_zlib", &value)] {
+            let item = Item::from_parts(key.to_string(), value.to_vec(), 42, 60);
+            if let Err(error) = client.set(&item) {
+                panic!("did not expect set to fail: {}", error)
+            }
+        }
+
+        // The value is appended after what the buffer held
+        let mut buf = Vec::from("head:");
+        let cas_id = match client.get_into("get_into_plain", &mut buf) {
+            Ok(Some(ItemMeta {
+                flags: 42,
+                cas_id,
+                len: 5,
+            })) if buf == b"head:value" => cas_id,
+            other => panic!("expected the value appended. Got: {:?} {:?}", other, buf),
+        };
+        match client.gets("get_into_plain") {
+            Ok(Some(item)) if item.cas_id == cas_id => (),
+            other => panic!("expected the cas id of gets. Got: {:?}", other),
+        }
+        buf.clear();
+        match client.get_into("get_into_zlib", &mut buf) {
+            Ok(Some(meta)) if meta.len == value.len() && buf == value => {}
+            other => panic!("expected the value decompressed. Got: {:?}", other),
+        }
+        match client.get_into("get_into_missing", &mut buf) {
+            Ok(None) if buf == value => (),
+            other => panic!("expected a miss leaving the buffer. Got: {:?}", other),
+        }
+
+        // A response cut in the middle of the value
+        let addr = mock_server(b"VALUE key 0 10 1\r\nfive!");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let mut buf = Vec::from("head:");
+        match client
+            .get_into("key", &mut buf)
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::CorruptResponse(_)) if buf == b"head:" => (),
+            other => panic!("expected the buffer untouched. Got: {:?} {:?}", other, buf),
+        }
+    }
+
+    #[test]
+    fn test_get_multi() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let keys = ["multi_a", "multi_b", "multi_c"];
+        for key in keys {
+            let item = Item::from_parts(key.to_string(), Vec::from(key), 0, 60);
+            if let Err(error) = client.set(&item) {
+                panic!("did not expect set to fail: {}", error)
+            }
+        }
+
+        let items = match client.get_multi(&["multi_a", "multi_b", "multi_c", "multi_missing"]) {
+            Ok(items) => items,
+            Err(error) => panic!("did not expect get multi to fail: {}", error),
+        };
+        if items.len() != keys.len() {
+            panic!("expected {} items, got {}", keys.len(), items.len())
+        }
+        for key in keys {
+            match items.get(key) {
+                Some(item) if item.value == key.as_bytes() => (),
+                other => panic!("unexpected item for key {}: {:?}", key, other),
+            }
+        }
+
+        let long_key = "k".repeat(251);
+        match client.get_multi(&["multi_a", &long_key]) {
+            Err(OperationError::MalformedKey(KeyError::TooLong(251))) => (),
+            other => panic!("expected a malformed key error. Got: {:?}", other),
+        }
+
+        for key in keys {
+            if let Err(error) = client.delete(key) {
+                panic!("did not expect delete to fail: {}", error)
+            }
+        }
+    }
+
+    #[test]
+    fn illegal_keys_are_rejected() {
+        let long_key = "k".repeat(250);
+        if let Err(error) = legal_key(&long_key) {
+            panic!("expected a 250 byte key to be legal: {}", error)
+        }
+        let cases = [
+            ("k".repeat(251), KeyError::TooLong(251)),
+            (String::new(), KeyError::Empty),
+            (String::from("a b"), KeyError::IllegalByte(b' ', 1)),
+            (String::from("a\r\nb"), KeyError::IllegalByte(b'\r', 1)),
+            (String::from("\tab"), KeyError::IllegalByte(b'\t', 0)),
+            (String::from("ab\x7f"), KeyError::IllegalByte(0x7f, 2)),
+        ];
+        for (key, reason) in cases {
+            match legal_key(&key) {
+                Err(OperationError::MalformedKey(got)) if got == reason => (),
+                other => panic!("expected {:?} for {:?}. Got: {:?}", reason, key, other),
+            }
+        }
+        if let Err(error) = legal_key("ключ:1") {
+            panic!("expected non-ASCII keys to be legal: {}", error)
+        }
+
+        // Nothing is written, so the next command on the connection still gets its own response
+        let addr = mock_server(b"DELETED\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.delete("a b") {
+            Err(OperationError::MalformedKey(KeyError::IllegalByte(b' ', 1))) => (),
+            other => panic!("expected a malformed key error. Got: {:?}", other),
+        }
+        if let Err(error) = client.delete("a") {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn response_lines_are_read_into_a_reused_buffer() {
+        let _guard = lock_local_server();
+        let mut client = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0).unwrap();
+        let mut conn = client.get_conn(client.server_addr()).unwrap();
+        // The first response sizes the buffer
+        if let Err(error) = Client::version_from_conn(&mut conn) {
+            panic!("did not expect version to fail: {}", error)
+        }
+        let (allocations, failed) = allocation_count(|| {
+            (0..100)
+                .map(|_| Client::version_from_conn(&mut conn))
+                .any(|version| version.is_err())
+        });
+        // Only the returned version strings are allocated
+        if failed || allocations > 100 {
+            panic!(
+                "expected one allocation per version. Got: {} for 100",
+                allocations
+            )
+        }
+
+        // A failed read must not leave bytes behind for the next one
+        conn.line_buf.extend_from_slice(b"VERSION stale");
+        match Client::version_from_conn(&mut conn) {
+            Ok(version) if !version.contains("stale") => (),
+            other => panic!("expected a fresh version line. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn large_values_are_written_without_copies() {
+        let item = Item::new("large", vec![b'v'; 512 * 1024]).unwrap();
+
+        let addr = mock_server(b"STORED\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match largest_allocation(|| client.set(&item)) {
+            (largest, Ok(())) if largest < item.value.len() => (),
+            other => panic!(
+                "expected set to allocate less than the value. Got: {:?}",
+                other
+            ),
+        }
+
+        let addr = mock_server(b"HD\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let flags = MetaSetFlags::default();
+        match largest_allocation(|| client.meta_set(&item.key, &item.value, &flags)) {
+            (largest, Ok(_)) if largest < item.value.len() => (),
+            other => panic!(
+                "expected meta set to allocate less than the value. Got: {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn oversized_values_are_rejected_before_sending() {
+        let (addr, lines) = recording_server(b"STORED\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let item = Item::new("large", vec![b'v'; 1024 * 1024 + 1]).unwrap();
+        let expected = (1024 * 1024 + 1, 1024 * 1024);
+        match client.set(&item) {
+            Err(OperationError::ValueTooLarge { size, limit }) if (size, limit) == expected => (),
+            other => panic!("expected a value too large error. Got: {:?}", other),
+        }
+        match client.meta_set(&item.key, &item.value, &MetaSetFlags::default()) {
+            Err(OperationError::ValueTooLarge { .. }) => (),
+            other => panic!("expected a value too large error. Got: {:?}", other),
+        }
+        match client.set_multi(std::slice::from_ref(&item)) {
+            Ok(failures) => match failures.as_slice() {
+                [(key, OperationError::ValueTooLarge { .. })] if key == "large" => (),
+                _ => panic!("expected large to fail. Got: {:?}", failures),
+            },
+            Err(error) => panic!("did not expect set multi to fail: {}", error),
+        }
+        if let Ok(line) = lines.recv_timeout(Duration::from_millis(100)) {
+            panic!("expected nothing to be sent. Got: {}", line)
+        }
+
+        client.set_max_value_size(4 * 1024 * 1024);
+        if let Err(error) = client.set(&item) {
+            panic!(
+                "did not expect set to fail under the raised limit: {}",
+                error
+            )
+        }
+        match lines.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) if line == "set large 0 0 1048577" => (),
+            other => panic!("expected the set command line. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reserved_flags_are_rejected_and_stripped() {
+        let (addr, lines) = recording_server(b"STORED\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let item = Item::new("key", "value").unwrap().with_flags(1 << 24);
+        match client.set(&item) {
+            Err(OperationError::ReservedFlags(flags)) if flags == 1 << 24 => (),
+            other => panic!("expected a reserved flags error. Got: {:?}", other),
+        }
+        match client.set_multi(std::slice::from_ref(&item)) {
+            Ok(failures) => match failures.as_slice() {
+                [(key, OperationError::ReservedFlags(_))] if key == "key" => (),
+                _ => panic!("expected key to fail. Got: {:?}", failures),
+            },
+            Err(error) => panic!("did not expect set multi to fail: {}", error),
+        }
+        if let Ok(line) = lines.recv_timeout(Duration::from_millis(100)) {
+            panic!("expected nothing to be sent. Got: {}", line)
+        }
+        if let Err(error) = client.set(&item.with_flags(Flags::USER_MASK)) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match lines.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) if line == "set key 16777215 0 5" => (),
+            other => panic!("expected the user flags to be sent as is. Got: {:?}", other),
+        }
+
+        // 0x01000005: reserved bits set by the client next to user flags of 5
+        let addr = mock_server(b"VALUE key 16777221 5\r\nvalue\r\nEND\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.get("key") {
+            Ok(Some(item)) if item.flags == 5 => (),
+            other => panic!(
+                "expected the reserved flags to be stripped. Got: {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn test_compression_threshold() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client.with_compression_threshold(Some(1024)),
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let raw_flags = MetaGetFlags {
+            value: true,
+            client_flags: true,
+            ..Default::default()
+        };
+
+        let html = b"<li class=\"fragment\">item</li>".repeat(1000);
+        let items = [
+            Item::new("compressed_html", html.clone())
+                .unwrap()
+                .with_flags(3),
+            Item::new("compressed_small", "<li>item</li>").unwrap(),
+            // Doesn't repeat, so compressing it would only make it larger
+            Item::new("compressed_noise", noise(4096)).unwrap(),
+        ];
+        for item in &items {
+            if let Err(error) = client.set(item) {
+                panic!("did not expect set to fail: {}", error)
+            }
+        }
+        match client.meta_get("compressed_html", &raw_flags) {
+            Ok(Some(item))
+                if item.flags == Some(0x8000_0003)
+                    && item
+                        .value
+                        .as_ref()
+                        .is_some_and(|value| value.len() < html.len() / 10) => {}
+            other => panic!("expected a compressed value. Got: {:?}", other),
+        }
+        for key in ["compressed_small", "compressed_noise"] {
+            match client.meta_get(key, &raw_flags) {
+                Ok(Some(item)) if item.flags == Some(0) => (),
+                other => panic!("expected {} to be stored as is. Got: {:?}", key, other),
+            }
+        }
+
+        // Compressed values are decompressed whatever the threshold of the client reading them
+        client.set_compression_threshold(None);
+        let keys: Vec<&str> = items.iter().map(|item| item.key.as_str()).collect();
+        match client.get_multi(&keys) {
+            Ok(found) if items.iter().all(|item| found.get(&item.key) == Some(item)) => (),
+            other => panic!("expected every item back as set. Got: {:?}", other),
+        }
+        match client.get("compressed_html") {
+            Ok(Some(item)) if item == items[0] => (),
+            other => panic!("expected the html back. Got: {:?}", other),
+        }
+
+        // Flagged as compressed by the client, which `set` doesn't let callers do
+        let corrupt = StoredValue {
+            value: b"not zlib".as_slice().into(),
+            flags: 0x8000_0000,
+            expiration: Expiration::Never,
+        };
+        if let Err(error) = client.set_encoded("compressed_corrupt", corrupt) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.get("compressed_corrupt") {
+            Err(OperationError::Decompress(_)) => (),
+            other => panic!("expected a decompress error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn corrupt_responses_quote_a_short_excerpt() {
+        // Value bytes read as a response line by a connection that lost track of the responses
+        let mut response = vec![b'v'; 64 * 1024];
+        response.extend_from_slice(b"\r\n");
+        let addr = mock_server(response.leak());
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.set(&Item::new("key", "value").unwrap()) {
+            Err(error)
+                if matches!(error.without_context(), OperationError::CorruptResponse(_))
+                    && error.to_string().len() < 200 => {}
+            other => panic!("expected a short corrupt response error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn connection_errors_name_the_verb_key_and_server() {
+        let addr = mock_server(b"VALUE key zero 5\r\nvalue\r\nEND\r\n");
+        let mut client =
+            Client::new(addr.clone(), None, 0).expect("could not connect to mock server");
+        let key = "k".repeat(250);
+        let error = match client.get(&key) {
+            Err(error @ OperationError::WithContext { .. }) => error,
+            other => panic!("expected an error with context. Got: {:?}", other),
+        };
+        let message = error.to_string();
+        let excerpt = format!("get `{}...", "k".repeat(64));
+        if !message.contains(&excerpt) || !message.contains(&addr) || message.contains(&key) {
+            panic!(
+                "expected the verb, a cut short key and the server. Got: {}",
+                message
+            )
+        }
+        match error.without_context() {
+            OperationError::CorruptResponse(_) => (),
+            other => panic!("expected a corrupt response error. Got: {:?}", other),
+        }
+
+        // Outcomes are returned as they are
+        let addr = mock_server(b"NOT_FOUND\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.delete("key") {
+            Err(OperationError::CacheMiss) => (),
+            other => panic!("expected a bare cache miss. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn server_errors_carry_their_message() {
+        let responses: [(&'static [u8], &str); 2] = [
+            (
+                b"SERVER_ERROR out of memory storing object\r\n",
+                "out of memory storing object",
+            ),
+            (
+                b"SERVER_ERROR object too large for cache\r\n",
+                "object too large for cache",
+            ),
+        ];
+        for (response, message) in responses {
+            let addr = mock_server(response);
+            let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+            match client
+                .set(&Item::new("key", "value").unwrap())
+                .as_ref()
+                .map_err(OperationError::without_context)
+            {
+                Err(OperationError::Server(error_msg)) if error_msg == message => (),
+                other => panic!("expected a server error {:?}. Got: {:?}", message, other),
+            }
+            // The connection is in sync, the error being the whole response
+            if client
+                .pool_stats()
+                .values()
+                .any(|stats| stats.discarded != 0)
+            {
+                panic!("expected the connection to be kept")
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_commands_name_their_verb() {
+        type Op = fn(&mut Client) -> Result<(), OperationError>;
+        let ops: [(&str, Op); 4] = [
+            ("set", |client| {
+                client.set(&Item::new("key", "value").unwrap())
+            }),
+            ("get", |client| client.get("key").map(|_| ())),
+            ("delete", |client| client.delete("key")),
+            ("incr", |client| client.increment("key", 1).map(|_| ())),
+        ];
+        for (verb, op) in ops {
+            let addr = mock_server(b"ERROR\r\n");
+            let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+            match op(&mut client)
+                .as_ref()
+                .map_err(OperationError::without_context)
+            {
+                Err(OperationError::UnsupportedCommand(name)) if name == verb => (),
+                other => panic!("expected {} to be unsupported. Got: {:?}", verb, other),
+            }
+            if client
+                .pool_stats()
+                .values()
+                .any(|stats| stats.discarded != 0)
+            {
+                panic!("expected the connection to be kept")
+            }
+        }
+
+        type Operation = fn(&mut Client) -> Result<(), OperationError>;
+        let operations: [(&str, Operation); 5] = [
+            ("get", |client| client.get("key").map(drop)),
+            ("delete", |client| client.delete("key")),
+            ("incr", |client| client.increment("key", 1).map(drop)),
+            ("version", |client| client.version().map(drop)),
+            ("stats", |client| client.stats().map(drop)),
+        ];
+        for (name, operation) in operations {
+            let addr = mock_server(b"SERVER_ERROR busy\r\n");
+            let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+            let error = match operation(&mut client) {
+                Err(OperationError::ServerFailures(mut failures)) if failures.len() == 1 => {
+                    failures.remove(0).1
+                }
+                Err(error) => error,
+                Ok(()) => panic!("expected {} to fail", name),
+            };
+            match error.without_context() {
+                OperationError::Server(error_msg) if error_msg == "busy" => (),
+                other => panic!(
+                    "expected {} to fail with the server error. Got: {:?}",
+                    name, other
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn get_multi_failure_names_the_server() {
+        let addr = mock_server(b"SERVER_ERROR out of memory\r\n");
+        let mut client =
+            Client::new(addr.clone(), None, 0).expect("could not connect to mock server");
+        match client.get_multi(&["a", "b"]) {
+            Err(OperationError::ServerFailures(failures)) => {
+                if failures.len() != 1 || failures[0].0.to_string() != addr {
+                    panic!("expected the failure to name {}. Got: {:?}", addr, failures)
+                }
+            }
+            other => panic!("expected a server failure. Got: {:?}", other),
         }
-        // Test `flush_all`
-        if let Err(error) = client.flush_all() {
-            panic!("Did not expect flush all to fail: {}", error)
+    }
+
+    #[test]
+    fn test_gets() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let item_key = "gets_key".to_string();
+        let item = Item::from_parts(item_key.clone(), Vec::from("first"), 0, 60);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+
+        let first_cas_id = match client.gets(&item_key) {
+            Ok(Some(item)) => item.cas_id,
+            other => panic!("expected an item. Got: {:?}", other),
+        };
+        match client.gets(&item_key) {
+            Ok(Some(item)) if item.cas_id == first_cas_id => (),
+            other => panic!("expected cas id {}. Got: {:?}", first_cas_id, other),
+        }
+
+        let item = Item::from_parts(item_key.clone(), Vec::from("second"), 0, 60);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.gets(&item_key) {
+            Ok(Some(item)) if item.cas_id != first_cas_id => (),
+            other => panic!("expected the cas id to change. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete(&item_key) {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let stats = match client.stats() {
+            Ok(stats) => stats,
+            Err(error) => panic!("did not expect stats to fail: {}", error),
+        };
+        let server_addr: std::net::SocketAddr = LOCALHOST_TCP_ADDR.parse().unwrap();
+        match stats.get(&server_addr) {
+            Some(server_stats) => {
+                for name in ["pid", "uptime", "version", "get_hits", "get_misses"] {
+                    if !server_stats.contains_key(name) {
+                        panic!("expected stat {} to be present", name)
+                    }
+                }
+            }
+            None => panic!("expected stats for {}", server_addr),
+        }
+    }
+
+    #[test]
+    fn test_server_stats() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let _ = client.get("server_stats_key");
+        let stats = match client.server_stats() {
+            Ok(stats) => stats,
+            Err(error) => panic!("did not expect server_stats to fail: {}", error),
+        };
+        let server_addr: std::net::SocketAddr = LOCALHOST_TCP_ADDR.parse().unwrap();
+        match stats.get(&server_addr) {
+            Some(server_stats)
+                if server_stats.uptime.is_some()
+                    && server_stats.version.is_some()
+                    && server_stats.limit_maxbytes.is_some()
+                    && server_stats.hit_ratio().is_some() => {}
+            other => panic!("expected typed stats for {}. Got: {:?}", server_addr, other),
+        }
+    }
+
+    #[test]
+    fn aggregate_stats_reports_failed_servers_and_sums_the_rest() {
+        let _guard = lock_local_server();
+        let busy = mock_server(b"SERVER_ERROR busy\r\n");
+        let servers = [LOCALHOST_TCP_ADDR, busy.as_str()];
+        let mut client = Client::with_servers(&servers, None, 0, ProtocolMode::Ascii)
+            .expect("could not connect to servers");
+
+        let cluster = match client.aggregate_stats() {
+            Ok(cluster) => cluster,
+            Err(error) => panic!("did not expect aggregate_stats to fail: {}", error),
+        };
+        let busy_addr: std::net::SocketAddr = busy.parse().unwrap();
+        match cluster.failures.as_slice() {
+            [(addr, OperationError::Server(_))] if *addr == busy_addr => (),
+            other => panic!(
+                "expected {} to be reported as failed. Got: {:?}",
+                busy_addr, other
+            ),
+        }
+        match cluster {
+            crate::ClusterStats {
+                servers: 1,
+                max_uptime: Some(_),
+                min_uptime: Some(_),
+                limit_maxbytes,
+                ..
+            } if limit_maxbytes > 0 => (),
+            other => panic!("expected the stats of the local server. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_stats_returns_no_stats() {
+        let addr = mock_server(b"END\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.stats() {
+            Err(OperationError::ServerFailures(failures)) => match failures.as_slice() {
+                [(_, OperationError::NoStats)] => (),
+                _ => panic!("expected a no stats error. Got: {:?}", failures),
+            },
+            other => panic!("expected a no stats error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stats_with_arg() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        for arg in ["items", "slabs", "sizes"] {
+            if let Err(error) = client.stats_with_arg(arg) {
+                panic!("did not expect stats {} to fail: {}", arg, error)
+            }
+        }
+
+        match client.stats_with_arg("items\r\nflush_all") {
+            Err(OperationError::Client(_)) => (),
+            other => panic!("expected an invalid argument error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stats_with_arg_respects_timeout() {
+        let addr = mock_server(b"");
+        let mut client = Client::new(addr, Some(Duration::from_millis(100)), 0)
+            .expect("could not connect to mock server");
+        let start = std::time::Instant::now();
+        match client.stats_with_arg("sizes") {
+            Err(OperationError::ServerFailures(failures)) => match failures.as_slice() {
+                [(_, OperationError::Timeout(_))] => (),
+                _ => panic!("expected a timeout. Got: {:?}", failures),
+            },
+            other => panic!("expected a timeout. Got: {:?}", other),
+        }
+        if start.elapsed() > std::time::Duration::from_secs(2) {
+            panic!("expected stats to give up after the configured timeout")
+        }
+    }
+
+    #[test]
+    fn stalled_server_times_out_and_discards_the_connection() {
+        let addr = mock_server(b"");
+        let mut client = Client::new(addr, Some(Duration::from_millis(100)), 0)
+            .expect("could not connect to mock server");
+        let start = Instant::now();
+        match client
+            .get("key")
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::Timeout(_)) => (),
+            other => panic!("expected a timeout. Got: {:?}", other),
+        }
+        if start.elapsed() > Duration::from_secs(2) {
+            panic!("expected get to give up after the configured timeout")
+        }
+        // The response may still arrive, so the connection can't be reused
+        if !client.pool().free_conns.is_empty() {
+            panic!("expected the timed out connection to be discarded")
+        }
+    }
+
+    #[test]
+    fn cache_misses_reuse_the_connection_and_timeouts_do_not() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let dials = |client: &Client| client.pool_stats().values().map(|s| s.dials).sum::<u64>();
+        let before = dials(&client);
+        match client.delete("connection_reuse_missing_key") {
+            Err(OperationError::CacheMiss) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+        if let Err(error) = client.get("connection_reuse_missing_key") {
+            panic!("did not expect get to fail: {}", error)
+        }
+        if dials(&client) != before {
+            panic!("expected the get to reuse the connection of the cache miss")
+        }
+
+        let addr = slow_server(Duration::from_millis(500));
+        let mut client = Client::new(addr, Some(Duration::from_millis(100)), 0)
+            .expect("could not connect to mock server");
+        let before = dials(&client);
+        for _ in 0..2 {
+            match client
+                .get("key")
+                .as_ref()
+                .map_err(OperationError::without_context)
+            {
+                Err(OperationError::Timeout(_)) => (),
+                other => panic!("expected a timeout. Got: {:?}", other),
+            }
+        }
+        // The timed out connection would read the late response, so the second get dialed
+        if dials(&client) != before + 1 {
+            panic!(
+                "expected the get after a timeout to dial. Got {} dials",
+                dials(&client) - before
+            )
+        }
+    }
+
+    #[test]
+    fn test_flush_all_with_delay() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let item_key = "flush_delay_key".to_string();
+        let item = Item::from_parts(item_key.clone(), Vec::from("value"), 0, 60);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+
+        if let Err(error) = client.flush_all(Some(30)) {
+            panic!("did not expect flush all to fail: {}", error)
+        }
+        match client.get(&item_key) {
+            Ok(Some(_)) => (),
+            other => panic!(
+                "expected item to be readable before the delay. Got: {:?}",
+                other
+            ),
+        }
+
+        // An immediate flush replaces the pending delayed one so later tests aren't affected
+        if let Err(error) = client.flush_all(None) {
+            panic!("did not expect flush all to fail: {}", error)
+        }
+        match client.get(&item_key) {
+            Ok(None) => (),
+            other => panic!("expected item to be flushed. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_version() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let versions = match client.version() {
+            Ok(versions) => versions,
+            Err(error) => panic!("did not expect version to fail: {}", error),
+        };
+        let server_addr: std::net::SocketAddr = LOCALHOST_TCP_ADDR.parse().unwrap();
+        match versions.get(&server_addr) {
+            Some(version) if !version.is_empty() && !version.ends_with('\n') => (),
+            other => panic!("expected a version for {}. Got: {:?}", server_addr, other),
+        }
+    }
+
+    #[test]
+    fn malformed_version_returns_corrupt_response() {
+        let addr = mock_server(b"1.6.21\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.version() {
+            Err(OperationError::ServerFailures(failures)) => match failures.as_slice() {
+                [(_, OperationError::CorruptResponse(_))] => (),
+                _ => panic!("expected a corrupt response error. Got: {:?}", failures),
+            },
+            other => panic!("expected a corrupt response error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn close_sends_quit() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut lines = Vec::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                lines.push(std::mem::take(&mut line));
+            }
+            lines
+        });
+
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        client.close();
+        if !client.pool().free_conns.is_empty() {
+            panic!("expected the pool to be empty after close")
+        }
+        let lines = server.join().unwrap();
+        if lines != vec!["quit\r\n".to_string()] {
+            panic!("expected a single quit command. Got: {:?}", lines)
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        client.close();
+        if let Err(error) = client.version() {
+            panic!(
+                "expected operations after close to dial a new connection: {}",
+                error
+            )
+        }
+    }
+
+    #[test]
+    fn test_noreply() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let num_keys = 10_000;
+        for i in 0..num_keys {
+            let item = Item::from_parts(format!("noreply_{}", i), Vec::from("value"), 0, 60);
+            if let Err(error) = client.set(&item) {
+                panic!("did not expect set to fail: {}", error)
+            }
+        }
+
+        for i in 0..num_keys {
+            let item = Item::from_parts(format!("noreply_{}", i), Vec::from("other"), 0, 1);
+            if let Err(error) = client.set_noreply(&item) {
+                panic!("did not expect set noreply to fail: {}", error)
+            }
+        }
+
+        // The connection must still be in sync after the noreply commands
+        match client.get("noreply_0") {
+            Ok(Some(item)) if item.value == b"other" => (),
+            other => panic!("expected the noreply set to be applied. Got: {:?}", other),
+        }
+
+        if let Err(error) =
+            client.touch_noreply("noreply_0", Expiration::In(Duration::from_secs(60)))
+        {
+            panic!("did not expect touch noreply to fail: {}", error)
+        }
+        if let Err(error) = client.delete_noreply("noreply_1") {
+            panic!("did not expect delete noreply to fail: {}", error)
+        }
+        if let Err(error) = client.delete_noreply("noreply_missing") {
+            panic!(
+                "did not expect delete noreply of a missing key to fail: {}",
+                error
+            )
+        }
+        match client.get_multi(&["noreply_0", "noreply_1"]) {
+            Ok(items) if items.contains_key("noreply_0") && !items.contains_key("noreply_1") => {}
+            other => panic!("expected only noreply_0 to remain. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_verbosity() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        if let Err(error) = client.verbosity(1) {
+            panic!("did not expect verbosity to fail: {}", error)
+        }
+        if let Err(error) = client.verbosity(0) {
+            panic!("did not expect verbosity to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn verbosity_failure_names_the_server() {
+        let addr = mock_server(b"ERROR\r\n");
+        let mut client =
+            Client::new(addr.clone(), None, 0).expect("could not connect to mock server");
+        match client.verbosity(1) {
+            Err(OperationError::ServerFailures(failures)) => {
+                if failures.len() != 1 || failures[0].0.to_string() != addr {
+                    panic!("expected the failure to name {}. Got: {:?}", addr, failures)
+                }
+            }
+            other => panic!("expected a server failure. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_set_multi() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        // Let the oversized item through to the server
+        client.set_max_value_size(4 * 1024 * 1024);
+
+        let mut items: Vec<Item> = (0..100)
+            .map(|i| Item::from_parts(format!("set_multi_{}", i), Vec::from(i.to_string()), 0, 60))
+            .collect();
+        // Too large for the server, which must not throw off the pairing of the following responses
+        items[50] = Item::from_parts("set_multi_50".to_string(), vec![0; 2 * 1024 * 1024], 0, 60);
+
+        let failures = match client.set_multi(&items) {
+            Ok(failures) => failures,
+            Err(error) => panic!("did not expect set multi to fail: {}", error),
+        };
+        match failures.as_slice() {
+            [(key, _)] if key == "set_multi_50" => (),
+            _ => panic!("expected only set_multi_50 to fail. Got: {:?}", failures),
+        }
+
+        let keys: Vec<String> = (0..100).map(|i| format!("set_multi_{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let found = match client.get_multi(&keys) {
+            Ok(found) => found,
+            Err(error) => panic!("did not expect get multi to fail: {}", error),
+        };
+        if found.len() != 99 || found.contains_key("set_multi_50") {
+            panic!("expected every item but set_multi_50 to be stored")
+        }
+        if found["set_multi_51"].value != b"51" {
+            panic!("expected set_multi_51 to hold its own value")
+        }
+    }
+
+    #[test]
+    fn set_multi_reports_failed_keys_in_order() {
+        let addr = mock_server(b"STORED\r\nNOT_STORED\r\nSTORED\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let items: Vec<Item> = ["a", "b", "c"]
+            .iter()
+            .map(|key| Item::from_parts(key.to_string(), Vec::from("value"), 0, 0))
+            .collect();
+        match client.set_multi(&items) {
+            Ok(failures) => match failures.as_slice() {
+                [(key, OperationError::NotStored)] if key == "b" => (),
+                _ => panic!("expected only b to fail. Got: {:?}", failures),
+            },
+            Err(error) => panic!("did not expect set multi to fail: {}", error),
+        }
+    }
+
+    #[test]
+    fn test_delete_multi() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        for key in ["delete_multi_a", "delete_multi_c"] {
+            let item = Item::from_parts(key.to_string(), Vec::from("value"), 0, 60);
+            if let Err(error) = client.set(&item) {
+                panic!("did not expect set to fail: {}", error)
+            }
+        }
+
+        let deleted =
+            match client.delete_multi(&["delete_multi_a", "delete_multi_b", "delete_multi_c"]) {
+                Ok(deleted) => deleted,
+                Err(error) => panic!("did not expect delete multi to fail: {}", error),
+            };
+        let expected: std::collections::HashMap<String, bool> = [
+            ("delete_multi_a".to_string(), true),
+            ("delete_multi_b".to_string(), false),
+            ("delete_multi_c".to_string(), true),
+        ]
+        .into_iter()
+        .collect();
+        if deleted != expected {
+            panic!("expected {:?}. Got: {:?}", expected, deleted)
+        }
+    }
+
+    #[test]
+    fn delete_multi_failure_discards_the_connection() {
+        let addr = mock_server(b"DELETED\r\nBUSY\r\nNOT_FOUND\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.delete_multi(&["a", "b", "c"]) {
+            Err(OperationError::ServerFailures(_)) => (),
+            other => panic!("expected a server failure. Got: {:?}", other),
+        }
+        if !client.pool().free_conns.is_empty() {
+            panic!("expected the desynchronized connection to be discarded")
+        }
+    }
+
+    #[test]
+    fn test_touch_multi() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        for key in ["touch_multi_a", "touch_multi_c"] {
+            let item = Item::from_parts(key.to_string(), Vec::from("value"), 0, 1);
+            if let Err(error) = client.set(&item) {
+                panic!("did not expect set to fail: {}", error)
+            }
+        }
+
+        let missing = match client.touch_multi(
+            &["touch_multi_a", "touch_multi_b", "touch_multi_c"],
+            Expiration::In(Duration::from_secs(60)),
+        ) {
+            Ok(missing) => missing,
+            Err(error) => panic!("did not expect touch multi to fail: {}", error),
+        };
+        if missing.len() != 1 || !missing.contains("touch_multi_b") {
+            panic!(
+                "expected only touch_multi_b to be missing. Got: {:?}",
+                missing
+            )
+        }
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        match client.get_multi(&["touch_multi_a", "touch_multi_c"]) {
+            Ok(items) if items.len() == 2 => (),
+            other => panic!(
+                "expected touched items to still be retrievable. Got: {:?}",
+                other
+            ),
+        }
+        if let Err(error) = client.delete_multi(&["touch_multi_a", "touch_multi_c"]) {
+            panic!("did not expect delete multi to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn test_gets_multi() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        // Values containing protocol terminators must be read by length, not by line
+        let tricky_value = Vec::from("line\r\nEND\r\nVALUE x 0 1\r\n");
+        let item = Item::from_parts("gets_multi_a".to_string(), tricky_value.clone(), 0, 60);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        let item = Item::from_parts("gets_multi_c".to_string(), Vec::from("c"), 0, 60);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+
+        let items = match client.gets_multi(&["gets_multi_a", "gets_multi_b", "gets_multi_c"]) {
+            Ok(items) => items,
+            Err(error) => panic!("did not expect gets multi to fail: {}", error),
+        };
+        if items.len() != 2 {
+            panic!("expected two items. Got: {:?}", items)
+        }
+        if items["gets_multi_a"].value != tricky_value {
+            panic!("expected the value to be read by length")
+        }
+        let single_cas_id = match client.gets("gets_multi_c") {
+            Ok(Some(item)) => item.cas_id,
+            other => panic!("expected an item. Got: {:?}", other),
+        };
+        if items["gets_multi_c"].cas_id == 0 || items["gets_multi_c"].cas_id != single_cas_id {
+            panic!("expected gets multi to return the cas unique")
+        }
+    }
+
+    #[test]
+    fn gets_multi_ignores_unknown_columns() {
+        let addr = mock_server(b"VALUE a 1 2 10 extra\r\naa\r\nVALUE c 3 1 30\r\nc\r\nEND\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let items = match client.gets_multi(&["a", "b", "c"]) {
+            Ok(items) => items,
+            Err(error) => panic!("did not expect gets multi to fail: {}", error),
+        };
+        match (items.get("a"), items.get("c")) {
+            (Some(a), Some(c))
+                if a.value == b"aa" && a.cas_id == 10 && c.flags == 3 && c.cas_id == 30 => {}
+            _ => panic!("unexpected items: {:?}", items),
+        }
+    }
+
+    #[test]
+    fn test_increment_with_initial() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let key = "incr_initial_counter";
+        match client.increment_with_initial(key, 5, 10, Expiration::In(Duration::from_secs(60))) {
+            Ok(10) => (),
+            other => panic!(
+                "expected the counter to be created with 10. Got: {:?}",
+                other
+            ),
+        }
+        match client.increment_with_initial(key, 5, 10, Expiration::In(Duration::from_secs(60))) {
+            Ok(15) => (),
+            other => panic!(
+                "expected the counter to be incremented to 15. Got: {:?}",
+                other
+            ),
+        }
+        if let Err(error) = client.delete(key) {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn increment_with_initial_retries_after_losing_the_race() {
+        // incr misses, another client wins the add, the retried incr sees its value
+        let addr = scripted_server(&[
+            (1, b"NOT_FOUND\r\n"),
+            (2, b"NOT_STORED\r\n"),
+            (1, b"15\r\n"),
+        ]);
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.increment_with_initial(
+            "counter",
+            5,
+            10,
+            Expiration::In(Duration::from_secs(60)),
+        ) {
+            Ok(15) => (),
+            other => panic!("expected the winner's value plus delta. Got: {:?}", other),
         }
     }
 }
@@ -0,0 +1,191 @@
+use crate::errors::OperationError;
+use crate::expiration::Expiration;
+use crate::item::Item;
+use crate::trace;
+use crate::Client;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// A lock on `key`, held by whoever `add`s it first until its TTL runs out or it's released; Meant
+// for deduplicating work across processes, not for mutual exclusion that must never fail: the
+// lock expires after `ttl` even while its holder is still working (or paused), after which
+// another process can take it, so `ttl` must comfortably exceed the longest critical section;
+// The server can also evict the lock early under memory pressure, or lose it on a restart;
+#[derive(Debug, Clone)]
+pub struct MemcacheLock {
+    key: String,
+    ttl: Expiration,
+}
+
+impl MemcacheLock {
+    pub fn new(key: impl Into<String>, ttl: Expiration) -> Self {
+        MemcacheLock {
+            key: key.into(),
+            ttl,
+        }
+    }
+
+    // Takes the lock, or returns `None` while another holder has it; Fails for a `ttl` that never
+    // expires, which a crashed holder would keep forever;
+    pub fn try_lock(&self, client: &mut Client) -> Result<Option<LockGuard>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.try_lock", &self.key), || {
+            if self.ttl == Expiration::Never {
+                return Err(OperationError::InvalidExpiration(
+                    "a lock must expire".to_string(),
+                ));
+            }
+            let guard = LockGuard {
+                key: self.key.clone(),
+                token: lock_token(),
+            };
+            let item = Item::new(&*guard.key, &*guard.token)?.with_expiration(self.ttl);
+            match client.add(&item) {
+                Ok(()) => Ok(Some(guard)),
+                Err(OperationError::NotStored) => Ok(None),
+                Err(error) => Err(error),
+            }
+        })
+    }
+}
+
+// A held `MemcacheLock`, identified by the random token stored as its value; Dropping the guard
+// doesn't release the lock, which then expires with its TTL, so releasing doesn't hide network
+// calls (and their errors) in `Drop`;
+#[derive(Debug)]
+#[must_use = "the lock is held until released or expired"]
+pub struct LockGuard {
+    key: String,
+    token: String,
+}
+
+impl LockGuard {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    // See `Client::unlock`;
+    pub fn release(self, client: &mut Client) -> Result<bool, OperationError> {
+        client.unlock(&self)
+    }
+}
+
+// Unique enough for lock holders; `RandomState` is seeded differently every time
+fn lock_token() -> String {
+    let high = RandomState::new().build_hasher().finish();
+    let low = RandomState::new().build_hasher().finish();
+    format!("{:016x}{:016x}", high, low)
+}
+
+impl Client {
+    // Shorthand for `MemcacheLock::new(key, ttl).try_lock(self)`;
+    pub fn try_lock(
+        &mut self,
+        key: &str,
+        ttl: Expiration,
+    ) -> Result<Option<LockGuard>, OperationError> {
+        MemcacheLock::new(key, ttl).try_lock(self)
+    }
+
+    // Releases the lock if `guard` still holds it, returning whether it did; A lock that expired,
+    // and possibly went to another holder meanwhile, is left alone; The token is compared on a
+    // `gets` and the lock replaced, through `compare_and_swap`, by an already expired tombstone,
+    // so a holder that takes the lock in between isn't released by mistake;
+    pub fn unlock(&mut self, guard: &LockGuard) -> Result<bool, OperationError> {
+        trace::in_span(trace::op_span!("memcache.unlock", &guard.key), || {
+            let held = match self.gets(&guard.key)? {
+                Some(item) if item.value == guard.token.as_bytes() => item,
+                _ => return Ok(false),
+            };
+            let tombstone = Item {
+                value: Vec::new(),
+                expiration: Expiration::Raw(-1),
+                ..held
+            };
+            match self.compare_and_swap(&tombstone) {
+                Ok(()) => Ok(true),
+                Err(OperationError::CASConflict) | Err(OperationError::CacheMiss) => Ok(false),
+                Err(error) => Err(error),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemcacheLock;
+    use crate::{
+        errors::OperationError,
+        expiration::Expiration,
+        tests::{lock_local_server, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_memcache_lock() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let mut other = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0).unwrap();
+        let ttl = Expiration::In(Duration::from_secs(60));
+        let _ = client.delete("cron_lock");
+
+        let guard = match client.try_lock("cron_lock", ttl) {
+            Ok(Some(guard)) => guard,
+            other => panic!("expected to take the lock. Got: {:?}", other),
+        };
+        match other.try_lock("cron_lock", ttl) {
+            Ok(None) => (),
+            other => panic!("expected the lock to be held. Got: {:?}", other),
+        }
+        match guard.release(&mut client) {
+            Ok(true) => (),
+            other => panic!("expected the lock to be released. Got: {:?}", other),
+        }
+
+        // A holder whose lock expired doesn't release the next holder's
+        let lock = MemcacheLock::new("cron_lock", ttl);
+        let stale = match lock.try_lock(&mut client) {
+            Ok(Some(guard)) => guard,
+            other => panic!("expected to take the released lock. Got: {:?}", other),
+        };
+        client.delete("cron_lock").unwrap();
+        let current = match lock.try_lock(&mut other) {
+            Ok(Some(guard)) if guard.token() != stale.token() => guard,
+            other => panic!("expected to take the expired lock. Got: {:?}", other),
+        };
+        match stale.release(&mut client) {
+            Ok(false) => (),
+            other => panic!("expected a stale release to be refused. Got: {:?}", other),
+        }
+        match client.get("cron_lock") {
+            Ok(Some(item)) if item.value == current.token().as_bytes() => (),
+            other => panic!(
+                "expected the current holder to keep the lock. Got: {:?}",
+                other
+            ),
+        }
+        match client.unlock(&current) {
+            Ok(true) => (),
+            other => panic!("expected the lock to be released. Got: {:?}", other),
+        }
+        match client.unlock(&current) {
+            Ok(false) => (),
+            other => panic!("expected a released lock not to be held. Got: {:?}", other),
+        }
+
+        match client.try_lock("cron_lock", Expiration::Never) {
+            Err(OperationError::InvalidExpiration(_)) => (),
+            other => panic!(
+                "expected a lock that never expires to fail. Got: {:?}",
+                other
+            ),
+        }
+    }
+}
@@ -0,0 +1,1352 @@
+use crate::{
+    errors::{response_excerpt, KeyError, OperationError, WriteReadLineError},
+    flags::Flags,
+    item::ValuePreview,
+    legal_key,
+    protocol::{self, Arg},
+    trace, Client, Conn, CR_LF,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+const VERB_META_GET: &str = "mg";
+const VERB_META_SET: &str = "ms";
+const VERB_META_DELETE: &str = "md";
+const VERB_META_ARITHMETIC: &str = "ma";
+const VERB_META_DEBUG: &str = "me";
+const VERB_META_NOOP: &str = "mn";
+
+const RESULT_META_HIT: &[u8] = b"HD";
+const RESULT_META_VALUE: &[u8] = b"VA";
+const RESULT_META_MISS: &[u8] = b"EN\r\n";
+const RESULT_META_NOOP: &[u8] = b"MN\r\n";
+const RESULT_META_NOT_STORED: &[u8] = b"NS";
+const RESULT_META_EXISTS: &[u8] = b"EX";
+const RESULT_META_NOT_FOUND: &[u8] = b"NF";
+const RESULT_META_DEBUG: &[u8] = b"ME";
+
+// Flags of a meta get (`mg`) request; Each enabled flag asks the server to return the
+// corresponding piece of metadata;
+#[derive(Debug, Default, Clone)]
+pub struct MetaGetFlags {
+    // `v`: return the item value
+    pub value: bool,
+    // `f`: return the client flags
+    pub client_flags: bool,
+    // `t`: return the remaining TTL in seconds
+    pub ttl: bool,
+    // `c`: return the CAS id
+    pub cas: bool,
+    // `h`: return whether the item has been hit before
+    pub hit: bool,
+    // `l`: return the seconds since the item was last accessed
+    pub last_access: bool,
+    // `q`: don't send a response on a miss
+    pub quiet: bool,
+}
+
+impl MetaGetFlags {
+    fn tokens<'a>(&self) -> impl Iterator<Item = Arg<'a>> {
+        [
+            (self.value, "v"),
+            (self.client_flags, "f"),
+            (self.ttl, "t"),
+            (self.cas, "c"),
+            (self.hit, "h"),
+            (self.last_access, "l"),
+            (self.quiet, "q"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, token)| enabled.then_some(Arg::Str(token)))
+    }
+}
+
+// Item returned by a meta get; Fields are only populated when requested through `MetaGetFlags`;
+#[derive(Default, Clone)]
+pub struct MetaItem {
+    // `k`: only returned by batched reads, which need it to match responses to keys
+    pub key: Option<String>,
+    pub value: Option<Vec<u8>>,
+    pub flags: Option<u32>,
+    // Remaining TTL in seconds, `-1` when the item doesn't expire
+    pub ttl: Option<i64>,
+    pub cas_id: Option<u64>,
+    pub hit_before: Option<bool>,
+    // Seconds since the item was last accessed
+    pub last_access: Option<u64>,
+    // `X`: the item was invalidated and is stale
+    pub stale: bool,
+    // `W`: this client won the right to recache the item
+    pub win: bool,
+    // `Z`: another client already won the right to recache the item
+    pub win_sent: bool,
+}
+
+// Shows the value the same way as `Item` does;
+impl std::fmt::Debug for MetaItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetaItem")
+            .field("key", &self.key)
+            .field("value", &self.value.as_deref().map(ValuePreview))
+            .field("flags", &self.flags)
+            .field("ttl", &self.ttl)
+            .field("cas_id", &self.cas_id)
+            .field("hit_before", &self.hit_before)
+            .field("last_access", &self.last_access)
+            .field("stale", &self.stale)
+            .field("win", &self.win)
+            .field("win_sent", &self.win_sent)
+            .finish()
+    }
+}
+
+// Storage mode of a meta set (`M` flag), covering every classic storage verb;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MetaSetMode {
+    #[default]
+    Set,
+    Add,
+    Replace,
+    Append,
+    Prepend,
+}
+
+impl MetaSetMode {
+    fn token(&self) -> &'static str {
+        match self {
+            MetaSetMode::Set => "MS",
+            MetaSetMode::Add => "ME",
+            MetaSetMode::Replace => "MR",
+            MetaSetMode::Append => "MA",
+            MetaSetMode::Prepend => "MP",
+        }
+    }
+}
+
+// Flags of a meta set (`ms`) request;
+#[derive(Debug, Default, Clone)]
+pub struct MetaSetFlags {
+    // `M`: storage mode
+    pub mode: MetaSetMode,
+    // `F`: client flags to store alongside the value, up to `Flags::USER_MASK` like
+    // `Item::flags`
+    pub client_flags: Option<u32>,
+    // `T`: TTL in seconds
+    pub ttl: Option<i32>,
+    // `C`: only store if the item's CAS matches
+    pub compare_cas: Option<u64>,
+    // `E`: use this CAS for the stored item instead of generating one
+    pub new_cas: Option<u64>,
+    // `I`: invalidate; With `C`, a CAS older than the item's marks it stale instead of failing
+    pub invalidate: bool,
+    // `c`: return the CAS of the stored item
+    pub return_cas: bool,
+}
+
+impl MetaSetFlags {
+    fn tokens<'a>(&self) -> impl Iterator<Item = Arg<'a>> {
+        [
+            (self.mode != MetaSetMode::Set).then_some(Arg::Str(self.mode.token())),
+            self.client_flags.map(|flags| Arg::Flag(b'F', flags.into())),
+            self.ttl.map(|ttl| Arg::SignedFlag(b'T', ttl.into())),
+            self.compare_cas.map(|cas| Arg::Flag(b'C', cas)),
+            self.new_cas.map(|cas| Arg::Flag(b'E', cas)),
+            self.invalidate.then_some(Arg::Str("I")),
+            self.return_cas.then_some(Arg::Str("c")),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+// Flags of a meta delete (`md`) request;
+#[derive(Debug, Default, Clone)]
+pub struct MetaDeleteFlags {
+    // `C`: only delete if the item's CAS matches
+    pub compare_cas: Option<u64>,
+    // `I`: invalidate; Marks the item stale instead of removing it
+    pub invalidate: bool,
+    // `T`: new TTL in seconds of an invalidated item
+    pub ttl: Option<i32>,
+}
+
+impl MetaDeleteFlags {
+    fn tokens<'a>(&self) -> impl Iterator<Item = Arg<'a>> {
+        [
+            self.compare_cas.map(|cas| Arg::Flag(b'C', cas)),
+            self.invalidate.then_some(Arg::Str("I")),
+            self.ttl.map(|ttl| Arg::SignedFlag(b'T', ttl.into())),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+// Mode of a meta arithmetic (`M` flag);
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MetaArithMode {
+    #[default]
+    Increment,
+    Decrement,
+}
+
+// Flags of a meta arithmetic (`ma`) request;
+#[derive(Debug, Default, Clone)]
+pub struct MetaArithFlags {
+    // `M`: increment or decrement
+    pub mode: MetaArithMode,
+    // `D`: delta to apply, defaults to 1 on the server
+    pub delta: Option<u64>,
+    // `N`: create a missing counter with this TTL in seconds
+    pub auto_create_ttl: Option<u32>,
+    // `J`: initial value of a counter created through `N`
+    pub initial: Option<u64>,
+    // `T`: update the TTL in seconds
+    pub ttl: Option<i32>,
+    // `C`: only apply if the item's CAS matches
+    pub compare_cas: Option<u64>,
+    // `v`: return the resulting value
+    pub return_value: bool,
+}
+
+impl MetaArithFlags {
+    fn tokens<'a>(&self) -> impl Iterator<Item = Arg<'a>> {
+        [
+            (self.mode == MetaArithMode::Decrement).then_some(Arg::Str("MD")),
+            self.delta.map(|delta| Arg::Flag(b'D', delta)),
+            self.auto_create_ttl.map(|ttl| Arg::Flag(b'N', ttl.into())),
+            self.initial.map(|initial| Arg::Flag(b'J', initial)),
+            self.ttl.map(|ttl| Arg::SignedFlag(b'T', ttl.into())),
+            self.compare_cas.map(|cas| Arg::Flag(b'C', cas)),
+            self.return_value.then_some(Arg::Str("v")),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+// Internal item metadata returned by a meta debug; Fields missing from the response (or
+// not known by the server version) are left empty;
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ItemDebugInfo {
+    // `exp`: seconds until the item expires, `-1` when it doesn't expire
+    pub expiration: Option<i64>,
+    // `la`: seconds since the item was last accessed
+    pub last_access: Option<u64>,
+    pub cas_id: Option<u64>,
+    // `fetch`: whether the item has been fetched before
+    pub fetched: Option<bool>,
+    // `cls`: slab class the item is stored in
+    pub slab_class: Option<u32>,
+    // `size`: total size of the item in memory, in bytes
+    pub size: Option<u64>,
+}
+
+// Flags returned by a successful meta command that doesn't return an item;
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MetaResult {
+    pub cas_id: Option<u64>,
+}
+
+impl Client {
+    pub fn meta_get(
+        &mut self,
+        key: &str,
+        flags: &MetaGetFlags,
+    ) -> Result<Option<MetaItem>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.meta_get", key), || {
+            self.ascii_only("meta_get")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            self.send_meta_get(key, false, flags)
+        })
+    }
+
+    // Like `meta_get` for keys the ASCII protocol can't carry, with spaces, control characters
+    // or raw bytes; The key (after the key prefix) is sent base64 encoded along with the `b`
+    // flag, servers are picked by the encoded key;
+    pub fn meta_get_binary(
+        &mut self,
+        key: &[u8],
+        flags: &MetaGetFlags,
+    ) -> Result<Option<MetaItem>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.meta_get_binary", key), || {
+            self.ascii_only("meta_get_binary")?;
+            let key = binary_key(&[self.key_prefix.as_bytes(), key].concat())?;
+            self.send_meta_get(&key, true, flags)
+        })
+    }
+
+    // `key` is already checked, and base64 encoded when `binary` is set;
+    fn send_meta_get(
+        &mut self,
+        key: &str,
+        binary: bool,
+        flags: &MetaGetFlags,
+    ) -> Result<Option<MetaItem>, OperationError> {
+        let args = [Arg::Str(key)]
+            .into_iter()
+            .chain(binary.then_some(Arg::Str("b")))
+            .chain(flags.tokens());
+
+        self.with_key_conn(VERB_META_GET, key, |conn| {
+            let line = if flags.quiet {
+                // A quiet miss produces no output at all, so the no-op marks the end of the
+                // response
+                protocol::write_command(&mut conn.writer, VERB_META_GET, args)?;
+                conn.meta_noop()?;
+                conn.read_line()?.to_vec()
+            } else {
+                conn.write_command(VERB_META_GET, args)?;
+                conn.read_line()?.to_vec()
+            };
+            if flags.quiet && line.as_slice() == RESULT_META_NOOP {
+                return Ok(None);
+            }
+            let item = parse_meta_get_response(conn, line);
+            // The no-op is answered after an error line too, and must be read to keep the
+            // connection in step
+            if flags.quiet && !matches!(&item, Err(error) if error.fatal_to_connection()) {
+                let line = conn.read_line()?;
+                if line != RESULT_META_NOOP {
+                    return Err(OperationError::CorruptResponse(format!(
+                        "expected meta no-op response, got: {}",
+                        response_excerpt(line)
+                    )));
+                }
+            }
+            item
+        })
+    }
+
+    // Pipelines a quiet meta get per key, fenced by a no-op; Missing keys are simply absent
+    // from the result;
+    pub fn meta_get_multi(
+        &mut self,
+        keys: &[&str],
+        flags: &MetaGetFlags,
+    ) -> Result<HashMap<String, MetaItem>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.meta_get_multi"), || {
+            self.ascii_only("meta_get_multi")?;
+            let keys = self.prefixed_keys(keys);
+            let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+            keys.iter().try_for_each(|key| legal_key(key))?;
+            let mut items = HashMap::new();
+            if keys.is_empty() {
+                return Ok(items);
+            }
+
+            let mut failures = Vec::new();
+            for (addr, keys) in self.group_by_server(keys.to_vec(), |key| *key)? {
+                let result = self.with_conn(addr, |conn| {
+                    Client::meta_get_multi_from_conn(conn, &keys, flags, &mut items)
+                });
+                if let Err(error) = result {
+                    failures.push((addr, error));
+                }
+            }
+            if !failures.is_empty() {
+                return Err(OperationError::ServerFailures(failures));
+            }
+            Ok(items
+                .into_iter()
+                .map(|(key, item)| {
+                    let key = self.unprefixed(key);
+                    let item = MetaItem {
+                        key: Some(key.clone()),
+                        ..item
+                    };
+                    (key, item)
+                })
+                .collect())
+        })
+    }
+
+    fn meta_get_multi_from_conn(
+        conn: &mut Conn,
+        keys: &[&str],
+        flags: &MetaGetFlags,
+        items: &mut HashMap<String, MetaItem>,
+    ) -> Result<(), OperationError> {
+        let flags = MetaGetFlags {
+            quiet: false,
+            ..flags.clone()
+        };
+        for key in keys {
+            let args = [Arg::Str(key)]
+                .into_iter()
+                .chain(flags.tokens())
+                .chain([Arg::Str("k"), Arg::Str("q")]);
+            protocol::write_command(&mut conn.writer, VERB_META_GET, args)?;
+        }
+        conn.meta_noop()?;
+
+        // The first error of a key that left the connection usable, reported once the rest of
+        // the responses are read up to the no-op
+        let mut failure = None;
+        loop {
+            let line = conn.read_line()?.to_vec();
+            if line == RESULT_META_NOOP {
+                return failure.map_or(Ok(()), Err);
+            }
+            match Client::meta_get_multi_response(conn, line, keys, items) {
+                Err(error) if !error.fatal_to_connection() => {
+                    failure.get_or_insert(error);
+                }
+                result => result?,
+            }
+        }
+    }
+
+    // Reads the response starting with `line` into `items`;
+    fn meta_get_multi_response(
+        conn: &mut Conn,
+        line: Vec<u8>,
+        keys: &[&str],
+        items: &mut HashMap<String, MetaItem>,
+    ) -> Result<(), OperationError> {
+        // Quiet misses aren't answered, so every response must be a hit for one of the keys
+        let item = parse_meta_get_response(conn, line)?.ok_or_else(|| {
+            OperationError::CorruptResponse("unexpected miss in meta get response".to_string())
+        })?;
+        match item.key.clone() {
+            Some(key) if keys.contains(&key.as_str()) => {
+                items.insert(key, item);
+                Ok(())
+            }
+            other => Err(OperationError::CorruptResponse(format!(
+                "unexpected key in meta get response: {:?}",
+                other
+            ))),
+        }
+    }
+
+    // `NS`, `EX` and `NF` map onto `NotStored`, `CASConflict` and `CacheMiss` respectively;
+    pub fn meta_set(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        flags: &MetaSetFlags,
+    ) -> Result<MetaResult, OperationError> {
+        trace::in_span(trace::op_span!("memcache.meta_set", key), || {
+            self.ascii_only("meta_set")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            self.send_meta_set(key, false, value, flags)
+        })
+    }
+
+    // Like `meta_set` for keys the ASCII protocol can't carry, see `meta_get_binary`;
+    pub fn meta_set_binary(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        flags: &MetaSetFlags,
+    ) -> Result<MetaResult, OperationError> {
+        trace::in_span(trace::op_span!("memcache.meta_set_binary", key), || {
+            self.ascii_only("meta_set_binary")?;
+            let key = binary_key(&[self.key_prefix.as_bytes(), key].concat())?;
+            self.send_meta_set(&key, true, value, flags)
+        })
+    }
+
+    fn send_meta_set(
+        &mut self,
+        key: &str,
+        binary: bool,
+        value: &[u8],
+        flags: &MetaSetFlags,
+    ) -> Result<MetaResult, OperationError> {
+        if let Some(client_flags) = flags.client_flags {
+            Flags::from_user(client_flags)?;
+        }
+        self.check_value_size(value)?;
+        trace::record_value_size(value.len());
+        let args = [Arg::Str(key), Arg::Uint(value.len() as u64)]
+            .into_iter()
+            .chain(binary.then_some(Arg::Str("b")))
+            .chain(flags.tokens());
+
+        // The value is written straight from the caller's slice rather than copied after the
+        // command line
+        let line = self.with_key_conn(VERB_META_SET, key, |conn| {
+            protocol::write_command(&mut conn.writer, VERB_META_SET, args)?;
+            conn.writer
+                .write_all(value)
+                .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))?;
+            conn.write_read_line(CR_LF)
+                .map(<[u8]>::to_vec)
+                .map_err(OperationError::Io)
+        })?;
+        let (status, tokens) = split_meta_response(&line, VERB_META_SET)?;
+        match status {
+            RESULT_META_HIT => parse_meta_result(tokens),
+            RESULT_META_NOT_STORED => Err(OperationError::NotStored),
+            RESULT_META_EXISTS => Err(OperationError::CASConflict),
+            RESULT_META_NOT_FOUND => Err(OperationError::CacheMiss),
+            _ => Err(OperationError::CorruptResponse(format!(
+                "unexpected meta set response line: {}",
+                response_excerpt(&line)
+            ))),
+        }
+    }
+
+    // `NF` and `EX` map onto `CacheMiss` and `CASConflict` respectively;
+    pub fn meta_delete(
+        &mut self,
+        key: &str,
+        flags: &MetaDeleteFlags,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.meta_delete", key), || {
+            self.ascii_only("meta_delete")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            self.send_meta_delete(key, false, flags)
+        })
+    }
+
+    // Like `meta_delete` for keys the ASCII protocol can't carry, see `meta_get_binary`;
+    pub fn meta_delete_binary(
+        &mut self,
+        key: &[u8],
+        flags: &MetaDeleteFlags,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.meta_delete_binary", key), || {
+            self.ascii_only("meta_delete_binary")?;
+            let key = binary_key(&[self.key_prefix.as_bytes(), key].concat())?;
+            self.send_meta_delete(&key, true, flags)
+        })
+    }
+
+    fn send_meta_delete(
+        &mut self,
+        key: &str,
+        binary: bool,
+        flags: &MetaDeleteFlags,
+    ) -> Result<(), OperationError> {
+        let args = [Arg::Str(key)]
+            .into_iter()
+            .chain(binary.then_some(Arg::Str("b")))
+            .chain(flags.tokens());
+
+        let line = self.with_key_conn(VERB_META_DELETE, key, |conn| {
+            conn.write_command(VERB_META_DELETE, args)?;
+            conn.read_line()
+                .map(<[u8]>::to_vec)
+                .map_err(OperationError::Io)
+        })?;
+        let (status, _) = split_meta_response(&line, VERB_META_DELETE)?;
+        match status {
+            RESULT_META_HIT => Ok(()),
+            RESULT_META_NOT_FOUND => Err(OperationError::CacheMiss),
+            RESULT_META_EXISTS => Err(OperationError::CASConflict),
+            _ => Err(OperationError::CorruptResponse(format!(
+                "unexpected meta delete response line: {}",
+                response_excerpt(&line)
+            ))),
+        }
+    }
+
+    // Returns the resulting value when `return_value` is set; `NF`, `NS` and `EX` map onto
+    // `CacheMiss`, `NotStored` and `CASConflict` respectively;
+    pub fn meta_arithmetic(
+        &mut self,
+        key: &str,
+        flags: &MetaArithFlags,
+    ) -> Result<Option<u64>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.meta_arithmetic", key), || {
+            self.ascii_only("meta_arithmetic")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            let args = [Arg::Str(key)].into_iter().chain(flags.tokens());
+
+            self.with_key_conn(VERB_META_ARITHMETIC, key, |conn| {
+                conn.write_command(VERB_META_ARITHMETIC, args)?;
+                let line = conn.read_line()?;
+                let (status, tokens) = split_meta_response(line, VERB_META_ARITHMETIC)?;
+                match status {
+                    RESULT_META_HIT => Ok(None),
+                    RESULT_META_VALUE => {
+                        let size =
+                            parse_meta_number::<usize>(tokens.first().copied(), "value size")?;
+                        let value = read_meta_value(conn, size)?;
+                        parse_meta_number(Some(&value), "value").map(Some)
+                    }
+                    RESULT_META_NOT_FOUND => Err(OperationError::CacheMiss),
+                    RESULT_META_NOT_STORED => Err(OperationError::NotStored),
+                    RESULT_META_EXISTS => Err(OperationError::CASConflict),
+                    _ => Err(OperationError::CorruptResponse(format!(
+                        "unexpected meta arithmetic response line: {}",
+                        response_excerpt(line)
+                    ))),
+                }
+            })
+        })
+    }
+
+    pub fn meta_debug(&mut self, key: &str) -> Result<Option<ItemDebugInfo>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.meta_debug", key), || {
+            self.ascii_only("meta_debug")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            let line = self.with_key_conn(VERB_META_DEBUG, key, |conn| {
+                conn.write_command(VERB_META_DEBUG, [Arg::Str(key)])?;
+                conn.read_line()
+                    .map(<[u8]>::to_vec)
+                    .map_err(OperationError::Io)
+            })?;
+            if line.as_slice() == RESULT_META_MISS {
+                return Ok(None);
+            }
+            let (status, tokens) = split_meta_response(&line, VERB_META_DEBUG)?;
+            if status != RESULT_META_DEBUG {
+                return Err(OperationError::CorruptResponse(format!(
+                    "unexpected meta debug response line: {}",
+                    response_excerpt(&line)
+                )));
+            }
+
+            let mut info = ItemDebugInfo::default();
+            // The first token echoes the key
+            for token in tokens.into_iter().skip(1) {
+                let mut pair = token.splitn(2, |&x| x == b'=');
+                let (name, value) = (pair.next().unwrap_or_default(), pair.next());
+                match name {
+                    b"exp" => info.expiration = Some(parse_meta_number(value, "exp")?),
+                    b"la" => info.last_access = Some(parse_meta_number(value, "la")?),
+                    b"cas" => info.cas_id = Some(parse_meta_number(value, "cas")?),
+                    b"fetch" => info.fetched = Some(value == Some(b"yes".as_slice())),
+                    b"cls" => info.slab_class = Some(parse_meta_number(value, "cls")?),
+                    b"size" => info.size = Some(parse_meta_number(value, "size")?),
+                    // Fields added by newer server versions are skipped
+                    _ => (),
+                }
+            }
+            Ok(Some(info))
+        })
+    }
+}
+
+impl Conn {
+    // Writes a no-op and flushes everything written before it; As the server answers commands
+    // in order, reading its `MN\r\n` means every prior response has arrived;
+    pub(crate) fn meta_noop(&mut self) -> Result<(), WriteReadLineError> {
+        self.write_command(VERB_META_NOOP, [])
+    }
+}
+
+// Encodes a binary key for the `b` flag; The server still allows 250 bytes on the wire, so that's
+// the limit of the encoded key, 186 bytes before encoding;
+fn binary_key(key: &[u8]) -> Result<String, OperationError> {
+    if key.is_empty() {
+        return Err(OperationError::MalformedKey(KeyError::Empty));
+    }
+    let key = BASE64.encode(key);
+    legal_key(&key)?;
+    Ok(key)
+}
+
+// Splits `<status> <flags>*\r\n` into the status code and its flags;
+fn split_meta_response<'a>(
+    line: &'a [u8],
+    verb: &str,
+) -> Result<(&'a [u8], Vec<&'a [u8]>), OperationError> {
+    if let Some(error) = protocol::error_response(line, verb) {
+        return Err(error);
+    }
+    if !line.ends_with(CR_LF) {
+        return Err(OperationError::CorruptResponse(format!(
+            "unterminated meta response line: {}",
+            response_excerpt(line)
+        )));
+    }
+    let mut tokens = line[..line.len() - CR_LF.len()]
+        .split(|&x| x == b' ')
+        .filter(|token| !token.is_empty());
+    let status = tokens.next().unwrap_or_default();
+    Ok((status, tokens.collect()))
+}
+
+// Reads the `<data block>\r\n` following a `VA <size>` line;
+fn read_meta_value(conn: &mut Conn, size: usize) -> Result<Vec<u8>, OperationError> {
+    let mut value_buf = vec![0; size + CR_LF.len()];
+    conn.reader.read_exact(&mut value_buf).map_err(|error| {
+        OperationError::CorruptResponse(format!("could not read value: {}", error))
+    })?;
+    if !value_buf.ends_with(CR_LF) {
+        return Err(OperationError::CorruptResponse(
+            "corrupt meta value read".to_string(),
+        ));
+    }
+    value_buf.truncate(size);
+    Ok(value_buf)
+}
+
+fn parse_meta_result(tokens: Vec<&[u8]>) -> Result<MetaResult, OperationError> {
+    let mut result = MetaResult::default();
+    for token in tokens {
+        if token[0] == b'c' {
+            result.cas_id = Some(parse_meta_number(Some(&token[1..]), "cas")?);
+        }
+    }
+    Ok(result)
+}
+
+// Parses `HD <flags>*\r\n`, `VA <size> <flags>*\r\n<data block>\r\n` or `EN\r\n`;
+fn parse_meta_get_response(
+    conn: &mut Conn,
+    line: Vec<u8>,
+) -> Result<Option<MetaItem>, OperationError> {
+    if line.as_slice() == RESULT_META_MISS {
+        return Ok(None);
+    }
+    let (status, tokens) = split_meta_response(&line, VERB_META_GET)?;
+    let mut tokens = tokens.into_iter();
+    let mut item = MetaItem::default();
+    match status {
+        RESULT_META_HIT => (),
+        RESULT_META_VALUE => {
+            let size = parse_meta_number::<usize>(tokens.next(), "value size")?;
+            trace::record_value_size(size);
+            item.value = Some(read_meta_value(conn, size)?);
+        }
+        _ => {
+            return Err(OperationError::CorruptResponse(format!(
+                "unexpected meta get response line: {}",
+                response_excerpt(&line)
+            )))
+        }
+    }
+
+    for token in tokens {
+        let value = Some(&token[1..]);
+        match token[0] {
+            b'k' => item.key = Some(String::from_utf8_lossy(&token[1..]).into_owned()),
+            b'f' => item.flags = Some(parse_meta_number(value, "client flags")?),
+            b't' => item.ttl = Some(parse_meta_number(value, "ttl")?),
+            b'c' => item.cas_id = Some(parse_meta_number(value, "cas")?),
+            b'h' => item.hit_before = Some(parse_meta_number::<u8>(value, "hit")? == 1),
+            b'l' => item.last_access = Some(parse_meta_number(value, "last access")?),
+            b'X' => item.stale = true,
+            b'W' => item.win = true,
+            b'Z' => item.win_sent = true,
+            // Flags we didn't ask for (or don't know about yet) are skipped
+            _ => (),
+        }
+    }
+    Ok(Some(item))
+}
+
+fn parse_meta_number<T: std::str::FromStr>(
+    token: Option<&[u8]>,
+    name: &str,
+) -> Result<T, OperationError> {
+    token
+        .and_then(|token| std::str::from_utf8(token).ok())
+        .and_then(|token| token.parse::<T>().ok())
+        .ok_or_else(|| OperationError::CorruptResponse(format!("could not parse meta {}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ItemDebugInfo, MetaArithFlags, MetaArithMode, MetaDeleteFlags, MetaGetFlags, MetaSetFlags,
+        MetaSetMode,
+    };
+    use crate::{
+        errors::{KeyError, OperationError},
+        flags::Flags,
+        item::Item,
+        tests::{
+            lock_local_server, mock_server, recording_server, scripted_server, LOCALHOST_TCP_ADDR,
+        },
+        Client,
+    };
+    use std::time::Duration;
+
+    // The connection must be left at the start of the next response
+    fn expect_next_get_to_hit(client: &mut Client) {
+        match client.get("next") {
+            Ok(Some(item)) if item.value == b"x" => (),
+            other => panic!("expected the next get to hit. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn meta_get_multi_reads_past_a_server_error_up_to_the_no_op() {
+        let addr = scripted_server(&[
+            (
+                3,
+                b"SERVER_ERROR out of memory\r\nVA 5 kb\r\nvalue\r\nMN\r\n",
+            ),
+            (1, b"VALUE next 0 1\r\nx\r\nEND\r\n"),
+        ]);
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let flags = MetaGetFlags {
+            value: true,
+            ..Default::default()
+        };
+        match client.meta_get_multi(&["a", "b"], &flags) {
+            Err(OperationError::ServerFailures(failures)) => match failures.as_slice() {
+                [(_, OperationError::Server(message))] if message == "out of memory" => (),
+                _ => panic!("expected the server error. Got: {:?}", failures),
+            },
+            other => panic!("expected a server failure. Got: {:?}", other),
+        }
+        expect_next_get_to_hit(&mut client);
+    }
+
+    #[test]
+    fn quiet_meta_get_reads_the_no_op_after_a_server_error() {
+        let addr = scripted_server(&[
+            (2, b"SERVER_ERROR out of memory\r\nMN\r\n"),
+            (1, b"VALUE next 0 1\r\nx\r\nEND\r\n"),
+        ]);
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let flags = MetaGetFlags {
+            quiet: true,
+            ..Default::default()
+        };
+        match client
+            .meta_get("key", &flags)
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::Server(message)) if message == "out of memory" => (),
+            other => panic!("expected the server error. Got: {:?}", other),
+        }
+        expect_next_get_to_hit(&mut client);
+    }
+
+    #[test]
+    fn test_meta_get() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let item = Item::from_parts("meta_get_key".to_string(), Vec::from("value"), 42, 60);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+
+        let flags = MetaGetFlags {
+            value: true,
+            client_flags: true,
+            ttl: true,
+            cas: true,
+            hit: true,
+            last_access: true,
+            quiet: false,
+        };
+        let item = match client.meta_get("meta_get_key", &flags) {
+            Ok(Some(item)) => item,
+            other => panic!("expected an item. Got: {:?}", other),
+        };
+        if item.value.as_deref() != Some(b"value".as_slice()) {
+            panic!("expected value to be value. Got: {:?}", item.value)
+        }
+        if item.flags != Some(42) {
+            panic!("expected flags to be 42. Got: {:?}", item.flags)
+        }
+        match item.ttl {
+            Some(ttl) if ttl > 0 && ttl <= 60 => (),
+            other => panic!("expected a ttl of at most 60 seconds. Got: {:?}", other),
+        }
+        if item.cas_id.unwrap_or_default() == 0 || item.hit_before.is_none() {
+            panic!("expected cas and hit metadata. Got: {:?}", item)
+        }
+
+        // Without `v` the value isn't returned
+        let flags = MetaGetFlags {
+            cas: true,
+            ..Default::default()
+        };
+        match client.meta_get("meta_get_key", &flags) {
+            Ok(Some(item)) if item.value.is_none() && item.cas_id.is_some() => (),
+            other => panic!("expected metadata only. Got: {:?}", other),
+        }
+
+        match client.meta_get("meta_get_missing", &MetaGetFlags::default()) {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+
+        // Quiet misses send nothing back, which must not block the read
+        let flags = MetaGetFlags {
+            value: true,
+            quiet: true,
+            ..Default::default()
+        };
+        match client.meta_get("meta_get_missing", &flags) {
+            Ok(None) => (),
+            other => panic!("expected a quiet miss. Got: {:?}", other),
+        }
+        match client.meta_get("meta_get_key", &flags) {
+            Ok(Some(item)) if item.value.is_some() => (),
+            other => panic!("expected a quiet hit. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete("meta_get_key") {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn meta_get_skips_unknown_flags() {
+        let addr = mock_server(b"VA 2 f5 Zfoo c9 s2\r\nhi\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let flags = MetaGetFlags {
+            value: true,
+            client_flags: true,
+            cas: true,
+            ..Default::default()
+        };
+        match client.meta_get("key", &flags) {
+            Ok(Some(item))
+                if item.value.as_deref() == Some(b"hi".as_slice())
+                    && item.flags == Some(5)
+                    && item.cas_id == Some(9) => {}
+            other => panic!("unexpected meta get result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn meta_get_corrupt_flag_returns_corrupt_response() {
+        let addr = mock_server(b"HD cabc\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let flags = MetaGetFlags {
+            cas: true,
+            ..Default::default()
+        };
+        match client
+            .meta_get("key", &flags)
+            .as_ref()
+            .map_err(OperationError::without_context)
+        {
+            Err(OperationError::CorruptResponse(_)) => (),
+            other => panic!("expected a corrupt response error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_meta_set() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let _ = client.delete("meta_set_key");
+
+        let flags = MetaSetFlags {
+            mode: MetaSetMode::Replace,
+            ..Default::default()
+        };
+        match client.meta_set("meta_set_key", b"value", &flags) {
+            Err(OperationError::NotStored) => (),
+            other => panic!(
+                "expected replace of a missing key to fail. Got: {:?}",
+                other
+            ),
+        }
+
+        let flags = MetaSetFlags {
+            mode: MetaSetMode::Add,
+            client_flags: Some(7),
+            ttl: Some(60),
+            return_cas: true,
+            ..Default::default()
+        };
+        let cas_id = match client.meta_set("meta_set_key", b"value", &flags) {
+            Ok(result) => match result.cas_id {
+                Some(cas_id) => cas_id,
+                None => panic!("expected the new cas to be returned. Got: {:?}", result),
+            },
+            Err(error) => panic!("did not expect meta set to fail: {}", error),
+        };
+        match client.meta_set("meta_set_key", b"value", &flags) {
+            Err(OperationError::NotStored) => (),
+            other => panic!("expected add of an existing key to fail. Got: {:?}", other),
+        }
+
+        let flags = MetaSetFlags {
+            compare_cas: Some(cas_id + 1),
+            ..Default::default()
+        };
+        match client.meta_set("meta_set_key", b"other", &flags) {
+            Err(OperationError::CASConflict) => (),
+            other => panic!("expected a cas conflict. Got: {:?}", other),
+        }
+        match client.meta_set("meta_set_missing", b"other", &flags) {
+            Err(OperationError::CacheMiss) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+
+        // The client-provided CAS replaces the server-generated one
+        let flags = MetaSetFlags {
+            mode: MetaSetMode::Append,
+            compare_cas: Some(cas_id),
+            new_cas: Some(cas_id + 100),
+            return_cas: true,
+            ..Default::default()
+        };
+        match client.meta_set("meta_set_key", b"-appended", &flags) {
+            Ok(result) if result.cas_id == Some(cas_id + 100) => (),
+            other => panic!("expected the provided cas to be used. Got: {:?}", other),
+        }
+        match client.gets("meta_set_key") {
+            Ok(Some(item)) if item.value == b"value-appended" && item.flags == 7 => (),
+            other => panic!("expected the appended item. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete("meta_set_key") {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn meta_set_unexpected_status_returns_corrupt_response() {
+        let addr = mock_server(b"OK\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.meta_set("key", b"value", &MetaSetFlags::default()) {
+            Err(OperationError::CorruptResponse(_)) => (),
+            other => panic!("expected a corrupt response error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn meta_set_rejects_reserved_flags() {
+        let (addr, lines) = recording_server(b"HD\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        // The codec id of chunk manifests
+        let flags = MetaSetFlags {
+            client_flags: Some(6 << 24),
+            ..Default::default()
+        };
+        match client.meta_set("key", b"value", &flags) {
+            Err(OperationError::ReservedFlags(flags)) if flags == 6 << 24 => (),
+            other => panic!("expected a reserved flags error. Got: {:?}", other),
+        }
+        match client.meta_set_binary(b"key", b"value", &flags) {
+            Err(OperationError::ReservedFlags(_)) => (),
+            other => panic!("expected a reserved flags error. Got: {:?}", other),
+        }
+        if let Ok(line) = lines.recv_timeout(Duration::from_millis(100)) {
+            panic!("expected nothing to be sent. Got: {}", line)
+        }
+        let flags = MetaSetFlags {
+            client_flags: Some(Flags::USER_MASK),
+            ..Default::default()
+        };
+        if let Err(error) = client.meta_set("key", b"value", &flags) {
+            panic!("did not expect meta set to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn test_meta_delete() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let item = Item::from_parts("meta_delete_key".to_string(), Vec::from("value"), 0, 0);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        let cas_id = match client.gets("meta_delete_key") {
+            Ok(Some(item)) => item.cas_id,
+            other => panic!("expected an item. Got: {:?}", other),
+        };
+
+        let flags = MetaDeleteFlags {
+            compare_cas: Some(cas_id + 1),
+            ..Default::default()
+        };
+        match client.meta_delete("meta_delete_key", &flags) {
+            Err(OperationError::CASConflict) => (),
+            other => panic!("expected a cas conflict. Got: {:?}", other),
+        }
+
+        // Invalidating keeps the item around, marked stale, with its new TTL
+        let flags = MetaDeleteFlags {
+            invalidate: true,
+            ttl: Some(30),
+            ..Default::default()
+        };
+        if let Err(error) = client.meta_delete("meta_delete_key", &flags) {
+            panic!("did not expect meta delete to fail: {}", error)
+        }
+        let flags = MetaGetFlags {
+            ttl: true,
+            hit: true,
+            ..Default::default()
+        };
+        match client.meta_get("meta_delete_key", &flags) {
+            Ok(Some(item)) if item.stale && item.win && matches!(item.ttl, Some(1..=30)) => (),
+            other => panic!("expected a stale item. Got: {:?}", other),
+        }
+        // Only the first reader wins the right to recache it
+        match client.meta_get("meta_delete_key", &flags) {
+            Ok(Some(item)) if item.stale && !item.win && item.win_sent => (),
+            other => panic!("expected a stale item already won. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.meta_delete("meta_delete_key", &MetaDeleteFlags::default()) {
+            panic!("did not expect meta delete to fail: {}", error)
+        }
+        match client.meta_delete("meta_delete_key", &MetaDeleteFlags::default()) {
+            Err(OperationError::CacheMiss) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_meta_arithmetic() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let _ = client.delete("meta_arithmetic_key");
+
+        let flags = MetaArithFlags {
+            return_value: true,
+            ..Default::default()
+        };
+        match client.meta_arithmetic("meta_arithmetic_key", &flags) {
+            Err(OperationError::CacheMiss) => (),
+            other => panic!("expected a cache miss. Got: {:?}", other),
+        }
+
+        // A missing counter is created with the initial value, without applying the delta
+        let flags = MetaArithFlags {
+            delta: Some(5),
+            auto_create_ttl: Some(60),
+            initial: Some(10),
+            return_value: true,
+            ..Default::default()
+        };
+        match client.meta_arithmetic("meta_arithmetic_key", &flags) {
+            Ok(Some(10)) => (),
+            other => panic!("expected the initial value. Got: {:?}", other),
+        }
+        match client.meta_arithmetic("meta_arithmetic_key", &flags) {
+            Ok(Some(15)) => (),
+            other => panic!("expected the incremented value. Got: {:?}", other),
+        }
+
+        let flags = MetaArithFlags {
+            mode: MetaArithMode::Decrement,
+            delta: Some(20),
+            ..Default::default()
+        };
+        match client.meta_arithmetic("meta_arithmetic_key", &flags) {
+            Ok(None) => (),
+            other => panic!("expected no value without v. Got: {:?}", other),
+        }
+        match client.get("meta_arithmetic_key") {
+            Ok(Some(item)) if item.value == b"0" => (),
+            other => panic!("expected decrement to stop at 0. Got: {:?}", other),
+        }
+
+        let flags = MetaArithFlags {
+            compare_cas: Some(0),
+            ..Default::default()
+        };
+        match client.meta_arithmetic("meta_arithmetic_key", &flags) {
+            Err(OperationError::CASConflict) => (),
+            other => panic!("expected a cas conflict. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.delete("meta_arithmetic_key") {
+            panic!("did not expect delete to fail: {}", error)
+        }
+    }
+
+    #[test]
+    fn meta_arithmetic_not_stored_returns_not_stored() {
+        let addr = mock_server(b"NS\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.meta_arithmetic("key", &MetaArithFlags::default()) {
+            Err(OperationError::NotStored) => (),
+            other => panic!("expected not stored. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_meta_binary_keys() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let key = b"legacy key\r\n\x00\xff";
+        if let Err(error) = client.meta_set_binary(key, b"value", &MetaSetFlags::default()) {
+            panic!("did not expect meta set to fail: {}", error)
+        }
+        let flags = MetaGetFlags {
+            value: true,
+            ..Default::default()
+        };
+        match client.meta_get_binary(key, &flags) {
+            Ok(Some(item)) if item.value.as_deref() == Some(b"value".as_slice()) => (),
+            other => panic!("expected the stored value. Got: {:?}", other),
+        }
+        // The same bytes as a text key are another key
+        match client.meta_get("bGVnYWN5IGtleQ0KAP8=", &flags) {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+        if let Err(error) = client.meta_delete_binary(key, &MetaDeleteFlags::default()) {
+            panic!("did not expect meta delete to fail: {}", error)
+        }
+        match client.meta_get_binary(key, &flags) {
+            Ok(None) => (),
+            other => panic!("expected a miss after the delete. Got: {:?}", other),
+        }
+
+        // 186 bytes encode to 248, 187 to 252
+        if let Err(error) = client.meta_set_binary(&[0; 186], b"value", &MetaSetFlags::default()) {
+            panic!("did not expect a 186 byte key to be rejected: {}", error)
+        }
+        if let Err(error) = client.meta_delete_binary(&[0; 186], &MetaDeleteFlags::default()) {
+            panic!("did not expect meta delete to fail: {}", error)
+        }
+        match client.meta_get_binary(&[0; 187], &flags) {
+            Err(OperationError::MalformedKey(KeyError::TooLong(252))) => (),
+            other => panic!("expected the encoded key to be too long. Got: {:?}", other),
+        }
+        match client.meta_get_binary(b"", &flags) {
+            Err(OperationError::MalformedKey(KeyError::Empty)) => (),
+            other => panic!("expected an empty key error. Got: {:?}", other),
+        }
+        match client.get("legacy key") {
+            Err(error @ OperationError::MalformedKey(_))
+                if error.to_string().contains("meta_get_binary") => {}
+            other => panic!(
+                "expected the error to point at the meta API. Got: {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn test_meta_debug() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let item = Item::from_parts("meta_debug_key".to_string(), Vec::from("value"), 0, 0);
+        if let Err(error) = client.set(&item) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        let cas_id = match client.gets("meta_debug_key") {
+            Ok(Some(item)) => item.cas_id,
+            other => panic!("expected an item. Got: {:?}", other),
+        };
+        match client.meta_debug("meta_debug_key") {
+            Ok(Some(info))
+                if info.expiration == Some(-1)
+                    && info.cas_id == Some(cas_id)
+                    && info.fetched == Some(true)
+                    && info.size.is_some() => {}
+            other => panic!("unexpected meta debug result: {:?}", other),
+        }
+
+        if let Err(error) = client.delete("meta_debug_key") {
+            panic!("did not expect delete to fail: {}", error)
+        }
+        match client.meta_debug("meta_debug_key") {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn meta_debug_skips_unknown_fields() {
+        let addr = mock_server(b"ME key exp=10 la=2 cas=7 fetch=no cls=3 size=64 new=1\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let expected = ItemDebugInfo {
+            expiration: Some(10),
+            last_access: Some(2),
+            cas_id: Some(7),
+            fetched: Some(false),
+            slab_class: Some(3),
+            size: Some(64),
+        };
+        match client.meta_debug("key") {
+            Ok(Some(info)) if info == expected => (),
+            other => panic!("unexpected meta debug result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn meta_debug_without_meta_support_returns_unsupported_command() {
+        let addr = mock_server(b"ERROR\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        match client.meta_debug("key") {
+            Err(OperationError::UnsupportedCommand(verb)) if verb == "me" => (),
+            other => panic!("expected an unsupported command error. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_meta_get_multi() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        let keys: Vec<String> = (0..10).map(|i| format!("meta_get_multi_{}", i)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            let _ = client.delete(key);
+            if i % 2 == 0 {
+                let item = Item::from_parts(key.clone(), Vec::from(format!("value_{}", i)), 0, 0);
+                if let Err(error) = client.set(&item) {
+                    panic!("did not expect set to fail: {}", error)
+                }
+            }
+        }
+
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let flags = MetaGetFlags {
+            value: true,
+            ..Default::default()
+        };
+        let items = match client.meta_get_multi(&keys, &flags) {
+            Ok(items) => items,
+            Err(error) => panic!("did not expect meta get multi to fail: {}", error),
+        };
+        if items.len() != 5 {
+            panic!("expected 5 items. Got: {:?}", items)
+        }
+        for (i, key) in keys.iter().enumerate() {
+            let expected = (i % 2 == 0).then(|| Vec::from(format!("value_{}", i)));
+            if items.get(*key).and_then(|item| item.value.clone()) != expected {
+                panic!("unexpected item for {}: {:?}", key, items.get(*key))
+            }
+        }
+
+        // The connection is still aligned after the fence
+        match client.meta_get(keys[0], &flags) {
+            Ok(Some(item)) if item.value.as_deref() == Some(b"value_0".as_slice()) => (),
+            other => panic!("expected an item. Got: {:?}", other),
+        }
+
+        for (i, key) in keys.iter().enumerate() {
+            if i % 2 == 0 {
+                if let Err(error) = client.delete(key) {
+                    panic!("did not expect delete to fail: {}", error)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::errors::OperationError;
+use crate::item::Item;
+use crate::Client;
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Instant;
+
+impl Client {
+    // Multi gets (`get_multi` and `gets_multi`) spanning several servers ask every server from a
+    // thread of its own, so they take about as long as the slowest server rather than the sum
+    // of them; Connections are checked out and dialed up front, one thread is spawned per server
+    // involved, and the connections are pooled again once every thread is done; Off by default,
+    // as spawning threads costs more than the round trips it saves on fast networks;
+    pub fn set_parallel_multi_get(&mut self, parallel: bool) {
+        self.parallel_multi_get = parallel;
+    }
+
+    pub fn with_parallel_multi_get(mut self, parallel: bool) -> Self {
+        self.set_parallel_multi_get(parallel);
+        self
+    }
+
+    // Runs the multi get of every group on a thread, collecting the items and failures; Returns
+    // the groups to run again sequentially, whose pooled connection turned out to be stale, so
+    // they get the retries of `with_retried_conn`;
+    pub(crate) fn parallel_retrieve_multi<'a>(
+        &mut self,
+        groups: HashMap<SocketAddr, Vec<&'a str>>,
+        verb: &str,
+        with_cas: bool,
+        items: &mut HashMap<String, Item>,
+        failures: &mut Vec<(SocketAddr, OperationError)>,
+    ) -> HashMap<SocketAddr, Vec<&'a str>> {
+        let mut checked_out = Vec::with_capacity(groups.len());
+        for (addr, keys) in groups {
+            let pooled = self.get_free_conn(addr);
+            let was_pooled = pooled.is_some();
+            match pooled.map_or_else(|| self.dial_conn(addr, Instant::now()), Ok) {
+                Ok(conn) => checked_out.push((addr, keys, conn, was_pooled)),
+                Err(error) => failures.push((addr, error)),
+            }
+        }
+
+        let joined: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = checked_out
+                .into_iter()
+                .map(|(addr, keys, mut conn, was_pooled)| {
+                    let worker = scope.spawn(move || {
+                        let mut found = HashMap::new();
+                        let result = Client::retrieve_multi_from_conn(
+                            &mut conn, verb, with_cas, &keys, &mut found,
+                        );
+                        (conn, keys, result.map(|()| found))
+                    });
+                    (addr, was_pooled, worker)
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|(addr, was_pooled, worker)| (addr, was_pooled, worker.join()))
+                .collect()
+        });
+
+        let mut stale = HashMap::new();
+        for (addr, was_pooled, joined) in joined {
+            let (conn, keys, result) = match joined {
+                Ok(done) => done,
+                // The connection was dropped as the thread unwound
+                Err(panic) => {
+                    failures.push((addr, OperationError::WorkerPanicked(panic_message(panic))));
+                    continue;
+                }
+            };
+            let stale_conn = was_pooled
+                && matches!(&result, Err(OperationError::Io(error)) if !error.is_timeout());
+            match self.release_conn(conn, result) {
+                Ok(found) => items.extend(found),
+                Err(_) if stale_conn => {
+                    stale.insert(addr, keys);
+                }
+                Err(error) => failures.push((addr, error)),
+            }
+        }
+        stale
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::panic_message;
+    use crate::{
+        binary::ProtocolMode,
+        errors::OperationError,
+        tests::{mock_server, slow_server},
+        Client,
+    };
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn parallel_multi_gets_ask_servers_concurrently() {
+        let latency = Duration::from_millis(300);
+        let addrs: Vec<String> = (0..3).map(|_| slow_server(latency)).collect();
+        let addrs: Vec<&str> = addrs.iter().map(String::as_str).collect();
+        let timeout = Some(Duration::from_secs(2));
+        let mut client = Client::with_servers(&addrs, timeout, 0, ProtocolMode::Ascii)
+            .unwrap()
+            .with_parallel_multi_get(true);
+        let keys: Vec<String> = (0..300).map(|i| format!("parallel_{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+        let started = Instant::now();
+        match client.get_multi(&keys) {
+            Ok(items) if items.len() == keys.len() => (),
+            other => panic!("expected every key to be found. Got: {:?}", other),
+        }
+        // Asking the servers one after the other would take three times the latency
+        if started.elapsed() >= latency * 2 {
+            panic!(
+                "expected the servers to be asked at once. Took: {:?}",
+                started.elapsed()
+            )
+        }
+        match client.gets_multi(&keys) {
+            Ok(items) if items.len() == keys.len() => (),
+            other => panic!("expected every key to be found. Got: {:?}", other),
+        }
+        if client.pool_stats().values().any(|stats| stats.idle != 1) {
+            panic!(
+                "expected the connections to be pooled again. Got: {:?}",
+                client.pool_stats()
+            )
+        }
+    }
+
+    #[test]
+    fn parallel_multi_get_failures_name_the_server() {
+        let answering = slow_server(Duration::ZERO);
+        let failing = mock_server(b"SERVER_ERROR busy\r\n");
+        let servers = [answering.as_str(), failing.as_str()];
+        let mut client = Client::with_servers(&servers, None, 0, ProtocolMode::Ascii)
+            .unwrap()
+            .with_parallel_multi_get(true);
+        let keys: Vec<String> = (0..20).map(|i| format!("parallel_{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        match client.get_multi(&keys) {
+            Err(OperationError::ServerFailures(failures)) => match failures.as_slice() {
+                [(addr, OperationError::Server(_))] if addr.to_string() == failing => (),
+                other => panic!("expected only {} to fail. Got: {:?}", failing, other),
+            },
+            other => panic!("expected a server failure. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn worker_panics_become_errors() {
+        let panicked = std::thread::spawn(|| panic!("worker {}", 1)).join();
+        match panicked.map_err(panic_message) {
+            Err(message) if message == "worker 1" => (),
+            other => panic!("expected the panic message. Got: {:?}", other),
+        }
+        let message = OperationError::WorkerPanicked("worker 1".to_string()).to_string();
+        if message != "memcache: multi get worker panicked: worker 1" {
+            panic!("unexpected message: {}", message)
+        }
+    }
+}
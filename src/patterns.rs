@@ -0,0 +1,478 @@
+use crate::compression::decompressed;
+use crate::errors::{GetOrSetError, OperationError};
+use crate::expiration::Expiration;
+use crate::flags::Flags;
+use crate::item::{Item, StoredValue};
+use crate::Client;
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Codec id flagging values framed by `get_or_set_with_early_expiration`, of the range kept for
+// the codecs of this crate
+const EARLY_EXPIRATION_CODEC_ID: u8 = 7;
+// Version of the framing, bumped whenever the header changes
+const EARLY_EXPIRATION_VERSION: u8 = 1;
+// `<version> <cost in ms: u32> <expiry in unix ms: u64>`, big-endian, before the value
+const EARLY_EXPIRATION_HEADER_LEN: usize = 13;
+
+// What `get_or_set_with_early_expiration` stores ahead of the value, to decide if a read should
+// recompute it early
+#[derive(Debug, PartialEq)]
+struct EarlyExpiration {
+    // How long computing the value took
+    cost: Duration,
+    // When the value expires on the server, since the unix epoch
+    expiry: Duration,
+}
+
+impl EarlyExpiration {
+    fn frame(&self, value: &[u8]) -> Vec<u8> {
+        let mut framed = Vec::with_capacity(EARLY_EXPIRATION_HEADER_LEN + value.len());
+        framed.push(EARLY_EXPIRATION_VERSION);
+        let cost = u32::try_from(self.cost.as_millis()).unwrap_or(u32::MAX);
+        framed.extend_from_slice(&cost.to_be_bytes());
+        let expiry = u64::try_from(self.expiry.as_millis()).unwrap_or(u64::MAX);
+        framed.extend_from_slice(&expiry.to_be_bytes());
+        framed.extend_from_slice(value);
+        framed
+    }
+
+    // `None` for a version this client doesn't know, or a value too short for a header;
+    fn unframe(mut framed: Vec<u8>) -> Option<(Self, Vec<u8>)> {
+        if framed.len() < EARLY_EXPIRATION_HEADER_LEN || framed[0] != EARLY_EXPIRATION_VERSION {
+            return None;
+        }
+        let cost = u32::from_be_bytes(framed[1..5].try_into().ok()?);
+        let expiry = u64::from_be_bytes(framed[5..13].try_into().ok()?);
+        let header = EarlyExpiration {
+            cost: Duration::from_millis(u64::from(cost)),
+            expiry: Duration::from_millis(expiry),
+        };
+        framed.drain(..EARLY_EXPIRATION_HEADER_LEN);
+        Some((header, framed))
+    }
+
+    // XFetch: recompute once `now - cost * beta * ln(random)` reaches the expiry, `random` being
+    // uniform in (0, 1]; The closer the expiry and the costlier the value, the likelier a read
+    // is to volunteer;
+    fn expires_early(&self, now: Duration, beta: f64, random: f64) -> bool {
+        let early = self.cost.as_secs_f64() * beta * -random.ln();
+        now.as_secs_f64() + early >= self.expiry.as_secs_f64()
+    }
+}
+
+// Uniform in (0, 1]; `RandomState` is seeded differently every time
+fn random_unit() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    ((random >> 11) + 1) as f64 / (1u64 << 53) as f64
+}
+
+// An item as read by `get_or_set_with_early_expiration`
+enum EarlyExpiringValue {
+    Framed(EarlyExpiration, Vec<u8>),
+    // Stored by other means
+    Plain(Vec<u8>),
+    // Framed by a version this client doesn't know
+    UnknownVersion,
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+impl Client {
+    // Gets `key`, computing and storing it with `f` on a miss; The value is stored with `add`, so
+    // concurrent fillers don't overwrite each other, and a filler that loses the race returns the
+    // winner's value instead of its own; The flag is whether the returned value was computed by
+    // `f`, which is also the case when the winner's value is gone by the time it's read again;
+    pub fn get_or_set_with<F, E>(
+        &mut self,
+        key: &str,
+        ttl: Expiration,
+        f: F,
+    ) -> Result<(Vec<u8>, bool), GetOrSetError<E>>
+    where
+        F: FnOnce() -> Result<Vec<u8>, E>,
+    {
+        if let Some(item) = self.get(key)? {
+            return Ok((item.value, false));
+        }
+        let value = f().map_err(GetOrSetError::Compute)?;
+        let item = Item::new(key, value)?.with_expiration(ttl);
+        match self.add(&item) {
+            Ok(()) => Ok((item.value, true)),
+            Err(OperationError::NotStored) => match self.get(key)? {
+                Some(winner) => Ok((winner.value, false)),
+                None => Ok((item.value, true)),
+            },
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    // `get_or_set_with` that protects hot keys from stampedes when they expire: the value is
+    // stored with how long `f` took to compute it and when it expires, and every read draws
+    // (XFetch) whether to recompute it ahead of the expiry, so a single reader tends to refresh
+    // it early, with `set`, while the others keep getting the still valid value; `beta` scales how
+    // early, `1.0` being the usual choice and `0.0` never refreshing early; Values are framed
+    // with a versioned header and flagged with a reserved codec id, so they must only be read
+    // through this method, and values stored by others without the flag are returned as they
+    // are;
+    pub fn get_or_set_with_early_expiration<F, E>(
+        &mut self,
+        key: &str,
+        ttl: Duration,
+        beta: f64,
+        f: F,
+    ) -> Result<(Vec<u8>, bool), GetOrSetError<E>>
+    where
+        F: FnOnce() -> Result<Vec<u8>, E>,
+    {
+        let refresh = match self.get_encoded(key)? {
+            Some(item) => match self.early_expiring_value(item)? {
+                EarlyExpiringValue::Framed(header, value) => {
+                    let now = since_epoch(SystemTime::now());
+                    if !header.expires_early(now, beta, random_unit()) {
+                        return Ok((value, false));
+                    }
+                    true
+                }
+                EarlyExpiringValue::Plain(value) => return Ok((value, false)),
+                // Recomputed in the framing this client knows
+                EarlyExpiringValue::UnknownVersion => true,
+            },
+            None => false,
+        };
+
+        let started = Instant::now();
+        let value = f().map_err(GetOrSetError::Compute)?;
+        let header = EarlyExpiration {
+            cost: started.elapsed(),
+            expiry: since_epoch(SystemTime::now() + ttl),
+        };
+        let stored = StoredValue {
+            value: Cow::Owned(header.frame(&value)),
+            flags: Flags::default()
+                .with_codec(EARLY_EXPIRATION_CODEC_ID)
+                .wire(),
+            expiration: Expiration::In(ttl),
+        };
+        if refresh {
+            self.set_encoded(key, stored)?;
+            return Ok((value, true));
+        }
+        match self.add_encoded(key, stored) {
+            Ok(()) => Ok((value, true)),
+            Err(OperationError::NotStored) => match self.get_encoded(key)? {
+                Some(winner) => match self.early_expiring_value(winner)? {
+                    EarlyExpiringValue::Framed(_, winner) | EarlyExpiringValue::Plain(winner) => {
+                        Ok((winner, false))
+                    }
+                    EarlyExpiringValue::UnknownVersion => Ok((value, true)),
+                },
+                None => Ok((value, true)),
+            },
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn early_expiring_value(&self, item: Item) -> Result<EarlyExpiringValue, OperationError> {
+        let item = decompressed(item)?;
+        if Flags::from_wire(item.flags).codec() != EARLY_EXPIRATION_CODEC_ID {
+            return Ok(EarlyExpiringValue::Plain(self.returned_item(item)?.value));
+        }
+        Ok(match EarlyExpiration::unframe(item.value) {
+            Some((header, value)) => EarlyExpiringValue::Framed(header, value),
+            None => EarlyExpiringValue::UnknownVersion,
+        })
+    }
+
+    // Updates `key` to what `f` makes of its current value, `None` when it's missing; The update
+    // is written with `compare_and_swap`, or `add` for a missing key, and `f` is called again on
+    // the new current value whenever another writer got there first, up to `max_attempts` times;
+    // `f` returning `None` gives up without writing; Returns the value written; The update keeps
+    // the item's flags and is written with `expiration`, as `gets` doesn't return the item's own;
+    pub fn cas_loop<F>(
+        &mut self,
+        key: &str,
+        expiration: Expiration,
+        max_attempts: u32,
+        mut f: F,
+    ) -> Result<Option<Vec<u8>>, OperationError>
+    where
+        F: FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        for _ in 0..max_attempts {
+            let current = self.gets(key)?;
+            let value = match f(current.as_ref().map(|item| item.value.as_slice())) {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            let (item, result) = match current {
+                Some(current) => {
+                    let item = Item {
+                        value,
+                        expiration,
+                        ..current
+                    };
+                    let result = self.compare_and_swap(&item);
+                    (item, result)
+                }
+                None => {
+                    let item = Item::new(key, value)?.with_expiration(expiration);
+                    let result = self.add(&item);
+                    (item, result)
+                }
+            };
+            match result {
+                Ok(()) => return Ok(Some(item.value)),
+                // Changed, deleted or added by another writer since `gets`
+                Err(OperationError::CASConflict)
+                | Err(OperationError::CacheMiss)
+                | Err(OperationError::NotStored) => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Err(OperationError::CASAttemptsExhausted(max_attempts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EarlyExpiration;
+    use crate::{
+        errors::{GetOrSetError, OperationError},
+        expiration::Expiration,
+        item::Item,
+        meta::MetaGetFlags,
+        tests::{lock_local_server, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_get_or_set_with() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let ttl = Expiration::In(Duration::from_secs(60));
+        let _ = client.delete("read_through");
+        let _ = client.delete("read_through_race");
+
+        match client.get_or_set_with("read_through", ttl, || Ok::<_, String>(b"first".to_vec())) {
+            Ok((value, true)) if value == b"first" => (),
+            other => panic!("expected the value to be computed. Got: {:?}", other),
+        }
+        match client.get_or_set_with("read_through", ttl, || -> Result<Vec<u8>, String> {
+            panic!("did not expect a hit to compute the value")
+        }) {
+            Ok((value, false)) if value == b"first" => (),
+            other => panic!("expected the stored value. Got: {:?}", other),
+        }
+
+        match client.get_or_set_with("read_through_race", ttl, || Err("backend down")) {
+            Err(GetOrSetError::Compute("backend down")) => (),
+            other => panic!("expected the closure's error. Got: {:?}", other),
+        }
+        match client.get("read_through_race") {
+            Ok(None) => (),
+            other => panic!("expected nothing to be stored. Got: {:?}", other),
+        }
+
+        // Another filler stores its value while this one computes
+        let mut other = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0).unwrap();
+        match client.get_or_set_with("read_through_race", ttl, || {
+            other
+                .add(&Item::new("read_through_race", "winner").unwrap())
+                .map_err(|error| error.to_string())?;
+            Ok::<_, String>(b"loser".to_vec())
+        }) {
+            Ok((value, false)) if value == b"winner" => (),
+            other => panic!("expected the winner's value. Got: {:?}", other),
+        }
+        match client.get("read_through_race") {
+            Ok(Some(item)) if item.value == b"winner" => (),
+            other => panic!("expected the winner's value to be kept. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cas_loop() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let mut other = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0).unwrap();
+        let append = |current: Option<&[u8]>| {
+            let mut value = current.unwrap_or_default().to_vec();
+            value.push(b'+');
+            Some(value)
+        };
+        let _ = client.delete("cas_loop");
+
+        match client.cas_loop("cas_loop", Expiration::Never, 3, append) {
+            Ok(Some(value)) if value == b"+" => (),
+            other => panic!("expected a missing key to be added. Got: {:?}", other),
+        }
+        if let Err(error) = client.set(&Item::new("cas_loop", "x").unwrap().with_flags(7)) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        let ttl = Expiration::In(Duration::from_secs(60));
+        match client.cas_loop("cas_loop", ttl, 3, append) {
+            Ok(Some(value)) if value == b"x+" => (),
+            other => panic!("expected the value to be updated. Got: {:?}", other),
+        }
+        let flags = MetaGetFlags {
+            value: true,
+            client_flags: true,
+            ttl: true,
+            ..Default::default()
+        };
+        match client.meta_get("cas_loop", &flags) {
+            Ok(Some(item))
+                if item.value.as_deref() == Some(b"x+".as_slice())
+                    && item.flags == Some(7)
+                    && matches!(item.ttl, Some(ttl) if ttl > 0 && ttl <= 60) => {}
+            other => panic!(
+                "expected the update with the item's flags and the ttl. Got: {:?}",
+                other
+            ),
+        }
+
+        // Another writer changes the value between the read and the cas
+        let mut calls = 0;
+        match client.cas_loop("cas_loop", Expiration::Never, 3, |current| {
+            calls += 1;
+            if calls == 1 {
+                other.set(&Item::new("cas_loop", "y").unwrap()).unwrap();
+            }
+            append(current)
+        }) {
+            Ok(Some(value)) if value == b"y+" && calls == 2 => (),
+            other => panic!(
+                "expected a retry on the other writer's value. Got: {:?} after {} calls",
+                other, calls
+            ),
+        }
+
+        // Another writer adds the missing key first
+        let _ = client.delete("cas_loop");
+        calls = 0;
+        match client.cas_loop("cas_loop", Expiration::Never, 3, |current| {
+            calls += 1;
+            if calls == 1 {
+                other.add(&Item::new("cas_loop", "z").unwrap()).unwrap();
+            }
+            append(current)
+        }) {
+            Ok(Some(value)) if value == b"z+" && calls == 2 => (),
+            other => panic!(
+                "expected a retry on the added value. Got: {:?} after {} calls",
+                other, calls
+            ),
+        }
+
+        calls = 0;
+        match client.cas_loop("cas_loop", Expiration::Never, 3, |current| {
+            calls += 1;
+            other.set(&Item::new("cas_loop", "w").unwrap()).unwrap();
+            append(current)
+        }) {
+            Err(OperationError::CASAttemptsExhausted(3)) if calls == 3 => (),
+            other => panic!(
+                "expected the attempts to run out. Got: {:?} after {} calls",
+                other, calls
+            ),
+        }
+
+        match client.cas_loop("cas_loop", Expiration::Never, 3, |_| None) {
+            Ok(None) => (),
+            other => panic!("expected the update to be aborted. Got: {:?}", other),
+        }
+        match client.get("cas_loop") {
+            Ok(Some(item)) if item.value == b"w" => (),
+            other => panic!("expected an aborted update not to write. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_early_expiration_header() {
+        let header = EarlyExpiration {
+            cost: Duration::from_millis(250),
+            expiry: Duration::from_millis(1_700_000_060_000),
+        };
+        let framed = header.frame(b"value");
+        match EarlyExpiration::unframe(framed.clone()) {
+            Some((unframed, value)) if unframed == header && value == b"value" => (),
+            other => panic!("expected the header to read back. Got: {:?}", other),
+        }
+        let mut unknown = framed;
+        unknown[0] = 2;
+        if EarlyExpiration::unframe(unknown).is_some()
+            || EarlyExpiration::unframe(vec![1]).is_some()
+        {
+            panic!("expected unknown framings not to be read")
+        }
+
+        // A minute before the expiry, a 250ms value only refreshes on very unlucky draws
+        let now = Duration::from_secs(1_700_000_000);
+        if header.expires_early(now, 1.0, 0.5) || header.expires_early(now, 0.0, 1e-300) {
+            panic!("did not expect the value to expire early")
+        }
+        if !header.expires_early(now, 1.0, 1e-110) || !header.expires_early(now, 1000.0, 0.5) {
+            panic!("expected the value to expire early")
+        }
+        if !header.expires_early(header.expiry, 0.0, 0.5) {
+            panic!("expected the value to expire at its expiry")
+        }
+    }
+
+    #[test]
+    fn test_get_or_set_with_early_expiration() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+        let ttl = Duration::from_secs(60);
+        let _ = client.delete("xfetch");
+        let compute = |value: &'static str| {
+            move || {
+                std::thread::sleep(Duration::from_millis(5));
+                Ok::<_, String>(value.as_bytes().to_vec())
+            }
+        };
+
+        match client.get_or_set_with_early_expiration("xfetch", ttl, 1.0, compute("first")) {
+            Ok((value, true)) if value == b"first" => (),
+            other => panic!("expected the value to be computed. Got: {:?}", other),
+        }
+        match client.get("xfetch") {
+            Ok(Some(item)) if item.flags == 0 && item.value.ends_with(b"first") => (),
+            other => panic!("expected the framed value. Got: {:?}", other),
+        }
+        match client.get_or_set_with_early_expiration("xfetch", ttl, 0.0, compute("second")) {
+            Ok((value, false)) if value == b"first" => (),
+            other => panic!("expected the stored value. Got: {:?}", other),
+        }
+        // So early that every read volunteers
+        match client.get_or_set_with_early_expiration("xfetch", ttl, 1e9, compute("second")) {
+            Ok((value, true)) if value == b"second" => (),
+            other => panic!("expected the value to be refreshed. Got: {:?}", other),
+        }
+
+        // Values stored by others aren't mistaken for framed ones
+        let plain = Item::new("xfetch", "plain").unwrap().with_flags(1);
+        if let Err(error) = client.set(&plain) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.get_or_set_with_early_expiration("xfetch", ttl, 1e9, compute("third")) {
+            Ok((value, false)) if value == b"plain" => (),
+            other => panic!("expected the plain value as is. Got: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::errors::{OperationError, WriteReadLineError};
+use crate::expiration::Expiration;
+use crate::item::{Item, StoredValue};
+use crate::protocol::{self, Arg};
+use crate::trace;
+use crate::{
+    legal_key, Client, Conn, RESULT_DELETED, RESULT_TOUCHED, VERB_ADD, VERB_DECR, VERB_DELETE,
+    VERB_GET, VERB_INCR, VERB_REPLACE, VERB_SET, VERB_TOUCH,
+};
+use std::borrow::Cow;
+use std::io::Write;
+
+// A batch of commands, obtained from `Client::pipeline`, written to each server in one go before
+// any response is read; `execute` returns the result of every command in the order they were
+// queued; Commands to the same server run in order, those to different servers are independent;
+#[derive(Debug, Default, Clone)]
+pub struct Pipeline<'a> {
+    ops: Vec<Op<'a>>,
+}
+
+#[derive(Debug, Clone)]
+enum Op<'a> {
+    Get(&'a str),
+    Store(&'static str, &'a Item),
+    Delete(&'a str),
+    Touch(&'a str, Expiration),
+    Arithmetic(&'static str, &'a str, u64),
+}
+
+// What a queued command returned, by command;
+#[derive(Debug, PartialEq)]
+pub enum PipelineResponse {
+    // `None` when the key is missing
+    Item(Option<Item>),
+    Stored,
+    Deleted,
+    Touched,
+    // The new value of an increment or decrement
+    Counter(u64),
+}
+
+// A queued command ready to be written, with its key as sent
+struct Prepared<'a> {
+    index: usize,
+    key: Cow<'a, str>,
+    command: Command<'a>,
+}
+
+enum Command<'a> {
+    Get,
+    Store(&'static str, StoredValue<'a>),
+    Delete,
+    Touch(i32),
+    Arithmetic(&'static str, u64),
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    pub fn get(&mut self, key: &'a str) -> &mut Self {
+        self.ops.push(Op::Get(key));
+        self
+    }
+
+    pub fn set(&mut self, item: &'a Item) -> &mut Self {
+        self.ops.push(Op::Store(VERB_SET, item));
+        self
+    }
+
+    pub fn add(&mut self, item: &'a Item) -> &mut Self {
+        self.ops.push(Op::Store(VERB_ADD, item));
+        self
+    }
+
+    pub fn replace(&mut self, item: &'a Item) -> &mut Self {
+        self.ops.push(Op::Store(VERB_REPLACE, item));
+        self
+    }
+
+    pub fn delete(&mut self, key: &'a str) -> &mut Self {
+        self.ops.push(Op::Delete(key));
+        self
+    }
+
+    pub fn touch(&mut self, key: &'a str, expiration: Expiration) -> &mut Self {
+        self.ops.push(Op::Touch(key, expiration));
+        self
+    }
+
+    pub fn increment(&mut self, key: &'a str, delta: u64) -> &mut Self {
+        self.ops.push(Op::Arithmetic(VERB_INCR, key, delta));
+        self
+    }
+
+    pub fn decrement(&mut self, key: &'a str, delta: u64) -> &mut Self {
+        self.ops.push(Op::Arithmetic(VERB_DECR, key, delta));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    // Sends the queued commands, one write per server, and returns their results positionally;
+    // A command that fails on its own (a miss, an invalid key, a value that's too large) only
+    // fails its result, while a server whose connection fails, or whose responses can't be
+    // matched to the commands any more, fails the whole batch with `ServerFailures` and has its
+    // connection dropped; Commands aren't retried, as increments and the like can't be resent;
+    pub fn execute(
+        &self,
+        client: &mut Client,
+    ) -> Result<Vec<Result<PipelineResponse, OperationError>>, OperationError> {
+        trace::in_span(trace::op_span!("memcache.pipeline"), || {
+            client.ascii_only("pipeline")?;
+            let mut results = Vec::with_capacity(self.ops.len());
+            let mut prepared = Vec::with_capacity(self.ops.len());
+            for (index, op) in self.ops.iter().enumerate() {
+                match client.prepare_pipelined(index, op) {
+                    Ok(command) => prepared.push(command),
+                    Err(error) => results.push((index, Err(error))),
+                }
+            }
+
+            let mut failures = Vec::new();
+            for (addr, commands) in client.group_by_server(prepared, |command| &command.key)? {
+                match client.with_conn(addr, |conn| Client::pipeline_to_conn(conn, &commands)) {
+                    Ok(responses) => {
+                        for (command, response) in commands.iter().zip(responses) {
+                            let response = match response {
+                                Ok(PipelineResponse::Item(Some(item))) => client
+                                    .returned_item(item)
+                                    .map(|item| PipelineResponse::Item(Some(item))),
+                                response => response,
+                            };
+                            results.push((command.index, response));
+                        }
+                    }
+                    Err(error) => failures.push((addr, error)),
+                }
+            }
+            if !failures.is_empty() {
+                return Err(OperationError::ServerFailures(failures));
+            }
+            results.sort_by_key(|(index, _)| *index);
+            Ok(results.into_iter().map(|(_, result)| result).collect())
+        })
+    }
+}
+
+impl Client {
+    // Starts an empty `Pipeline`;
+    pub fn pipeline<'a>(&self) -> Pipeline<'a> {
+        Pipeline::new()
+    }
+
+    // Everything that can fail before `op` is written, as in the single command;
+    fn prepare_pipelined<'a>(
+        &self,
+        index: usize,
+        op: &Op<'a>,
+    ) -> Result<Prepared<'a>, OperationError> {
+        let (key, command) = match *op {
+            Op::Get(key) => (key, Command::Get),
+            Op::Store(verb, item) => {
+                let key = self.prefixed(&item.key);
+                self.check_storable(&key, item)?;
+                let stored = self.compressed(item.stored());
+                self.check_value_size(&stored.value)?;
+                let command = Command::Store(verb, stored);
+                return Ok(Prepared {
+                    index,
+                    key,
+                    command,
+                });
+            }
+            Op::Delete(key) => (key, Command::Delete),
+            Op::Touch(key, expiration) => (key, Command::Touch(expiration.wire_value()?)),
+            Op::Arithmetic(verb, key, delta) => (key, Command::Arithmetic(verb, delta)),
+        };
+        let key = self.prefixed(key);
+        legal_key(&key)?;
+        Ok(Prepared {
+            index,
+            key,
+            command,
+        })
+    }
+
+    // Writes every command with a single flush, then reads one response per command; Per
+    // command failures are returned in their place, while IO errors and responses that don't
+    // belong to their command abort the batch, as every response after them would be misread;
+    fn pipeline_to_conn(
+        conn: &mut Conn,
+        commands: &[Prepared],
+    ) -> Result<Vec<Result<PipelineResponse, OperationError>>, OperationError> {
+        for Prepared { key, command, .. } in commands {
+            let written = match command {
+                Command::Get => {
+                    protocol::write_command(&mut conn.writer, VERB_GET, [Arg::Str(key)])
+                }
+                Command::Store(verb, stored) => {
+                    protocol::write_storage_command(
+                        &mut conn.writer,
+                        verb,
+                        key,
+                        stored,
+                        None,
+                        false,
+                    )?;
+                    Ok(())
+                }
+                Command::Delete => {
+                    protocol::write_command(&mut conn.writer, VERB_DELETE, [Arg::Str(key)])
+                }
+                Command::Touch(exptime) => {
+                    let args = [Arg::Str(key), Arg::Int((*exptime).into())];
+                    protocol::write_command(&mut conn.writer, VERB_TOUCH, args)
+                }
+                Command::Arithmetic(verb, delta) => protocol::write_command(
+                    &mut conn.writer,
+                    verb,
+                    [Arg::Str(key), Arg::Uint(*delta)],
+                ),
+            };
+            written?;
+        }
+        conn.writer
+            .flush()
+            .map_err(|error| OperationError::Io(WriteReadLineError::Flush(error)))?;
+
+        let mut responses = Vec::with_capacity(commands.len());
+        for Prepared { key, command, .. } in commands {
+            let response = match command {
+                Command::Get => Client::read_pipelined_get(conn, key),
+                Command::Store(verb, _) => {
+                    Client::read_storage_response(conn, verb).map(|()| PipelineResponse::Stored)
+                }
+                Command::Delete => conn
+                    .read_line()
+                    .map_err(OperationError::Io)
+                    .and_then(|line| protocol::expected_result(line, VERB_DELETE, RESULT_DELETED))
+                    .map(|()| PipelineResponse::Deleted),
+                Command::Touch(_) => conn
+                    .read_line()
+                    .map_err(OperationError::Io)
+                    .and_then(|line| protocol::expected_result(line, VERB_TOUCH, RESULT_TOUCHED))
+                    .map(|()| PipelineResponse::Touched),
+                Command::Arithmetic(verb, _) => conn
+                    .read_line()
+                    .map_err(OperationError::Io)
+                    .and_then(|line| protocol::incr_decr_result(line, verb))
+                    .map(PipelineResponse::Counter),
+            };
+            // Error lines such as a `SERVER_ERROR` for an object too large for the cache are
+            // the command's own response, leaving the next one where it's expected
+            match response {
+                Err(error) if error.fatal_to_connection() => return Err(error),
+                response => responses.push(response),
+            }
+        }
+        Ok(responses)
+    }
+
+    fn read_pipelined_get(conn: &mut Conn, key: &str) -> Result<PipelineResponse, OperationError> {
+        let mut items = Vec::new();
+        Client::parse_get_response(conn, VERB_GET, false, |item| items.push(item))?;
+        match items.pop() {
+            None => Ok(PipelineResponse::Item(None)),
+            Some(item) if items.is_empty() && item.key == key => {
+                Ok(PipelineResponse::Item(Some(item)))
+            }
+            Some(item) => Err(OperationError::CorruptResponse(format!(
+                "got {} as the response to a get of {}",
+                item.key, key
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PipelineResponse::{Counter, Deleted, Item as Found, Stored};
+    use crate::errors::OperationError::{CacheMiss, MalformedKey, NotStored, ValueTooLarge};
+    use crate::{
+        errors::OperationError,
+        item::Item,
+        tests::{lock_local_server, mock_server, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+
+    #[test]
+    fn test_pipeline() {
+        let _guard = lock_local_server();
+        let mut client = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0).unwrap();
+        let _ = client.delete("pipeline_missing");
+        let first = Item::new("pipeline_first", "1").unwrap();
+        let second = Item::new("pipeline_second", "two").unwrap();
+        let too_large = Item::new("pipeline_large", vec![0; 2 * 1024 * 1024]).unwrap();
+
+        let mut pipeline = client.pipeline();
+        pipeline
+            .set(&first)
+            .set(&second)
+            .set(&too_large)
+            .add(&first)
+            .increment("pipeline_first", 4)
+            .get("pipeline_first")
+            .get("pipeline_missing")
+            .get("bad key")
+            .delete("pipeline_second")
+            .delete("pipeline_second")
+            .get("pipeline_second");
+        let results = match pipeline.execute(&mut client) {
+            Ok(results) if results.len() == pipeline.len() => results,
+            other => panic!("expected a result per command. Got: {:?}", other),
+        };
+        match &results[..5] {
+            [Ok(Stored), Ok(Stored), Err(ValueTooLarge { .. }), Err(NotStored), Ok(Counter(5))] => {
+            }
+            other => panic!(
+                "expected the stores and increment in order. Got: {:?}",
+                other
+            ),
+        }
+        match &results[5..8] {
+            [Ok(Found(Some(item))), Ok(Found(None)), Err(MalformedKey(_))]
+                if item.key == "pipeline_first" && item.value == b"5" => {}
+            other => panic!("expected the gets in order. Got: {:?}", other),
+        }
+        match &results[8..] {
+            [Ok(Deleted), Err(CacheMiss), Ok(Found(None))] => (),
+            other => panic!("expected the deletes in order. Got: {:?}", other),
+        }
+        match client.pipeline().execute(&mut client) {
+            Ok(results) if results.is_empty() => (),
+            other => panic!("expected an empty pipeline to do nothing. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn misaligned_pipeline_responses_fail_the_batch() {
+        // Answers the first get with another key's value
+        let addr = mock_server(b"VALUE other 0 1\r\nx\r\nEND\r\nEND\r\n");
+        let mut client = Client::new(addr, None, 0).unwrap();
+        let mut pipeline = client.pipeline();
+        pipeline.get("first").get("second");
+        match pipeline.execute(&mut client) {
+            Err(OperationError::ServerFailures(failures)) => match failures.as_slice() {
+                [(_, OperationError::CorruptResponse(_))] => (),
+                other => panic!("expected a corrupt response. Got: {:?}", other),
+            },
+            other => panic!("expected the batch to fail. Got: {:?}", other),
+        }
+        if client.pool_stats().values().any(|stats| stats.idle > 0) {
+            panic!("expected the misaligned connection to be dropped")
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use crate::errors::{response_excerpt, OperationError, WriteReadLineError};
+use crate::item::{Item, StoredValue};
+use crate::{
+    next_field, CR_LF, NOREPLY, RESULT_CLIENT_ERROR_PREFIX, RESULT_END, RESULT_ERROR,
+    RESULT_EXISTS, RESULT_NOT_FOUND, RESULT_NOT_STORED, RESULT_OK, RESULT_SERVER_ERROR_PREFIX,
+    RESULT_STAT_PREFIX, RESULT_STORED, RESULT_VALUE_PREFIX, RESULT_VERSION_PREFIX, VERB_STATS,
+    VERB_VERSION,
+};
+use std::io::{self, Write};
+
+// Formatting of ASCII commands and parsing of their responses, shared by `Client` and
+// `AsyncClient`, which only differ in how the bytes get to and from the server
+
+// Header of a `VALUE <key> <flags> <bytes> [<cas unique>]\r\n` line
+#[derive(Debug)]
+pub(crate) struct ValueHeader {
+    key: String,
+    pub(crate) flags: u32,
+    // Bytes of the data block, without its `\r\n`
+    pub(crate) size: usize,
+    pub(crate) cas_id: u64,
+}
+
+// Parses a line of a get response, `None` being its `END\r\n`; `with_cas` is set for
+// `gets`/`gats`, whose header carries the extra `cas unique` column;
+pub(crate) fn parse_value_header(
+    line: &[u8],
+    verb: &str,
+    with_cas: bool,
+) -> Result<Option<ValueHeader>, OperationError> {
+    if line == RESULT_END {
+        return Ok(None);
+    }
+    if let Some(error) = error_response(line, verb) {
+        return Err(error);
+    }
+    if !line.starts_with(RESULT_VALUE_PREFIX) {
+        return Err(OperationError::CorruptResponse(format!(
+            "unexpected get response line: {}",
+            response_excerpt(line)
+        )));
+    }
+    let line = line.strip_suffix(CR_LF).unwrap_or(line);
+    let mut split = line.split(|&x| x == b' ');
+    let _ = split.next(); // NOTE: Ignore first token
+    let key = String::from_utf8(next_field(&mut split, "key")?.to_vec()).map_err(|error| {
+        OperationError::CorruptResponse(format!("could not parse the item key: {}", error))
+    })?;
+    let flags = String::from_utf8(next_field(&mut split, "flags")?.to_vec()).map_err(|error| {
+        OperationError::CorruptResponse(format!("could not parse flags: {}", error))
+    })?;
+    let flags = match flags.parse::<u32>() {
+        Ok(flags) => flags,
+        Err(error) => {
+            return Err(OperationError::CorruptResponse(format!(
+                "could not convert flags into an integer: {}",
+                error
+            )))
+        }
+    };
+
+    let size = String::from_utf8(next_field(&mut split, "size")?.to_vec()).map_err(|error| {
+        OperationError::CorruptResponse(format!("could not parse size: {}", error))
+    })?;
+
+    let size = match size.parse::<u32>() {
+        Ok(size) => size as usize,
+        Err(error) => {
+            return Err(OperationError::CorruptResponse(format!(
+                "could parse the item value size: {}",
+                error
+            )))
+        }
+    };
+
+    let cas_id = if with_cas {
+        let cas_id =
+            String::from_utf8(next_field(&mut split, "cas unique")?.to_vec()).map_err(|error| {
+                OperationError::CorruptResponse(format!("could not parse cas unique: {}", error))
+            })?;
+        match cas_id.parse::<u64>() {
+            Ok(cas_id) => cas_id,
+            Err(error) => {
+                return Err(OperationError::CorruptResponse(format!(
+                    "could not convert cas unique into an integer: {}",
+                    error
+                )))
+            }
+        }
+    } else {
+        0
+    };
+    Ok(Some(ValueHeader {
+        key,
+        flags,
+        size,
+        cas_id,
+    }))
+}
+
+// The item of `header`, given the `size + 2` bytes of its `<data block>\r\n`;
+pub(crate) fn value_item(header: ValueHeader, mut block: Vec<u8>) -> Result<Item, OperationError> {
+    if !block.ends_with(CR_LF) {
+        return Err(OperationError::CorruptResponse(
+            "corrupt get result read".to_string(),
+        ));
+    }
+    block.truncate(block.len() - CR_LF.len());
+    let mut item = Item::from_parts(header.key, block, header.flags, 0);
+    item.cas_id = header.cas_id;
+    Ok(item)
+}
+
+// An argument of a command line; Numbers are formatted straight into the writer, without going
+// through `fmt` or an intermediate `String`
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Arg<'a> {
+    Str(&'a str),
+    Uint(u64),
+    Int(i64),
+    // A meta flag followed by its numeric token, e.g. `T30`
+    Flag(u8, u64),
+    SignedFlag(u8, i64),
+}
+
+// Writes (without flushing) `<verb> <args...>\r\n`, every argument preceded by a space;
+pub(crate) fn write_command<'a>(
+    writer: &mut impl Write,
+    verb: &str,
+    args: impl IntoIterator<Item = Arg<'a>>,
+) -> Result<(), WriteReadLineError> {
+    write_command_line(writer, verb, args).map_err(WriteReadLineError::Write)
+}
+
+fn write_command_line<'a>(
+    writer: &mut impl Write,
+    verb: &str,
+    args: impl IntoIterator<Item = Arg<'a>>,
+) -> io::Result<()> {
+    let mut numbers = itoa::Buffer::new();
+    writer.write_all(verb.as_bytes())?;
+    for arg in args {
+        writer.write_all(b" ")?;
+        match arg {
+            Arg::Str(arg) => writer.write_all(arg.as_bytes())?,
+            Arg::Uint(number) => writer.write_all(numbers.format(number).as_bytes())?,
+            Arg::Int(number) => writer.write_all(numbers.format(number).as_bytes())?,
+            Arg::Flag(flag, number) => {
+                writer.write_all(&[flag])?;
+                writer.write_all(numbers.format(number).as_bytes())?
+            }
+            Arg::SignedFlag(flag, number) => {
+                writer.write_all(&[flag])?;
+                writer.write_all(numbers.format(number).as_bytes())?
+            }
+        }
+    }
+    writer.write_all(CR_LF)
+}
+
+// Writes (without flushing) `<verb> <key> <flags> <exptime> <bytes> [<cas unique>]\r\n<data
+// block>\r\n`;
+pub(crate) fn write_storage_command(
+    writer: &mut impl Write,
+    verb: &str,
+    key: &str,
+    stored: &StoredValue,
+    cas: Option<u64>,
+    noreply: bool,
+) -> Result<(), OperationError> {
+    let exptime = stored.expiration.wire_value()?;
+    let args = [
+        Arg::Str(key),
+        Arg::Uint(stored.flags.into()),
+        Arg::Int(exptime.into()),
+        Arg::Uint(stored.value.len() as u64),
+    ];
+    let args = args
+        .into_iter()
+        .chain(cas.map(Arg::Uint))
+        .chain(noreply.then_some(Arg::Str(NOREPLY)));
+    write_command(writer, verb, args)?;
+    writer
+        .write_all(&stored.value)
+        .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))?;
+    writer
+        .write_all(CR_LF)
+        .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))
+}
+
+// The error of an `ERROR\r\n`, `CLIENT_ERROR <message>\r\n` or `SERVER_ERROR <message>\r\n`
+// line, which the server may send instead of any command's usual response; `verb` is the
+// command the line answers;
+pub(crate) fn error_response(line: &[u8], verb: &str) -> Option<OperationError> {
+    if line == RESULT_ERROR {
+        return Some(OperationError::UnsupportedCommand(verb.to_string()));
+    }
+    let message = |prefix: &[u8]| {
+        String::from_utf8_lossy(&line[prefix.len()..])
+            .trim_end()
+            .to_string()
+    };
+    if line.starts_with(RESULT_SERVER_ERROR_PREFIX) {
+        return Some(OperationError::Server(message(RESULT_SERVER_ERROR_PREFIX)));
+    }
+    if line.starts_with(RESULT_CLIENT_ERROR_PREFIX) {
+        return Some(OperationError::Client(message(RESULT_CLIENT_ERROR_PREFIX)));
+    }
+    None
+}
+
+// Result of a storage command's response line;
+pub(crate) fn storage_result(line: &[u8], verb: &str) -> Result<(), OperationError> {
+    match line {
+        RESULT_STORED => Ok(()),
+        RESULT_NOT_STORED => Err(OperationError::NotStored),
+        RESULT_EXISTS => Err(OperationError::CASConflict),
+        RESULT_NOT_FOUND => Err(OperationError::CacheMiss),
+        _ => Err(error_response(line, verb).unwrap_or_else(|| {
+            OperationError::CorruptResponse(format!(
+                "unexpected response from server: {}",
+                response_excerpt(line),
+            ))
+        })),
+    }
+}
+
+// Result of a response line that's `expect` (or `OK`) on success;
+// NOTE: `expect` String?
+pub(crate) fn expected_result(
+    line: &[u8],
+    verb: &str,
+    expect: &[u8],
+) -> Result<(), OperationError> {
+    match line {
+        _ if line == expect => Ok(()),
+        RESULT_OK => Ok(()),
+        RESULT_NOT_STORED => Err(OperationError::NotStored),
+        RESULT_EXISTS => Err(OperationError::CASConflict),
+        RESULT_NOT_FOUND => Err(OperationError::CacheMiss),
+        _ => Err(error_response(line, verb).unwrap_or_else(|| {
+            OperationError::CorruptResponse(format!(
+                "unexpected response line: {}", // TODO: Include command here `from {}`
+                response_excerpt(line)
+            ))
+        })),
+    }
+}
+
+// The new value of an `incr` or `decr` response line;
+pub(crate) fn incr_decr_result(line: &[u8], verb: &str) -> Result<u64, OperationError> {
+    if line == RESULT_NOT_FOUND {
+        return Err(OperationError::CacheMiss);
+    }
+    if let Some(error) = error_response(line, verb) {
+        return Err(error);
+    }
+    let digits = line.strip_suffix(CR_LF).unwrap_or(line);
+    String::from_utf8(digits.to_vec())
+        .map_err(|_| OperationError::CorruptResponse("invalid UTF-8 sequence".to_string()))?
+        .parse::<u64>()
+        .map_err(|_| OperationError::CorruptResponse("failed to parse integer".to_string()))
+}
+
+pub(crate) fn version_result(line: &[u8]) -> Result<String, OperationError> {
+    if let Some(error) = error_response(line, VERB_VERSION) {
+        return Err(error);
+    }
+    if !line.starts_with(RESULT_VERSION_PREFIX) || !line.ends_with(CR_LF) {
+        return Err(OperationError::CorruptResponse(format!(
+            "unexpected version response line: {}",
+            response_excerpt(line)
+        )));
+    }
+    String::from_utf8(line[RESULT_VERSION_PREFIX.len()..line.len() - CR_LF.len()].to_vec())
+        .map_err(|_| OperationError::CorruptResponse("invalid UTF-8 sequence".to_string()))
+}
+
+// Parses a `STAT <name> <value>\r\n` line, `None` being the closing `END\r\n`;
+pub(crate) fn parse_stat_line(line: &[u8]) -> Result<Option<(String, String)>, OperationError> {
+    if line == RESULT_END {
+        return Ok(None);
+    }
+    if let Some(error) = error_response(line, VERB_STATS) {
+        return Err(error);
+    }
+    if !line.starts_with(RESULT_STAT_PREFIX) || !line.ends_with(CR_LF) {
+        return Err(OperationError::CorruptResponse(format!(
+            "unexpected stats response line: {}",
+            response_excerpt(line)
+        )));
+    }
+    let line_str =
+        String::from_utf8(line[RESULT_STAT_PREFIX.len()..line.len() - CR_LF.len()].to_vec())
+            .map_err(|_| OperationError::CorruptResponse("invalid UTF-8 sequence".to_string()))?;
+    // Values such as `version` or `libevent` may contain spaces
+    Ok(Some(match line_str.split_once(' ') {
+        Some((name, value)) => (name.to_string(), value.to_string()),
+        None => (line_str, String::new()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_command, write_storage_command, Arg};
+    use crate::expiration::Expiration;
+    use crate::item::StoredValue;
+    use std::borrow::Cow;
+
+    #[test]
+    fn commands_are_written_without_fmt() {
+        let mut command = Vec::new();
+        let args = [
+            Arg::Str("key"),
+            Arg::Uint(u64::MAX),
+            Arg::Int(-1),
+            Arg::Flag(b'C', 0),
+            Arg::SignedFlag(b'T', i64::MIN),
+        ];
+        if let Err(error) = write_command(&mut command, "mg", args) {
+            panic!("did not expect writing to a Vec to fail: {:?}", error)
+        }
+        let expected = format!("mg key {} -1 C0 T{}\r\n", u64::MAX, i64::MIN);
+        if command != expected.as_bytes() {
+            panic!(
+                "expected {:?}. Got: {:?}",
+                expected,
+                String::from_utf8_lossy(&command)
+            )
+        }
+
+        let stored = StoredValue {
+            value: Cow::Borrowed(b"value"),
+            flags: 42,
+            expiration: Expiration::Raw(-1),
+        };
+        let mut command = Vec::new();
+        if let Err(error) =
+            write_storage_command(&mut command, "cas", "key", &stored, Some(7), true)
+        {
+            panic!("did not expect writing to a Vec to fail: {}", error)
+        }
+        match command.as_slice() {
+            b"cas key 42 -1 5 7 noreply\r\nvalue\r\n" => (),
+            other => panic!(
+                "expected a cas command. Got: {:?}",
+                String::from_utf8_lossy(other)
+            ),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+// How transient failures are retried: reconnecting after a failed connect and re-sending a safe
+// command (e.g. `get` or `version`) after an IO error or timeout; Commands that aren't safe to
+// resend, such as storage commands, are never retried;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    // Attempts in total, the first one included; `1` (or `0`) never retries
+    pub max_attempts: u32,
+    // Delay before the first retry, multiplied by `multiplier` before every further one
+    pub base_delay: Duration,
+    pub multiplier: f64,
+    // Sleeps a random delay between zero and the computed one ("full jitter") so clients that
+    // failed together don't retry together
+    pub jitter: bool,
+    // No retry is started that would end past this much time since the operation began
+    pub deadline: Option<Duration>,
+}
+
+impl RetryPolicy {
+    // Fails on the first error, the default;
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            multiplier: 1.0,
+            jitter: false,
+            deadline: None,
+        }
+    }
+
+    // Delay before retry number `attempt`, starting at 1;
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs = self.base_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = Duration::try_from_secs_f64(secs.max(0.0)).unwrap_or(Duration::MAX);
+        if !self.jitter {
+            return delay;
+        }
+        // `RandomState` is seeded differently every time, no need for a random number crate
+        let random = RandomState::new().build_hasher().finish();
+        delay.mul_f64(random as f64 / u64::MAX as f64)
+    }
+
+    // Delay before retrying once `attempts` attempts of an operation that began at `started`
+    // failed, `None` when it's out of attempts or the retry would overrun the deadline;
+    pub(crate) fn next_delay(&self, attempts: u32, started: Instant) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        let delay = self.delay(attempts);
+        match self.deadline {
+            Some(deadline) if started.elapsed().saturating_add(delay) >= deadline => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_retry_delays() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(10),
+            multiplier: 2.0,
+            jitter: false,
+            deadline: None,
+        };
+        let delays: Vec<Duration> = (1..=3).map(|attempt| policy.delay(attempt)).collect();
+        if delays != [10, 20, 40].map(Duration::from_millis) {
+            panic!("expected exponential delays. Got: {:?}", delays)
+        }
+        let started = Instant::now();
+        if policy.next_delay(3, started).is_none() || policy.next_delay(4, started).is_some() {
+            panic!("expected 4 attempts in total")
+        }
+        if RetryPolicy::none().next_delay(1, started).is_some() {
+            panic!("expected no retries")
+        }
+
+        let policy = RetryPolicy {
+            jitter: true,
+            deadline: Some(Duration::from_millis(15)),
+            ..policy
+        };
+        if (1..=3).any(|attempt| policy.delay(attempt) > delays[attempt as usize - 1]) {
+            panic!("expected jittered delays to stay under the computed ones")
+        }
+        let policy = RetryPolicy {
+            jitter: false,
+            ..policy
+        };
+        if policy.next_delay(1, started).is_none() || policy.next_delay(2, started).is_some() {
+            panic!("expected retries to stop at the deadline")
+        }
+    }
+}
@@ -0,0 +1,1215 @@
+use crate::errors::{ConnError, OperationError};
+use crate::hasher::KeyHasher;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+const KETAMA_POINTS_PER_SERVER: usize = 160;
+
+/// Maps every key onto the server it's stored on, see `Client::new_from_selector`;
+///
+/// A selector that sends every key to the first of its servers:
+///
+/// ```
+/// use rsmemcache::{Client, OperationError, ProtocolMode, ServerSelector};
+/// use std::io::{BufRead, BufReader, Write};
+/// use std::net::{SocketAddr, TcpListener};
+///
+/// #[derive(Debug)]
+/// struct FirstServer(Vec<SocketAddr>);
+///
+/// impl ServerSelector for FirstServer {
+///     fn pick_server(&self, _key: &str) -> Result<SocketAddr, OperationError> {
+///         self.0.first().copied().ok_or(OperationError::NoServers)
+///     }
+///
+///     fn each(
+///         &self,
+///         f: &mut dyn FnMut(SocketAddr) -> Result<(), OperationError>,
+///     ) -> Result<(), OperationError> {
+///         self.0.iter().try_for_each(|addr| f(*addr))
+///     }
+/// }
+///
+/// // The first server answers every command with a miss, the second one hangs up
+/// let server = |answers: bool| {
+///     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+///     let addr = listener.local_addr().unwrap();
+///     std::thread::spawn(move || {
+///         for mut stream in listener.incoming().flatten().filter(|_| answers) {
+///             let mut reader = BufReader::new(stream.try_clone().unwrap());
+///             let mut line = String::new();
+///             while reader.read_line(&mut line).unwrap_or(0) > 0 {
+///                 if stream.write_all(b"END\r\n").is_err() {
+///                     break;
+///                 }
+///                 line.clear();
+///             }
+///         }
+///     });
+///     addr
+/// };
+/// let selector = FirstServer(vec![server(true), server(false)]);
+/// let mut client = Client::new_from_selector(selector, None, 1, ProtocolMode::Ascii).unwrap();
+/// assert!(matches!(client.get("key"), Ok(None)));
+/// ```
+pub trait ServerSelector: std::fmt::Debug + Send + Sync {
+    fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError>;
+
+    // Calls `f` with every server, stopping at the first error; The client sends commands meant
+    // for every server, such as `flush_all` or `stats`, to the ones `each` goes through;
+    fn each(
+        &self,
+        f: &mut dyn FnMut(SocketAddr) -> Result<(), OperationError>,
+    ) -> Result<(), OperationError>;
+
+    // Adds `server` to the `ServerList` behind the selector, returning the address it resolved
+    // to; Selectors without one have a fixed set of servers and reject it;
+    fn add_server(&self, server: &str) -> Result<SocketAddr, ConnError> {
+        match self.server_list() {
+            Some(server_list) => server_list.add_server(server),
+            None => Err(ConnError::InvalidServerAddr(
+                server.to_string(),
+                "the selector has a fixed set of servers".to_string(),
+            )),
+        }
+    }
+
+    fn remove_server(&self, server: &str) -> Option<SocketAddr> {
+        self.server_list()?.remove_server(server)
+    }
+
+    fn addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs = Vec::new();
+        // Collecting never fails, so only the selector itself can cut it short
+        let _ = self.each(&mut |addr| {
+            addrs.push(addr);
+            Ok(())
+        });
+        addrs
+    }
+
+    // The `ServerList` behind the selector, if any; Failover, auto ejection and DNS refresh work
+    // on it, so selectors without one go without them;
+    fn server_list(&self) -> Option<&ServerList> {
+        None
+    }
+}
+
+// Servers a client spreads keys over, mapping every key onto one of them by the CRC32 (or another
+// `KeyHasher`) of the key modulo the number of servers (like gomemcache's `ServerList`); Weighted
+// servers count as many times as their weight;
+// Lookups only take a read lock, so they proceed concurrently and the servers can be replaced
+// while a client shared between threads keeps mapping keys;
+#[derive(Debug, Default)]
+pub struct ServerList {
+    servers: RwLock<Vec<ServerEntry>>,
+    key_hasher: KeyHasher,
+}
+
+#[derive(Debug)]
+struct ServerEntry {
+    // As configured, either a literal socket address or `host:port`
+    name: String,
+    addr: SocketAddr,
+    // Share of the keys relative to the other servers, a server of weight 3 gets three times the
+    // keys of one of weight 1 and a server of weight 0 none
+    weight: u32,
+    // When `name` was last resolved
+    resolved_at: Instant,
+    // Connect failures in a row, reset by any successful connect
+    connect_failures: u32,
+    // Set while the server is ejected, keys are mapped onto the other servers until then
+    ejected_until: Option<Instant>,
+}
+
+impl ServerEntry {
+    fn new(name: &str, weight: u32) -> Result<Self, ConnError> {
+        Ok(Self {
+            name: name.to_string(),
+            addr: resolve_server_addr(name)?,
+            weight,
+            resolved_at: Instant::now(),
+            connect_failures: 0,
+            ejected_until: None,
+        })
+    }
+
+    fn is_live(&self) -> bool {
+        self.ejected_until.is_none()
+    }
+}
+
+// Ejects a server after `failure_threshold` connect failures in a row; Once `window` is over
+// it's probed with a `version` and either reinstated or ejected for another window;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoEject {
+    pub failure_threshold: u32,
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerState {
+    Live,
+    // Keys are mapped onto the other servers until the server is probed again
+    Ejected,
+}
+
+impl ServerList {
+    pub fn new(servers: &[&str]) -> Result<Self, ConnError> {
+        let server_list = ServerList::default();
+        server_list.set_servers(servers)?;
+        Ok(server_list)
+    }
+
+    pub fn new_weighted(servers: &[(&str, u32)]) -> Result<Self, ConnError> {
+        let server_list = ServerList::default();
+        server_list.set_servers_weighted(servers)?;
+        Ok(server_list)
+    }
+
+    // Hashes keys with `key_hasher` instead of CRC32, e.g. to place keys like another client
+    // library sharing the servers;
+    pub fn with_key_hasher(mut self, key_hasher: KeyHasher) -> Self {
+        self.key_hasher = key_hasher;
+        self
+    }
+
+    // Replaces the servers, resolving every hostname; Every server is given a weight of 1;
+    pub fn set_servers(&self, servers: &[&str]) -> Result<(), ConnError> {
+        let servers: Vec<(&str, u32)> = servers.iter().map(|server| (*server, 1)).collect();
+        self.set_servers_weighted(&servers)
+    }
+
+    // Replaces the servers along with their weights; On error the list is left unchanged;
+    // Hostnames are resolved before the lock is taken, so lookups aren't held up meanwhile;
+    pub fn set_servers_weighted(&self, servers: &[(&str, u32)]) -> Result<(), ConnError> {
+        let mut entries = Vec::with_capacity(servers.len());
+        for (server, weight) in servers {
+            entries.push(ServerEntry::new(server, *weight)?);
+        }
+        *write_lock(&self.servers) = entries;
+        Ok(())
+    }
+
+    // Appends `server` with a weight of 1, returning the address it resolved to;
+    pub fn add_server(&self, server: &str) -> Result<SocketAddr, ConnError> {
+        let entry = ServerEntry::new(server, 1)?;
+        let addr = entry.addr;
+        write_lock(&self.servers).push(entry);
+        Ok(addr)
+    }
+
+    // Removes the server configured as `server`, returning its address if it was in the list;
+    // Once the last server is removed keys fail with `NoServers`;
+    pub fn remove_server(&self, server: &str) -> Option<SocketAddr> {
+        let mut servers = write_lock(&self.servers);
+        let position = servers.iter().position(|entry| entry.name == server)?;
+        Some(servers.remove(position).addr)
+    }
+
+    // Only live servers are picked, so keys of an ejected server are spread over the others;
+    // Every server counts as many times as its weight;
+    pub fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError> {
+        self.pick_slot(self.key_hasher.hash(key.as_bytes()))
+    }
+
+    // Live server at `slot` modulo the total weight of the live servers;
+    pub(crate) fn pick_slot(&self, slot: u64) -> Result<SocketAddr, OperationError> {
+        let servers = read_lock(&self.servers);
+        let live = servers
+            .iter()
+            .filter(|server| server.is_live() && server.weight > 0);
+        let total: u64 = live.clone().map(|server| u64::from(server.weight)).sum();
+        if total == 0 {
+            return Err(OperationError::NoServers);
+        }
+        let mut slot = slot % total;
+        for server in live {
+            if slot < u64::from(server.weight) {
+                return Ok(server.addr);
+            }
+            slot -= u64::from(server.weight);
+        }
+        Err(OperationError::NoServers)
+    }
+
+    pub(crate) fn entries(&self) -> Vec<(String, SocketAddr)> {
+        read_lock(&self.servers)
+            .iter()
+            .map(|server| (server.name.clone(), server.addr))
+            .collect()
+    }
+
+    // Servers after `addr` in list order, wrapping around, so every key fails over to the same
+    // alternates;
+    pub(crate) fn failover_addrs(&self, addr: SocketAddr) -> Vec<SocketAddr> {
+        let servers = read_lock(&self.servers);
+        let Some(position) = servers.iter().position(|server| server.addr == addr) else {
+            return Vec::new();
+        };
+        let (before, after) = servers.split_at(position);
+        after[1..]
+            .iter()
+            .chain(before)
+            .filter(|server| server.is_live() && server.addr != addr)
+            .map(|server| server.addr)
+            .collect()
+    }
+
+    pub fn states(&self) -> Vec<(SocketAddr, ServerState)> {
+        read_lock(&self.servers)
+            .iter()
+            .map(|server| match server.is_live() {
+                true => (server.addr, ServerState::Live),
+                false => (server.addr, ServerState::Ejected),
+            })
+            .collect()
+    }
+
+    pub(crate) fn record_connect(&self, addr: SocketAddr) {
+        for server in write_lock(&self.servers)
+            .iter_mut()
+            .filter(|server| server.addr == addr)
+        {
+            server.connect_failures = 0;
+        }
+    }
+
+    // Ejects the server once it failed to connect `failure_threshold` times in a row; Failures of
+    // an ejected server push its ejection back;
+    pub(crate) fn record_connect_failure(&self, addr: SocketAddr, auto_eject: AutoEject) {
+        for server in write_lock(&self.servers)
+            .iter_mut()
+            .filter(|server| server.addr == addr)
+        {
+            server.connect_failures = server.connect_failures.saturating_add(1);
+            if server.connect_failures >= auto_eject.failure_threshold {
+                server.ejected_until = Some(Instant::now() + auto_eject.window);
+            }
+        }
+    }
+
+    pub(crate) fn eject(&self, addr: SocketAddr, window: Duration) {
+        for server in write_lock(&self.servers)
+            .iter_mut()
+            .filter(|server| server.addr == addr)
+        {
+            server.ejected_until = Some(Instant::now() + window);
+        }
+    }
+
+    pub(crate) fn reinstate(&self, addr: SocketAddr) {
+        for server in write_lock(&self.servers)
+            .iter_mut()
+            .filter(|server| server.addr == addr)
+        {
+            server.connect_failures = 0;
+            server.ejected_until = None;
+        }
+    }
+
+    pub(crate) fn reinstate_all(&self) {
+        for server in write_lock(&self.servers).iter_mut() {
+            server.connect_failures = 0;
+            server.ejected_until = None;
+        }
+    }
+
+    // Addresses of the ejected servers whose ejection window is over;
+    pub(crate) fn probes_due(&self) -> Vec<SocketAddr> {
+        let now = Instant::now();
+        read_lock(&self.servers)
+            .iter()
+            .filter(|server| server.ejected_until.is_some_and(|until| until <= now))
+            .map(|server| server.addr)
+            .collect()
+    }
+
+    // Addresses of the servers last resolved more than `interval` ago;
+    pub(crate) fn resolved_before(&self, interval: Duration) -> Vec<SocketAddr> {
+        read_lock(&self.servers)
+            .iter()
+            .filter(|server| server.resolved_at.elapsed() >= interval)
+            .map(|server| server.addr)
+            .collect()
+    }
+
+    // Resolves the server at `addr` again, returning its new address if it moved; A failed
+    // lookup keeps the cached address; The lookup runs without holding the lock;
+    pub(crate) fn resolve_again(&self, addr: SocketAddr) -> Option<SocketAddr> {
+        let name = read_lock(&self.servers)
+            .iter()
+            .find(|server| server.addr == addr)?
+            .name
+            .clone();
+        let resolved = resolve_server_addr(&name).ok();
+        let mut servers = write_lock(&self.servers);
+        let server = servers
+            .iter_mut()
+            .find(|server| server.addr == addr && server.name == name)?;
+        server.resolved_at = Instant::now();
+        match resolved {
+            Some(resolved) if resolved != addr => {
+                server.addr = resolved;
+                Some(resolved)
+            }
+            _ => None,
+        }
+    }
+
+    // Points the server at `from` to `to` without resolving it;
+    #[cfg(test)]
+    pub(crate) fn set_addr(&self, from: SocketAddr, to: SocketAddr) {
+        for server in write_lock(&self.servers)
+            .iter_mut()
+            .filter(|server| server.addr == from)
+        {
+            server.addr = to;
+        }
+    }
+}
+
+impl ServerSelector for ServerList {
+    fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError> {
+        ServerList::pick_server(self, key)
+    }
+
+    // `f` is called without holding the lock, so it may change the servers;
+    fn each(
+        &self,
+        f: &mut dyn FnMut(SocketAddr) -> Result<(), OperationError>,
+    ) -> Result<(), OperationError> {
+        let addrs: Vec<SocketAddr> = read_lock(&self.servers)
+            .iter()
+            .map(|server| server.addr)
+            .collect();
+        addrs.into_iter().try_for_each(f)
+    }
+
+    fn server_list(&self) -> Option<&ServerList> {
+        Some(self)
+    }
+}
+
+// Places every server on a ring at 160 points per unit of weight (by default) and
+// maps a key onto the server of the first point at or after the key's hash (like libmemcached's
+// ketama); Adding or removing a server only moves the keys next to its points, unlike
+// `ServerList` which remaps most keys;
+#[derive(Debug, Default)]
+pub struct KetamaSelector {
+    // Servers on the ring, ejection and DNS refresh work on them as usual
+    servers: ServerList,
+    // Always locked before `servers`, and held while the servers change so the two stay in step
+    ring: RwLock<KetamaRing>,
+}
+
+#[derive(Debug)]
+struct KetamaRing {
+    // Points of a server of weight 1
+    points_per_server: usize,
+    // Hashes both the keys and the points, MD5 by default
+    key_hasher: KeyHasher,
+    // Builds the points like libmemcached's `KETAMA_WEIGHTED`, see `libmemcached_compatible`
+    libmemcached: bool,
+    // Points of every server, sorted, along with the index of the server in `servers`
+    points: Vec<(u32, usize)>,
+}
+
+impl Default for KetamaRing {
+    fn default() -> Self {
+        Self {
+            points_per_server: KETAMA_POINTS_PER_SERVER,
+            key_hasher: KeyHasher::Md5,
+            libmemcached: false,
+            points: Vec::new(),
+        }
+    }
+}
+
+impl KetamaSelector {
+    pub fn new(servers: &[&str]) -> Result<Self, ConnError> {
+        let selector = KetamaSelector::default();
+        selector.set_servers(servers)?;
+        Ok(selector)
+    }
+
+    // Places every server at `points_per_server` points instead of 160; Fewer points rebuild the
+    // ring faster for large clusters, more spread the keys of small ones more evenly;
+    pub fn with_points_per_server(
+        servers: &[&str],
+        points_per_server: usize,
+    ) -> Result<Self, ConnError> {
+        let selector = KetamaSelector::default();
+        selector.set_points_per_server(points_per_server)?;
+        selector.set_servers(servers)?;
+        Ok(selector)
+    }
+
+    pub fn new_weighted(servers: &[(&str, u32)]) -> Result<Self, ConnError> {
+        let selector = KetamaSelector::default();
+        selector.set_servers_weighted(servers)?;
+        Ok(selector)
+    }
+
+    // Replaces the servers and rebuilds the ring; On error both are left unchanged;
+    pub fn set_servers(&self, servers: &[&str]) -> Result<(), ConnError> {
+        self.rebuild_ring(|server_list| server_list.set_servers(servers))
+    }
+
+    // Like `set_servers`, reweighting a server only moves keys to or from that server;
+    pub fn set_servers_weighted(&self, servers: &[(&str, u32)]) -> Result<(), ConnError> {
+        self.rebuild_ring(|server_list| server_list.set_servers_weighted(servers))
+    }
+
+    // Rebuilds the ring with `points_per_server` points per unit of weight, which must be
+    // greater than zero; Selectors with the same servers and points place keys the same;
+    pub fn set_points_per_server(&self, points_per_server: usize) -> Result<(), ConnError> {
+        if points_per_server == 0 {
+            return Err(ConnError::InvalidPointsPerServer);
+        }
+        let mut ring = write_lock(&self.ring);
+        ring.points_per_server = points_per_server;
+        ring.points = ketama_ring(&read_lock(&self.servers.servers), &ring);
+        Ok(())
+    }
+
+    // Hashes keys and points with `key_hasher` instead of MD5, rebuilding the ring; MD5 digests
+    // give four points each, the other hashes one;
+    pub fn with_key_hasher(mut self, key_hasher: KeyHasher) -> Self {
+        let ring = self.ring.get_mut().unwrap_or_else(PoisonError::into_inner);
+        ring.key_hasher = key_hasher;
+        ring.points = ketama_ring(&read_lock(&self.servers.servers), ring);
+        self
+    }
+
+    // Builds the ring following libmemcached's `update_continuum` with
+    // `MEMCACHED_BEHAVIOR_KETAMA_WEIGHTED`, meant for both clients to share the servers without
+    // caching keys twice: every server gets 40 MD5 digests of `host:port-index` (`host-index` on
+    // port 11211) per share of the total weight, scaled by the number of servers, and keys are
+    // hashed with MD5; libmemcached always works from 160 points per server, so any points set
+    // with `set_points_per_server` are left unused; Placements haven't been checked against
+    // libmemcached itself; Ejected servers are skipped while libmemcached rebuilds the ring
+    // without them, so placements could only agree with every server live;
+    pub fn libmemcached_compatible(mut self) -> Self {
+        let ring = self.ring.get_mut().unwrap_or_else(PoisonError::into_inner);
+        ring.key_hasher = KeyHasher::Md5;
+        ring.libmemcached = true;
+        ring.points = ketama_ring(&read_lock(&self.servers.servers), ring);
+        self
+    }
+
+    // Ejected servers are skipped, so their keys move on to the next server on the ring;
+    pub fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError> {
+        let ring = read_lock(&self.ring);
+        let hash = ring.key_hasher.hash(key.as_bytes()) as u32;
+        let ring = &ring.points;
+        let servers = read_lock(&self.servers.servers);
+        let start = ring.partition_point(|(point, _)| *point < hash);
+        ring[start..]
+            .iter()
+            .chain(&ring[..start])
+            .filter_map(|(_, index)| servers.get(*index))
+            .find(|server| server.is_live())
+            .map(|server| server.addr)
+            .ok_or(OperationError::NoServers)
+    }
+
+    // Changes the servers through `change` and rebuilds the ring if it succeeds;
+    fn rebuild_ring<T>(
+        &self,
+        change: impl FnOnce(&ServerList) -> Result<T, ConnError>,
+    ) -> Result<T, ConnError> {
+        let mut ring = write_lock(&self.ring);
+        let changed = change(&self.servers)?;
+        ring.points = ketama_ring(&read_lock(&self.servers.servers), &ring);
+        Ok(changed)
+    }
+}
+
+impl ServerSelector for KetamaSelector {
+    fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError> {
+        KetamaSelector::pick_server(self, key)
+    }
+
+    fn each(
+        &self,
+        f: &mut dyn FnMut(SocketAddr) -> Result<(), OperationError>,
+    ) -> Result<(), OperationError> {
+        self.servers.each(f)
+    }
+
+    // The ring is rebuilt, only the keys next to the points of the server move;
+    fn add_server(&self, server: &str) -> Result<SocketAddr, ConnError> {
+        self.rebuild_ring(|server_list| server_list.add_server(server))
+    }
+
+    fn remove_server(&self, server: &str) -> Option<SocketAddr> {
+        self.rebuild_ring(|server_list| Ok(server_list.remove_server(server)))
+            .ok()
+            .flatten()
+    }
+
+    fn server_list(&self) -> Option<&ServerList> {
+        Some(&self.servers)
+    }
+}
+
+// Points of every server are hashed from its name as configured, `host:port-0`, `host:port-1`
+// and so on, so they don't move when the server is resolved to another address;
+// The last digest is cut short when the count isn't a multiple of the points per digest;
+fn ketama_ring(servers: &[ServerEntry], ring: &KetamaRing) -> Vec<(u32, usize)> {
+    let per_digest = ketama_points(ring.key_hasher, b"").len();
+    let total_weight: u64 = servers.iter().map(|server| u64::from(server.weight)).sum();
+    let mut points = Vec::new();
+    for (index, server) in servers.iter().enumerate() {
+        let (name, count) = if ring.libmemcached {
+            let count = libmemcached_points(server.weight, total_weight, servers.len());
+            (libmemcached_host(&server.name), count)
+        } else {
+            let count = ring
+                .points_per_server
+                .saturating_mul(server.weight as usize);
+            (server.name.as_str(), count)
+        };
+        for digest_index in 0..count.div_ceil(per_digest) {
+            let input = format!("{}-{}", name, digest_index);
+            let digest = ketama_points(ring.key_hasher, input.as_bytes());
+            let taken = (count - digest_index * per_digest).min(per_digest);
+            points.extend(digest[..taken].iter().map(|point| (*point, index)));
+        }
+    }
+    points.sort_unstable();
+    points
+}
+
+// Points of a server, computed in `f32` and rounded down to whole digests like libmemcached's
+// `update_continuum`, from its fixed `MEMCACHED_POINTS_PER_SERVER_KETAMA` of 160;
+fn libmemcached_points(weight: u32, total_weight: u64, servers: usize) -> usize {
+    if total_weight == 0 {
+        return 0;
+    }
+    let share = weight as f32 / total_weight as f32;
+    let digests = share * KETAMA_POINTS_PER_SERVER as f32 / 4.0 * servers as f32;
+    ((f64::from(digests) + 0.0000000001) as f32).floor() as usize * 4
+}
+
+// libmemcached leaves the default port out of the names it hashes;
+fn libmemcached_host(name: &str) -> &str {
+    match name.rsplit_once(':') {
+        Some((host, "11211")) => host,
+        _ => name,
+    }
+}
+
+// Splits the MD5 digest of `input` into four little-endian 32-bit points, other hashes give a
+// single point;
+fn ketama_points(key_hasher: KeyHasher, input: &[u8]) -> Vec<u32> {
+    if key_hasher != KeyHasher::Md5 {
+        return vec![key_hasher.hash(input) as u32];
+    }
+    md5::compute(input)
+        .0
+        .chunks_exact(4)
+        .map(|point| u32::from_le_bytes([point[0], point[1], point[2], point[3]]))
+        .collect()
+}
+
+// Cycles through the servers whatever the key, for servers sharing the same data such as
+// mcrouter instances; Weighted servers come up as many times per cycle as their weight;
+#[derive(Debug, Default)]
+pub struct RoundRobinSelector {
+    // Servers cycled through, ejection and DNS refresh work on them as usual
+    servers: ServerList,
+    // Picks so far, wrapping around on overflow
+    next: AtomicUsize,
+}
+
+impl RoundRobinSelector {
+    pub fn new(servers: &[&str]) -> Result<Self, ConnError> {
+        let selector = RoundRobinSelector::default();
+        selector.set_servers(servers)?;
+        Ok(selector)
+    }
+
+    pub fn new_weighted(servers: &[(&str, u32)]) -> Result<Self, ConnError> {
+        let selector = RoundRobinSelector::default();
+        selector.set_servers_weighted(servers)?;
+        Ok(selector)
+    }
+
+    pub fn set_servers(&self, servers: &[&str]) -> Result<(), ConnError> {
+        self.servers.set_servers(servers)
+    }
+
+    pub fn set_servers_weighted(&self, servers: &[(&str, u32)]) -> Result<(), ConnError> {
+        self.servers.set_servers_weighted(servers)
+    }
+
+    // Ejected servers are skipped;
+    pub fn pick_server(&self, _key: &str) -> Result<SocketAddr, OperationError> {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        self.servers.pick_slot(next as u64)
+    }
+}
+
+impl ServerSelector for RoundRobinSelector {
+    fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError> {
+        RoundRobinSelector::pick_server(self, key)
+    }
+
+    fn each(
+        &self,
+        f: &mut dyn FnMut(SocketAddr) -> Result<(), OperationError>,
+    ) -> Result<(), OperationError> {
+        self.servers.each(f)
+    }
+
+    fn server_list(&self) -> Option<&ServerList> {
+        Some(&self.servers)
+    }
+}
+
+// Scores every server for a key and maps the key onto the highest scoring one (rendezvous or
+// highest random weight hashing); Keys spread evenly, and adding or removing a server only moves
+// the keys it wins or won;
+#[derive(Debug)]
+pub struct RendezvousSelector {
+    // Servers scored, ejection and DNS refresh work on them as usual
+    servers: ServerList,
+    // Hashes every server along with the key, MD5 by default
+    key_hasher: KeyHasher,
+}
+
+impl Default for RendezvousSelector {
+    fn default() -> Self {
+        Self {
+            servers: ServerList::default(),
+            key_hasher: KeyHasher::Md5,
+        }
+    }
+}
+
+impl RendezvousSelector {
+    // Every server is given a weight of 1;
+    pub fn new(servers: &[&str]) -> Result<Self, ConnError> {
+        let selector = RendezvousSelector::default();
+        selector.set_servers(servers)?;
+        Ok(selector)
+    }
+
+    pub fn new_weighted(servers: &[(&str, u32)]) -> Result<Self, ConnError> {
+        let selector = RendezvousSelector::default();
+        selector.set_servers_weighted(servers)?;
+        Ok(selector)
+    }
+
+    // Scores servers with `key_hasher` instead of MD5;
+    pub fn with_key_hasher(mut self, key_hasher: KeyHasher) -> Self {
+        self.key_hasher = key_hasher;
+        self
+    }
+
+    pub fn set_servers(&self, servers: &[&str]) -> Result<(), ConnError> {
+        self.servers.set_servers(servers)
+    }
+
+    // A server of weight 2 wins twice as many keys as one of weight 1 and one of weight 0 none;
+    pub fn set_servers_weighted(&self, servers: &[(&str, u32)]) -> Result<(), ConnError> {
+        self.servers.set_servers_weighted(servers)
+    }
+
+    // Ejected servers aren't scored, so their keys go to the runner-up;
+    pub fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError> {
+        let mut best: Option<(f64, SocketAddr)> = None;
+        let servers = read_lock(&self.servers.servers);
+        for server in servers
+            .iter()
+            .filter(|server| server.is_live() && server.weight > 0)
+        {
+            let score = rendezvous_score(self.key_hasher, &server.name, key, server.weight);
+            match best {
+                Some((best_score, _)) if best_score >= score => (),
+                _ => best = Some((score, server.addr)),
+            }
+        }
+        best.map(|(_, addr)| addr).ok_or(OperationError::NoServers)
+    }
+}
+
+impl ServerSelector for RendezvousSelector {
+    fn pick_server(&self, key: &str) -> Result<SocketAddr, OperationError> {
+        RendezvousSelector::pick_server(self, key)
+    }
+
+    fn each(
+        &self,
+        f: &mut dyn FnMut(SocketAddr) -> Result<(), OperationError>,
+    ) -> Result<(), OperationError> {
+        self.servers.each(f)
+    }
+
+    fn server_list(&self) -> Option<&ServerList> {
+        Some(&self.servers)
+    }
+}
+
+// Score of the server named `server` for `key`, `-weight / ln(x)` with `x` in (0, 1) hashed from
+// both, so every server wins a share of the keys proportional to its weight;
+fn rendezvous_score(key_hasher: KeyHasher, server: &str, key: &str, weight: u32) -> f64 {
+    let input = [server.as_bytes(), b"-", key.as_bytes()].concat();
+    // At most the top 53 bits, which fit an `f64` exactly, the half keeps `x` off 0
+    let bits = key_hasher.bits().min(53);
+    let hash = key_hasher.hash(&input) >> (key_hasher.bits() - bits);
+    let x = (hash as f64 + 0.5) / (1u64 << bits) as f64;
+    -f64::from(weight) / x.ln()
+}
+
+// A panic while the servers were locked can't leave them half updated, every change is a single
+// assignment, so a poisoned lock is used as is;
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Resolves `server` to the first of its socket addresses, which may be IPv4 or IPv6; Anything
+// that isn't a literal socket address is looked up as `host:port`;
+pub(crate) fn resolve_server_addr(server: &str) -> Result<SocketAddr, ConnError> {
+    let parse_error = match SocketAddr::from_str(server) {
+        Ok(addr) => return Ok(addr),
+        Err(error) => error,
+    };
+    match server.rsplit_once(':') {
+        // Without brackets the port can't be told apart from the last group of the address
+        Some((host, _)) if host.contains(':') => Err(ConnError::InvalidServerAddr(
+            server.to_string(),
+            "IPv6 addresses must be bracketed, e.g. `[::1]:11211`".to_string(),
+        )),
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => server
+            .to_socket_addrs()
+            .and_then(|mut addrs| {
+                addrs
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))
+            })
+            .map_err(|error| ConnError::Resolve(host.to_string(), error)),
+        _ => Err(ConnError::AddrParseError(server.to_string(), parse_error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_lock, KetamaSelector, RendezvousSelector, RoundRobinSelector, ServerList,
+        ServerSelector,
+    };
+    use crate::errors::{ConnError, OperationError};
+    use crate::hasher::KeyHasher;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_pick_server() {
+        match ServerList::default().pick_server("key") {
+            Err(OperationError::NoServers) => (),
+            other => panic!("expected no servers. Got: {:?}", other),
+        }
+
+        let servers = ["127.0.0.1:11211", "127.0.0.1:11212", "127.0.0.1:11213"];
+        let server_list = ServerList::new(&servers).expect("could not build the server list");
+        // crc32("foo") = 0x8c736521, which is 2 modulo 3
+        match server_list.pick_server("foo") {
+            Ok(addr) if addr.to_string() == servers[2] => (),
+            other => panic!("expected the third server. Got: {:?}", other),
+        }
+        let addrs = server_list.addrs();
+        if server_list.failover_addrs(addrs[1]) != [addrs[2], addrs[0]] {
+            panic!("expected the alternates to follow the list order")
+        }
+    }
+
+    #[test]
+    fn test_ketama_pick_server() {
+        let servers: Vec<String> = (0..10)
+            .map(|i| format!("127.0.0.1:{}", 11211 + i))
+            .collect();
+        let servers: Vec<&str> = servers.iter().map(String::as_str).collect();
+        let selector = KetamaSelector::new(&servers).expect("could not build the ring");
+        let without_first = KetamaSelector::new(&servers[1..]).expect("could not build the ring");
+
+        let removed = selector.addrs()[0];
+        let mut remapped = 0;
+        for i in 0..10_000 {
+            let key = format!("key:{}", i);
+            let (before, after) =
+                match (selector.pick_server(&key), without_first.pick_server(&key)) {
+                    (Ok(before), Ok(after)) => (before, after),
+                    other => panic!("expected both rings to map the key. Got: {:?}", other),
+                };
+            if before == after {
+                continue;
+            }
+            if before != removed {
+                panic!(
+                    "expected only keys of the removed server to move. Got: {}",
+                    key
+                )
+            }
+            remapped += 1;
+        }
+        // A tenth of the keys were on the removed server
+        if !(700..=1300).contains(&remapped) {
+            panic!("expected about 10% of the keys to move. Got: {}", remapped)
+        }
+    }
+
+    // Counts the keys of a 100k sample every server gets;
+    fn key_spread(selector: &dyn ServerSelector) -> HashMap<SocketAddr, usize> {
+        let mut spread = HashMap::new();
+        for i in 0..100_000 {
+            let addr = match selector.pick_server(&format!("key:{}", i)) {
+                Ok(addr) => addr,
+                other => panic!("expected the key to be mapped. Got: {:?}", other),
+            };
+            *spread.entry(addr).or_insert(0) += 1;
+        }
+        spread
+    }
+
+    #[test]
+    fn test_rendezvous_pick_server() {
+        let servers: Vec<String> = (0..5).map(|i| format!("127.0.0.1:{}", 11211 + i)).collect();
+        let servers: Vec<&str> = servers.iter().map(String::as_str).collect();
+        let selector = RendezvousSelector::new(&servers).expect("could not build the selector");
+        let spread = key_spread(&selector);
+        // 20k keys each, give or take 3%
+        if spread.len() != 5
+            || spread
+                .values()
+                .any(|keys| !(19_400..=20_600).contains(keys))
+        {
+            panic!("expected an even spread. Got: {:?}", spread)
+        }
+
+        let weighted = [(servers[0], 1), (servers[1], 2), (servers[2], 0)];
+        let selector =
+            RendezvousSelector::new_weighted(&weighted).expect("could not build the selector");
+        let spread = key_spread(&selector);
+        let addrs = selector.addrs();
+        // A third and two thirds of the keys
+        match (
+            spread.get(&addrs[0]),
+            spread.get(&addrs[1]),
+            spread.get(&addrs[2]),
+        ) {
+            (Some(light), Some(heavy), None)
+                if (32_000..=34_700).contains(light) && (65_300..=68_000).contains(heavy) => {}
+            _ => panic!("expected a spread following the weights. Got: {:?}", spread),
+        }
+    }
+
+    #[test]
+    fn test_weighted_servers() {
+        let servers = [("127.0.0.1:11211", 1), ("127.0.0.1:11212", 3)];
+        let selectors: [Box<dyn ServerSelector>; 3] = [
+            Box::new(ServerList::new_weighted(&servers).unwrap()),
+            Box::new(KetamaSelector::new_weighted(&servers).unwrap()),
+            Box::new(RendezvousSelector::new_weighted(&servers).unwrap()),
+        ];
+        for selector in selectors {
+            let spread = key_spread(selector.as_ref());
+            let addrs = selector.addrs();
+            // A quarter and three quarters of the keys
+            match (spread.get(&addrs[0]), spread.get(&addrs[1])) {
+                (Some(light), Some(heavy))
+                    if (2.7..3.3).contains(&(*heavy as f64 / *light as f64)) => {}
+                _ => panic!(
+                    "expected 3x the keys on the heavy server. Got: {:?}",
+                    spread
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_robin_pick_server() {
+        let servers = ["127.0.0.1:11211", "127.0.0.1:11212", "127.0.0.1:11213"];
+        let selector = RoundRobinSelector::new(&servers).expect("could not build the selector");
+        let picks: Vec<String> = (0..6)
+            .map(|_| selector.pick_server("key").unwrap().to_string())
+            .collect();
+        if picks != [servers, servers].concat() {
+            panic!(
+                "expected the servers in turn whatever the key. Got: {:?}",
+                picks
+            )
+        }
+
+        let selector = Arc::new(selector);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let selector = Arc::clone(&selector);
+                std::thread::spawn(move || {
+                    (0..300)
+                        .map(|i| selector.pick_server(&i.to_string()).unwrap())
+                        .collect::<Vec<SocketAddr>>()
+                })
+            })
+            .collect();
+        let mut spread: HashMap<SocketAddr, usize> = HashMap::new();
+        for addr in threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+        {
+            *spread.entry(addr).or_insert(0) += 1;
+        }
+        if spread.len() != 3 || spread.values().any(|picks| *picks != 400) {
+            panic!(
+                "expected every server to be picked as often. Got: {:?}",
+                spread
+            )
+        }
+    }
+
+    #[test]
+    fn test_add_and_remove_servers() {
+        let servers = ["127.0.0.1:11211", "127.0.0.1:11212"];
+        let selectors: [Box<dyn ServerSelector>; 2] = [
+            Box::new(ServerList::new(&servers[..1]).unwrap()),
+            Box::new(KetamaSelector::new(&servers[..1]).unwrap()),
+        ];
+        for selector in selectors {
+            let added = selector
+                .add_server(servers[1])
+                .expect("could not add the server");
+            if !(0..100).any(|i| selector.pick_server(&i.to_string()).ok() == Some(added)) {
+                panic!("expected keys to map to the added server")
+            }
+            for server in servers {
+                if selector.remove_server(server).is_none() {
+                    panic!("expected {} to be removed", server)
+                }
+            }
+            match selector.pick_server("key") {
+                Err(OperationError::NoServers) => (),
+                other => panic!("expected no servers. Got: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_pick_server_with_long_keys() {
+        let servers = ["127.0.0.1:11211", "127.0.0.1:11212"];
+        let server_list = ServerList::new(&servers).expect("could not build the server list");
+        // Longer than memcached allows, which is for the client to reject, not the selector
+        let key = "k".repeat(300);
+        match server_list.pick_server(&key) {
+            Ok(addr) if server_list.addrs().contains(&addr) => (),
+            other => panic!("expected one of the servers. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_servers_change_while_keys_are_mapped() {
+        let servers = ["127.0.0.1:11211", "127.0.0.1:11212", "127.0.0.1:11213"];
+        let selector = Arc::new(KetamaSelector::new(&servers).expect("could not build the ring"));
+        let lookups: Vec<_> = (0..4)
+            .map(|_| {
+                let selector = Arc::clone(&selector);
+                std::thread::spawn(move || {
+                    for i in 0..2_000 {
+                        match selector.pick_server(&i.to_string()) {
+                            Ok(addr) if servers.contains(&addr.to_string().as_str()) => (),
+                            other => panic!("expected one of the servers. Got: {:?}", other),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for i in 0..50 {
+            let servers = if i % 2 == 0 {
+                &servers[..2]
+            } else {
+                &servers[1..]
+            };
+            if let Err(error) = selector.set_servers(servers) {
+                panic!("did not expect replacing the servers to fail: {}", error)
+            }
+        }
+        for lookup in lookups {
+            lookup.join().unwrap();
+        }
+    }
+
+    // Standard deviation of the keys per server, relative to the mean;
+    fn spread_deviation(selector: &dyn ServerSelector) -> f64 {
+        let spread = key_spread(selector);
+        let mean = 100_000.0 / selector.addrs().len() as f64;
+        let variance = selector
+            .addrs()
+            .iter()
+            .map(|addr| (*spread.get(addr).unwrap_or(&0) as f64 - mean).powi(2))
+            .sum::<f64>()
+            / mean.powi(2);
+        (variance / selector.addrs().len() as f64).sqrt()
+    }
+
+    #[test]
+    fn test_ketama_points_per_server() {
+        let servers: Vec<String> = (0..10)
+            .map(|i| format!("127.0.0.1:{}", 11211 + i))
+            .collect();
+        let servers: Vec<&str> = servers.iter().map(String::as_str).collect();
+        match KetamaSelector::with_points_per_server(&servers, 0) {
+            Err(ConnError::InvalidPointsPerServer) => (),
+            other => panic!("expected zero points to be rejected. Got: {:?}", other),
+        }
+
+        let deviations: Vec<f64> = [5, 40, 500]
+            .into_iter()
+            .map(|points| {
+                let selector = KetamaSelector::with_points_per_server(&servers, points).unwrap();
+                spread_deviation(&selector)
+            })
+            .collect();
+        if deviations[0] <= deviations[1] || deviations[1] <= deviations[2] {
+            panic!(
+                "expected more points to spread keys more evenly. Got: {:?}",
+                deviations
+            )
+        }
+
+        let rebuilt = KetamaSelector::new(&servers).unwrap();
+        if let Err(error) = rebuilt.set_points_per_server(40) {
+            panic!("did not expect changing the points to fail: {}", error)
+        }
+        let built = KetamaSelector::with_points_per_server(&servers, 40).unwrap();
+        if (0..1_000).any(|i| {
+            rebuilt.pick_server(&i.to_string()).ok() != built.pick_server(&i.to_string()).ok()
+        }) {
+            panic!("expected both rings to place keys the same")
+        }
+    }
+
+    #[test]
+    fn test_ketama_libmemcached_weighted_ring() {
+        // The weighted servers of libmemcached's ketama tests; The expected points and servers
+        // were computed by this implementation of `update_continuum` and `dispatch_host` from
+        // libmemcached 1.0, not by libmemcached; To check them, add the same servers and weights
+        // to a `memcached_st` with `MEMCACHED_BEHAVIOR_KETAMA_WEIGHTED` set, and
+        // `memcached_server_by_key` must name the expected server of every key
+        let servers = [
+            ("10.0.1.1:11211", 600),
+            ("10.0.1.2:11211", 300),
+            ("10.0.1.3:11211", 200),
+            ("10.0.1.4:11211", 350),
+            ("10.0.1.5:11211", 1000),
+            ("10.0.1.6:11211", 800),
+            ("10.0.1.7:11211", 950),
+            ("10.0.1.8:11211", 100),
+        ];
+        let selector = KetamaSelector::new_weighted(&servers)
+            .unwrap()
+            .libmemcached_compatible();
+        let mut points = [0; 8];
+        for (_, index) in &read_lock(&selector.ring).points {
+            points[*index] += 1;
+        }
+        if points != [176, 88, 56, 104, 296, 236, 280, 28] {
+            panic!("expected the weighted point counts. Got: {:?}", points)
+        }
+        // The points per server don't carry over to a libmemcached ring
+        let fewer_points = KetamaSelector::with_points_per_server(&[], 40)
+            .unwrap()
+            .libmemcached_compatible();
+        if let Err(error) = fewer_points.set_servers_weighted(&servers) {
+            panic!("did not expect setting the servers to fail: {}", error)
+        }
+        if read_lock(&fewer_points.ring).points != read_lock(&selector.ring).points {
+            panic!("expected the ring of 160 points per server")
+        }
+        let expected = [
+            ("apple", "10.0.1.5:11211"),
+            ("daddy", "10.0.1.7:11211"),
+            ("feta", "10.0.1.1:11211"),
+            ("hello", "10.0.1.6:11211"),
+            ("kettle", "10.0.1.3:11211"),
+            ("lemon", "10.0.1.5:11211"),
+            ("nectar", "10.0.1.3:11211"),
+            ("pear", "10.0.1.1:11211"),
+        ];
+        for (key, server) in expected {
+            match selector.pick_server(key) {
+                Ok(addr) if addr.to_string() == server => (),
+                other => panic!("expected {} for {:?}. Got: {:?}", server, key, other),
+            }
+        }
+
+        // Only the default port is left out of the hashed names
+        let servers = [
+            ("10.0.2.1:11211", 1),
+            ("10.0.2.2:11212", 2),
+            ("10.0.2.3:11213", 1),
+        ];
+        let selector = KetamaSelector::new_weighted(&servers)
+            .unwrap()
+            .libmemcached_compatible();
+        let expected = [
+            ("apple", "10.0.2.2:11212"),
+            ("carton", "10.0.2.1:11211"),
+            ("eagle", "10.0.2.3:11213"),
+            ("grape", "10.0.2.3:11213"),
+            ("kettle", "10.0.2.2:11212"),
+            ("olive", "10.0.2.1:11211"),
+        ];
+        for (key, server) in expected {
+            match selector.pick_server(key) {
+                Ok(addr) if addr.to_string() == server => (),
+                other => panic!("expected {} for {:?}. Got: {:?}", server, key, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_hashers() {
+        let servers = ["127.0.0.1:11211", "127.0.0.1:11212", "127.0.0.1:11213"];
+        let server_list = ServerList::new(&servers)
+            .unwrap()
+            .with_key_hasher(KeyHasher::Fnv1a32);
+        let addrs = server_list.addrs();
+        for i in 0..100 {
+            let key = i.to_string();
+            let expected = addrs[(KeyHasher::Fnv1a32.hash(key.as_bytes()) % 3) as usize];
+            match server_list.pick_server(&key) {
+                Ok(addr) if addr == expected => (),
+                other => panic!("expected {} for {:?}. Got: {:?}", expected, key, other),
+            }
+        }
+
+        let selectors: [Box<dyn ServerSelector>; 2] = [
+            Box::new(
+                KetamaSelector::new(&servers)
+                    .unwrap()
+                    .with_key_hasher(KeyHasher::XxHash64),
+            ),
+            Box::new(
+                RendezvousSelector::new(&servers)
+                    .unwrap()
+                    .with_key_hasher(KeyHasher::Fnv1a64),
+            ),
+        ];
+        for selector in &selectors {
+            let spread = key_spread(selector.as_ref());
+            if spread.len() != servers.len() {
+                panic!("expected keys on every server. Got: {:?}", spread)
+            }
+        }
+    }
+}
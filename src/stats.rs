@@ -0,0 +1,303 @@
+use crate::errors::OperationError;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+// The general purpose statistics of a server (`stats` without argument), with the well-known
+// entries parsed into fields of the width memcached reports them with; Fields are `None` when
+// the server didn't report them, e.g. older versions or proxies;
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerStats {
+    pub pid: Option<u32>,
+    // Seconds since the server started
+    pub uptime: Option<u32>,
+    // The server's UNIX time
+    pub time: Option<u32>,
+    pub version: Option<String>,
+    pub pointer_size: Option<u32>,
+    pub threads: Option<u32>,
+    pub curr_connections: Option<u64>,
+    pub total_connections: Option<u64>,
+    pub curr_items: Option<u64>,
+    pub total_items: Option<u64>,
+    // Bytes used to store items
+    pub bytes: Option<u64>,
+    pub limit_maxbytes: Option<u64>,
+    pub bytes_read: Option<u64>,
+    pub bytes_written: Option<u64>,
+    pub cmd_get: Option<u64>,
+    pub cmd_set: Option<u64>,
+    pub cmd_flush: Option<u64>,
+    pub cmd_touch: Option<u64>,
+    pub get_hits: Option<u64>,
+    pub get_misses: Option<u64>,
+    pub get_expired: Option<u64>,
+    pub delete_hits: Option<u64>,
+    pub delete_misses: Option<u64>,
+    pub incr_hits: Option<u64>,
+    pub incr_misses: Option<u64>,
+    pub decr_hits: Option<u64>,
+    pub decr_misses: Option<u64>,
+    pub cas_hits: Option<u64>,
+    pub cas_misses: Option<u64>,
+    // CAS commands that found the item changed
+    pub cas_badval: Option<u64>,
+    pub touch_hits: Option<u64>,
+    pub touch_misses: Option<u64>,
+    // Items evicted to make room for new ones before they expired
+    pub evictions: Option<u64>,
+    // Entries of expired items reused for new ones
+    pub reclaimed: Option<u64>,
+    // Entries that aren't fields, or whose value didn't parse, as the server reported them
+    pub extra: HashMap<String, String>,
+}
+
+impl ServerStats {
+    // Parses the pairs returned by `Client::stats`; A value that doesn't parse is kept in
+    // `extra` rather than failing the rest;
+    pub fn from_stats(stats: HashMap<String, String>) -> Self {
+        let mut parsed = ServerStats::default();
+        for (name, value) in stats {
+            let known = match name.as_str() {
+                "pid" => parse_into(&mut parsed.pid, &value),
+                "uptime" => parse_into(&mut parsed.uptime, &value),
+                "time" => parse_into(&mut parsed.time, &value),
+                "version" => parse_into(&mut parsed.version, &value),
+                "pointer_size" => parse_into(&mut parsed.pointer_size, &value),
+                "threads" => parse_into(&mut parsed.threads, &value),
+                "curr_connections" => parse_into(&mut parsed.curr_connections, &value),
+                "total_connections" => parse_into(&mut parsed.total_connections, &value),
+                "curr_items" => parse_into(&mut parsed.curr_items, &value),
+                "total_items" => parse_into(&mut parsed.total_items, &value),
+                "bytes" => parse_into(&mut parsed.bytes, &value),
+                "limit_maxbytes" => parse_into(&mut parsed.limit_maxbytes, &value),
+                "bytes_read" => parse_into(&mut parsed.bytes_read, &value),
+                "bytes_written" => parse_into(&mut parsed.bytes_written, &value),
+                "cmd_get" => parse_into(&mut parsed.cmd_get, &value),
+                "cmd_set" => parse_into(&mut parsed.cmd_set, &value),
+                "cmd_flush" => parse_into(&mut parsed.cmd_flush, &value),
+                "cmd_touch" => parse_into(&mut parsed.cmd_touch, &value),
+                "get_hits" => parse_into(&mut parsed.get_hits, &value),
+                "get_misses" => parse_into(&mut parsed.get_misses, &value),
+                "get_expired" => parse_into(&mut parsed.get_expired, &value),
+                "delete_hits" => parse_into(&mut parsed.delete_hits, &value),
+                "delete_misses" => parse_into(&mut parsed.delete_misses, &value),
+                "incr_hits" => parse_into(&mut parsed.incr_hits, &value),
+                "incr_misses" => parse_into(&mut parsed.incr_misses, &value),
+                "decr_hits" => parse_into(&mut parsed.decr_hits, &value),
+                "decr_misses" => parse_into(&mut parsed.decr_misses, &value),
+                "cas_hits" => parse_into(&mut parsed.cas_hits, &value),
+                "cas_misses" => parse_into(&mut parsed.cas_misses, &value),
+                "cas_badval" => parse_into(&mut parsed.cas_badval, &value),
+                "touch_hits" => parse_into(&mut parsed.touch_hits, &value),
+                "touch_misses" => parse_into(&mut parsed.touch_misses, &value),
+                "evictions" => parse_into(&mut parsed.evictions, &value),
+                "reclaimed" => parse_into(&mut parsed.reclaimed, &value),
+                _ => false,
+            };
+            if !known {
+                parsed.extra.insert(name, value);
+            }
+        }
+        parsed
+    }
+
+    // Share of gets that found the key, `None` before the first get;
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let hits = self.get_hits?;
+        let gets = hits.checked_add(self.get_misses?)?;
+        if gets == 0 {
+            return None;
+        }
+        Some(hits as f64 / gets as f64)
+    }
+}
+
+// Totals of the `ServerStats` of every server, see `Client::aggregate_stats`; Counters are
+// summed, servers that didn't report one counting as zero;
+#[derive(Debug, Default)]
+pub struct ClusterStats {
+    // Servers whose stats are included
+    pub servers: usize,
+    // Servers that failed to answer, left out of the totals
+    pub failures: Vec<(SocketAddr, OperationError)>,
+    // Uptime of the longest and shortest running servers, the latter telling a recent restart
+    pub max_uptime: Option<u32>,
+    pub min_uptime: Option<u32>,
+    pub curr_connections: u64,
+    pub total_connections: u64,
+    pub curr_items: u64,
+    pub total_items: u64,
+    pub bytes: u64,
+    pub limit_maxbytes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub cmd_get: u64,
+    pub cmd_set: u64,
+    pub get_hits: u64,
+    pub get_misses: u64,
+    pub evictions: u64,
+}
+
+impl ClusterStats {
+    // Totals of `stats`, with no failures;
+    pub fn from_servers<'a>(stats: impl IntoIterator<Item = &'a ServerStats>) -> Self {
+        let mut cluster = ClusterStats::default();
+        let add = |total: &mut u64, value: Option<u64>| {
+            *total = total.saturating_add(value.unwrap_or(0));
+        };
+        for server in stats {
+            cluster.servers += 1;
+            if let Some(uptime) = server.uptime {
+                cluster.max_uptime = cluster.max_uptime.max(Some(uptime));
+                cluster.min_uptime = Some(cluster.min_uptime.map_or(uptime, |min| min.min(uptime)));
+            }
+            add(&mut cluster.curr_connections, server.curr_connections);
+            add(&mut cluster.total_connections, server.total_connections);
+            add(&mut cluster.curr_items, server.curr_items);
+            add(&mut cluster.total_items, server.total_items);
+            add(&mut cluster.bytes, server.bytes);
+            add(&mut cluster.limit_maxbytes, server.limit_maxbytes);
+            add(&mut cluster.bytes_read, server.bytes_read);
+            add(&mut cluster.bytes_written, server.bytes_written);
+            add(&mut cluster.cmd_get, server.cmd_get);
+            add(&mut cluster.cmd_set, server.cmd_set);
+            add(&mut cluster.get_hits, server.get_hits);
+            add(&mut cluster.get_misses, server.get_misses);
+            add(&mut cluster.evictions, server.evictions);
+        }
+        cluster
+    }
+
+    // Share of gets that found the key across the servers, `None` before the first get;
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let gets = self.get_hits.checked_add(self.get_misses)?;
+        if gets == 0 {
+            return None;
+        }
+        Some(self.get_hits as f64 / gets as f64)
+    }
+}
+
+impl From<HashMap<String, String>> for ServerStats {
+    fn from(stats: HashMap<String, String>) -> Self {
+        ServerStats::from_stats(stats)
+    }
+}
+
+// Sets `field` to `value` if it parses, telling whether it did;
+fn parse_into<T: FromStr>(field: &mut Option<T>, value: &str) -> bool {
+    match value.parse() {
+        Ok(value) => {
+            *field = Some(value);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClusterStats, ServerStats};
+    use std::collections::HashMap;
+
+    fn stats(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn well_known_entries_are_parsed() {
+        let parsed = ServerStats::from_stats(stats(&[
+            ("uptime", "3600"),
+            ("curr_connections", "10"),
+            ("bytes", "18446744073709551615"),
+            ("version", "1.6.21"),
+            ("get_hits", "3"),
+            ("get_misses", "1"),
+        ]));
+        let expected = ServerStats {
+            uptime: Some(3600),
+            curr_connections: Some(10),
+            bytes: Some(u64::MAX),
+            version: Some("1.6.21".to_string()),
+            get_hits: Some(3),
+            get_misses: Some(1),
+            ..Default::default()
+        };
+        if parsed != expected {
+            panic!("expected {:?}. Got: {:?}", expected, parsed)
+        }
+        match parsed.hit_ratio() {
+            Some(0.75) => (),
+            other => panic!("expected a hit ratio of 0.75. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_and_malformed_entries_are_kept_in_extra() {
+        let parsed = ServerStats::from_stats(stats(&[
+            ("rusage_user", "0.123456"),
+            // Over the 32 bits of a pid
+            ("pid", "4294967296"),
+            ("evictions", "-1"),
+            ("cmd_get", "7"),
+            // Connection counters are 64 bits
+            ("total_connections", "4294967296"),
+        ]));
+        let extra = stats(&[
+            ("rusage_user", "0.123456"),
+            ("pid", "4294967296"),
+            ("evictions", "-1"),
+        ]);
+        if parsed.extra != extra
+            || parsed.pid.is_some()
+            || parsed.cmd_get != Some(7)
+            || parsed.total_connections != Some(4294967296)
+        {
+            panic!("expected the unparsed entries in extra. Got: {:?}", parsed)
+        }
+        match parsed.hit_ratio() {
+            None => (),
+            other => panic!("expected no hit ratio without gets. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cluster_stats_sum_counters_and_bound_uptimes() {
+        let servers = [
+            ServerStats::from_stats(stats(&[
+                ("uptime", "100"),
+                ("get_hits", "3"),
+                ("get_misses", "1"),
+                ("bytes", "10"),
+            ])),
+            ServerStats::from_stats(stats(&[
+                ("uptime", "5"),
+                ("get_hits", "1"),
+                ("get_misses", "3"),
+                ("curr_connections", "2"),
+            ])),
+        ];
+        let cluster = ClusterStats::from_servers(&servers);
+        match cluster {
+            ClusterStats {
+                servers: 2,
+                max_uptime: Some(100),
+                min_uptime: Some(5),
+                get_hits: 4,
+                get_misses: 4,
+                bytes: 10,
+                curr_connections: 2,
+                ..
+            } => (),
+            other => panic!("expected summed stats. Got: {:?}", other),
+        }
+        match cluster.hit_ratio() {
+            Some(0.5) => (),
+            other => panic!("expected a hit ratio of 0.5. Got: {:?}", other),
+        }
+    }
+}
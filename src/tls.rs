@@ -0,0 +1,131 @@
+use crate::errors::ConnError;
+use rustls::pki_types::{pem::PemObject, CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io;
+use std::net::TcpStream;
+use std::sync::Arc;
+
+pub(crate) type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// TLS settings for servers started with `--enable-ssl` (memcached 1.5.13+);
+#[derive(Debug, Clone)]
+pub struct ClientTlsConfig {
+    config: Arc<ClientConfig>,
+    // Name the server certificate is verified against, also sent as SNI
+    server_name: ServerName<'static>,
+}
+
+impl ClientTlsConfig {
+    // Trusts the PEM encoded certificates in `root_ca_pem` and expects the server certificate
+    // to be valid for `server_name`;
+    pub fn new(root_ca_pem: &[u8], server_name: &str) -> Result<Self, ConnError> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(root_ca_pem) {
+            let cert = cert.map_err(|error| ConnError::TlsConfig(error.to_string()))?;
+            roots
+                .add(cert)
+                .map_err(|error| ConnError::TlsConfig(error.to_string()))?;
+        }
+        if roots.is_empty() {
+            return Err(ConnError::TlsConfig(
+                "no root certificates found".to_string(),
+            ));
+        }
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|error| ConnError::TlsConfig(error.to_string()))?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|error| ConnError::TlsConfig(error.to_string()))?;
+        Ok(Self {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+
+    // Runs the handshake to completion so failures surface when dialing rather than on the
+    // first command;
+    pub(crate) fn connect(&self, tcp: TcpStream) -> io::Result<TlsStream> {
+        let conn = ClientConnection::new(self.config.clone(), self.server_name.clone())
+            .map_err(io::Error::other)?;
+        let mut stream = StreamOwned::new(conn, tcp);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientTlsConfig;
+    use crate::{errors::OperationError, Client};
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    // Self-signed certificate, only used to build a config that is then pointed at a server
+    // which doesn't speak TLS
+    const TEST_CA_PEM: &[u8] = b"-----BEGIN CERTIFICATE-----
+MIIBfzCCASWgAwIBAgIUXOkZ8FOHO1TmzUQpOGVfQtvdvlIwCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJbG9jYWxob3N0MCAXDTI2MTAxNDEzMDYzNVoYDzIxMjYwOTIw
+MTMwNjM1WjAUMRIwEAYDVQQDDAlsb2NhbGhvc3QwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAAQ/jK8o8Fe42BG5ioLnNEDYPLb6+iY7WBlYHbSlKx0jviwAtkdeP8U2
+A1flBiWUHg9bsma50fsY/6f8D6jut5pzo1MwUTAdBgNVHQ4EFgQUPMmTLLXajEUj
+tGwbA4313iNhgucwHwYDVR0jBBgwFoAUPMmTLLXajEUjtGwbA4313iNhgucwDwYD
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiEA3pDBIjdtujQgOHPmWJbD
+hzPZQnhFHSWK/56xnqs0IxACID1uTcROkDtsOmniA4jUIPpL4SnVvmQcaIVqJEzC
+JmyN
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn handshake_with_a_plaintext_server_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut stream = stream;
+                let _ = stream.write_all(b"ERROR\r\n");
+            }
+        });
+
+        let tls = ClientTlsConfig::new(TEST_CA_PEM, "localhost").expect("could not build config");
+        let client = Client::new(addr.to_string(), None, 0).expect("could not connect");
+        let mut client = client.with_tls(Some(tls));
+        match client.version() {
+            Err(OperationError::ServerFailures(failures)) => match failures.as_slice() {
+                [(failed_addr, OperationError::TlsHandshake(_, _))] if *failed_addr == addr => (),
+                _ => panic!("expected a handshake error. Got: {:?}", failures),
+            },
+            other => panic!("expected a handshake error. Got: {:?}", other),
+        }
+    }
+
+    // Runs against a memcached started with `--enable-ssl` when `RSMEMCACHE_TLS_ADDR`,
+    // `RSMEMCACHE_TLS_CA` (path to the CA certificate) and `RSMEMCACHE_TLS_NAME` are set
+    #[test]
+    fn test_tls_local_server() {
+        let (Ok(addr), Ok(ca_path), Ok(name)) = (
+            std::env::var("RSMEMCACHE_TLS_ADDR"),
+            std::env::var("RSMEMCACHE_TLS_CA"),
+            std::env::var("RSMEMCACHE_TLS_NAME"),
+        ) else {
+            return;
+        };
+        let ca_pem = std::fs::read(ca_path).expect("could not read the CA certificate");
+        let tls = ClientTlsConfig::new(&ca_pem, &name).expect("could not build config");
+        let mut client = Client::new(addr, None, 0)
+            .expect("could not connect to local TLS server")
+            .with_tls(Some(tls));
+        for _ in 0..2 {
+            if let Err(error) = client.version() {
+                panic!("did not expect version over TLS to fail: {}", error)
+            }
+        }
+        if client.pool().free_conns.len() != 1 {
+            panic!("expected the TLS connection to be reused")
+        }
+    }
+}
@@ -0,0 +1,317 @@
+// Spans and events of the `tracing` feature; Every public operation runs in a span named
+// `memcache.<operation>`, created before anything can fail, whose `outcome` is `hit` or `miss`
+// for retrievals, `ok` for other successes, `miss` for cache misses and `error` otherwise, with
+// the error in `error`; Single key operations also record `key_len`, `addr` and `value_size`,
+// the size of the value as sent or read; Connections dialed, reused and discarded are `debug`
+// events; Without the feature spans are zero sized and the helpers only run the operation;
+
+#[cfg(feature = "tracing")]
+pub(crate) use enabled::*;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use disabled::*;
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use crate::errors::OperationError;
+    #[cfg(feature = "async")]
+    use std::future::Future;
+    use std::net::SocketAddr;
+    #[cfg(feature = "async")]
+    use tracing::Instrument;
+    use tracing::{field, Span};
+
+    pub(crate) type OpSpan = Span;
+
+    // The span of an operation, see the module comment;
+    macro_rules! op_span {
+        ($name:literal, $key:expr) => {
+            ::tracing::info_span!(
+                $name,
+                key_len = $key.len(),
+                addr = ::tracing::field::Empty,
+                value_size = ::tracing::field::Empty,
+                outcome = ::tracing::field::Empty,
+                error = ::tracing::field::Empty,
+            )
+        };
+        ($name:literal) => {
+            ::tracing::info_span!(
+                $name,
+                outcome = ::tracing::field::Empty,
+                error = ::tracing::field::Empty,
+            )
+        };
+    }
+    pub(crate) use op_span;
+
+    // Runs `operation` in `span`;
+    pub(crate) fn in_span<T>(
+        span: OpSpan,
+        operation: impl FnOnce() -> Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        let result = span.in_scope(operation);
+        record_outcome(&span, result.as_ref().map(|_| "ok"));
+        result
+    }
+
+    // `in_span` of an operation that misses with `None`;
+    pub(crate) fn in_retrieval_span<T>(
+        span: OpSpan,
+        operation: impl FnOnce() -> Result<Option<T>, OperationError>,
+    ) -> Result<Option<T>, OperationError> {
+        let result = span.in_scope(operation);
+        record_outcome(&span, result.as_ref().map(hit_or_miss));
+        result
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) async fn in_async_span<T>(
+        span: OpSpan,
+        operation: impl Future<Output = Result<T, OperationError>>,
+    ) -> Result<T, OperationError> {
+        let result = operation.instrument(span.clone()).await;
+        record_outcome(&span, result.as_ref().map(|_| "ok"));
+        result
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) async fn in_async_retrieval_span<T>(
+        span: OpSpan,
+        operation: impl Future<Output = Result<Option<T>, OperationError>>,
+    ) -> Result<Option<T>, OperationError> {
+        let result = operation.instrument(span.clone()).await;
+        record_outcome(&span, result.as_ref().map(hit_or_miss));
+        result
+    }
+
+    fn hit_or_miss<T>(found: &Option<T>) -> &'static str {
+        match found {
+            Some(_) => "hit",
+            None => "miss",
+        }
+    }
+
+    fn record_outcome(span: &Span, outcome: Result<&'static str, &OperationError>) {
+        match outcome {
+            Ok(outcome) => {
+                span.record("outcome", outcome);
+            }
+            Err(error) if error.is_cache_miss() => {
+                span.record("outcome", "miss");
+            }
+            Err(error) => {
+                span.record("outcome", "error");
+                span.record("error", field::display(error));
+            }
+        }
+    }
+
+    // Names the server on the span of the running operation;
+    pub(crate) fn record_addr(addr: SocketAddr) {
+        Span::current().record("addr", field::display(addr));
+    }
+
+    pub(crate) fn record_value_size(size: usize) {
+        Span::current().record("value_size", size);
+    }
+
+    pub(crate) fn conn_dialed(addr: SocketAddr) {
+        tracing::debug!(%addr, "dialed connection");
+    }
+
+    pub(crate) fn conn_reused(addr: SocketAddr) {
+        tracing::debug!(%addr, "reusing idle connection");
+    }
+
+    pub(crate) fn conn_discarded(addr: SocketAddr) {
+        tracing::debug!(%addr, "discarded connection");
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    use crate::errors::OperationError;
+    #[cfg(feature = "async")]
+    use std::future::Future;
+    use std::net::SocketAddr;
+
+    pub(crate) struct OpSpan;
+
+    // The key isn't evaluated, so nothing is left of the span;
+    macro_rules! op_span {
+        ($name:literal $(, $key:expr)?) => {
+            $crate::trace::OpSpan
+        };
+    }
+    pub(crate) use op_span;
+
+    pub(crate) fn in_span<T>(
+        _span: OpSpan,
+        operation: impl FnOnce() -> Result<T, OperationError>,
+    ) -> Result<T, OperationError> {
+        operation()
+    }
+
+    pub(crate) fn in_retrieval_span<T>(
+        _span: OpSpan,
+        operation: impl FnOnce() -> Result<Option<T>, OperationError>,
+    ) -> Result<Option<T>, OperationError> {
+        operation()
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) async fn in_async_span<T>(
+        _span: OpSpan,
+        operation: impl Future<Output = Result<T, OperationError>>,
+    ) -> Result<T, OperationError> {
+        operation.await
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) async fn in_async_retrieval_span<T>(
+        _span: OpSpan,
+        operation: impl Future<Output = Result<Option<T>, OperationError>>,
+    ) -> Result<Option<T>, OperationError> {
+        operation.await
+    }
+
+    pub(crate) fn record_addr(_addr: SocketAddr) {}
+
+    pub(crate) fn record_value_size(_size: usize) {}
+
+    pub(crate) fn conn_dialed(_addr: SocketAddr) {}
+
+    pub(crate) fn conn_reused(_addr: SocketAddr) {}
+
+    pub(crate) fn conn_discarded(_addr: SocketAddr) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::{errors::OperationError, tests::mock_server, Client};
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    #[derive(Debug)]
+    struct RecordedSpan {
+        metadata: &'static Metadata<'static>,
+        fields: HashMap<&'static str, String>,
+    }
+
+    // Keeps every span and event message, tracking the entered spans for `Span::current`
+    #[derive(Clone, Default)]
+    struct Recorder {
+        spans: Arc<Mutex<Vec<RecordedSpan>>>,
+        events: Arc<Mutex<Vec<String>>>,
+        entered: Arc<Mutex<Vec<Id>>>,
+    }
+
+    struct Fields<'a>(&'a mut HashMap<&'static str, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+            let mut span = RecordedSpan {
+                metadata: attributes.metadata(),
+                fields: HashMap::new(),
+            };
+            attributes.record(&mut Fields(&mut span.fields));
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, id: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            let span = &mut spans[id.into_u64() as usize - 1];
+            values.record(&mut Fields(&mut span.fields));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = HashMap::new();
+            event.record(&mut Fields(&mut fields));
+            let message = fields.remove("message").unwrap_or_default();
+            self.events.lock().unwrap().push(message);
+        }
+
+        fn enter(&self, id: &Id) {
+            self.entered.lock().unwrap().push(id.clone());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> Current {
+            match self.entered.lock().unwrap().last() {
+                Some(id) => {
+                    let metadata = self.spans.lock().unwrap()[id.into_u64() as usize - 1].metadata;
+                    Current::new(id.clone(), metadata)
+                }
+                None => Current::none(),
+            }
+        }
+    }
+
+    #[test]
+    fn operations_record_their_outcome_on_success_and_failure() {
+        let addr = mock_server(b"VALUE traced 0 5\r\nhello\r\nEND\r\n");
+        let recorder = Recorder::default();
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            // Created here so the connection is dialed under the recorder
+            let mut client =
+                Client::new(addr.clone(), None, 0).expect("could not connect to mock server");
+            match client.get("bad key") {
+                Err(OperationError::MalformedKey(_)) => (),
+                other => panic!("expected a malformed key error. Got: {:?}", other),
+            }
+            match client.get("traced") {
+                Ok(Some(item)) if item.value == b"hello" => (),
+                other => panic!("expected a hit. Got: {:?}", other),
+            }
+        });
+
+        let spans = recorder.spans.lock().unwrap();
+        let fields: Vec<_> = spans
+            .iter()
+            .map(|span| (span.metadata.name(), &span.fields))
+            .collect();
+        match fields.as_slice() {
+            [("memcache.get", failed), ("memcache.get", hit)]
+                if failed["outcome"] == "error"
+                    && failed.contains_key("error")
+                    && !failed.contains_key("addr")
+                    && hit["outcome"] == "hit"
+                    && hit["key_len"] == "6"
+                    && hit["addr"] == addr
+                    && hit["value_size"] == "5" => {}
+            other => panic!("expected a failed and a hit get span. Got: {:?}", other),
+        }
+        let events = recorder.events.lock().unwrap();
+        if !events.contains(&"dialed connection".to_string()) {
+            panic!("expected a dial event. Got: {:?}", events)
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use crate::compression::decompressed;
+use crate::errors::OperationError;
+use crate::expiration::Expiration;
+use crate::flags::Flags;
+use crate::item::StoredValue;
+use crate::trace;
+use crate::Client;
+use std::borrow::Cow;
+
+// Type of a value stored by `set_as`, recorded as the codec id of its flags so `get_as` can
+// refuse to read it as another type; Bytes are stored as they are, the same as by `set`;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Bytes,
+    String,
+    // Plain decimal, so `increment` and `decrement` work on them
+    Integer,
+    Bool,
+    Float,
+    // Stored by a `ValueCodec`, with its id
+    Codec(u8),
+}
+
+impl ValueType {
+    pub(crate) fn codec_id(self) -> u8 {
+        match self {
+            ValueType::Bytes => 0,
+            ValueType::String => 2,
+            ValueType::Integer => 3,
+            ValueType::Bool => 4,
+            ValueType::Float => 5,
+            ValueType::Codec(id) => id,
+        }
+    }
+
+    pub(crate) fn from_codec_id(id: u8) -> Self {
+        match id {
+            0 => ValueType::Bytes,
+            2 => ValueType::String,
+            3 => ValueType::Integer,
+            4 => ValueType::Bool,
+            5 => ValueType::Float,
+            id => ValueType::Codec(id),
+        }
+    }
+}
+
+// A value `set_as` can store;
+pub trait ToMemcacheValue {
+    const VALUE_TYPE: ValueType;
+
+    fn to_memcache_value(&self) -> Cow<'_, [u8]>;
+}
+
+// A value `get_as` can read, from values stored as `VALUE_TYPE`;
+pub trait FromMemcacheValue: Sized {
+    const VALUE_TYPE: ValueType;
+
+    fn from_memcache_value(bytes: Vec<u8>) -> Result<Self, OperationError>;
+}
+
+impl<T: ToMemcacheValue + ?Sized> ToMemcacheValue for &T {
+    const VALUE_TYPE: ValueType = T::VALUE_TYPE;
+
+    fn to_memcache_value(&self) -> Cow<'_, [u8]> {
+        (**self).to_memcache_value()
+    }
+}
+
+impl ToMemcacheValue for [u8] {
+    const VALUE_TYPE: ValueType = ValueType::Bytes;
+
+    fn to_memcache_value(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl ToMemcacheValue for Vec<u8> {
+    const VALUE_TYPE: ValueType = ValueType::Bytes;
+
+    fn to_memcache_value(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl FromMemcacheValue for Vec<u8> {
+    const VALUE_TYPE: ValueType = ValueType::Bytes;
+
+    fn from_memcache_value(bytes: Vec<u8>) -> Result<Self, OperationError> {
+        Ok(bytes)
+    }
+}
+
+impl ToMemcacheValue for str {
+    const VALUE_TYPE: ValueType = ValueType::String;
+
+    fn to_memcache_value(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl ToMemcacheValue for String {
+    const VALUE_TYPE: ValueType = ValueType::String;
+
+    fn to_memcache_value(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl FromMemcacheValue for String {
+    const VALUE_TYPE: ValueType = ValueType::String;
+
+    fn from_memcache_value(bytes: Vec<u8>) -> Result<Self, OperationError> {
+        String::from_utf8(bytes).map_err(|error| OperationError::Codec(error.into()))
+    }
+}
+
+impl ToMemcacheValue for bool {
+    const VALUE_TYPE: ValueType = ValueType::Bool;
+
+    fn to_memcache_value(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(if *self { b"1" } else { b"0" })
+    }
+}
+
+impl FromMemcacheValue for bool {
+    const VALUE_TYPE: ValueType = ValueType::Bool;
+
+    fn from_memcache_value(bytes: Vec<u8>) -> Result<Self, OperationError> {
+        match bytes.as_slice() {
+            b"1" => Ok(true),
+            b"0" => Ok(false),
+            _ => Err(OperationError::Codec("bool value is not 0 or 1".into())),
+        }
+    }
+}
+
+// Parses the decimal (or float) text of a value;
+fn parse_text<T: std::str::FromStr>(bytes: &[u8]) -> Result<T, OperationError>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    std::str::from_utf8(bytes)
+        .map_err(|error| OperationError::Codec(error.into()))?
+        .parse()
+        .map_err(|error: T::Err| OperationError::Codec(error.into()))
+}
+
+impl ToMemcacheValue for f64 {
+    const VALUE_TYPE: ValueType = ValueType::Float;
+
+    // `Display` prints the shortest text that parses back to the same float
+    fn to_memcache_value(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.to_string().into_bytes())
+    }
+}
+
+impl FromMemcacheValue for f64 {
+    const VALUE_TYPE: ValueType = ValueType::Float;
+
+    fn from_memcache_value(bytes: Vec<u8>) -> Result<Self, OperationError> {
+        parse_text(&bytes)
+    }
+}
+
+macro_rules! integer_values {
+    ($($integer:ty),*) => {
+        $(
+            impl ToMemcacheValue for $integer {
+                const VALUE_TYPE: ValueType = ValueType::Integer;
+
+                fn to_memcache_value(&self) -> Cow<'_, [u8]> {
+                    Cow::Owned(self.to_string().into_bytes())
+                }
+            }
+
+            // Integers stored as another integer type are read as long as they fit
+            impl FromMemcacheValue for $integer {
+                const VALUE_TYPE: ValueType = ValueType::Integer;
+
+                fn from_memcache_value(bytes: Vec<u8>) -> Result<Self, OperationError> {
+                    parse_text(&bytes)
+                }
+            }
+        )*
+    };
+}
+
+integer_values!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+impl Client {
+    // Stores `value` along with its type, e.g. `set_as("count", 42u64, Expiration::Never)`;
+    // `set` remains for items with flags of their own;
+    pub fn set_as<V: ToMemcacheValue>(
+        &mut self,
+        key: &str,
+        value: V,
+        expiration: Expiration,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.set_as", key), || {
+            let stored = StoredValue {
+                value: value.to_memcache_value(),
+                flags: Flags::default().with_codec(V::VALUE_TYPE.codec_id()).wire(),
+                expiration,
+            };
+            self.set_encoded(key, stored)
+        })
+    }
+
+    // Reads a value stored by `set_as` as `V`; Values stored as another type (byte values
+    // stored by `set` included, unless read as `Vec<u8>`) fail with `ValueTypeMismatch`;
+    pub fn get_as<V: FromMemcacheValue>(&mut self, key: &str) -> Result<Option<V>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get_as", key), || {
+            let item = match self.get_encoded(key)? {
+                Some(item) => decompressed(item)?,
+                None => return Ok(None),
+            };
+            let stored = ValueType::from_codec_id(Flags::from_wire(item.flags).codec());
+            if stored != V::VALUE_TYPE {
+                return Err(OperationError::ValueTypeMismatch {
+                    stored,
+                    requested: V::VALUE_TYPE,
+                });
+            }
+            V::from_memcache_value(item.value).map(Some)
+        })
+    }
+
+    // Stores `value` as plain bytes with no flags, the same as `set`;
+    pub fn set_string(
+        &mut self,
+        key: &str,
+        value: &str,
+        expiration: Expiration,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.set_string", key), || {
+            let stored = StoredValue {
+                value: Cow::Borrowed(value.as_bytes()),
+                flags: 0,
+                expiration,
+            };
+            self.set_encoded(key, stored)
+        })
+    }
+
+    // Reads any value as a string, failing with `InvalidUtf8` when it isn't one;
+    pub fn get_string(&mut self, key: &str) -> Result<Option<String>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get_string", key), || {
+            match self.get(key)? {
+                Some(item) => String::from_utf8(item.value)
+                    .map(Some)
+                    .map_err(OperationError::InvalidUtf8),
+                None => Ok(None),
+            }
+        })
+    }
+
+    // Stores `value` in plain decimal with no flags, so `increment` and `decrement` work on it;
+    pub fn set_u64(
+        &mut self,
+        key: &str,
+        value: u64,
+        expiration: Expiration,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.set_u64", key), || {
+            let value = value.to_string();
+            self.set_string(key, &value, expiration)
+        })
+    }
+
+    // Reads a counter, failing with `InvalidCounter` when the value isn't a decimal `u64`;
+    pub fn get_u64(&mut self, key: &str) -> Result<Option<u64>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get_u64", key), || {
+            let item = match self.get(key)? {
+                Some(item) => item,
+                None => return Ok(None),
+            };
+            // The server decrements counters in place, padding the shorter number with spaces
+            let digits = item.value.trim_ascii_end();
+            // Not UTF-8 means not digits either, which `parse` reports the same way
+            String::from_utf8_lossy(digits)
+                .parse()
+                .map(Some)
+                .map_err(OperationError::InvalidCounter)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FromMemcacheValue, ToMemcacheValue, ValueType};
+    use crate::{
+        errors::OperationError,
+        expiration::Expiration,
+        item::Item,
+        tests::{lock_local_server, mock_server, LOCALHOST_TCP_ADDR},
+        Client,
+    };
+
+    fn round_trip<V: ToMemcacheValue + FromMemcacheValue>(value: &V) -> Option<V> {
+        V::from_memcache_value(value.to_memcache_value().into_owned()).ok()
+    }
+
+    #[test]
+    fn test_value_conversions() {
+        let wire: [(&[u8], Vec<u8>); 6] = [
+            (b"42", 42u64.to_memcache_value().into_owned()),
+            (b"-7", (-7i8).to_memcache_value().into_owned()),
+            (b"1", true.to_memcache_value().into_owned()),
+            (b"0.1", 0.1f64.to_memcache_value().into_owned()),
+            (b"text", "text".to_memcache_value().into_owned()),
+            (
+                b"\xff",
+                [0xffu8].as_slice().to_memcache_value().into_owned(),
+            ),
+        ];
+        for (expected, got) in wire {
+            if got != expected {
+                panic!("expected {:?} to be stored. Got: {:?}", expected, got)
+            }
+        }
+        if round_trip(&u128::MAX) != Some(u128::MAX)
+            || round_trip(&i64::MIN) != Some(i64::MIN)
+            || round_trip(&f64::MAX) != Some(f64::MAX)
+            || round_trip(&false) != Some(false)
+            || round_trip(&String::from("ünïcode")).as_deref() != Some("ünïcode")
+        {
+            panic!("expected values to read back as they were")
+        }
+        match u8::from_memcache_value(Vec::from("300")) {
+            Err(OperationError::Codec(_)) => (),
+            other => panic!("expected 300 not to fit a u8. Got: {:?}", other),
+        }
+        if ValueType::from_codec_id(ValueType::Float.codec_id()) != ValueType::Float
+            || ValueType::from_codec_id(1) != ValueType::Codec(1)
+        {
+            panic!("expected value types to map onto codec ids and back")
+        }
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        if let Err(error) = client.set_as("typed_count", 42u64, Expiration::Never) {
+            panic!("did not expect set as to fail: {}", error)
+        }
+        match client.get_as::<u64>("typed_count") {
+            Ok(Some(42)) => (),
+            other => panic!("expected 42 back. Got: {:?}", other),
+        }
+        // Integers stay integers through the server's arithmetic
+        if let Err(error) = client.increment("typed_count", 1) {
+            panic!("did not expect increment to fail: {}", error)
+        }
+        match client.get_as::<i32>("typed_count") {
+            Ok(Some(43)) => (),
+            other => panic!("expected 43 back. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.set_as("typed_text", "42", Expiration::Never) {
+            panic!("did not expect set as to fail: {}", error)
+        }
+        match client.get_as::<u64>("typed_text") {
+            Err(OperationError::ValueTypeMismatch {
+                stored: ValueType::String,
+                requested: ValueType::Integer,
+            }) => (),
+            other => panic!("expected a value type mismatch. Got: {:?}", other),
+        }
+        match client.get_as::<String>("typed_text") {
+            Ok(Some(text)) if text == "42" => (),
+            other => panic!("expected the text back. Got: {:?}", other),
+        }
+
+        for (key, value) in [("typed_true", true), ("typed_false", false)] {
+            if let Err(error) = client.set_as(key, value, Expiration::Never) {
+                panic!("did not expect set as to fail: {}", error)
+            }
+            match client.get_as::<bool>(key) {
+                Ok(Some(got)) if got == value => (),
+                other => panic!("expected {} back. Got: {:?}", value, other),
+            }
+        }
+        if let Err(error) = client.set_as("typed_float", 2.5f64, Expiration::Never) {
+            panic!("did not expect set as to fail: {}", error)
+        }
+        match client.get_as::<f64>("typed_float") {
+            Ok(Some(2.5)) => (),
+            other => panic!("expected 2.5 back. Got: {:?}", other),
+        }
+
+        // Bytes are plain items both ways
+        if let Err(error) = client.set(&Item::new("typed_bytes", "raw").unwrap()) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.get_as::<Vec<u8>>("typed_bytes") {
+            Ok(Some(value)) if value == b"raw" => (),
+            other => panic!("expected the raw bytes back. Got: {:?}", other),
+        }
+        match client.get_as::<bool>("typed_bytes") {
+            Err(OperationError::ValueTypeMismatch {
+                stored: ValueType::Bytes,
+                ..
+            }) => (),
+            other => panic!("expected a value type mismatch. Got: {:?}", other),
+        }
+        match client.get_as::<u64>("typed_missing") {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_string_and_counter_accessors() {
+        let _guard = lock_local_server();
+        let mut client = match Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0) {
+            Ok(client) => client,
+            Err(error) => panic!("could not connect to local server: {:?}", error),
+        };
+
+        if let Err(error) = client.set_u64("counter_accessor", 9, Expiration::Never) {
+            panic!("did not expect set u64 to fail: {}", error)
+        }
+        match client.increment("counter_accessor", 1) {
+            Ok(10) => (),
+            other => panic!("expected the counter to increment to 10. Got: {:?}", other),
+        }
+        match client.get_u64("counter_accessor") {
+            Ok(Some(10)) => (),
+            other => panic!("expected 10 back. Got: {:?}", other),
+        }
+        match client.decrement("counter_accessor", 3) {
+            Ok(7) => (),
+            other => panic!("expected the counter to decrement to 7. Got: {:?}", other),
+        }
+        match client.get_u64("counter_accessor") {
+            Ok(Some(7)) => (),
+            other => panic!("expected 7 back. Got: {:?}", other),
+        }
+        if let Err(error) = client.set_u64("counter_accessor", u64::MAX - 1, Expiration::Never) {
+            panic!("did not expect set u64 to fail: {}", error)
+        }
+        match client.increment("counter_accessor", 1) {
+            Ok(u64::MAX) => (),
+            other => panic!("expected the counter to reach u64::MAX. Got: {:?}", other),
+        }
+        match client.get_string("counter_accessor") {
+            Ok(Some(value)) if value == u64::MAX.to_string() => (),
+            other => panic!("expected the counter in plain decimal. Got: {:?}", other),
+        }
+
+        if let Err(error) = client.set_string("string_accessor", "héllo", Expiration::Never) {
+            panic!("did not expect set string to fail: {}", error)
+        }
+        match client.get_string("string_accessor") {
+            Ok(Some(value)) if value == "héllo" => (),
+            other => panic!("expected the string back. Got: {:?}", other),
+        }
+        match client.get_u64("string_accessor") {
+            Err(OperationError::InvalidCounter(_)) => (),
+            other => panic!("expected an invalid counter error. Got: {:?}", other),
+        }
+        if let Err(error) = client.set(&Item::new("string_accessor", vec![0xff, 0xfe]).unwrap()) {
+            panic!("did not expect set to fail: {}", error)
+        }
+        match client.get_string("string_accessor") {
+            Err(OperationError::InvalidUtf8(_)) => (),
+            other => panic!("expected an invalid UTF-8 error. Got: {:?}", other),
+        }
+        match (
+            client.get_string("missing_accessor"),
+            client.get_u64("missing_accessor"),
+        ) {
+            (Ok(None), Ok(None)) => (),
+            other => panic!("expected misses. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn counters_decremented_in_place_are_read_without_padding() {
+        let addr = mock_server(b"VALUE counter 0 3\r\n9  \r\nEND\r\n");
+        let mut client = Client::new(addr, None, 0).unwrap();
+        match client.get_u64("counter") {
+            Ok(Some(9)) => (),
+            other => panic!("expected 9 back. Got: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use crate::errors::{response_excerpt, timed_out, OperationError, WriteReadLineError};
+use crate::expiration::Expiration;
+use crate::flags::Flags;
+use crate::protocol::{self, Arg, ValueHeader};
+use crate::trace;
+use crate::{legal_key, Client, Conn, CR_LF, RESULT_END, VERB_GET, VERB_SET};
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read, Write};
+use std::time::Instant;
+
+// Unread bytes a dropped `ValueReader` still reads through to keep its connection; The connection
+// is discarded when more is left, reconnecting being cheaper than reading the rest of a large
+// value
+const MAX_DRAINED_BYTES: usize = 16 * 1024;
+// Bytes `set_from_reader` reads from its source at a time
+const COPY_CHUNK_SIZE: usize = 8 * 1024;
+
+// Value of a `Client::get_reader` hit, read off the connection as it's consumed; The connection
+// goes back to the pool once the value and the end of the response have been read, or when the
+// reader is dropped, see `MAX_DRAINED_BYTES`;
+pub struct ValueReader<'a> {
+    client: &'a mut Client,
+    // `None` once the connection was released
+    body: Option<Body>,
+    flags: u32,
+    stored_len: usize,
+}
+
+enum Body {
+    Raw(DataBlock),
+    // Values the client compressed are decompressed as they're read
+    Zlib(ZlibDecoder<DataBlock>),
+}
+
+// The data block of a get response, without its `\r\n`, on the connection it's read from
+struct DataBlock {
+    conn: Conn,
+    remaining: usize,
+}
+
+impl Read for DataBlock {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining);
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = self.conn.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+impl std::fmt::Debug for ValueReader<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValueReader")
+            .field("flags", &self.flags)
+            .field("stored_len", &self.stored_len)
+            .field("done", &self.body.is_none())
+            .finish()
+    }
+}
+
+impl ValueReader<'_> {
+    // Without the reserved bits, like `Item::flags`
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    // Size of the value as stored on the server, i.e. before decompression;
+    pub fn stored_len(&self) -> usize {
+        self.stored_len
+    }
+
+    fn take_block(&mut self) -> Option<DataBlock> {
+        match self.body.take()? {
+            Body::Raw(block) => Some(block),
+            Body::Zlib(decoder) => Some(decoder.into_inner()),
+        }
+    }
+
+    // Reads the `\r\nEND\r\n` after a fully read data block and releases the connection;
+    fn finish(&mut self, block: DataBlock) -> Result<(), OperationError> {
+        let DataBlock {
+            mut conn,
+            remaining,
+        } = block;
+        let result = if remaining != 0 {
+            Err(OperationError::CorruptResponse(
+                "compressed value ended before its data block".to_string(),
+            ))
+        } else {
+            Client::read_value_end(&mut conn)
+        };
+        self.client.release_conn(conn, result)
+    }
+}
+
+impl Read for ValueReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.body.as_mut() {
+            None => return Ok(0),
+            Some(Body::Raw(block)) => block.read(buf),
+            Some(Body::Zlib(decoder)) => decoder.read(buf),
+        };
+        match read {
+            Ok(0) if !buf.is_empty() => {
+                if let Some(block) = self.take_block() {
+                    self.finish(block)
+                        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                }
+                Ok(0)
+            }
+            Ok(read) => Ok(read),
+            Err(error) => {
+                if let Some(block) = self.take_block() {
+                    self.client.discard_conn(block.conn);
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+// Whatever is left of the value is read through and dropped when that's cheap, so the
+// connection can be reused; Otherwise it's discarded, never going back to the pool in the
+// middle of a response;
+impl Drop for ValueReader<'_> {
+    fn drop(&mut self) {
+        let Some(mut block) = self.take_block() else {
+            return;
+        };
+        if block.remaining > MAX_DRAINED_BYTES {
+            self.client.discard_conn(block.conn);
+            return;
+        }
+        match io::copy(&mut block, &mut io::sink()) {
+            Ok(_) => {
+                let _ = self.finish(block);
+            }
+            Err(_) => self.client.discard_conn(block.conn),
+        }
+    }
+}
+
+impl Client {
+    // `get` handing the value out as a reader over the connection rather than reading it into
+    // memory, for large values that are copied somewhere else; The client can't be used until
+    // the reader is dropped;
+    pub fn get_reader(&mut self, key: &str) -> Result<Option<ValueReader<'_>>, OperationError> {
+        trace::in_retrieval_span(trace::op_span!("memcache.get_reader", key), move || {
+            self.ascii_only("get_reader")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            let addr = self.pick_server(key)?;
+            let started = Instant::now();
+            // Like `retry_on_stale_conn`, a pooled connection the server closed is replaced once
+            let pooled = match self.get_free_conn(addr) {
+                Some(mut conn) => match Client::read_value_header(&mut conn, key) {
+                    Err(OperationError::Io(error)) if !error.is_timeout() => {
+                        self.discard_conn(conn);
+                        None
+                    }
+                    result => Some((conn, result)),
+                },
+                None => None,
+            };
+            let (conn, header) = match pooled {
+                Some(attempt) => attempt,
+                None => {
+                    let mut conn = self.dial_key_conn(addr, started)?;
+                    let header = Client::read_value_header(&mut conn, key);
+                    (conn, header)
+                }
+            };
+            trace::record_addr(conn.addr);
+            let header = match header {
+                Ok(Some(header)) => header,
+                result => return self.release_conn(conn, result.map(|_| None)),
+            };
+            trace::record_value_size(header.size);
+
+            let flags = Flags::from_wire(header.flags);
+            let block = DataBlock {
+                conn,
+                remaining: header.size,
+            };
+            let body = match flags.compressed() {
+                true => Body::Zlib(ZlibDecoder::new(block)),
+                false => Body::Raw(block),
+            };
+            Ok(Some(ValueReader {
+                client: self,
+                body: Some(body),
+                flags: flags.user(),
+                stored_len: header.size,
+            }))
+        })
+    }
+
+    // `set` of a value copied from `source` as it's written, `len` being its exact size; The
+    // value isn't compressed, nor is the command retried as whatever was read from `source` is
+    // gone; If `source` fails or ends early the connection is closed, leaving the server with an
+    // incomplete command it drops, and `ValueSource` is returned;
+    pub fn set_from_reader(
+        &mut self,
+        key: &str,
+        flags: u32,
+        expiration: Expiration,
+        len: usize,
+        source: &mut impl Read,
+    ) -> Result<(), OperationError> {
+        trace::in_span(trace::op_span!("memcache.set_from_reader", key), || {
+            self.ascii_only("set_from_reader")?;
+            let key = &self.prefixed(key);
+            legal_key(key)?;
+            let flags = Flags::from_user(flags)?.wire();
+            let exptime = expiration.wire_value()?;
+            self.check_value_len(len)?;
+            trace::record_value_size(len);
+            let addr = self.pick_server(key)?;
+            let mut conn = self.get_conn(addr)?;
+            trace::record_addr(conn.addr);
+            let args = [
+                Arg::Str(key),
+                Arg::Uint(flags.into()),
+                Arg::Int(exptime.into()),
+                Arg::Uint(len as u64),
+            ];
+            let written = protocol::write_command(&mut conn.writer, VERB_SET, args)
+                .map_err(OperationError::Io)
+                .and_then(|()| Client::copy_value(&mut conn, len, source));
+            if let Err(error) = written {
+                self.discard_conn(conn);
+                return match error {
+                    OperationError::Io(error) if error.is_timeout() => {
+                        Err(OperationError::Timeout(timed_out(error.into_inner())))
+                    }
+                    error => Err(error),
+                };
+            }
+            let result = Client::read_storage_response(&mut conn, VERB_SET);
+            self.release_conn(conn, result)
+        })
+    }
+
+    // Writes the `len` bytes data block read from `source`, and flushes it;
+    fn copy_value(
+        conn: &mut Conn,
+        len: usize,
+        source: &mut impl Read,
+    ) -> Result<(), OperationError> {
+        let mut chunk = [0; COPY_CHUNK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let read = match source.read(&mut chunk[..remaining.min(COPY_CHUNK_SIZE)]) {
+                Ok(0) => {
+                    return Err(OperationError::ValueSource(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("source ended after {} of {} bytes", len - remaining, len),
+                    )))
+                }
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(OperationError::ValueSource(error)),
+            };
+            conn.writer
+                .write_all(&chunk[..read])
+                .map_err(|error| OperationError::Io(WriteReadLineError::Write(error)))?;
+            remaining -= read;
+        }
+        conn.write_line(CR_LF).map_err(OperationError::Io)
+    }
+
+    // Sends `get <key>` and reads up to the data block; `None` on a miss;
+    fn read_value_header(
+        conn: &mut Conn,
+        key: &str,
+    ) -> Result<Option<ValueHeader>, OperationError> {
+        conn.write_command(VERB_GET, [Arg::Str(key)])?;
+        protocol::parse_value_header(conn.read_line()?, VERB_GET, false)
+    }
+
+    // Reads the `\r\n` ending a data block and the `END\r\n` after it;
+    pub(crate) fn read_value_end(conn: &mut Conn) -> Result<(), OperationError> {
+        let mut block_end = [0; 2];
+        conn.reader
+            .read_exact(&mut block_end)
+            .map_err(|error| OperationError::Io(WriteReadLineError::Read(error)))?;
+        if block_end != CR_LF {
+            return Err(OperationError::CorruptResponse(
+                "corrupt get result read".to_string(),
+            ));
+        }
+        let line = conn.read_line()?;
+        if line != RESULT_END {
+            return Err(OperationError::CorruptResponse(format!(
+                "expected the end of the get response, got: {}",
+                response_excerpt(line)
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{COPY_CHUNK_SIZE, MAX_DRAINED_BYTES};
+    use crate::errors::OperationError;
+    use crate::expiration::Expiration;
+    use crate::item::Item;
+    use crate::tests::{lock_local_server, mock_server, noise, LOCALHOST_TCP_ADDR};
+    use crate::Client;
+    use std::io::Read;
+    use std::time::Duration;
+
+    fn discarded(client: &Client) -> u64 {
+        client.pool_stats()[LOCALHOST_TCP_ADDR].discarded
+    }
+
+    #[test]
+    fn test_get_reader() {
+        let _guard = lock_local_server();
+        let mut client = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0)
+            .unwrap()
+            .with_compression_threshold(Some(1024));
+        let large = noise(4 * MAX_DRAINED_BYTES);
+        let compressible = b"<li>item</li>".repeat(1000);
+        for (key, value) in [
+            ("get_reader_large", large.as_slice()),
+            ("get_reader_small", b"small value"),
+            ("get_reader_zlib", compressible.as_slice()),
+        ] {
+            let item = Item::from_parts(key.to_string(), value.to_vec(), 42, 60);
+            if let Err(error) = client.set(&item) {
+                panic!("did not expect set to fail: {}", error)
+            }
+        }
+
+        for (key, value) in [
+            ("get_reader_large", &large),
+            ("get_reader_zlib", &compressible),
+        ] {
+            let mut read = Vec::new();
+            match client.get_reader(key) {
+                Ok(Some(mut reader)) if reader.flags() == 42 => {
+                    if let Err(error) = reader.read_to_end(&mut read) {
+                        panic!("did not expect reading the value to fail: {}", error)
+                    }
+                }
+                other => panic!("expected a reader. Got: {:?}", other),
+            }
+            if &read != value {
+                panic!("expected the value of {}. Got {} bytes", key, read.len())
+            }
+        }
+        match client.get_reader("get_reader_missing") {
+            Ok(None) => (),
+            other => panic!("expected a miss. Got: {:?}", other),
+        }
+        if discarded(&client) != 0 {
+            panic!("expected fully read values to keep their connection")
+        }
+
+        // The rest of a small value is drained, a large one costs the connection
+        for (key, expected_discarded) in [("get_reader_small", 0), ("get_reader_large", 1)] {
+            match client.get_reader(key) {
+                Ok(Some(mut reader)) => {
+                    if let Err(error) = reader.read_exact(&mut [0; 4]) {
+                        panic!("did not expect reading the value to fail: {}", error)
+                    }
+                }
+                other => panic!("expected a reader. Got: {:?}", other),
+            }
+            if discarded(&client) != expected_discarded {
+                panic!(
+                    "expected {} discarded connections after {}. Got: {}",
+                    expected_discarded,
+                    key,
+                    discarded(&client)
+                )
+            }
+            match client.get("get_reader_small") {
+                Ok(Some(item)) if item.value == b"small value" => (),
+                other => panic!(
+                    "expected the next get to read its own item. Got: {:?}",
+                    other
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_from_reader() {
+        let _guard = lock_local_server();
+        let mut client = Client::new(String::from(LOCALHOST_TCP_ADDR), None, 0).unwrap();
+        let expiration = Expiration::In(Duration::from_secs(60));
+        let value = noise(3 * COPY_CHUNK_SIZE + 5);
+        let result = client.set_from_reader(
+            "set_from_reader",
+            42,
+            expiration,
+            value.len(),
+            &mut &value[..],
+        );
+        if let Err(error) = result {
+            panic!("did not expect set_from_reader to fail: {}", error)
+        }
+        match client.get("set_from_reader") {
+            Ok(Some(item)) if item.value == value && item.flags == 42 => (),
+            other => panic!("expected the streamed value. Got: {:?}", other),
+        }
+
+        // The `quit` of a graceful close would make up the missing bytes of the data block
+        let result = client.set_from_reader(
+            "set_from_reader_short",
+            0,
+            expiration,
+            10,
+            &mut &b"abcdef"[..],
+        );
+        match result {
+            Err(OperationError::ValueSource(_)) => (),
+            other => panic!("expected a value source error. Got: {:?}", other),
+        }
+        if client.pool_stats()[LOCALHOST_TCP_ADDR].discarded != 1 {
+            panic!("expected the connection of the aborted set to be discarded")
+        }
+        match client.get("set_from_reader_short") {
+            Ok(None) => (),
+            other => panic!("expected nothing stored. Got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn truncated_values_fail_the_read() {
+        let addr = mock_server(b"VALUE key 0 10\r\nfive!");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let read = client
+            .get_reader("key")
+            .map(|reader| reader.map(|mut reader| reader.read_to_end(&mut Vec::new())));
+        match read {
+            Ok(Some(Err(_))) => (),
+            other => panic!("expected the read to fail. Got: {:?}", other),
+        }
+
+        // Responses that go on after the value
+        let addr = mock_server(b"VALUE key 0 5\r\nvalue\r\nVALUE other 0 5\r\n");
+        let mut client = Client::new(addr, None, 0).expect("could not connect to mock server");
+        let read = client
+            .get_reader("key")
+            .map(|reader| reader.map(|mut reader| reader.read_to_end(&mut Vec::new())));
+        let error = match read {
+            Ok(Some(Err(error))) => error,
+            other => panic!("expected the read to fail. Got: {:?}", other),
+        };
+        match error
+            .into_inner()
+            .map(|error| error.downcast::<OperationError>())
+        {
+            Some(Ok(error)) if matches!(*error, OperationError::CorruptResponse(_)) => (),
+            other => panic!("expected a corrupt response error. Got: {:?}", other),
+        }
+    }
+}